@@ -0,0 +1,48 @@
+//! Compares walking the AST with running the compiled program, on expressions evaluated
+//! many times with different values of a variable, as plots, tables and integrals do.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use textcalculator::compile::Program;
+use textcalculator::evaluator::{evaluate_with, Context, ValueWithUnit};
+use textcalculator::parser::parse_expression;
+
+/// The number of values of the variable per iteration.
+const SAMPLES: usize = 1000;
+
+/// Expressions of `x` with the unit of `x`.
+const WORKLOADS: &[(&str, &str, Option<&str>)] = &[
+    ("plot", "sin(x) / x + x^2 / 10", None),
+    ("units", "0.5 * 9.81 m/s^2 * x^2 to km", Some("s")),
+    ("conditions", "x > 0 and x mod 2 < 1 ? sqrt(x) * 2 pi : -x", None),
+];
+
+fn evaluation(c: &mut Criterion) {
+    let xs: Vec<f64> = (0..SAMPLES).map(|i| i as f64 / 10.0 - 50.0).collect();
+    for &(name, input, unit) in WORKLOADS {
+        let expr = parse_expression(input).unwrap();
+        let mut group = c.benchmark_group(name);
+        group.bench_function("tree", |b| {
+            let mut context = Context::new();
+            b.iter(|| {
+                for &x in &xs {
+                    context.set_variable("x", ValueWithUnit::new(x, unit.map(String::from)));
+                    black_box(evaluate_with(&expr, &context).ok());
+                }
+            })
+        });
+        group.bench_function("compiled", |b| {
+            let mut program = Program::compile(&expr, &[("x", unit)], &Context::new()).unwrap();
+            b.iter(|| {
+                for &x in &xs {
+                    black_box(program.evaluate(&[x]).ok());
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, evaluation);
+criterion_main!(benches);
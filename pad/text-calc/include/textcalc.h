@@ -0,0 +1,91 @@
+#ifndef TEXTCALC_H
+#define TEXTCALC_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// What an evaluation produced.
+typedef enum TextCalcKind {
+  // A number, in `value`, with its unit in `unit`.
+  TEXT_CALC_KIND_NUMBER = 0,
+  // A boolean, in `value` as `1` or `0`.
+  TEXT_CALC_KIND_BOOLEAN = 1,
+  // An expression with free symbols, simplified, in `text`.
+  TEXT_CALC_KIND_SYMBOLIC = 2,
+  // The roots of a top-level `solve`, listed in `text`; `value` and `unit` hold the first.
+  TEXT_CALC_KIND_ROOTS = 3,
+  // The evaluation failed; the message is in `error`.
+  TEXT_CALC_KIND_ERROR = 4,
+} TextCalcKind;
+
+// An opaque calculator context, created by [`textcalc_new`] and destroyed by [`textcalc_free`].
+typedef struct TextCalc TextCalc;
+
+// The result of [`textcalc_eval`]. Unused string fields are null.
+typedef struct TextCalcResult {
+  // What the evaluation produced.
+  enum TextCalcKind kind;
+  // The numeric value, or `NaN` when there is none.
+  double value;
+  // The unit of `value`, or null for a unitless value.
+  char *unit;
+  // The result as the CLI prints it, e.g., `2.2 lb`, or null on error.
+  char *text;
+  // The error message, or null on success.
+  char *error;
+} TextCalcResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a calculator context with the default settings.
+//
+// # Returns
+//
+// * A new context, to be destroyed with [`textcalc_free`].
+struct TextCalc *textcalc_new(void);
+
+// Destroys a calculator context. Passing null does nothing.
+//
+// # Safety
+//
+// `context` must be null or a pointer returned by [`textcalc_new`] that has not been freed.
+void textcalc_free(struct TextCalc *context);
+
+// Evaluates one expression, e.g., `1 kg to lb`, or assignment, e.g., `d = 5 km`. Assigned
+// variables are kept in the context for later calls.
+//
+// # Arguments
+//
+// * `context` - The calculator context.
+// * `expression` - A NUL-terminated UTF-8 string.
+//
+// # Returns
+//
+// * The result, whose strings must be released with [`textcalc_result_free`].
+//
+// # Safety
+//
+// `context` must be a live pointer from [`textcalc_new`] and `expression` a valid
+// NUL-terminated string. A context must not be used from two threads at once.
+struct TextCalcResult textcalc_eval(struct TextCalc *context, const char *expression);
+
+// Releases the strings of a result and sets them to null. Passing null does nothing.
+//
+// # Safety
+//
+// `result` must be null or point to a result returned by [`textcalc_eval`] whose strings
+// have not been released already.
+void textcalc_result_free(struct TextCalcResult *result);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TEXTCALC_H */
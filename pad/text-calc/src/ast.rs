@@ -2,8 +2,19 @@
 //!
 //! This module defines the structures used to represent parsed expressions.
 
+use std::fmt;
+
+/// A line of input in a session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// Binds a variable to the value of an expression (e.g., `x = 5 m`).
+    Assignment { name: String, expr: Expr },
+    /// An expression to evaluate.
+    Expression(Expr),
+}
+
 /// Represents an expression node in the AST.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     /// A numeric literal.
     Number(f64),
@@ -14,6 +25,8 @@ pub enum Expr {
         /// The unit of the value, if any.
         unit: Option<String>,
     },
+    /// A free symbol (e.g., `x`).
+    Symbol(String),
     /// An expression with a unary operator (e.g., `-x`).
     UnaryOp {
         /// The unary operator.
@@ -30,12 +43,14 @@ pub enum Expr {
         /// The right-hand side expression.
         right: Box<Expr>,
     },
-    /// A function call with a name and an argument (e.g., `sin(x)`).
+    /// A function call with a name and its arguments (e.g., `sin(x)` or `diff(x^2, x)`).
+    ///
+    /// A conditional `c ? a : b` is parsed as the call `if(c, a, b)`.
     Function {
         /// The name of the function.
         name: String,
-        /// The argument of the function.
-        arg: Box<Expr>,
+        /// The arguments of the function.
+        args: Vec<Expr>,
     },
     /// A unit conversion expression (e.g., `5 kg to lb`).
     Conversion {
@@ -44,19 +59,32 @@ pub enum Expr {
         /// The target unit.
         target_unit: String,
     },
+    /// An equation, only valid as a function argument (e.g., `x^2 = 4` in `solve(x^2 = 4, x)`).
+    Equation {
+        /// The left-hand side.
+        left: Box<Expr>,
+        /// The right-hand side.
+        right: Box<Expr>,
+    },
 }
 
 /// Represents a unary operator.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOp {
     /// Unary plus (`+`).
     Plus,
     /// Unary minus (`-`).
     Minus,
+    /// Logical negation (`not`).
+    Not,
+    /// Postfix factorial (`!`), the gamma function for non-integers.
+    Factorial,
+    /// Postfix percent (`%`), a hundredth of its operand.
+    Percent,
 }
 
 /// Represents a binary operator.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOp {
     /// Addition operator (`+`).
     Add,
@@ -66,7 +94,213 @@ pub enum BinaryOp {
     Multiply,
     /// Division operator (`/`).
     Divide,
+    /// Floor division operator (`//`), rounding the quotient down.
+    FloorDivide,
+    /// Modulo operator (`mod`), with the sign of the divisor.
+    Modulo,
     /// Exponentiation operator (`^`).
     Power,
+    /// A value with its standard uncertainty (`±`), e.g., `9.81 ± 0.02`.
+    PlusMinus,
+    /// Equality comparison (`==`).
+    Equal,
+    /// Inequality comparison (`!=`).
+    NotEqual,
+    /// Less-than comparison (`<`).
+    Less,
+    /// Less-than-or-equal comparison (`<=`).
+    LessEqual,
+    /// Greater-than comparison (`>`).
+    Greater,
+    /// Greater-than-or-equal comparison (`>=`).
+    GreaterEqual,
+    /// Logical conjunction (`and`).
+    And,
+    /// Logical disjunction (`or`).
+    Or,
+}
+
+impl BinaryOp {
+    /// Checks whether the operator compares two values (`==`, `<`, ...).
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Equal
+                | BinaryOp::NotEqual
+                | BinaryOp::Less
+                | BinaryOp::LessEqual
+                | BinaryOp::Greater
+                | BinaryOp::GreaterEqual
+        )
+    }
+
+    /// Checks whether the operator combines booleans (`and`, `or`).
+    pub fn is_logical(self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+}
+
+/// Binding strength of an equation (`=`), the loosest construct.
+const PREC_EQUATION: u8 = 0;
+/// Binding strength of a logical `not`.
+const PREC_NOT: u8 = 3;
+/// Binding strength of a conversion (`to`).
+const PREC_CONVERSION: u8 = 5;
+/// Binding strength of a unary sign, between `±` and powers.
+const PREC_UNARY: u8 = 9;
+/// Binding strength of a postfix factorial.
+const PREC_POSTFIX: u8 = 11;
+/// Binding strength of literals, symbols, function calls and groupings.
+const PREC_ATOM: u8 = 12;
+
+impl BinaryOp {
+    /// Returns how tightly the operator binds; higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            _ if self.is_comparison() => 4,
+            BinaryOp::Add | BinaryOp::Subtract => 6,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::FloorDivide | BinaryOp::Modulo => 7,
+            BinaryOp::PlusMinus => 8,
+            _ => 10,
+        }
+    }
 }
 
+impl Expr {
+    /// Returns how tightly the expression binds when printed.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Number(n) | Expr::NumberWithUnit { value: n, .. } if n.is_sign_negative() => PREC_UNARY,
+            // A number with a suffix such as `2 x` is a product.
+            Expr::NumberWithUnit { unit: Some(_), .. } => BinaryOp::Multiply.precedence(),
+            Expr::UnaryOp { op: UnaryOp::Not, .. } => PREC_NOT,
+            Expr::UnaryOp { op: UnaryOp::Factorial | UnaryOp::Percent, .. } => PREC_POSTFIX,
+            Expr::UnaryOp { .. } => PREC_UNARY,
+            Expr::BinaryOp { op, .. } => op.precedence(),
+            Expr::Conversion { .. } => PREC_CONVERSION,
+            Expr::Equation { .. } => PREC_EQUATION,
+            _ => PREC_ATOM,
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOp::Plus => write!(f, "+"),
+            UnaryOp::Minus => write!(f, "-"),
+            UnaryOp::Not => write!(f, "not "),
+            UnaryOp::Factorial => write!(f, "!"),
+            UnaryOp::Percent => write!(f, "%"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryOp::Add => write!(f, " + "),
+            BinaryOp::Subtract => write!(f, " - "),
+            BinaryOp::Multiply => write!(f, " * "),
+            BinaryOp::Divide => write!(f, " / "),
+            BinaryOp::FloorDivide => write!(f, " // "),
+            BinaryOp::Modulo => write!(f, " mod "),
+            BinaryOp::Power => write!(f, "^"),
+            BinaryOp::PlusMinus => write!(f, " ± "),
+            BinaryOp::Equal => write!(f, " == "),
+            BinaryOp::NotEqual => write!(f, " != "),
+            BinaryOp::Less => write!(f, " < "),
+            BinaryOp::LessEqual => write!(f, " <= "),
+            BinaryOp::Greater => write!(f, " > "),
+            BinaryOp::GreaterEqual => write!(f, " >= "),
+            BinaryOp::And => write!(f, " and "),
+            BinaryOp::Or => write!(f, " or "),
+        }
+    }
+}
+
+impl Expr {
+    /// Returns which operands of the expression are printed in parentheses: the operand of a
+    /// unary operator or a conversion, or the left and right operands of a binary operator.
+    pub(crate) fn operand_parentheses(&self) -> (bool, bool) {
+        match self {
+            Expr::UnaryOp { op: UnaryOp::Factorial | UnaryOp::Percent, expr } => (expr.precedence() < PREC_POSTFIX, false),
+            Expr::UnaryOp { expr, .. } => (expr.precedence() < self.precedence(), false),
+            Expr::BinaryOp { left, op, right } => {
+                let precedence = op.precedence();
+                // Power is right-associative and its exponent may be signed, comparisons do
+                // not chain, and the other operators are left-associative.
+                match op {
+                    BinaryOp::Power => (left.precedence() <= precedence, right.precedence() < PREC_UNARY),
+                    BinaryOp::Add | BinaryOp::Multiply => {
+                        (left.precedence() < precedence, right.precedence() < precedence)
+                    }
+                    // `±` does not chain, but a number with a unit binds tightly, as in `2 m ± 1 cm`.
+                    BinaryOp::PlusMinus => {
+                        let parens = |operand: &Expr| {
+                            operand.precedence() <= precedence && !matches!(operand, Expr::NumberWithUnit { .. })
+                        };
+                        (parens(left), parens(right))
+                    }
+                    // Comparisons do not chain.
+                    _ if op.is_comparison() => (left.precedence() <= precedence, right.precedence() <= precedence),
+                    _ => (left.precedence() < precedence, right.precedence() <= precedence),
+                }
+            }
+            Expr::Conversion { expr, .. } => (expr.precedence() <= PREC_CONVERSION, false),
+            _ => (false, false),
+        }
+    }
+}
+
+/// Writes `expr`, wrapping it in parentheses when `parenthesize` is set.
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expr, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Prints the expression using only the parentheses needed to parse it back to the same tree.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (first_parens, second_parens) = self.operand_parentheses();
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::NumberWithUnit { value, unit: Some(unit) } => write!(f, "{} {}", value, unit),
+            Expr::NumberWithUnit { value, unit: None } => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::UnaryOp { op: op @ (UnaryOp::Factorial | UnaryOp::Percent), expr } => {
+                write_operand(f, expr, first_parens)?;
+                write!(f, "{}", op)
+            }
+            Expr::UnaryOp { op, expr } => {
+                write!(f, "{}", op)?;
+                write_operand(f, expr, first_parens)
+            }
+            Expr::BinaryOp { left, op, right } => {
+                write_operand(f, left, first_parens)?;
+                write!(f, "{}", op)?;
+                write_operand(f, right, second_parens)
+            }
+            Expr::Function { name, args } => {
+                write!(f, "{}(", name)?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Conversion { expr, target_unit } => {
+                write_operand(f, expr, first_parens)?;
+                write!(f, " to {}", target_unit)
+            }
+            Expr::Equation { left, right } => write!(f, "{} = {}", left, right),
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! Batch evaluation over ranges and CSV files.
+//!
+//! `table` evaluates an expression for every value of one or more stepped variables;
+//! `map` evaluates an expression for every row of a CSV file, with the columns bound as variables;
+//! `amortization` lays out the payments of a loan.
+//!
+//! Expressions are evaluated with the settings, constants, variables and exchange rates of a
+//! [`Calculator`], as they would be by `eval`.
+
+use crate::ast::Expr;
+use crate::calculator::Calculator;
+use crate::compile::Program;
+use crate::evaluator::{evaluate_with, Context, ValueWithUnit};
+use crate::{finance, symbolic, units};
+use std::io::Read;
+
+/// The most rows a range may produce.
+const MAX_ROWS: usize = 100_000;
+
+/// A table of formatted cells with a header row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    /// The column headers, with units annotated as `name[unit]`.
+    pub headers: Vec<String>,
+    /// The rows, each with one cell per header.
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Formats the table as CSV.
+    pub fn to_csv(&self) -> Result<String, String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(&self.headers).map_err(|e| e.to_string())?;
+        for row in &self.rows {
+            writer.write_record(row).map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Formats the table as a Markdown table.
+    pub fn to_markdown(&self) -> String {
+        let escape = |cell: &String| cell.replace('|', "\\|");
+        let mut output = String::new();
+        output.push_str(&format!("| {} |\n", self.headers.iter().map(escape).collect::<Vec<_>>().join(" | ")));
+        output.push_str(&format!("|{}\n", " --- |".repeat(self.headers.len())));
+        for row in &self.rows {
+            output.push_str(&format!("| {} |\n", row.iter().map(escape).collect::<Vec<_>>().join(" | ")));
+        }
+        output
+    }
+}
+
+/// A variable stepped over a range of values, e.g., `x=1..10` or `d=0 m..5 m:0.5 m`.
+#[derive(Debug, Clone)]
+pub struct Range {
+    /// The variable name.
+    pub name: String,
+    /// The values, all in the unit of the start value.
+    pub values: Vec<ValueWithUnit>,
+}
+
+/// Parses a range specification of the form `name=start..end` or `name=start..end:step`.
+///
+/// The bounds and step are expressions and may carry units; the step defaults to 1.
+///
+/// # Arguments
+///
+/// * `spec` - The range specification.
+/// * `calculator` - The calculator the bounds and step are evaluated with.
+///
+/// # Returns
+///
+/// * `Ok(Range)` - The variable and its values.
+/// * `Err(String)` - An error message if the specification is malformed.
+pub fn parse_range(spec: &str, calculator: &Calculator) -> Result<Range, String> {
+    let malformed = || format!("Invalid range '{}'; expected name=start..end or name=start..end:step", spec);
+    let (name, bounds) = spec.split_once('=').ok_or_else(malformed)?;
+    let (start, rest) = bounds.split_once("..").ok_or_else(malformed)?;
+    let (end, step) = match rest.split_once(':') {
+        Some((end, step)) => (end, Some(step)),
+        None => (rest, None),
+    };
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(malformed());
+    }
+
+    let (start, end, step) = calculator.with_context(|context| {
+        let start = evaluate_text(start, calculator, context)?;
+        let in_start_unit = |text: &str| -> Result<f64, String> {
+            let value = evaluate_text(text, calculator, context)?;
+            match (&start.unit, &value.unit) {
+                (None, None) => Ok(value.value),
+                (Some(to), Some(from)) => units::convert(value.value, from, to),
+                _ => Err(format!("The range of '{}' must use the same units throughout", name)),
+            }
+        };
+        let end = in_start_unit(end)?;
+        let step = match step {
+            Some(step) => in_start_unit(step)?,
+            None => 1.0,
+        };
+        Ok::<_, String>((start, end, step))
+    })?;
+    if step.is_nan() || step <= 0.0 || !end.is_finite() || !start.value.is_finite() {
+        return Err(format!("The range of '{}' needs finite bounds and a positive step", name));
+    }
+
+    let count = ((end - start.value) / step + 1e-9).floor();
+    if count >= MAX_ROWS as f64 {
+        return Err(format!("The range of '{}' has too many values", name));
+    }
+    let values = (0..=count.max(-1.0) as i64)
+        .map(|i| ValueWithUnit::new(start.value + step * i as f64, start.unit.clone()))
+        .collect();
+    Ok(Range {
+        name: name.to_string(),
+        values,
+    })
+}
+
+/// Splits a header like `weight[kg]` into the column name and its unit.
+///
+/// # Arguments
+///
+/// * `header` - The column header.
+///
+/// # Returns
+///
+/// The trimmed column name, and the unit if the header has an annotation.
+pub fn parse_header(header: &str) -> (String, Option<String>) {
+    let header = header.trim();
+    match header.strip_suffix(']').and_then(|rest| rest.split_once('[')) {
+        Some((name, unit)) if !unit.trim().is_empty() => (name.trim().to_string(), Some(unit.trim().to_string())),
+        _ => (header.to_string(), None),
+    }
+}
+
+/// Evaluates an expression for every combination of the range values.
+///
+/// The first range varies slowest. Rows that fail to evaluate keep an empty result cell
+/// and their errors are returned alongside the table.
+///
+/// # Arguments
+///
+/// * `expression` - The expression to evaluate.
+/// * `ranges` - The stepped variables.
+/// * `calculator` - The calculator the expression is evaluated with; the ranges shadow its variables.
+///
+/// # Returns
+///
+/// * `Ok((Table, Vec<String>))` - The table and the errors of failed rows.
+/// * `Err(String)` - An error message if the expression cannot be parsed.
+pub fn table(expression: &str, ranges: &[Range], calculator: &Calculator) -> Result<(Table, Vec<String>), String> {
+    let ast = prepare(expression, calculator)?;
+    let mut combinations: Vec<Vec<&ValueWithUnit>> = vec![Vec::new()];
+    for range in ranges {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|prefix| {
+                range.values.iter().map(move |value| {
+                    let mut row = prefix.clone();
+                    row.push(value);
+                    row
+                })
+            })
+            .collect();
+        if combinations.len() > MAX_ROWS {
+            return Err("The table has too many rows".to_string());
+        }
+    }
+
+    let variables: Vec<(&str, Option<&str>)> = ranges
+        .iter()
+        .map(|range| (range.name.as_str(), range.values.first().and_then(|v| v.unit.as_deref())))
+        .collect();
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    calculator.with_context(|context| {
+        let mut program = Program::compile(&ast, &variables, context).ok();
+        for (index, values) in combinations.iter().enumerate() {
+            let row: Vec<Option<&ValueWithUnit>> = values.iter().map(|value| Some(*value)).collect();
+            match evaluate_row(&ast, program.as_mut(), &variables, &row, context) {
+                Ok(result) => results.push(Some(result)),
+                Err(e) => {
+                    errors.push(format!("Row {}: {}", index + 1, e));
+                    results.push(None);
+                }
+            }
+        }
+    });
+
+    let mut headers: Vec<String> = ranges
+        .iter()
+        .map(|range| annotate(&range.name, range.values.first().and_then(|v| v.unit.as_deref())))
+        .collect();
+    let result_unit = common_unit(&results);
+    headers.push(annotate(expression, result_unit.as_deref()));
+    let rows = combinations
+        .iter()
+        .zip(&results)
+        .map(|(values, result)| {
+            let mut row: Vec<String> = values.iter().map(|value| format_number(value.value)).collect();
+            row.push(format_cell(result.as_ref(), result_unit.as_deref()));
+            row
+        })
+        .collect();
+    Ok((Table { headers, rows }, errors))
+}
+
+/// Evaluates an expression for every row of a CSV file and appends the result as a new column.
+///
+/// Columns whose names are valid identifiers are bound as variables, in the unit given by
+/// their header annotation. Cells that are not numbers leave the variable unbound for that row.
+///
+/// # Arguments
+///
+/// * `input` - The CSV data, with a header row.
+/// * `expression` - The expression to evaluate per row.
+/// * `column` - The name of the new column.
+/// * `calculator` - The calculator the expression is evaluated with; the columns shadow its variables.
+///
+/// # Returns
+///
+/// * `Ok((Table, Vec<String>))` - The input with the new column, and the errors of failed rows.
+/// * `Err(String)` - An error message if the expression or the CSV cannot be parsed.
+pub fn map<R: Read>(
+    input: R,
+    expression: &str,
+    column: &str,
+    calculator: &Calculator,
+) -> Result<(Table, Vec<String>), String> {
+    let ast = prepare(expression, calculator)?;
+    let mut reader = csv::Reader::from_reader(input);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("Error reading CSV header: {}", e))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    let columns: Vec<(String, Option<String>)> = headers.iter().map(|header| parse_header(header)).collect();
+    let variables: Vec<(&str, Option<&str>)> =
+        columns.iter().map(|(name, unit)| (name.as_str(), unit.as_deref())).collect();
+
+    let mut rows = Vec::new();
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    calculator.with_context(|context| {
+        let mut program = Program::compile(&ast, &variables, context).ok();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| format!("Error reading CSV row {}: {}", index + 1, e))?;
+            let values: Vec<Option<ValueWithUnit>> = columns
+                .iter()
+                .zip(record.iter())
+                .map(|((_, unit), cell)| cell.trim().parse::<f64>().ok().map(|value| ValueWithUnit::new(value, unit.clone())))
+                .collect();
+            let row: Vec<Option<&ValueWithUnit>> = values.iter().map(Option::as_ref).collect();
+            match evaluate_row(&ast, program.as_mut(), &variables, &row, context) {
+                Ok(result) => results.push(Some(result)),
+                Err(e) => {
+                    errors.push(format!("Row {}: {}", index + 1, e));
+                    results.push(None);
+                }
+            }
+            rows.push(record.iter().map(str::to_string).collect::<Vec<_>>());
+        }
+        Ok::<_, String>(())
+    })?;
+
+    let result_unit = common_unit(&results);
+    let mut headers = headers;
+    headers.push(annotate(column, result_unit.as_deref()));
+    for (row, result) in rows.iter_mut().zip(&results) {
+        row.push(format_cell(result.as_ref(), result_unit.as_deref()));
+    }
+    Ok((Table { headers, rows }, errors))
+}
+
+/// Builds the amortization schedule of a loan repaid in equal payments at the end of each period.
+///
+/// # Arguments
+///
+/// * `rate` - The interest rate per period, e.g., `5%/12`.
+/// * `periods` - The number of payments.
+/// * `principal` - The amount borrowed, whose unit (such as a currency) goes in the headers.
+///
+/// # Returns
+///
+/// * `Ok(Table)` - One row per period with the payment, its interest and principal parts,
+///   and the balance left.
+/// * `Err(String)` - An error message if the rate or number of periods has a unit, or the
+///   number of periods is not a positive whole number.
+pub fn amortization(rate: &ValueWithUnit, periods: &ValueWithUnit, principal: &ValueWithUnit) -> Result<Table, String> {
+    if rate.unit.is_some() || periods.unit.is_some() {
+        return Err("The rate and number of periods of an amortization schedule must be unitless".to_string());
+    }
+    let unit = principal.unit.as_deref();
+    let headers = std::iter::once("period".to_string())
+        .chain(["payment", "interest", "principal", "balance"].iter().map(|name| annotate(name, unit)))
+        .collect();
+    let rows = finance::schedule(rate.value, periods.value, principal.value)?
+        .iter()
+        .map(|installment| {
+            let amounts = [installment.payment, installment.interest, installment.principal, installment.balance];
+            std::iter::once(installment.period.to_string()).chain(amounts.map(format_number)).collect()
+        })
+        .collect();
+    Ok(Table { headers, rows })
+}
+
+/// Parses an expression in the number format of the calculator and expands any derivatives in it.
+fn prepare(expression: &str, calculator: &Calculator) -> Result<Expr, String> {
+    symbolic::expand_derivatives(&calculator.parse(expression)?)
+}
+
+/// Evaluates an expression with one row of values bound to its variables.
+///
+/// The compiled program is used when every variable has a plain number in the unit it
+/// was compiled with; other rows, such as those with a missing cell, walk the tree.
+///
+/// # Arguments
+///
+/// * `ast` - The expression.
+/// * `program` - The expression compiled for `variables`, if it could be.
+/// * `variables` - The names of the variables, with their units.
+/// * `row` - The value of each variable, `None` to leave it unbound.
+/// * `context` - The context the variables are bound in.
+fn evaluate_row(
+    ast: &Expr,
+    program: Option<&mut Program>,
+    variables: &[(&str, Option<&str>)],
+    row: &[Option<&ValueWithUnit>],
+    context: &Context,
+) -> Result<ValueWithUnit, String> {
+    let fits = row.len() == variables.len()
+        && row.iter().zip(variables).all(|(value, (_, unit))| {
+            value.is_some_and(|value| !value.is_boolean() && value.uncertainty.is_none() && value.unit.as_deref() == *unit)
+        });
+    if let (Some(program), true) = (program, fits) {
+        let values: Vec<f64> = row.iter().flatten().map(|value| value.value).collect();
+        return program.call(&values);
+    }
+    let mut bound = context.clone();
+    for ((name, _), value) in variables.iter().zip(row) {
+        match value {
+            Some(value) => bound.set_variable(name, (*value).clone()),
+            None => {
+                bound.remove_variable(name);
+            }
+        }
+    }
+    let result = evaluate_with(ast, &bound);
+    bound.take_warnings().into_iter().for_each(|warning| context.warn(warning));
+    result
+}
+
+/// Parses and evaluates a standalone expression.
+fn evaluate_text(text: &str, calculator: &Calculator, context: &Context) -> Result<ValueWithUnit, String> {
+    evaluate_with(&prepare(text.trim(), calculator)?, context)
+}
+
+/// Returns the unit shared by all results, which then goes in the header instead of each cell.
+fn common_unit(results: &[Option<ValueWithUnit>]) -> Option<String> {
+    let mut units = results.iter().flatten().map(|result| &result.unit);
+    let first = units.next()?.clone();
+    units.all(|unit| *unit == first).then_some(first).flatten()
+}
+
+/// Appends a unit annotation to a header.
+fn annotate(name: &str, unit: Option<&str>) -> String {
+    match unit {
+        Some(unit) => format!("{}[{}]", name, unit),
+        None => name.to_string(),
+    }
+}
+
+/// Formats a result cell, writing the unit only when it is not already in the header.
+fn format_cell(result: Option<&ValueWithUnit>, header_unit: Option<&str>) -> String {
+    match result {
+        None => String::new(),
+        Some(result) if result.is_boolean() => result.to_string(),
+        Some(ValueWithUnit { value, unit: Some(unit), .. }) if header_unit != Some(unit.as_str()) => {
+            format!("{} {}", format_number(*value), unit)
+        }
+        Some(result) => format_number(result.value),
+    }
+}
+
+/// Formats a number rounded to 12 significant digits, hiding floating-point noise
+/// such as `3.9549999999999996` or a stepped `0.30000000000000004`.
+fn format_number(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return value.to_string();
+    }
+    let scale = 10f64.powi(11 - value.abs().log10().floor() as i32);
+    ((value * scale).round() / scale).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Rates;
+    use crate::evaluator::AngleMode;
+
+    #[test]
+    fn test_parse_range() {
+        let calculator = Calculator::new();
+        let range = parse_range("x=1..3", &calculator).unwrap();
+        assert_eq!(range.name, "x");
+        let values: Vec<f64> = range.values.iter().map(|v| v.value).collect();
+        assert_eq!(values, vec![1.0, 2.0, 3.0]);
+
+        let range = parse_range("d=0 m..1 km:250 m", &calculator).unwrap();
+        assert_eq!(range.values.len(), 5);
+        assert_eq!(range.values[4].value, 1000.0);
+        assert_eq!(range.values[4].unit, Some("m".to_string()));
+
+        assert!(parse_range("x=1..10:0", &calculator).is_err());
+        assert!(parse_range("x 1 10", &calculator).is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(parse_header("weight[kg]"), ("weight".to_string(), Some("kg".to_string())));
+        assert_eq!(parse_header(" price "), ("price".to_string(), None));
+    }
+
+    #[test]
+    fn test_table() {
+        let calculator = Calculator::new();
+        let ranges = vec![parse_range("x=1 m..3 m", &calculator).unwrap()];
+        let (table, errors) = table("x to ft", &ranges, &calculator).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(table.headers, vec!["x[m]", "x to ft[ft]"]);
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(table.rows[0][0], "1");
+        assert!(table.rows[0][1].starts_with("3.28"));
+        assert!(table.to_markdown().starts_with("| x[m] | x to ft[ft] |\n| --- | --- |\n"));
+    }
+
+    #[test]
+    fn test_table_uses_calculator() {
+        let mut calculator = Calculator::new();
+        calculator.settings_mut().angle_mode = AngleMode::Degrees;
+        calculator.set_variable("rate", ValueWithUnit::new(2.0, None));
+        let ranges = vec![parse_range("x=0..90:30", &calculator).unwrap()];
+        let (sines, errors) = table("rate * sin(x)", &ranges, &calculator).unwrap();
+        assert!(errors.is_empty());
+        let results: Vec<&str> = sines.rows.iter().map(|row| row[1].as_str()).collect();
+        assert_eq!(results, vec!["0", "1", "1.73205080757", "2"]);
+
+        let rates = Rates::parse_json(r#"{ "base": "EUR", "date": "2024-06-28", "rates": { "USD": 1.25 } }"#).unwrap();
+        calculator.set_rates(rates).unwrap();
+        let ranges = vec![parse_range("x=1..2", &calculator).unwrap()];
+        let (prices, errors) = table("x * 5 USD to EUR", &ranges, &calculator).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(prices.rows[1][1], "8");
+        assert!(calculator.take_warnings()[0].contains("2024-06-28"));
+    }
+
+    #[test]
+    fn test_map() {
+        let data = "item,price,weight[kg]\napple,2,0.5\npear,n/a,1\n";
+        let mut calculator = Calculator::new();
+        calculator.set_variable("tax", ValueWithUnit::new(1.13, None));
+        // A cell that is not a number leaves the column unbound even if the calculator has it.
+        calculator.set_variable("price", ValueWithUnit::new(5.0, None));
+        let (table, errors) = map(data.as_bytes(), "price * tax", "total", &calculator).unwrap();
+        assert_eq!(table.headers, vec!["item", "price", "weight[kg]", "total"]);
+        assert_eq!(table.rows[0][3], "2.26");
+        assert_eq!(table.rows[1][3], "");
+        assert_eq!(errors.len(), 1);
+
+        let (table, _) = map(data.as_bytes(), "weight to lb", "pounds", &calculator).unwrap();
+        assert_eq!(table.headers[3], "pounds[lb]");
+        assert_eq!(table.to_csv().unwrap().lines().nth(2).unwrap(), "pear,n/a,1,2.20462262185");
+    }
+
+    #[test]
+    fn test_amortization() {
+        let rate = ValueWithUnit::new(0.01, None);
+        let table = amortization(&rate, &ValueWithUnit::new(12.0, None), &ValueWithUnit::new(1000.0, Some("kg".to_string()))).unwrap();
+        assert_eq!(table.headers, vec!["period", "payment[kg]", "interest[kg]", "principal[kg]", "balance[kg]"]);
+        assert_eq!(table.rows.len(), 12);
+        assert_eq!(table.rows[0], vec!["1", "88.8487886783", "10", "78.8487886783", "921.151211322"]);
+        assert_eq!(table.rows[11][4], "0");
+        assert!(amortization(&rate, &ValueWithUnit::new(12.0, Some("s".to_string())), &rate).is_err());
+    }
+}
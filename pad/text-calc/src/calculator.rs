@@ -0,0 +1,752 @@
+//! Embeddable calculator.
+//!
+//! [`Calculator`] bundles the settings, custom units, constants, variables and native
+//! functions of a session behind a single [`Calculator::eval`] entry point, for programs
+//! that use TextCalc as a library.
+
+use crate::ast::{Expr, Statement};
+use crate::currency::Rates;
+use crate::evaluator::{self, AngleMode, Context, Notation, ValueWithUnit};
+use crate::parser::{self, NumberFormat};
+use crate::random::Rng;
+use crate::trace::TraceNode;
+use crate::uncertainty::{self, Propagation};
+use crate::units::{self, CustomUnit};
+use crate::{calculus, solver, symbolic};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Settings that change how input is read and evaluated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// How digit grouping and the decimal separator are written in numbers.
+    pub number_format: NumberFormat,
+    /// How uncertainties such as `9.81 ± 0.02` are carried through an expression.
+    pub propagation: Propagation,
+    /// Whether angles written as plain numbers, as in `sin(30)`, are radians or degrees.
+    pub angle_mode: AngleMode,
+    /// The seed of `rand()`, `randint` and dice rolls, for results that repeat from run to
+    /// run; `None` seeds them differently each run. It takes effect when the calculator is created.
+    pub seed: Option<u64>,
+    /// Significant digits of printed results, or decimal places in fixed notation; `None`
+    /// prints as many as it takes to read a number back.
+    pub precision: Option<usize>,
+    /// How the numbers of printed results are written.
+    pub notation: Notation,
+}
+
+/// The units a native function takes and returns; `None` stands for a unitless value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Signature {
+    /// The unit of each parameter, in order.
+    pub params: Vec<Option<String>>,
+    /// The unit of the result.
+    pub returns: Option<String>,
+}
+
+impl Signature {
+    /// Creates a signature from parameter and result units.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The unit of each parameter, e.g., `&[Some("m"), Some("s")]`.
+    /// * `returns` - The unit of the result, e.g., `Some("m/s")`.
+    pub fn new(params: &[Option<&str>], returns: Option<&str>) -> Self {
+        Self {
+            params: params.iter().map(|unit| unit.map(String::from)).collect(),
+            returns: returns.map(String::from),
+        }
+    }
+
+    /// Creates the signature of a function of `arity` unitless numbers returning a unitless number.
+    ///
+    /// # Arguments
+    ///
+    /// * `arity` - The number of parameters.
+    pub fn unitless(arity: usize) -> Self {
+        Self {
+            params: vec![None; arity],
+            returns: None,
+        }
+    }
+
+    /// Returns the number of parameters.
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+/// A function implemented in Rust that expressions can call by name.
+///
+/// Arguments are checked against the signature and converted into the parameter units
+/// before [`NativeFunction::call`] sees them, and the result is given the signature's
+/// result unit.
+pub trait NativeFunction {
+    /// Returns the units of the parameters and the result.
+    fn signature(&self) -> Signature;
+
+    /// Computes the result from arguments in the parameter units.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - One value per parameter.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(f64)` - The result, in the signature's result unit.
+    /// * `Err(String)` - An error message if the function cannot be computed.
+    fn call(&self, args: &[f64]) -> Result<f64, String>;
+}
+
+/// A function written in the calculator's own language, such as a script's `fn`.
+///
+/// Unlike a [`NativeFunction`], it takes and returns values with their units, and runs
+/// with the variables of the expression that calls it.
+pub trait UserFunction {
+    /// Returns the number of parameters.
+    fn arity(&self) -> usize;
+
+    /// Calls the function.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - One value per parameter.
+    /// * `context` - The variables and functions of the caller.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ValueWithUnit)` - The result.
+    /// * `Err(String)` - An error message if the function cannot be computed.
+    fn call(&self, args: &[ValueWithUnit], context: &Context) -> Result<ValueWithUnit, String>;
+}
+
+/// A native function made from a closure, see [`Calculator::register_fn`].
+struct ClosureFunction<F> {
+    signature: Signature,
+    function: F,
+}
+
+impl<F: Fn(&[f64]) -> Result<f64, String>> NativeFunction for ClosureFunction<F> {
+    fn signature(&self) -> Signature {
+        self.signature.clone()
+    }
+
+    fn call(&self, args: &[f64]) -> Result<f64, String> {
+        (self.function)(args)
+    }
+}
+
+/// The result of evaluating an input line.
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    /// A numeric or boolean value, with its unit if any.
+    Value(ValueWithUnit),
+    /// A simplified expression that still contains free symbols (e.g., the result of `diff`).
+    Symbolic(Expr),
+    /// The roots found by a top-level `solve`, with the name of the solved variable.
+    Roots(String, Vec<ValueWithUnit>),
+}
+
+impl Outcome {
+    /// Formats the outcome as it prints, with numbers written in the given notation, see
+    /// [`ValueWithUnit::format`].
+    ///
+    /// # Arguments
+    ///
+    /// * `precision` - Significant digits, or decimal places in fixed notation.
+    /// * `notation` - How numbers are written.
+    pub fn format(&self, precision: Option<usize>, notation: Notation) -> String {
+        match self {
+            Outcome::Value(value) => value.format(precision, notation),
+            Outcome::Symbolic(expr) => expr.to_string(),
+            Outcome::Roots(var, roots) => {
+                let roots: Vec<String> =
+                    roots.iter().map(|root| format!("{} = {}", var, root.format(precision, notation))).collect();
+                roots.join(", ")
+            }
+        }
+    }
+}
+
+/// Prints a value, a simplified expression, or roots as `x = 1, x = 2`.
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(None, Notation::Auto))
+    }
+}
+
+/// A calculator session: settings, custom units, constants, variables and native functions.
+///
+/// # Examples
+///
+/// ```
+/// use textcalculator::calculator::{Calculator, Signature};
+///
+/// let mut calculator = Calculator::new();
+/// calculator.define_unit("furlong", 201.168, "m").unwrap();
+/// calculator
+///     .register_fn("hypot", Signature::new(&[Some("m"), Some("m")], Some("m")), |args| {
+///         Ok(args[0].hypot(args[1]))
+///     })
+///     .unwrap();
+/// assert_eq!(calculator.eval("hypot(3 m, 400 cm)").unwrap().to_string(), "5 m");
+/// assert_eq!(calculator.eval("8 furlong to mi").unwrap().to_string(), "1 mi");
+/// ```
+#[derive(Debug, Default)]
+pub struct Calculator {
+    settings: Settings,
+    units: Vec<CustomUnit>,
+    constants: HashMap<String, ValueWithUnit>,
+    context: Context,
+}
+
+impl Calculator {
+    /// Creates a calculator with the default settings and no custom definitions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a calculator with the given settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - How input is read.
+    pub fn with_settings(settings: Settings) -> Self {
+        let mut context = Context::new();
+        context.set_angle_mode(settings.angle_mode);
+        if let Some(seed) = settings.seed {
+            context.set_seed(seed);
+        }
+        Self {
+            settings,
+            context,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the current settings.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Returns the settings for changing.
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Formats an outcome with the precision and notation of the settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `outcome` - The outcome to format, e.g., from [`Calculator::eval`].
+    pub fn format(&self, outcome: &Outcome) -> String {
+        outcome.format(self.settings.precision, self.settings.notation)
+    }
+
+    /// Defines a unit as a multiple of an existing unit expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The new unit's symbol, e.g., `furlong`.
+    /// * `factor` - How many `base` units one new unit is, e.g., `201.168`.
+    /// * `base` - A unit expression, e.g., `m`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the unit was added or redefined.
+    /// * `Err(String)` - An error message if the symbol is a built-in unit or invalid, or `base` is unknown.
+    pub fn define_unit(&mut self, symbol: &str, factor: f64, base: &str) -> Result<(), String> {
+        // Earlier custom units may serve as the base of later ones.
+        let unit = units::with_units(&self.units, || CustomUnit::new(symbol, factor, base))?;
+        self.units.retain(|existing| existing.symbol != symbol);
+        self.units.push(unit);
+        Ok(())
+    }
+
+    /// Adds the currencies of an exchange-rate snapshot as units, replacing those of any
+    /// earlier snapshot. Conversions between currencies then warn with the date of the rates.
+    ///
+    /// # Arguments
+    ///
+    /// * `rates` - The exchange rates, e.g., from [`Rates::load`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the currencies were added.
+    /// * `Err(String)` - An error message if a currency code is already a custom unit.
+    pub fn set_rates(&mut self, rates: Rates) -> Result<(), String> {
+        self.units.retain(|unit| unit.dimension != units::CURRENCY);
+        if let Some(unit) = self.units.iter().find(|unit| rates.rate(&unit.symbol).is_some()) {
+            return Err(format!("'{}' is already a unit", unit.symbol));
+        }
+        self.units.extend(rates.units());
+        self.context.set_rates(Some(Rc::new(rates)));
+        Ok(())
+    }
+
+    /// Returns the exchange rates added with [`Calculator::set_rates`], if any.
+    pub fn rates(&self) -> Option<&Rates> {
+        self.context.rates()
+    }
+
+    /// Defines a constant. Constants take precedence over the built-in constants and units
+    /// of the same name, and variables take precedence over constants.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The constant's name.
+    /// * `value` - The constant's value and unit.
+    pub fn define_constant(&mut self, name: &str, value: ValueWithUnit) {
+        self.constants.insert(name.to_string(), value);
+    }
+
+    /// Returns the constants defined with [`Calculator::define_constant`].
+    pub fn constants(&self) -> &HashMap<String, ValueWithUnit> {
+        &self.constants
+    }
+
+    /// Binds a variable, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    /// * `value` - The value to bind.
+    pub fn set_variable(&mut self, name: &str, value: ValueWithUnit) {
+        self.context.set_variable(name, value);
+    }
+
+    /// Sets the time after which evaluations stop with "Time limit exceeded", see
+    /// [`Context::set_deadline`].
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - The time to stop at, or `None` for no limit.
+    pub fn set_deadline(&mut self, deadline: Option<std::time::Instant>) {
+        self.context.set_deadline(deadline);
+    }
+
+    /// Checks that the deadline set with [`Calculator::set_deadline`] has not passed.
+    pub(crate) fn check_deadline(&self) -> Result<(), String> {
+        self.context.check_deadline()
+    }
+
+    /// Returns the value bound to a variable, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    pub fn variable(&self, name: &str) -> Option<&ValueWithUnit> {
+        self.context.variable(name)
+    }
+
+    /// Unbinds a variable, returning its value if it was bound.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    pub fn remove_variable(&mut self, name: &str) -> Option<ValueWithUnit> {
+        self.context.remove_variable(name)
+    }
+
+    /// Registers a native function under `name`.
+    ///
+    /// Native functions take precedence over built-in functions of the same name, except
+    /// the forms that do not evaluate their arguments first: `if`, `diff`, `solve` and the
+    /// calculus functions.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name expressions call the function by.
+    /// * `function` - The implementation.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the function was registered.
+    /// * `Err(String)` - An error message if the name is reserved or not a valid identifier.
+    pub fn register_function(&mut self, name: &str, function: impl NativeFunction + 'static) -> Result<(), String> {
+        check_function_name(name)?;
+        self.context.register_function(name, Rc::new(function));
+        Ok(())
+    }
+
+    /// Defines a function written in the calculator's own language under `name`. User
+    /// functions take precedence over native and built-in functions, with the same
+    /// exceptions as [`Calculator::register_function`].
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name expressions call the function by.
+    /// * `function` - The implementation.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the function was defined.
+    /// * `Err(String)` - An error message if the name is reserved or not a valid identifier.
+    pub fn define_function(&mut self, name: &str, function: Rc<dyn UserFunction>) -> Result<(), String> {
+        check_function_name(name)?;
+        self.context.define_function(name, function);
+        Ok(())
+    }
+
+    /// Registers a closure as a native function, see [`Calculator::register_function`].
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name expressions call the function by.
+    /// * `signature` - The units of the parameters and the result.
+    /// * `function` - Computes the result from arguments in the parameter units.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the function was registered.
+    /// * `Err(String)` - An error message if the name is reserved or not a valid identifier.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        function: impl Fn(&[f64]) -> Result<f64, String> + 'static,
+    ) -> Result<(), String> {
+        self.register_function(name, ClosureFunction { signature, function })
+    }
+
+    /// Evaluates one expression.
+    ///
+    /// Expressions with free symbols are simplified rather than evaluated numerically, and a
+    /// top-level `solve` returns all of its roots.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The expression to evaluate, e.g., `5 kg to lb`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Outcome)` - The result of the evaluation.
+    /// * `Err(String)` - An error message if parsing or evaluation fails.
+    pub fn eval(&self, input: &str) -> Result<Outcome, String> {
+        units::with_units(&self.units, || self.eval_expression(input))
+    }
+
+    /// Evaluates one expression step by step, recording each intermediate value and the
+    /// unit conversion factors used.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The expression to explain, e.g., `(5 kg + 3 kg) to lb`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(TraceNode)` - The trace of the expression; a failed step holds its error.
+    /// * `Err(String)` - An error message if parsing fails.
+    pub fn explain(&self, input: &str) -> Result<TraceNode, String> {
+        let ast = parser::parse_expression_with(input, self.settings.number_format)
+            .map_err(|e| format!("Error parsing expression '{}': {}", input, e))?;
+        let expanded = symbolic::expand_derivatives(&ast).map_err(|e| format!("Error evaluating '{}': {}", input, e))?;
+        let context = self.evaluation_context();
+        context.tracer().start();
+        units::with_units(&self.units, || {
+            let _ = evaluator::evaluate_with(&expanded, &context);
+        });
+        context.tracer().finish().ok_or_else(|| format!("Nothing to explain in '{}'", input))
+    }
+
+    /// Executes one line of a session: an assignment such as `x = 5 m`, which binds the
+    /// variable for later lines, or an expression, as with [`Calculator::eval`].
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line to execute.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Outcome)` - The result of the evaluation; for an assignment, the assigned value.
+    /// * `Err(String)` - An error message if parsing or evaluation fails, or an assigned value is not numeric.
+    pub fn execute(&mut self, line: &str) -> Result<Outcome, String> {
+        let statement = parser::parse_statement(line, self.settings.number_format)
+            .map_err(|e| format!("Error parsing expression '{}': {}", line, e))?;
+        self.execute_statement(line, statement)
+    }
+
+    /// Executes a parsed line of a session, see [`Calculator::execute`]; `line` is used in error messages.
+    pub(crate) fn execute_statement(&mut self, line: &str, statement: Statement) -> Result<Outcome, String> {
+        match statement {
+            Statement::Expression(ast) => self.evaluate_ast(line, &ast),
+            Statement::Assignment { name, expr } => {
+                match self.evaluate_ast(line, &expr)? {
+                    Outcome::Value(value) => {
+                        self.context.set_variable(&name, value.clone());
+                        Ok(Outcome::Value(value))
+                    }
+                    _ => Err(format!("Cannot assign '{}': the value of {} is not a number", line, name)),
+                }
+            }
+        }
+    }
+
+    /// Returns the variables bound so far, sorted by name.
+    pub fn variables(&self) -> Vec<(&str, &ValueWithUnit)> {
+        let mut variables: Vec<_> = self.context.variables().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        variables
+    }
+
+    /// Removes and returns the warnings recorded by earlier evaluations, such as an integral
+    /// that did not converge.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.context.take_warnings()
+    }
+
+    /// Parses an expression in the number format of the current settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The expression to parse.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Expr)` - The parsed expression.
+    /// * `Err(String)` - An error message if parsing fails.
+    pub fn parse(&self, input: &str) -> Result<Expr, String> {
+        parser::parse_expression_with(input, self.settings.number_format)
+            .map_err(|e| format!("Error parsing expression '{}': {}", input, e))
+    }
+
+    /// Runs `f` with the context expressions are evaluated in and the custom units in place,
+    /// for callers that evaluate an expression many times, such as tables and plots.
+    ///
+    /// The context holds the constants, variables, functions, exchange rates and random
+    /// generator of the calculator, with the angle mode of the current settings. Warnings
+    /// recorded in it are kept for `take_warnings`.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Evaluates with the context.
+    pub fn with_context<T>(&self, f: impl FnOnce(&Context) -> T) -> T {
+        let context = self.evaluation_context();
+        let result = units::with_units(&self.units, || f(&context));
+        if let std::borrow::Cow::Owned(copy) = &context {
+            copy.take_warnings().into_iter().for_each(|warning| self.context.warn(warning));
+        }
+        result
+    }
+
+    /// Evaluates a parsed expression with the custom units in place; `input` is used in error messages.
+    pub(crate) fn evaluate_ast(&self, input: &str, ast: &Expr) -> Result<Outcome, String> {
+        units::with_units(&self.units, || self.eval_ast(input, ast))
+    }
+
+    /// Evaluates one expression with the custom units in place.
+    fn eval_expression(&self, input: &str) -> Result<Outcome, String> {
+        let ast = self.parse(input)?;
+        self.eval_ast(input, &ast)
+    }
+
+    /// Evaluates a parsed expression with the custom units in place; `input` is used in error messages.
+    fn eval_ast(&self, input: &str, ast: &Expr) -> Result<Outcome, String> {
+        let expanded = symbolic::expand_derivatives(ast).map_err(|e| format!("Error evaluating '{}': {}", input, e))?;
+        let context = self.evaluation_context();
+        if let Expr::Function { name, args } = &expanded {
+            if let (true, Some(Expr::Symbol(var))) = (name == "solve", args.get(1)) {
+                return solver::solve_call(args, &context)
+                    .map(|roots| Outcome::Roots(var.clone(), roots))
+                    .map_err(|e| format!("Error evaluating '{}': {}", input, e));
+            }
+        }
+        // Only a derivative is left as an expression; any other unknown name is an error.
+        let free = symbolic::free_symbols(&expanded);
+        if has_derivative(ast) && free.iter().any(|name| context.variable(name).is_none()) {
+            return Ok(Outcome::Symbolic(symbolic::simplify(&expanded)));
+        }
+        let value = match self.settings.propagation {
+            Propagation::FirstOrder => evaluator::evaluate_with(&expanded, &context),
+            Propagation::MonteCarlo { samples } => {
+                let mut rng = Rng::new(uncertainty::MONTE_CARLO_SEED);
+                uncertainty::monte_carlo(&expanded, &context, samples, &mut rng)
+            }
+        };
+        // Warnings recorded on a copy of the context are kept for `take_warnings`.
+        if let std::borrow::Cow::Owned(copy) = &context {
+            copy.take_warnings().into_iter().for_each(|warning| self.context.warn(warning));
+        }
+        value
+            .map(Outcome::Value)
+            .map_err(|e| format!("Error evaluating '{}': {}", input, e))
+    }
+
+    /// Returns the context expressions are evaluated in: the constants, shadowed by the
+    /// variables, with the angle mode of the current settings.
+    fn evaluation_context(&self) -> std::borrow::Cow<'_, Context> {
+        if self.constants.is_empty() && self.context.angle_mode() == self.settings.angle_mode {
+            return std::borrow::Cow::Borrowed(&self.context);
+        }
+        let mut context = self.context.clone();
+        context.set_angle_mode(self.settings.angle_mode);
+        for (name, value) in &self.constants {
+            if context.variable(name).is_none() {
+                context.set_variable(name, value.clone());
+            }
+        }
+        std::borrow::Cow::Owned(context)
+    }
+}
+
+/// Checks that a function name is an identifier and not one of the forms that do not
+/// evaluate their arguments first.
+fn check_function_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("Invalid function name '{}'", name));
+    }
+    if matches!(name, "if" | "diff" | "solve") || calculus::is_calculus_function(name) {
+        return Err(format!("'{}' is a reserved function name", name));
+    }
+    Ok(())
+}
+
+/// Checks whether an expression takes a derivative with `diff`.
+fn has_derivative(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function { name, .. } if name == "diff" => true,
+        Expr::Function { args, .. } => args.iter().any(has_derivative),
+        Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => has_derivative(expr),
+        Expr::BinaryOp { left, right, .. } | Expr::Equation { left, right } => {
+            has_derivative(left) || has_derivative(right)
+        }
+        Expr::Number(_) | Expr::NumberWithUnit { .. } | Expr::Symbol(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        let calculator = Calculator::new();
+        assert_eq!(calculator.eval("5 kg + 3 kg").unwrap().to_string(), "8 kg");
+        assert_eq!(calculator.eval("diff(x^2, x)").unwrap().to_string(), "2 * x");
+        assert_eq!(calculator.eval("solve(x^2 = 4, x)").unwrap().to_string(), "x = -2, x = 2");
+        assert!(calculator.eval("1 +").is_err());
+        // Unknown names are errors outside of derivatives.
+        assert_eq!(
+            calculator.eval("5 foo").unwrap_err(),
+            "Error evaluating '5 foo': Unknown variable: foo"
+        );
+        assert!(calculator.eval("5 kgg to lb").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut calculator = Calculator::with_settings(Settings { number_format: NumberFormat::DecimalComma, ..Settings::default() });
+        assert_eq!(calculator.eval("1.000,5 * 2").unwrap().to_string(), "2001");
+        calculator.settings_mut().number_format = NumberFormat::Standard;
+        assert_eq!(calculator.settings().number_format, NumberFormat::Standard);
+        assert_eq!(calculator.eval("1000.5 * 2").unwrap().to_string(), "2001");
+        calculator.settings_mut().angle_mode = AngleMode::Degrees;
+        assert_eq!(calculator.eval("sin(90) + asin(1) / 90").unwrap().to_string(), "2");
+
+        // The evaluation context is copied when there are constants, and still draws in sequence.
+        let rolls = || {
+            let mut calculator = Calculator::with_settings(Settings { seed: Some(42), ..Settings::default() });
+            calculator.define_constant("sides", ValueWithUnit::new(6.0, None));
+            (0..5).map(|_| calculator.eval("dice(3, sides)").unwrap().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(rolls(), rolls());
+        assert!(rolls().windows(2).any(|pair| pair[0] != pair[1]));
+
+        calculator.settings_mut().precision = Some(3);
+        let outcome = calculator.eval("solve(x^2 = 2, x)").unwrap();
+        assert_eq!(calculator.format(&outcome), "x = -1.41, x = 1.41");
+        calculator.settings_mut().notation = Notation::Scientific;
+        assert_eq!(calculator.format(&calculator.eval("1500 m").unwrap()), "1.50e3 m");
+    }
+
+    #[test]
+    fn test_variables_and_constants() {
+        let mut calculator = Calculator::new();
+        calculator.define_constant("rho", ValueWithUnit::new(1000.0, Some("kg/m^3".to_string())));
+        calculator.set_variable("x", ValueWithUnit::new(3.0, None));
+        assert_eq!(calculator.eval("x^2 + 1").unwrap().to_string(), "10");
+        assert_eq!(calculator.eval("rho * 2 L to kg").unwrap().to_string(), "2 kg");
+        assert!(calculator.eval("y + x").is_err());
+        assert!(calculator.constants().contains_key("rho"));
+        assert_eq!(calculator.variable("x").unwrap().value, 3.0);
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let mut calculator = Calculator::new();
+        calculator
+            .register_fn("speed", Signature::new(&[Some("m"), Some("s")], Some("m/s")), |args| Ok(args[0] / args[1]))
+            .unwrap();
+        calculator.register_fn("double", Signature::unitless(1), |args| Ok(args[0] * 2.0)).unwrap();
+        assert_eq!(calculator.eval("speed(1 km, 1 min)").unwrap().to_string(), "16.666666666666668 m/s");
+        assert_eq!(calculator.eval("double(sin(0) + 2)").unwrap().to_string(), "4");
+        assert!(calculator.eval("speed(1 km)").is_err());
+        assert!(calculator.eval("speed(1 kg, 1 s)").is_err());
+        assert!(calculator.eval("double(2 m)").is_err());
+        assert!(calculator.register_fn("solve", Signature::unitless(1), |args| Ok(args[0])).is_err());
+        assert!(calculator.register_fn("2x", Signature::unitless(1), |args| Ok(args[0])).is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut calculator = Calculator::new();
+        calculator.define_unit("furlong", 201.168, "m").unwrap();
+        let trace = calculator.explain("2 furlong to m").unwrap();
+        assert_eq!(trace.result.unwrap().to_string(), "402.336 m");
+        assert_eq!(trace.notes, ["1 furlong = 201.168 m"]);
+        assert!(calculator.explain("1 kg + 1 m").unwrap().result.is_err());
+        assert!(calculator.explain("1 +").is_err());
+    }
+
+    #[test]
+    fn test_currencies() {
+        let mut calculator = Calculator::new();
+        let error = calculator.eval("100 USD to CAD").unwrap_err();
+        assert!(error.contains("USD is a currency, but no exchange-rate snapshot is loaded"), "{}", error);
+        let rates = Rates::parse_json(r#"{ "base": "EUR", "date": "2024-06-28", "rates": { "USD": 1.25 } }"#).unwrap();
+        calculator.set_rates(rates).unwrap();
+        assert_eq!(calculator.eval("100 EUR to USD").unwrap().to_string(), "125 USD");
+        assert_eq!(calculator.take_warnings(), ["Converted EUR to USD at the exchange rates of 2024-06-28"]);
+        assert_eq!(calculator.eval("20 USD/kg * 8 kg").unwrap().to_string(), "160 USD");
+        assert!(calculator.take_warnings().is_empty());
+        let error = calculator.eval("1 EUR + 1 USD").unwrap_err();
+        assert!(error.ends_with("Cannot mix EUR and USD in addition or subtraction; convert one with 'to' first"), "{}", error);
+        assert_eq!(calculator.eval("1 EUR + (1 USD to EUR)").unwrap().to_string(), "1.8 EUR");
+        let error = calculator.eval("100 USD / 4 EUR").unwrap_err();
+        assert!(error.ends_with("Cannot mix USD and EUR in multiplication or division; convert one with 'to' first"), "{}", error);
+        calculator.take_warnings();
+        // A conversion that fails used no exchange rates.
+        assert!(calculator.eval("1 USD to m").is_err());
+        assert!(calculator.eval("1 USD > 1 m").is_err());
+        assert!(calculator.take_warnings().is_empty());
+        let error = calculator.eval("1 CHF to EUR").unwrap_err();
+        assert!(error.ends_with("CHF is a currency that the exchange-rate snapshot does not have"), "{}", error);
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.execute("d = 5 km").unwrap().to_string(), "5 km");
+        assert_eq!(calculator.execute("t = 2 h").unwrap().to_string(), "2 h");
+        assert_eq!(calculator.execute("d / t to km/h").unwrap().to_string(), "2.5 km/h");
+        assert!(calculator.execute("y = x + 1").is_err());
+        assert!(calculator.variable("y").is_none());
+        let names: Vec<&str> = calculator.variables().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["d", "t"]);
+    }
+
+    #[test]
+    fn test_custom_units() {
+        let mut calculator = Calculator::new();
+        calculator.define_unit("furlong", 201.168, "m").unwrap();
+        calculator.define_unit("fortnight", 336.0, "h").unwrap();
+        calculator.define_unit("fpf", 1.0, "furlong/fortnight").unwrap();
+        assert_eq!(calculator.eval("8 furlong to mi").unwrap().to_string(), "1 mi");
+        assert!(calculator.eval("1 fpf to mm/s").is_ok());
+        assert!(Calculator::new().eval("1 furlong to m").is_err());
+        assert!(calculator.define_unit("m", 1.0, "ft").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Numeric integration, summation, products and limits.
+//!
+//! Each built-in here evaluates a sub-expression over a bound variable:
+//! `integrate(f, x, a, b)`, `sum(f, k, a, b)`, `prod(f, k, a, b)` and `limit(f, x, a)`.
+
+use crate::ast::{BinaryOp, Expr};
+use crate::evaluator::{arithmetic, evaluate_with, round_significant, BoundFunction, Context, ValueWithUnit};
+use crate::units;
+
+/// Absolute tolerance for integrals.
+const ABSOLUTE_TOLERANCE: f64 = 1e-10;
+/// Relative tolerance for integrals.
+const RELATIVE_TOLERANCE: f64 = 1e-10;
+/// Maximum number of interval bisections in adaptive quadrature.
+const MAX_SUBDIVISIONS: usize = 2000;
+/// Width of a range beyond which integrals change variable to cover it, see [`to_unit_interval`].
+const WIDE_RANGE: f64 = 1e6;
+/// Maximum number of terms in a sum or product.
+const MAX_TERMS: f64 = 10_000_000.0;
+
+/// Gauss-Kronrod 15-point abscissae on `[0, 1]`; the odd entries are the 7-point Gauss nodes.
+const KRONROD_NODES: [f64; 8] = [
+    0.9914553711208126,
+    0.9491079123427585,
+    0.8648644233597691,
+    0.7415311855993945,
+    0.5860872354676911,
+    0.4058451513773972,
+    0.20778495500789848,
+    0.0,
+];
+/// Gauss-Kronrod 15-point weights, matching `KRONROD_NODES`.
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022935322010529224,
+    0.06309209262997856,
+    0.10479001032225019,
+    0.14065325971552592,
+    0.1690047266392679,
+    0.19035057806478542,
+    0.20443294007529889,
+    0.20948214108472782,
+];
+/// Gauss 7-point weights for the nodes `KRONROD_NODES[1]`, `[3]`, `[5]` and `[7]`.
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.1294849661688697,
+    0.27970539148927664,
+    0.3818300505051189,
+    0.4179591836734694,
+];
+
+/// The result of a numeric integration.
+#[derive(Debug, Clone, Copy)]
+pub struct Integral {
+    /// The estimated value of the integral.
+    pub value: f64,
+    /// The estimated absolute error.
+    pub error: f64,
+    /// Whether the error estimate met the requested tolerance.
+    pub converged: bool,
+}
+
+/// Checks whether a function name is one of the built-ins handled by this module.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+///
+/// # Returns
+///
+/// * `true` for `integrate`, `sum`, `prod` and `limit`.
+pub fn is_calculus_function(name: &str) -> bool {
+    matches!(name, "integrate" | "sum" | "prod" | "limit")
+}
+
+/// Evaluates a call to `integrate`, `sum`, `prod` or `limit`.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+/// * `args` - The unevaluated arguments: the body, the bound variable, and its bounds or limit point.
+/// * `context` - The variable bindings of the surrounding expression.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The result.
+/// * `Err(String)` - An error message if the call is malformed or evaluation fails.
+pub fn call(name: &str, args: &[Expr], context: &Context) -> Result<ValueWithUnit, String> {
+    match (name, args) {
+        ("limit", [body, Expr::Symbol(var), point]) => limit_call(body, var, point, context),
+        ("limit", _) => Err("limit expects an expression, a variable and a point, e.g., limit(sin(x) / x, x, 0)".to_string()),
+        (_, [body, Expr::Symbol(var), low, high]) => {
+            let low = evaluate_with(low, context)?;
+            let high = evaluate_with(high, context)?;
+            let high_value = match (&low.unit, &high.unit) {
+                (None, None) => high.value,
+                (Some(low_unit), Some(high_unit)) => units::convert(high.value, high_unit, low_unit)?,
+                _ => return Err(format!("The bounds of {} must have the same units", name)),
+            };
+            let bounds = (low.value, high_value);
+            match name {
+                "integrate" => integrate_call(body, var, bounds, low.unit, context),
+                _ => series_call(name, body, var, bounds, low.unit, context),
+            }
+        }
+        _ => Err(format!(
+            "{} expects an expression, a variable and two bounds, e.g., {}(k^2, k, 1, 10)",
+            name, name
+        )),
+    }
+}
+
+/// Integrates `body` over `var` from `bounds.0` to `bounds.1`, in the unit of the bounds.
+fn integrate_call(
+    body: &Expr,
+    var: &str,
+    bounds: (f64, f64),
+    var_unit: Option<String>,
+    context: &Context,
+) -> Result<ValueWithUnit, String> {
+    // Evenly spread samples step over a peak near zero in a wide or infinite range, so
+    // such a range is integrated over t in (-1, 1) with x = t / (1 - t^2) instead.
+    let width = (bounds.1 - bounds.0).abs();
+    let wide = !width.is_finite() || width > WIDE_RANGE;
+    let (low, high) = if wide { (to_unit_interval(bounds.0), to_unit_interval(bounds.1)) } else { bounds };
+    let point = |t: f64| -> (f64, f64) {
+        if !wide {
+            return (t, 1.0);
+        }
+        let d = 1.0 - t * t;
+        (t / d, (1.0 + t * t) / (d * d))
+    };
+
+    let mut function = BoundFunction::new(body, var, var_unit.clone(), context);
+    let mut sample = |t: f64| {
+        let (x, scale) = point(t);
+        function.call(x).map(|result| ValueWithUnit::new(result.value * scale, result.unit))
+    };
+    // The integrand's unit is taken from its midpoint and must stay the same everywhere.
+    let body_unit = sample((low + high) / 2.0)?.unit;
+    let mut failure = None;
+    let integral = integrate(
+        |t| match sample(t) {
+            Ok(result) if result.unit == body_unit => result.value,
+            Ok(_) => {
+                failure.get_or_insert_with(|| "The integrand's unit changes over the range".to_string());
+                f64::NAN
+            }
+            Err(e) => {
+                failure.get_or_insert(e);
+                f64::NAN
+            }
+        },
+        low,
+        high,
+    );
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    if !integral.value.is_finite() {
+        return Err("The integral does not converge to a finite value".to_string());
+    }
+    if !integral.converged {
+        context.warn(format!(
+            "integrate did not converge; the estimated error is {:e}",
+            integral.error
+        ));
+    }
+
+    // The result carries the integrand's unit times the variable's unit (N over m gives J).
+    let (scale, unit) = units::multiply(body_unit.as_deref(), var_unit.as_deref())?;
+    // The quadrature is accurate to well under 12 digits, so drop the rounding noise.
+    Ok(ValueWithUnit::new(round_significant(integral.value * scale, 12), unit))
+}
+
+/// Maps `x` to the `t` in `[-1, 1]` with `x = t / (1 - t^2)`; infinities map to the ends.
+fn to_unit_interval(x: f64) -> f64 {
+    // t = 2x / (1 + sqrt(1 + 4x^2)), written in terms of 1 / 2x so that it cannot overflow.
+    let u = 0.5 / x;
+    1.0 / (u + u.signum() * u.hypot(1.0))
+}
+
+/// Adds up or multiplies `body` for `var` stepping by one from `bounds.0` to `bounds.1`.
+fn series_call(
+    name: &str,
+    body: &Expr,
+    var: &str,
+    bounds: (f64, f64),
+    var_unit: Option<String>,
+    context: &Context,
+) -> Result<ValueWithUnit, String> {
+    let (start, end) = bounds;
+    if !start.is_finite() || !end.is_finite() || end - start > MAX_TERMS {
+        return Err(format!("{} has too many terms", name));
+    }
+    if start.fract() != 0.0 || end.fract() != 0.0 {
+        return Err(format!("{} expects whole numbers as bounds, not {} and {}", name, start, end));
+    }
+
+    let mut function = BoundFunction::new(body, var, var_unit, context);
+    let mut result: Option<ValueWithUnit> = None;
+    let mut k = start;
+    while k <= end {
+        let term = function.call(k)?;
+        result = Some(match result {
+            None => term,
+            Some(total) if name == "sum" => arithmetic(BinaryOp::Add, &total, &term)?,
+            Some(total) => {
+                let (scale, unit) = units::multiply(total.unit.as_deref(), term.unit.as_deref())?;
+                ValueWithUnit::new(total.value * term.value * scale, unit)
+            }
+        });
+        k += 1.0;
+    }
+
+    // An empty range gives the identity of the operation.
+    Ok(result.unwrap_or(ValueWithUnit::new(if name == "sum" { 0.0 } else { 1.0 }, None)))
+}
+
+/// Estimates the limit of `body` as `var` approaches `point` from both sides.
+fn limit_call(body: &Expr, var: &str, point: &Expr, context: &Context) -> Result<ValueWithUnit, String> {
+    let point = evaluate_with(point, context)?;
+    let mut function = BoundFunction::new(body, var, point.unit.clone(), context);
+    let mut approach = |direction: f64| -> Result<ValueWithUnit, String> {
+        let mut previous: Option<ValueWithUnit> = None;
+        for k in 2..=12 {
+            let h = direction * 10f64.powi(-k) * point.value.abs().max(1.0);
+            let current = function.call(point.value + h)?;
+            if let Some(previous) = &previous {
+                let scale = current.value.abs().max(1.0);
+                if (current.value - previous.value).abs() <= 1e-9 * scale {
+                    return Ok(current);
+                }
+            }
+            previous = Some(current);
+        }
+        Err("The limit does not exist or could not be estimated".to_string())
+    };
+
+    let left = approach(-1.0)?;
+    let right = approach(1.0)?;
+    if left.unit != right.unit || (left.value - right.value).abs() > 1e-6 * left.value.abs().max(1.0) {
+        return Err(format!(
+            "The limit does not exist: approaching from the left gives {}, from the right {}",
+            left.value, right.value
+        ));
+    }
+    // Limits are only accurate to about 9 digits.
+    Ok(ValueWithUnit::new(round_significant((left.value + right.value) / 2.0, 9), left.unit))
+}
+
+/// Integrates `f` over `[a, b]` with adaptive Gauss-Kronrod (G7-K15) quadrature.
+///
+/// The interval with the largest error estimate is bisected until the total estimate
+/// meets the tolerance or the subdivision limit is reached.
+///
+/// # Arguments
+///
+/// * `f` - The integrand.
+/// * `a` - The lower bound.
+/// * `b` - The upper bound.
+///
+/// # Returns
+///
+/// * The integral, its estimated error and whether the estimate converged.
+pub fn integrate<F: FnMut(f64) -> f64>(mut f: F, a: f64, b: f64) -> Integral {
+    if a == b {
+        return Integral {
+            value: 0.0,
+            error: 0.0,
+            converged: true,
+        };
+    }
+
+    let mut intervals = vec![gauss_kronrod(&mut f, a, b)];
+    for _ in 0..MAX_SUBDIVISIONS {
+        let value: f64 = intervals.iter().map(|interval| interval.2).sum();
+        let error: f64 = intervals.iter().map(|interval| interval.3).sum();
+        if error <= ABSOLUTE_TOLERANCE.max(RELATIVE_TOLERANCE * value.abs()) {
+            return Integral {
+                value,
+                error,
+                converged: true,
+            };
+        }
+        if !error.is_finite() {
+            break;
+        }
+
+        let worst = intervals
+            .iter()
+            .enumerate()
+            .max_by(|x, y| x.1 .3.total_cmp(&y.1 .3))
+            .map(|(index, _)| index)
+            .unwrap();
+        let (low, high, _, _) = intervals.swap_remove(worst);
+        let middle = (low + high) / 2.0;
+        intervals.push(gauss_kronrod(&mut f, low, middle));
+        intervals.push(gauss_kronrod(&mut f, middle, high));
+    }
+
+    Integral {
+        value: intervals.iter().map(|interval| interval.2).sum(),
+        error: intervals.iter().map(|interval| interval.3).sum(),
+        converged: false,
+    }
+}
+
+/// Applies the G7-K15 rule on `[a, b]`, returning `(a, b, integral, error estimate)`.
+fn gauss_kronrod<F: FnMut(f64) -> f64>(f: &mut F, a: f64, b: f64) -> (f64, f64, f64, f64) {
+    let center = (a + b) / 2.0;
+    let half = (b - a) / 2.0;
+    let mut kronrod = 0.0;
+    let mut gauss = 0.0;
+    for (i, (&node, &weight)) in KRONROD_NODES.iter().zip(KRONROD_WEIGHTS.iter()).enumerate() {
+        let values = if node == 0.0 {
+            f(center)
+        } else {
+            f(center - half * node) + f(center + half * node)
+        };
+        kronrod += weight * values;
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * values;
+        }
+    }
+    (a, b, kronrod * half, ((kronrod - gauss) * half).abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate;
+    use crate::parser::parse_expression;
+    use std::f64::consts::PI;
+
+    fn eval(input: &str) -> Result<ValueWithUnit, String> {
+        evaluate(&parse_expression(input).unwrap())
+    }
+
+    #[test]
+    fn test_integrate() {
+        let result = eval("integrate(sin(x), x, 0, pi)").unwrap();
+        assert!((result.value - 2.0).abs() < 1e-12);
+        let integral = integrate(|x| 1.0 / x.sqrt(), 0.0, 1.0);
+        assert!((integral.value - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_integrate_wide_ranges() {
+        let result = eval("integrate(exp(-x^2), x, -1e10, 1e10)").unwrap();
+        assert!((result.value - PI.sqrt()).abs() < 1e-9);
+        assert!((eval("integrate(exp(-x), x, 0, 1e12)").unwrap().value - 1.0).abs() < 1e-9);
+        assert!((eval("integrate(1 / x^2, x, 1, 1e300)").unwrap().value - 1.0).abs() < 1e-9);
+        assert_eq!(to_unit_interval(f64::INFINITY), 1.0);
+        assert_eq!(to_unit_interval(f64::NEG_INFINITY), -1.0);
+        assert_eq!(to_unit_interval(0.0), 0.0);
+        let t = to_unit_interval(-3.0);
+        assert!((t / (1.0 - t * t) + 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_integrate_units() {
+        let result = eval("integrate(3 N, x, 0 m, 2 m)").unwrap();
+        assert!((result.value - 6.0).abs() < 1e-12);
+        assert_eq!(result.unit.as_deref(), Some("J"));
+    }
+
+    #[test]
+    fn test_integrate_warns_when_not_converged() {
+        let context = Context::new();
+        let expr = parse_expression("integrate(sin(1 / x), x, 0.000001, 1)").unwrap();
+        evaluate_with(&expr, &context).unwrap();
+        assert!(context.take_warnings().iter().any(|w| w.contains("did not converge")));
+    }
+
+    #[test]
+    fn test_sum_and_prod() {
+        assert_eq!(eval("sum(k^2, k, 1, 100)").unwrap().value, 338350.0);
+        assert_eq!(eval("prod(k, k, 1, 5)").unwrap().value, 120.0);
+        assert_eq!(eval("sum(k, k, 5, 1)").unwrap().value, 0.0);
+        assert_eq!(eval("sum(k, k, 1.5, 3)").unwrap_err(), "sum expects whole numbers as bounds, not 1.5 and 3");
+        assert!(eval("prod(k, k, 1, 2.5)").is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(eval("limit(sin(x) / x, x, 0)").unwrap().value, 1.0);
+        assert!(eval("limit(abs(x) / x, x, 0)").is_err());
+    }
+}
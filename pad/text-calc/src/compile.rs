@@ -0,0 +1,699 @@
+//! Compiling expressions once to evaluate them many times.
+//!
+//! Plots, tables, integrals, sums, limits and the solver evaluate the same expression for
+//! thousands of values of its variables. [`Program::compile`] does once what does not
+//! change between those evaluations: the parts of the expression that do not mention the
+//! variables are evaluated, units are checked and conversions resolved to factors, and
+//! functions are looked up. What is left is a flat list of instructions on plain numbers.
+//!
+//! A program gives exactly the results and errors of [`evaluator::evaluate_with`].
+//! Expressions it cannot promise that for, such as those with random numbers,
+//! uncertainties, user functions or currency conversions, do not compile; callers
+//! evaluate them the usual way instead.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::calculator::NativeFunction;
+use crate::evaluator::{self, AngleMode, Context, ValueWithUnit};
+use crate::units::{self, Converter};
+use crate::{calculus, finance, probability};
+use std::fmt;
+use std::rc::Rc;
+
+/// One step of a program, on a stack of numbers where booleans are 1 and 0.
+#[derive(Clone)]
+enum Instruction {
+    /// Pushes a number.
+    Constant(f64),
+    /// Pushes the value of a variable.
+    Load(usize),
+    /// Multiplies the top number by a factor, such as the scale of a product of units.
+    Scale(f64),
+    /// Converts the top number between units.
+    Convert(Converter),
+    Negate,
+    Not,
+    /// Divides the top number by 100.
+    Percent,
+    Factorial,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// Divides, scales the quotient and rounds it down.
+    FloorDivide(f64),
+    Modulo,
+    Power,
+    /// Compares two numbers, within the tolerance of `==`.
+    Compare(BinaryOp),
+    /// Checks two booleans for equality, or inequality if `false`.
+    CompareBooleans(bool),
+    /// Continues at an instruction.
+    Jump(usize),
+    /// Pops a boolean and continues at an instruction if it is false.
+    JumpUnless(usize),
+    /// Continues at an instruction, keeping the boolean on top, if it is `on`; pops it
+    /// otherwise. This is how `and` and `or` skip their right-hand side.
+    ShortCircuit { on: bool, target: usize },
+    /// Calls a built-in function on the top numbers, in the given units.
+    Builtin { name: String, units: Vec<Option<String>> },
+    /// Calls a native function on the top numbers, converted into its parameter units.
+    Native { function: Rc<dyn NativeFunction>, converters: Vec<Option<Converter>> },
+}
+
+/// An expression compiled for evaluating it with many values of some of its variables.
+pub struct Program {
+    code: Vec<Instruction>,
+    /// The number of variables the program takes values for.
+    variables: usize,
+    /// The unit of the result.
+    unit: Option<String>,
+    /// Whether the result is a boolean.
+    boolean: bool,
+    angle_mode: AngleMode,
+    /// Kept between evaluations to reuse its memory.
+    stack: Vec<f64>,
+}
+
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("instructions", &self.code.len())
+            .field("variables", &self.variables)
+            .field("unit", &self.unit)
+            .field("boolean", &self.boolean)
+            .finish()
+    }
+}
+
+impl Program {
+    /// Compiles an expression as a function of some of its variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The expression, with any `diff` already expanded.
+    /// * `variables` - The names of the variables that change between evaluations, with the
+    ///   unit their values will be given in.
+    /// * `context` - The bindings of the other variables and the functions, which are
+    ///   fixed at compile time. It is not kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Program)` - The compiled program.
+    /// * `Err(String)` - Why the expression cannot be compiled; it should then be evaluated
+    ///   with [`evaluator::evaluate_with`], which also reports any error it has.
+    pub fn compile(expr: &Expr, variables: &[(&str, Option<&str>)], context: &Context) -> Result<Self, String> {
+        // A trace records the steps of the tree walker.
+        if context.tracer().is_active() {
+            return Err(unsupported("a trace"));
+        }
+        let mut compiler = Compiler { context, variables, code: Vec::new() };
+        compiler.check(expr)?;
+        let result = compiler.expression(expr)?;
+        Ok(Self {
+            code: compiler.code,
+            variables: variables.len(),
+            unit: result.unit,
+            boolean: result.boolean,
+            angle_mode: context.angle_mode(),
+            stack: Vec::new(),
+        })
+    }
+
+    /// Returns the unit of the results.
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    /// Evaluates the program, giving the number alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - One value per variable, in the order and units they were compiled with.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(f64)` - The value of the expression, in [`Program::unit`]; `1` or `0` for a boolean.
+    /// * `Err(String)` - The error the expression gives for these values.
+    pub fn evaluate(&mut self, values: &[f64]) -> Result<f64, String> {
+        if values.len() != self.variables {
+            return Err(format!("The program takes {} values but got {}", self.variables, values.len()));
+        }
+        let Self { code, stack, angle_mode, .. } = self;
+        stack.clear();
+        let mut next = 0;
+        while let Some(instruction) = code.get(next) {
+            next += 1;
+            match instruction {
+                Instruction::Constant(value) => stack.push(*value),
+                Instruction::Load(slot) => stack.push(values[*slot]),
+                Instruction::Scale(factor) => unary(stack, |x| x * factor),
+                Instruction::Convert(converter) => unary(stack, |x| converter.apply(x)),
+                Instruction::Negate => unary(stack, |x| -x),
+                Instruction::Not => unary(stack, |x| flag(x == 0.0)),
+                Instruction::Percent => unary(stack, |x| x / 100.0),
+                Instruction::Factorial => {
+                    let x = pop(stack);
+                    stack.push(evaluator::factorial(x)?);
+                }
+                Instruction::Add => binary(stack, |a, b| Ok(a + b))?,
+                Instruction::Subtract => binary(stack, |a, b| Ok(a - b))?,
+                Instruction::Multiply => binary(stack, |a, b| Ok(a * b))?,
+                Instruction::Divide => binary(stack, |a, b| match b == 0.0 {
+                    true => Err("Division by zero error".to_string()),
+                    false => Ok(a / b),
+                })?,
+                Instruction::FloorDivide(scale) => binary(stack, |a, b| match b == 0.0 {
+                    true => Err("Division by zero error".to_string()),
+                    false => Ok((a / b * scale).floor()),
+                })?,
+                Instruction::Modulo => binary(stack, |a, b| match b == 0.0 {
+                    true => Err("Modulo by zero error".to_string()),
+                    false => Ok(a - b * (a / b).floor()),
+                })?,
+                Instruction::Power => binary(stack, |a, b| Ok(a.powf(b)))?,
+                Instruction::Compare(op) => binary(stack, |a, b| Ok(flag(evaluator::compare_numbers(*op, a, b)?)))?,
+                Instruction::CompareBooleans(equal) => binary(stack, |a, b| Ok(flag((a == b) == *equal)))?,
+                Instruction::Jump(target) => next = *target,
+                Instruction::JumpUnless(target) => {
+                    if pop(stack) == 0.0 {
+                        next = *target;
+                    }
+                }
+                Instruction::ShortCircuit { on, target } => {
+                    if (stack.last().copied().unwrap_or(f64::NAN) != 0.0) == *on {
+                        next = *target;
+                    } else {
+                        stack.pop();
+                    }
+                }
+                Instruction::Builtin { name, units } => {
+                    let start = stack.len().saturating_sub(units.len());
+                    let args: Vec<ValueWithUnit> = stack
+                        .drain(start..)
+                        .zip(units)
+                        .map(|(value, unit)| ValueWithUnit::new(value, unit.clone()))
+                        .collect();
+                    stack.push(evaluator::call_function(name, &args, *angle_mode)?.value);
+                }
+                Instruction::Native { function, converters } => {
+                    let start = stack.len().saturating_sub(converters.len());
+                    let args: Vec<f64> = stack
+                        .drain(start..)
+                        .zip(converters)
+                        .map(|(value, converter)| converter.map_or(value, |converter| converter.apply(value)))
+                        .collect();
+                    stack.push(function.call(&args)?);
+                }
+            }
+        }
+        Ok(pop(stack))
+    }
+
+    /// Evaluates the program, giving the value with its unit as [`evaluator::evaluate_with`] would.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - One value per variable, in the order and units they were compiled with.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ValueWithUnit)` - The value of the expression.
+    /// * `Err(String)` - The error the expression gives for these values.
+    pub fn call(&mut self, values: &[f64]) -> Result<ValueWithUnit, String> {
+        let value = self.evaluate(values)?;
+        Ok(match self.boolean {
+            true => ValueWithUnit::boolean(value != 0.0),
+            false => ValueWithUnit::new(value, self.unit.clone()),
+        })
+    }
+}
+
+/// Replaces the number on top of the stack by a function of it.
+fn unary(stack: &mut [f64], apply: impl FnOnce(f64) -> f64) {
+    if let Some(top) = stack.last_mut() {
+        *top = apply(*top);
+    }
+}
+
+/// Replaces the two numbers on top of the stack by a function of them.
+fn binary(stack: &mut Vec<f64>, apply: impl FnOnce(f64, f64) -> Result<f64, String>) -> Result<(), String> {
+    let right = pop(stack);
+    let left = pop(stack);
+    stack.push(apply(left, right)?);
+    Ok(())
+}
+
+/// Pops the number on top of the stack; the compiler keeps the stack from running out.
+fn pop(stack: &mut Vec<f64>) -> f64 {
+    stack.pop().unwrap_or(f64::NAN)
+}
+
+/// A boolean as a number.
+fn flag(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// The reason an expression does not compile.
+fn unsupported(what: &str) -> String {
+    format!("Expressions with {} are not compiled", what)
+}
+
+/// What the compiler knows of the value of a subexpression.
+#[derive(Debug, Clone, PartialEq)]
+struct Type {
+    unit: Option<String>,
+    boolean: bool,
+    /// The value, if the subexpression does not mention the variables.
+    constant: Option<f64>,
+}
+
+impl Type {
+    fn number(unit: Option<String>) -> Self {
+        Self { unit, boolean: false, constant: None }
+    }
+
+    fn boolean() -> Self {
+        Self { unit: None, boolean: true, constant: None }
+    }
+}
+
+/// Translates an expression into instructions.
+struct Compiler<'a> {
+    context: &'a Context,
+    variables: &'a [(&'a str, Option<&'a str>)],
+    code: Vec<Instruction>,
+}
+
+impl Compiler<'_> {
+    /// Returns the slot of a variable; a name given twice is the last one, as when binding it.
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.variables.iter().rposition(|(variable, _)| *variable == name)
+    }
+
+    /// Checks whether an expression mentions the variables, and so must be compiled rather
+    /// than evaluated once.
+    fn depends(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Number(_) => false,
+            Expr::NumberWithUnit { unit, .. } => {
+                unit.as_ref().is_some_and(|name| !units::is_unit(name) && self.slot(name).is_some())
+            }
+            Expr::Symbol(name) => self.slot(name).is_some(),
+            Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => self.depends(expr),
+            Expr::BinaryOp { left, right, .. } | Expr::Equation { left, right } => {
+                self.depends(left) || self.depends(right)
+            }
+            Expr::Function { args, .. } => args.iter().any(|arg| self.depends(arg)),
+        }
+    }
+
+    /// Rejects the expressions that may give different values for the same variables, or
+    /// read the variables in ways the compiler cannot see.
+    fn check(&self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Function { name, args } => {
+                if self.context.user_function(name).is_some() {
+                    return Err(unsupported("user functions"));
+                }
+                if probability::is_random_function(name) && self.context.function(name).is_none() {
+                    return Err(unsupported("random numbers"));
+                }
+                args.iter().try_for_each(|arg| self.check(arg))
+            }
+            Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => self.check(expr),
+            Expr::BinaryOp { left, right, .. } | Expr::Equation { left, right } => {
+                self.check(left)?;
+                self.check(right)
+            }
+            Expr::Number(_) | Expr::NumberWithUnit { .. } | Expr::Symbol(_) => Ok(()),
+        }
+    }
+
+    /// Compiles an expression, leaving its value on the stack.
+    fn expression(&mut self, expr: &Expr) -> Result<Type, String> {
+        if !self.depends(expr) {
+            let value = evaluator::evaluate_with(expr, self.context)?;
+            if value.uncertainty.is_some() {
+                return Err(unsupported("uncertainties"));
+            }
+            self.code.push(Instruction::Constant(value.value));
+            return Ok(Type { boolean: value.is_boolean(), constant: Some(value.value), unit: value.unit });
+        }
+        match expr {
+            Expr::Symbol(name) => {
+                let slot = self.slot(name).expect("a variable");
+                self.code.push(Instruction::Load(slot));
+                Ok(Type::number(self.variables[slot].1.map(String::from)))
+            }
+            // A variable written after a number, as in `3x`.
+            Expr::NumberWithUnit { value, unit: Some(name) } => {
+                let factor = self.number(&Expr::Symbol(name.clone()), "Implicit multiplication")?;
+                self.code.push(Instruction::Scale(*value));
+                Ok(Type::number(factor.unit))
+            }
+            Expr::UnaryOp { op: UnaryOp::Not, expr } => {
+                self.boolean(expr, "not")?;
+                self.code.push(Instruction::Not);
+                Ok(Type::boolean())
+            }
+            Expr::UnaryOp { op: UnaryOp::Factorial, expr } => {
+                if self.number(expr, "Factorial")?.unit.is_some() {
+                    return Err("Factorial requires a unitless value".to_string());
+                }
+                self.code.push(Instruction::Factorial);
+                Ok(Type::number(None))
+            }
+            Expr::UnaryOp { op: UnaryOp::Percent, expr } => {
+                let operand = self.number(expr, "'%'")?;
+                self.code.push(Instruction::Percent);
+                Ok(Type::number(operand.unit))
+            }
+            Expr::UnaryOp { op, expr } => {
+                let operand = self.number(expr, &format!("Unary {}", op))?;
+                if *op == UnaryOp::Minus {
+                    self.code.push(Instruction::Negate);
+                }
+                Ok(Type::number(operand.unit))
+            }
+            Expr::BinaryOp { left, op: op @ (BinaryOp::And | BinaryOp::Or), right } => {
+                let name = op.to_string();
+                self.boolean(left, name.trim())?;
+                let skip = self.emit(Instruction::ShortCircuit { on: *op == BinaryOp::Or, target: 0 });
+                self.boolean(right, name.trim())?;
+                self.patch(skip);
+                Ok(Type::boolean())
+            }
+            Expr::BinaryOp { left, op, right } if op.is_comparison() => self.comparison(*op, left, right),
+            Expr::BinaryOp { op: BinaryOp::PlusMinus, .. } => Err(unsupported("uncertainties")),
+            Expr::BinaryOp { left, op, right } => self.arithmetic(*op, left, right),
+            Expr::Function { name, args } if name == "if" => match args.as_slice() {
+                [condition, then, otherwise] => {
+                    self.boolean(condition, "if")?;
+                    let skip = self.emit(Instruction::JumpUnless(0));
+                    let then = self.expression(then)?;
+                    let end = self.emit(Instruction::Jump(0));
+                    self.patch(skip);
+                    let otherwise = self.expression(otherwise)?;
+                    self.patch(end);
+                    if then.unit != otherwise.unit || then.boolean != otherwise.boolean {
+                        return Err(unsupported("conditions choosing between units"));
+                    }
+                    Ok(Type { constant: None, ..then })
+                }
+                _ => Err("if expects a condition and two values, e.g., if(x > 0, x, -x)".to_string()),
+            },
+            Expr::Function { name, args } => self.call(name, args),
+            Expr::Equation { .. } => Err("Equations can only be used inside solve(equation, variable)".to_string()),
+            Expr::Conversion { expr, target_unit } => {
+                let mut operand = self.number(expr, "to")?;
+                // A plain number converted to an angle unit is an angle in the angle mode's unit.
+                if operand.unit.is_none() && units::is_angle(target_unit) {
+                    operand.unit = Some(self.context.angle_mode().unit().to_string());
+                }
+                let Some(source_unit) = operand.unit else {
+                    return Err("Cannot convert a unitless value".to_string());
+                };
+                let converter = self.converter(&source_unit, target_unit)?;
+                self.code.push(Instruction::Convert(converter));
+                Ok(Type::number(Some(target_unit.clone())))
+            }
+            Expr::Number(_) | Expr::NumberWithUnit { .. } => unreachable!("literals do not mention the variables"),
+        }
+    }
+
+    /// Compiles an expression that must give a number.
+    fn number(&mut self, expr: &Expr, operation: &str) -> Result<Type, String> {
+        let result = self.expression(expr)?;
+        if result.boolean {
+            return Err(format!("{} expects a number but got a boolean", operation));
+        }
+        Ok(result)
+    }
+
+    /// Compiles an expression that must give a boolean.
+    fn boolean(&mut self, expr: &Expr, operation: &str) -> Result<Type, String> {
+        let result = self.expression(expr)?;
+        if !result.boolean {
+            return Err(format!("{} expects a boolean but got a number", operation));
+        }
+        Ok(result)
+    }
+
+    /// Appends a jump, to be pointed at its target with [`Compiler::patch`].
+    fn emit(&mut self, jump: Instruction) -> usize {
+        self.code.push(jump);
+        self.code.len() - 1
+    }
+
+    /// Points a jump at the next instruction.
+    fn patch(&mut self, jump: usize) {
+        let next = self.code.len();
+        match &mut self.code[jump] {
+            Instruction::Jump(target) | Instruction::JumpUnless(target) | Instruction::ShortCircuit { target, .. } => {
+                *target = next;
+            }
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    /// Resolves a conversion, unless it changes currency, which the tree walker reports
+    /// with the date of the exchange rates.
+    fn converter(&self, from: &str, to: &str) -> Result<Converter, String> {
+        if self.context.rates().is_some_and(|rates| rates.describe(from, to).is_some()) {
+            return Err(unsupported("conversions between currencies"));
+        }
+        units::converter(from, to)
+    }
+
+    /// Compiles a comparison, converting the right-hand side into the unit of the left.
+    fn comparison(&mut self, op: BinaryOp, left: &Expr, right: &Expr) -> Result<Type, String> {
+        let (left, right) = (self.expression(left)?, self.expression(right)?);
+        if left.boolean || right.boolean {
+            if !(matches!(op, BinaryOp::Equal | BinaryOp::NotEqual) && left.boolean == right.boolean) {
+                return Err(format!("Cannot compare a boolean with '{}'", op.to_string().trim()));
+            }
+            self.code.push(Instruction::CompareBooleans(op == BinaryOp::Equal));
+            return Ok(Type::boolean());
+        }
+        match (&left.unit, &right.unit) {
+            (None, None) => {}
+            (Some(left_unit), Some(right_unit)) => {
+                let converter = self.converter(right_unit, left_unit)?;
+                self.code.push(Instruction::Convert(converter));
+            }
+            _ => return Err("Cannot compare a value with a unit to a unitless value".to_string()),
+        }
+        self.code.push(Instruction::Compare(op));
+        Ok(Type::boolean())
+    }
+
+    /// Compiles an arithmetic operator, resolving the unit of its result.
+    fn arithmetic(&mut self, op: BinaryOp, left: &Expr, right: &Expr) -> Result<Type, String> {
+        let operation = format!("'{}'", op.to_string().trim());
+        let left = self.number(left, &operation)?;
+        let right = self.number(right, &operation)?;
+        let (left_unit, right_unit) = (left.unit.as_deref(), right.unit.as_deref());
+        let (instruction, scale, unit) = match op {
+            BinaryOp::Add | BinaryOp::Subtract => {
+                match (left_unit, right_unit) {
+                    (None, None) => {}
+                    (Some(left_unit), Some(right_unit)) => match self.converter(right_unit, left_unit) {
+                        Ok(Converter::Temperature { .. }) => {
+                            return Err(format!(
+                                "Cannot add or subtract {} and {}; convert one with 'to' first",
+                                left_unit, right_unit
+                            ));
+                        }
+                        Ok(converter) => self.code.push(Instruction::Convert(converter)),
+                        Err(_) => return Err("Unit mismatch in addition or subtraction".to_string()),
+                    },
+                    _ => return Err("Unit mismatch in addition or subtraction".to_string()),
+                }
+                let instruction = if op == BinaryOp::Add { Instruction::Add } else { Instruction::Subtract };
+                (instruction, 1.0, left.unit)
+            }
+            BinaryOp::Multiply => {
+                let (scale, unit) = units::multiply(left_unit, right_unit)?;
+                (Instruction::Multiply, scale, unit)
+            }
+            BinaryOp::Divide => {
+                let (scale, unit) = units::divide(left_unit, right_unit)?;
+                (Instruction::Divide, scale, unit)
+            }
+            BinaryOp::FloorDivide => {
+                let (scale, unit) = units::divide(left_unit, right_unit)?;
+                (Instruction::FloorDivide(scale), 1.0, unit)
+            }
+            BinaryOp::Modulo => {
+                match (left_unit, right_unit) {
+                    (None, None) => {}
+                    (Some(left_unit), Some(right_unit)) => {
+                        self.code.push(Instruction::Convert(units::converter(right_unit, left_unit)?));
+                    }
+                    _ => return Err("Unit mismatch in mod".to_string()),
+                }
+                (Instruction::Modulo, 1.0, left.unit)
+            }
+            BinaryOp::Power => {
+                if right_unit.is_some() {
+                    return Err("Exponent must be unitless".to_string());
+                }
+                // The unit of the result depends on the exponent, which must then be fixed.
+                let unit = match (left_unit, right.constant) {
+                    (None, _) => None,
+                    (Some(unit), Some(exponent)) if exponent.fract() == 0.0 => units::power(unit, exponent as i32)?,
+                    (Some(_), Some(_)) => return Err("Cannot raise a value with a unit to a non-integer power".to_string()),
+                    (Some(_), None) => return Err(unsupported("units raised to a variable power")),
+                };
+                (Instruction::Power, 1.0, unit)
+            }
+            _ => unreachable!("comparison, logical and ± operators are compiled in expression"),
+        };
+        self.code.push(instruction);
+        if scale != 1.0 {
+            self.code.push(Instruction::Scale(scale));
+        }
+        Ok(Type::number(unit))
+    }
+
+    /// Compiles a call of a built-in or native function.
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<Type, String> {
+        let overridden = self.context.function(name).is_some();
+        // These take expressions rather than values, or lists and names of frequencies.
+        if matches!(name, "diff" | "solve")
+            || calculus::is_calculus_function(name)
+            || (finance::is_finance_function(name) && !overridden)
+        {
+            return Err(unsupported(&format!("{} of the variables", name)));
+        }
+        let units = args
+            .iter()
+            .map(|arg| Ok(self.number(arg, name)?.unit))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let Some(function) = self.context.shared_function(name) else {
+            // The unit of a built-in function's result depends on those of its arguments only.
+            let probe: Vec<ValueWithUnit> = units.iter().map(|unit| ValueWithUnit::new(1.0, unit.clone())).collect();
+            let result = evaluator::call_function(name, &probe, self.context.angle_mode())?;
+            self.code.push(Instruction::Builtin { name: name.to_string(), units });
+            return Ok(Type::number(result.unit));
+        };
+        let signature = function.signature();
+        if units.len() != signature.arity() {
+            return Err(format!("{} expects {} arguments but got {}", name, signature.arity(), units.len()));
+        }
+        let converters = units
+            .iter()
+            .zip(&signature.params)
+            .enumerate()
+            .map(|(index, (unit, param))| match (unit, param) {
+                (None, None) => Ok(None),
+                (Some(unit), Some(param)) => units::converter(unit, param).map(Some),
+                (_, Some(param)) => Err(format!("Argument {} of {} must be in {}", index + 1, name, param)),
+                (Some(unit), None) => Err(format!("Argument {} of {} must be unitless, not {}", index + 1, name, unit)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        self.code.push(Instruction::Native { function, converters });
+        Ok(Type::number(signature.returns))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::Signature;
+    use crate::parser::parse_expression;
+
+    /// Checks that the program gives what the tree walker gives at each value of `x`.
+    fn assert_matches(input: &str, unit: Option<&str>, xs: &[f64], context: &Context) {
+        let expr = parse_expression(input).unwrap();
+        let mut program = Program::compile(&expr, &[("x", unit)], context).unwrap();
+        let mut local = context.clone();
+        for &x in xs {
+            local.set_variable("x", ValueWithUnit::new(x, unit.map(String::from)));
+            let expected = evaluator::evaluate_with(&expr, &local);
+            match (program.call(&[x]), expected) {
+                (Ok(actual), Ok(expected)) => {
+                    assert_eq!(actual.value.to_bits(), expected.value.to_bits(), "{} at {}", input, x);
+                    assert_eq!((actual.unit, actual.kind), (expected.unit, expected.kind), "{} at {}", input, x);
+                }
+                (actual, expected) => assert_eq!(actual.err(), expected.err(), "{} at {}", input, x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_tree_walker() {
+        let context = Context::new();
+        let xs = [-2.5, -1.0, 0.0, 0.5, 3.0, 7.0];
+        for input in [
+            "sin(x) / x",
+            "3x^2 - 2x + 1",
+            "x mod 3 + x // 2 + 5%",
+            "x! + abs(-x) + sqrt(16)",
+            "-x^2 + 2 pi",
+            "normcdf(x, 1, 2)",
+            "1 / x",
+        ] {
+            assert_matches(input, None, &xs, &context);
+        }
+    }
+
+    #[test]
+    fn test_units() {
+        let context = Context::new();
+        assert_matches("x * 2 m / (4 s^2) to km/h", Some("s"), &[1.0, 2.5], &context);
+        assert_matches("x^2 * 3 km / (1 min) to m^3/s", Some("m"), &[3.0, 400.0], &context);
+        assert_matches("x to F", Some("C"), &[-40.0, 100.0], &context);
+        assert_matches("sin(x) + cos(x)", Some("deg"), &[30.0, 90.0], &context);
+        assert_matches("x + 250 m - 1 mi", Some("km"), &[0.0, 1.5], &context);
+
+        let expr = parse_expression("x + 1 s").unwrap();
+        assert!(Program::compile(&expr, &[("x", Some("m"))], &context).is_err());
+        let program = Program::compile(&expr, &[("x", Some("s"))], &context).unwrap();
+        assert_eq!(program.unit(), Some("s"));
+    }
+
+    #[test]
+    fn test_conditions_and_logic() {
+        let mut context = Context::new();
+        context.set_variable("limit", ValueWithUnit::new(2.0, None));
+        let xs = [-1.0, 0.0, 1.0, 2.0, 3.0];
+        assert_matches("x > 0 ? ln(x) : 0", None, &xs, &context);
+        assert_matches("x >= 0 and x < limit or x == 3", None, &xs, &context);
+        assert_matches("not (x != 1) == (x < limit)", None, &xs, &context);
+        assert_matches("if(x > 1, x * 1 m, 1 m) to cm", None, &xs, &context);
+    }
+
+    #[test]
+    fn test_native_functions() {
+        struct Speed;
+        impl NativeFunction for Speed {
+            fn signature(&self) -> Signature {
+                Signature::new(&[Some("m"), Some("s")], Some("m/s"))
+            }
+
+            fn call(&self, args: &[f64]) -> Result<f64, String> {
+                Ok(args[0] / args[1])
+            }
+        }
+        let mut context = Context::new();
+        context.register_function("speed", Rc::new(Speed));
+        assert_matches("speed(2 km, x) to km/h", Some("min"), &[1.0, 30.0], &context);
+    }
+
+    #[test]
+    fn test_not_compiled() {
+        let mut context = Context::new();
+        context.set_variable("g", ValueWithUnit::uncertain(9.81, 0.02, Some("m/s^2".to_string())));
+        for input in ["x + rand()", "x * g", "(x ± 1) * 2", "integrate(x * t, t, 0, 1)", "x * if(x > 0, 1 m, 1)"] {
+            let expr = parse_expression(input).unwrap();
+            assert!(Program::compile(&expr, &[("x", None)], &context).is_err(), "{}", input);
+        }
+        // Parts without the variables are evaluated once, however they are written.
+        let expr = parse_expression("integrate(t, t, 0, 2) * x").unwrap();
+        let mut program = Program::compile(&expr, &[("x", None)], &context).unwrap();
+        assert_eq!(program.evaluate(&[3.0]), Ok(6.0));
+        assert!(program.evaluate(&[]).is_err());
+    }
+}
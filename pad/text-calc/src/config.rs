@@ -0,0 +1,354 @@
+//! The user configuration file.
+//!
+//! `config.toml` in the configuration directory, usually `~/.config/textcalc`, holds the
+//! defaults of command-line flags, files of unit definitions, and a prelude script whose
+//! constants and functions every session starts with:
+//!
+//! ```toml
+//! precision = 6
+//! notation = "engineering"
+//! angle = "deg"
+//! units = ["units.txt"]
+//! prelude = "~/calc/prelude.calc"
+//! ```
+//!
+//! Relative paths are relative to the directory of the configuration file. A unit file
+//! defines one unit per line as a multiple of a unit expression, as in `furlong = 201.168 m`;
+//! `#` starts a comment.
+
+use crate::calculator::{Calculator, Settings};
+use crate::evaluator::{AngleMode, Notation};
+use crate::parser::NumberFormat;
+use crate::script::{Output, Script};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// The numeric type results are computed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// 64-bit floating point, the only backend so far.
+    #[default]
+    F64,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "f64" => Ok(Backend::F64),
+            _ => Err(format!("Unknown numeric backend: {} (only f64 is available)", name)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+/// The settings of a configuration file. A missing key keeps the built-in default, and
+/// [`Config::or`] lets command-line flags take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Significant digits of results, or decimal places in fixed notation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precision: Option<usize>,
+    /// How the numbers of results are written.
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub notation: Option<Notation>,
+    /// Whether angles written as plain numbers are radians or degrees.
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub angle: Option<AngleMode>,
+    /// How digit grouping and the decimal separator are written in numbers.
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub number_format: Option<NumberFormat>,
+    /// The numeric type results are computed in.
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+    /// The exchange-rate snapshot for currency units.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rates: Option<PathBuf>,
+    /// Files of unit definitions, loaded in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<PathBuf>,
+    /// A script run at startup, for constants and functions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prelude: Option<PathBuf>,
+}
+
+/// Reads and writes settings such as `notation = "fixed"` by their names.
+mod by_name {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map(Some).map_err(de::Error::custom)
+    }
+}
+
+impl Config {
+    /// Reads a configuration file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Config)` - The configuration, with paths relative to the file's directory.
+    /// * `Err(String)` - An error message if the file cannot be read or is not a valid configuration.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the configuration from '{}': {}", path.display(), e))?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+            .map_err(|e| format!("Invalid configuration in '{}': {}", path.display(), e))
+    }
+
+    /// Parses the TOML text of a configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The TOML text.
+    /// * `directory` - The directory relative paths are relative to.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Config)` - The configuration, with `~` expanded to the home directory in paths.
+    /// * `Err(String)` - An error message if the TOML is malformed, or a key or value is unknown.
+    pub fn parse(text: &str, directory: &Path) -> Result<Self, String> {
+        let mut config: Self = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        let resolve = |path: &mut PathBuf| *path = resolve_path(path, directory);
+        config.rates.iter_mut().chain(&mut config.units).chain(&mut config.prelude).for_each(resolve);
+        Ok(config)
+    }
+
+    /// Combines two configurations, keeping the settings of `self` and taking those it
+    /// lacks from `fallback`.
+    ///
+    /// # Arguments
+    ///
+    /// * `fallback` - The configuration that gives way, e.g., the file under command-line flags.
+    pub fn or(self, fallback: Config) -> Config {
+        Config {
+            precision: self.precision.or(fallback.precision),
+            notation: self.notation.or(fallback.notation),
+            angle: self.angle.or(fallback.angle),
+            number_format: self.number_format.or(fallback.number_format),
+            backend: self.backend.or(fallback.backend),
+            rates: self.rates.or(fallback.rates),
+            units: if self.units.is_empty() { fallback.units } else { self.units },
+            prelude: self.prelude.or(fallback.prelude),
+        }
+    }
+
+    /// Returns the calculator settings of the configuration, with defaults for those it lacks.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            number_format: self.number_format.unwrap_or_default(),
+            angle_mode: self.angle.unwrap_or_default(),
+            precision: self.precision,
+            notation: self.notation.unwrap_or_default(),
+            ..Settings::default()
+        }
+    }
+
+    /// Loads the unit files and then runs the prelude, so the prelude may use the units.
+    ///
+    /// # Arguments
+    ///
+    /// * `calculator` - The calculator to define the units, constants and functions in.
+    ///
+    /// # Returns
+    ///
+    /// The problems met, such as a unit file that cannot be read or an error in a line of
+    /// the prelude. They do not stop the loading.
+    pub fn load_definitions(&self, calculator: &mut Calculator) -> Vec<String> {
+        let mut problems = Vec::new();
+        for path in &self.units {
+            if let Err(e) = load_units(calculator, path) {
+                problems.push(e);
+            }
+        }
+        if let Some(path) = &self.prelude {
+            match std::fs::read_to_string(path) {
+                Ok(source) => problems.extend(run_prelude(calculator, &source, path)),
+                Err(e) => problems.push(format!("Failed to read the prelude '{}': {}", path.display(), e)),
+            }
+        }
+        problems
+    }
+}
+
+/// Expands a leading `~` to the home directory and makes a relative path relative to `directory`.
+fn resolve_path(path: &Path, directory: &Path) -> PathBuf {
+    let mut components = path.components();
+    match (components.next(), std::env::var_os("HOME")) {
+        (Some(Component::Normal(first)), Some(home)) if first == "~" => Path::new(&home).join(components.as_path()),
+        _ => directory.join(path),
+    }
+}
+
+/// Defines the units of a unit file in a calculator.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to define the units in.
+/// * `path` - The unit file.
+///
+/// # Returns
+///
+/// * `Ok(())` - If every unit was defined.
+/// * `Err(String)` - An error message if the file cannot be read or a definition is invalid;
+///   the units before it stay defined.
+pub fn load_units(calculator: &mut Calculator, path: &Path) -> Result<(), String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read units from '{}': {}", path.display(), e))?;
+    define_units(calculator, &text).map_err(|e| format!("Invalid units in '{}': {}", path.display(), e))
+}
+
+/// Defines units written one per line as `symbol = factor base`, e.g., `furlong = 201.168 m`.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to define the units in; later lines may build on earlier ones.
+/// * `text` - The definitions. `#` starts a comment, and blank lines are skipped.
+///
+/// # Returns
+///
+/// * `Ok(())` - If every unit was defined.
+/// * `Err(String)` - An error message naming the line of the first invalid definition.
+pub fn define_units(calculator: &mut Calculator, text: &str) -> Result<(), String> {
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let definition = line.split_once('=').and_then(|(symbol, size)| {
+            let size = size.trim();
+            let (factor, base) = size.split_at(size.find(char::is_whitespace).unwrap_or(size.len()));
+            Some((symbol.trim(), factor.parse::<f64>().ok()?, base.trim()))
+        });
+        let (symbol, factor, base) = definition
+            .ok_or_else(|| format!("Line {}: expected a definition such as 'furlong = 201.168 m'", index + 1))?;
+        calculator.define_unit(symbol, factor, base).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+    }
+    Ok(())
+}
+
+/// Runs a prelude script, keeping its variables and functions in the calculator. Results
+/// and printed text are discarded.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to run the prelude in.
+/// * `source` - The text of the prelude.
+/// * `path` - The prelude's file, for messages.
+///
+/// # Returns
+///
+/// The errors and warnings of the prelude, naming the file and line.
+pub fn run_prelude(calculator: &mut Calculator, source: &str, path: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    let result = Script::parse(source, calculator.settings().number_format).and_then(|script| {
+        script.run(calculator, &mut |output| match output {
+            Output::Error(e) => problems.push(format!("{}, {}", path.display(), e)),
+            Output::Warning(warning) => problems.push(format!("{}: {}", path.display(), warning)),
+            Output::Result { .. } | Output::Print(_) => (),
+        })
+    });
+    if let Err(e) = result {
+        problems.push(format!("{}, {}", path.display(), e));
+    }
+    problems
+}
+
+/// Returns where the calculator looks for its configuration: `textcalc/config.toml` in
+/// `$XDG_CONFIG_HOME`, or in `~/.config` if that is not set.
+pub fn default_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("textcalc").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "precision = 4\nnotation = \"eng\"\nangle = \"deg\"\nunits = [\"units.txt\", \"/opt/units.txt\"]\n";
+        let config = Config::parse(text, Path::new("/etc/textcalc")).unwrap();
+        assert_eq!(config.precision, Some(4));
+        assert_eq!(config.notation, Some(Notation::Engineering));
+        assert_eq!(config.angle, Some(AngleMode::Degrees));
+        assert_eq!(config.units, [PathBuf::from("/etc/textcalc/units.txt"), PathBuf::from("/opt/units.txt")]);
+        assert_eq!(config.backend, None);
+        assert_eq!(Config::parse("", Path::new("")).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text| Config::parse(text, Path::new("")).unwrap_err();
+        assert!(error("notation = \"roman\"").starts_with("Unknown notation: roman"));
+        assert!(error("backend = \"decimal\"").contains("only f64 is available"));
+        assert!(error("colour = \"red\"").contains("unknown field `colour`"));
+        assert!(error("precision = -1").contains("invalid value"));
+    }
+
+    #[test]
+    fn test_or_and_settings() {
+        let file = Config { precision: Some(4), angle: Some(AngleMode::Degrees), units: vec!["a".into()], ..Config::default() };
+        let flags = Config { precision: Some(8), notation: Some(Notation::Fixed), ..Config::default() };
+        let config = flags.or(file);
+        assert_eq!((config.precision, config.angle, config.units.len()), (Some(8), Some(AngleMode::Degrees), 1));
+        let settings = config.settings();
+        assert_eq!(settings.notation, Notation::Fixed);
+        assert_eq!(settings.angle_mode, AngleMode::Degrees);
+        assert_eq!(settings.number_format, NumberFormat::Standard);
+        let shown = toml::to_string(&config).unwrap();
+        assert!(shown.contains("notation = \"fixed\"\nangle = \"deg\"\n"), "{}", shown);
+    }
+
+    #[test]
+    fn test_define_units() {
+        let mut calculator = Calculator::new();
+        define_units(&mut calculator, "# Old lengths\nfurlong = 201.168 m\n\nleague = 24 furlong  # 3 miles\n").unwrap();
+        assert_eq!(calculator.eval("1 league to mi").unwrap().to_string(), "3 mi");
+        assert_eq!(
+            define_units(&mut calculator, "span = 0.2286 m\ncubit = two span\n").unwrap_err(),
+            "Line 2: expected a definition such as 'furlong = 201.168 m'"
+        );
+        assert!(define_units(&mut calculator, "ft = 1 m").unwrap_err().starts_with("Line 1: 'ft' is already a unit"));
+    }
+
+    #[test]
+    fn test_run_prelude() {
+        let mut calculator = Calculator::new();
+        let source = "g0 = 9.80665 m/s^2\nfn weight(mass) {\n    return mass * g0\n}\n1 +\n";
+        let problems = run_prelude(&mut calculator, source, Path::new("prelude.calc"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("prelude.calc, line 5, column 4: Parsing error"), "{}", problems[0]);
+        assert_eq!(calculator.execute("weight(2 kg) to N").unwrap().to_string(), "19.6133 N");
+    }
+}
@@ -0,0 +1,102 @@
+//! Physical and mathematical constants.
+//!
+//! Physical constants carry their units and use the CODATA 2018 recommended values, so
+//! they combine with quantities like any other value, e.g., `c * 3 s to km`.
+
+use std::f64::consts::{E, PI, TAU};
+
+/// A named constant.
+#[derive(Debug, Clone, Copy)]
+pub struct Constant {
+    /// The name used in expressions (e.g., `k_B`).
+    pub name: &'static str,
+    /// The value in the constant's unit.
+    pub value: f64,
+    /// The unit of the value, if any.
+    pub unit: Option<&'static str>,
+    /// A short description shown by `:const`.
+    pub description: &'static str,
+}
+
+/// Built-in constants.
+static CONSTANTS: &[Constant] = &[
+    // Mathematical constants
+    Constant { name: "pi", value: PI, unit: None, description: "ratio of a circle's circumference to its diameter" },
+    Constant { name: "tau", value: TAU, unit: None, description: "ratio of a circle's circumference to its radius" },
+    Constant { name: "e", value: E, unit: None, description: "base of the natural logarithm" },
+    Constant { name: "phi", value: 1.618033988749895, unit: None, description: "golden ratio" },
+    // Physical constants (CODATA 2018)
+    Constant { name: "c", value: 299792458.0, unit: Some("m/s"), description: "speed of light in vacuum" },
+    Constant { name: "G_newton", value: 6.67430e-11, unit: Some("m^3/kg/s^2"), description: "Newtonian constant of gravitation" },
+    Constant { name: "h_planck", value: 6.62607015e-34, unit: Some("J*s"), description: "Planck constant" },
+    Constant { name: "hbar", value: 1.054571817e-34, unit: Some("J*s"), description: "reduced Planck constant" },
+    Constant { name: "k_B", value: 1.380649e-23, unit: Some("J/K"), description: "Boltzmann constant" },
+    Constant { name: "N_A", value: 6.02214076e23, unit: Some("1/mol"), description: "Avogadro constant" },
+    Constant { name: "R", value: 8.314462618, unit: Some("J/mol/K"), description: "molar gas constant" },
+    Constant { name: "e_charge", value: 1.602176634e-19, unit: Some("A*s"), description: "elementary charge" },
+    Constant { name: "g0", value: 9.80665, unit: Some("m/s^2"), description: "standard acceleration of gravity" },
+    Constant { name: "atm", value: 101325.0, unit: Some("Pa"), description: "standard atmosphere" },
+];
+
+/// Looks up a constant by its name.
+///
+/// # Arguments
+///
+/// * `name` - The name of the constant.
+///
+/// # Returns
+///
+/// * `Some(&Constant)` - The constant, if the name is known.
+pub fn lookup(name: &str) -> Option<&'static Constant> {
+    CONSTANTS.iter().find(|constant| constant.name == name)
+}
+
+/// Returns all built-in constants, mathematical constants first.
+pub fn all() -> &'static [Constant] {
+    CONSTANTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("c").unwrap().value, 299792458.0);
+        assert_eq!(lookup("pi").unwrap().unit, None);
+        assert!(lookup("C").is_none());
+    }
+
+    #[test]
+    fn test_units_are_known() {
+        for constant in all() {
+            if let Some(unit) = constant.unit {
+                assert!(units::resolve(unit).is_ok(), "{} has unknown unit {}", constant.name, unit);
+            }
+        }
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        for (index, constant) in all().iter().enumerate() {
+            assert!(all()[index + 1..].iter().all(|other| other.name != constant.name));
+        }
+    }
+
+    #[test]
+    fn test_names_are_not_units() {
+        for constant in all() {
+            assert!(!units::is_unit(constant.name), "{} is also a unit", constant.name);
+            assert!(units::si_prefix(constant.name).is_none(), "{} is also an SI prefix", constant.name);
+        }
+    }
+
+    #[test]
+    fn test_derived_relations() {
+        let hbar = lookup("h_planck").unwrap().value / TAU;
+        assert!((hbar - lookup("hbar").unwrap().value).abs() / hbar < 1e-9);
+        let gas_constant = lookup("k_B").unwrap().value * lookup("N_A").unwrap().value;
+        assert!((gas_constant - lookup("R").unwrap().value).abs() < 1e-8);
+    }
+}
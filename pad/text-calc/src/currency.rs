@@ -0,0 +1,293 @@
+//! Currency units from a local snapshot of exchange rates.
+//!
+//! Rates never come from the network. They are read from a JSON or CSV file, usually the
+//! snapshot that `calc rates import` stores in the data directory, and every conversion
+//! between currencies reports the date of the rates it used.
+//!
+//! A JSON snapshot gives the rates per unit of a base currency, all of one date:
+//!
+//! ```json
+//! { "base": "EUR", "date": "2024-06-28", "rates": { "USD": 1.0705, "CAD": 1.4670 } }
+//! ```
+//!
+//! A CSV snapshot has a `currency,rate,date` header and dates each rate separately; the rates
+//! are per unit of a common base, which is listed with a rate of 1 if it is a currency too.
+
+use crate::units::{self, CustomUnit};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// ISO 4217 codes of widely traded currencies, recognized without a snapshot so that using
+/// one explains that no rates are loaded.
+static COMMON_CODES: &[&str] = &[
+    "AUD", "BGN", "BRL", "CAD", "CHF", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD", "HUF", "IDR", "ILS", "INR", "ISK",
+    "JPY", "KRW", "MXN", "MYR", "NOK", "NZD", "PHP", "PLN", "RON", "SEK", "SGD", "THB", "TRY", "USD", "ZAR",
+];
+
+/// Checks whether a name is the code of a widely traded currency, e.g., `USD`.
+///
+/// # Arguments
+///
+/// * `name` - The name to check.
+pub fn is_common_code(name: &str) -> bool {
+    COMMON_CODES.contains(&name)
+}
+
+/// The rate of one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    /// How many units of the currency one unit of the base currency buys.
+    pub per_base: f64,
+    /// The date the rate was observed, as written in the snapshot, e.g., `2024-06-28`.
+    pub date: String,
+}
+
+/// A snapshot of exchange rates.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rates {
+    currencies: BTreeMap<String, Rate>,
+}
+
+/// The JSON form of a snapshot, which is also how `calc rates import` stores it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    rates: BTreeMap<String, f64>,
+    /// Dates of the rates that differ from `date`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    dates: BTreeMap<String, String>,
+}
+
+impl Rates {
+    /// Reads a snapshot from a file, as CSV if its name ends in `.csv` and as JSON otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Rates)` - The rates.
+    /// * `Err(String)` - An error message if the file cannot be read or is not a valid snapshot.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read exchange rates from '{}': {}", path.display(), e))?;
+        let is_csv = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        let rates = if is_csv { Self::parse_csv(&text) } else { Self::parse_json(&text) };
+        rates.map_err(|e| format!("Invalid exchange rates in '{}': {}", path.display(), e))
+    }
+
+    /// Parses a JSON snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The JSON text.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Rates)` - The rates, including the base currency at a rate of 1.
+    /// * `Err(String)` - An error message if the JSON is malformed, or a rate is invalid or undated.
+    pub fn parse_json(text: &str) -> Result<Self, String> {
+        let snapshot: Snapshot = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut rates = Self::default();
+        let base = snapshot.base.iter().map(|base| (base, 1.0));
+        for (code, per_base) in base.chain(snapshot.rates.iter().map(|(code, rate)| (code, *rate))) {
+            let date = snapshot
+                .dates
+                .get(code)
+                .or(snapshot.date.as_ref())
+                .ok_or_else(|| format!("The rate of {} has no date", code))?;
+            rates.insert(code, per_base, date)?;
+        }
+        Ok(rates)
+    }
+
+    /// Parses a CSV snapshot with a `currency,rate,date` header.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The CSV text.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Rates)` - The rates.
+    /// * `Err(String)` - An error message if a column is missing, or a row is malformed or invalid.
+    pub fn parse_csv(text: &str) -> Result<Self, String> {
+        let mut reader = csv::Reader::from_reader(text.as_bytes());
+        let headers = reader.headers().map_err(|e| format!("Error reading CSV header: {}", e))?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("The CSV header has no '{}' column", name))
+        };
+        let (code, rate, date) = (column("currency")?, column("rate")?, column("date")?);
+        let mut rates = Self::default();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| format!("Error reading CSV row {}: {}", index + 1, e))?;
+            let cell = |column: usize| record.get(column).unwrap_or_default().trim();
+            let per_base = cell(rate)
+                .parse()
+                .map_err(|_| format!("Row {}: '{}' is not a rate", index + 1, cell(rate)))?;
+            rates.insert(cell(code), per_base, cell(date)).map_err(|e| format!("Row {}: {}", index + 1, e))?;
+        }
+        Ok(rates)
+    }
+
+    /// Adds a rate after checking it.
+    fn insert(&mut self, code: &str, per_base: f64, date: &str) -> Result<(), String> {
+        if code.len() != 3 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(format!("'{}' is not a currency code such as USD", code));
+        }
+        if units::lookup(code).is_some() {
+            return Err(format!("'{}' is already a unit", code));
+        }
+        if !per_base.is_finite() || per_base <= 0.0 {
+            return Err(format!("The rate of {} must be a positive number", code));
+        }
+        if date.is_empty() {
+            return Err(format!("The rate of {} has no date", code));
+        }
+        self.currencies.insert(code.to_string(), Rate { per_base, date: date.to_string() });
+        Ok(())
+    }
+
+    /// Writes the snapshot as JSON, in the form [`Rates::parse_json`] reads.
+    pub fn to_json(&self) -> String {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for rate in self.currencies.values() {
+            *counts.entry(&rate.date).or_default() += 1;
+        }
+        // The most common date is written once; the others per currency.
+        let date = counts.iter().max_by_key(|(_, count)| **count).map(|(date, _)| date.to_string());
+        let snapshot = Snapshot {
+            base: None,
+            rates: self.currencies.iter().map(|(code, rate)| (code.clone(), rate.per_base)).collect(),
+            dates: self
+                .currencies
+                .iter()
+                .filter(|(_, rate)| Some(&rate.date) != date.as_ref())
+                .map(|(code, rate)| (code.clone(), rate.date.clone()))
+                .collect(),
+            date,
+        };
+        serde_json::to_string_pretty(&snapshot).unwrap_or_default()
+    }
+
+    /// Returns the currencies and their rates, by currency code.
+    pub fn currencies(&self) -> impl Iterator<Item = (&str, &Rate)> {
+        self.currencies.iter().map(|(code, rate)| (code.as_str(), rate))
+    }
+
+    /// Returns the rate of a currency, if the snapshot has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The currency code, e.g., `USD`.
+    pub fn rate(&self, code: &str) -> Option<&Rate> {
+        self.currencies.get(code)
+    }
+
+    /// Returns the currencies as units, sized in units of the base currency.
+    pub fn units(&self) -> Vec<CustomUnit> {
+        self.currencies
+            .iter()
+            .map(|(code, rate)| CustomUnit { symbol: code.clone(), factor: 1.0 / rate.per_base, dimension: units::CURRENCY })
+            .collect()
+    }
+
+    /// Describes the rates a conversion between two units used, or `None` if it involved no
+    /// change of currency.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The unit converted from, e.g., `USD` or `USD/h`.
+    /// * `to` - The unit converted to.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(String)` - A note such as `USD to CAD at the exchange rates of 2024-06-28`.
+    pub fn describe(&self, from: &str, to: &str) -> Option<String> {
+        let codes = |unit: &str| -> Vec<String> {
+            unit.split(|c: char| !c.is_ascii_alphabetic())
+                .filter(|symbol| self.currencies.contains_key(*symbol))
+                .map(str::to_string)
+                .collect()
+        };
+        let (from_codes, to_codes) = (codes(from), codes(to));
+        if from_codes.is_empty() || from_codes == to_codes {
+            return None;
+        }
+        let mut dates: Vec<(&str, &str)> = from_codes
+            .iter()
+            .chain(&to_codes)
+            .map(|code| (code.as_str(), self.currencies[code].date.as_str()))
+            .collect();
+        dates.sort_by_key(|(_, date)| *date);
+        dates.dedup_by_key(|(_, date)| *date);
+        let when = match dates.as_slice() {
+            [(_, date)] => date.to_string(),
+            _ => dates.iter().map(|(code, date)| format!("{} ({})", date, code)).collect::<Vec<_>>().join(" and "),
+        };
+        Some(format!("{} to {} at the exchange rates of {}", from, to, when))
+    }
+}
+
+/// Returns where `calc rates import` stores the snapshot and the calculator looks for it:
+/// `textcalc/rates.json` in `$XDG_DATA_HOME`, or in `~/.local/share` if that is not set.
+pub fn default_path() -> Option<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data.join("textcalc").join("rates.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"{ "base": "EUR", "date": "2024-06-28", "rates": { "USD": 1.0705, "CAD": 1.467 } }"#;
+
+    #[test]
+    fn test_parse_json() {
+        let rates = Rates::parse_json(JSON).unwrap();
+        assert_eq!(rates.currencies().count(), 3);
+        assert_eq!(rates.rate("EUR"), Some(&Rate { per_base: 1.0, date: "2024-06-28".to_string() }));
+        assert_eq!(rates.rate("USD").unwrap().per_base, 1.0705);
+        assert!(Rates::parse_json(r#"{ "rates": { "USD": 1.0 } }"#).unwrap_err().contains("no date"));
+        assert!(Rates::parse_json(r#"{ "date": "2024-06-28", "rates": { "usd": 1.0 } }"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let rates = Rates::parse_csv("currency,rate,date\nEUR,1,2024-06-28\nJPY, 172.1 ,2024-06-27\n").unwrap();
+        assert_eq!(rates.rate("JPY"), Some(&Rate { per_base: 172.1, date: "2024-06-27".to_string() }));
+        assert_eq!(Rates::parse_csv("currency,rate\nEUR,1\n").unwrap_err(), "The CSV header has no 'date' column");
+        assert_eq!(Rates::parse_csv("currency,rate,date\nEUR,-1,2024-06-28\n").unwrap_err(), "Row 1: The rate of EUR must be a positive number");
+    }
+
+    #[test]
+    fn test_to_json_round_trip() {
+        let rates = Rates::parse_csv("currency,rate,date\nEUR,1,2024-06-28\nUSD,1.07,2024-06-28\nJPY,172.1,2024-06-27\n").unwrap();
+        let json = rates.to_json();
+        assert!(json.contains("\"JPY\": \"2024-06-27\""), "{}", json);
+        assert_eq!(Rates::parse_json(&json).unwrap(), rates);
+    }
+
+    #[test]
+    fn test_units_and_describe() {
+        let rates = Rates::parse_json(JSON).unwrap();
+        units::with_units(&rates.units(), || {
+            assert!((units::convert(100.0, "EUR", "USD").unwrap() - 107.05).abs() < 1e-9);
+            assert!(units::is_currency("CAD") && !units::is_currency("CAD/h"));
+        });
+        assert_eq!(rates.describe("USD", "CAD").unwrap(), "USD to CAD at the exchange rates of 2024-06-28");
+        assert_eq!(rates.describe("USD/h", "USD/min"), None);
+        assert_eq!(rates.describe("m", "ft"), None);
+    }
+}
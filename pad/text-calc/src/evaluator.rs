@@ -3,16 +3,558 @@
 //! This module evaluates the AST and computes the result, handling units and conversions.
 
 use crate::ast::{Expr, UnaryOp, BinaryOp};
+use crate::calculator::{NativeFunction, UserFunction};
+use crate::compile::Program;
+use crate::currency::{self, Rates};
+use crate::random::Rng;
+use crate::trace::Tracer;
+use crate::{calculus, constants, finance, probability, solver, symbolic, units};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Relative tolerance for `==` and the other comparisons, so that `0.1 + 0.2 == 0.3`.
+const EQUALITY_TOLERANCE: f64 = 1e-12;
 
 /// Represents a value with an optional unit.
 #[derive(Debug, Clone)]
 pub struct ValueWithUnit {
-    /// The numeric value.
+    /// The numeric value; `1` or `0` for a boolean.
     pub value: f64,
     /// The unit of the value, if any.
     pub unit: Option<String>,
+    /// Whether the value is a number or a boolean.
+    pub kind: ValueKind,
+    /// The standard uncertainty of a measured value, in the value's unit, if any.
+    pub uncertainty: Option<f64>,
+}
+
+/// The kind of a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// A number, possibly with a unit.
+    Number,
+    /// A boolean, produced by a comparison, a logical operator or `true` / `false`.
+    Boolean,
+}
+
+impl ValueWithUnit {
+    /// Creates a number with an optional unit.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The numeric value.
+    /// * `unit` - The unit of the value, if any.
+    pub fn new(value: f64, unit: Option<String>) -> Self {
+        Self {
+            value,
+            unit,
+            kind: ValueKind::Number,
+            uncertainty: None,
+        }
+    }
+
+    /// Creates a measured number with its standard uncertainty, e.g., `9.81 ± 0.02 m/s^2`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The central value.
+    /// * `uncertainty` - The standard uncertainty, in the same unit as the value.
+    /// * `unit` - The unit of the value, if any.
+    pub fn uncertain(value: f64, uncertainty: f64, unit: Option<String>) -> Self {
+        Self {
+            uncertainty: Some(uncertainty),
+            ..Self::new(value, unit)
+        }
+    }
+
+    /// Creates a boolean.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The truth value.
+    pub fn boolean(value: bool) -> Self {
+        Self {
+            value: if value { 1.0 } else { 0.0 },
+            unit: None,
+            kind: ValueKind::Boolean,
+            uncertainty: None,
+        }
+    }
+
+    /// Checks whether the value is a boolean.
+    pub fn is_boolean(&self) -> bool {
+        self.kind == ValueKind::Boolean
+    }
+
+    /// Returns the truth value of a boolean, or an error naming `operation` for a number.
+    fn truth(&self, operation: &str) -> Result<bool, String> {
+        match self.kind {
+            ValueKind::Boolean => Ok(self.value != 0.0),
+            ValueKind::Number => Err(format!("{} expects a boolean but got a number", operation)),
+        }
+    }
+
+    /// Returns the value if it is a number, or an error naming `operation` for a boolean.
+    pub(crate) fn number(self, operation: &str) -> Result<Self, String> {
+        match self.kind {
+            ValueKind::Number => Ok(self),
+            ValueKind::Boolean => Err(format!("{} expects a number but got a boolean", operation)),
+        }
+    }
+}
+
+/// Prints the value followed by its unit, or `true` / `false` for a boolean. A measured
+/// value prints as `9.81 ± 0.02 m/s^2`, rounded to the precision of its uncertainty.
+impl fmt::Display for ValueWithUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(None, Notation::Auto))
+    }
+}
+
+impl ValueWithUnit {
+    /// Formats the value as it prints, with the number written in the given notation.
+    ///
+    /// # Arguments
+    ///
+    /// * `precision` - Significant digits, or decimal places in fixed notation; `None` writes
+    ///   as many digits as it takes to read the number back. A measured value is always
+    ///   rounded to the precision of its uncertainty instead.
+    /// * `notation` - How the number is written.
+    pub fn format(&self, precision: Option<usize>, notation: Notation) -> String {
+        let number = match self.uncertainty {
+            Some(uncertainty) => format_uncertain(self.value, uncertainty),
+            None => format_number(self.value, precision, notation),
+        };
+        match (self.kind, &self.unit) {
+            (ValueKind::Boolean, _) => (self.value != 0.0).to_string(),
+            (ValueKind::Number, Some(unit)) => format!("{} {}", number, unit),
+            (ValueKind::Number, None) => number,
+        }
+    }
+}
+
+/// How the numbers of results are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Notation {
+    /// Decimals, switching to scientific notation for very large or small magnitudes.
+    #[default]
+    Auto,
+    /// Decimals only, as in `1234.5`.
+    Fixed,
+    /// One digit before the point and an exponent, as in `1.2345e3`.
+    Scientific,
+    /// An exponent that is a multiple of three, as in `12.5e3` or `470e-9`.
+    Engineering,
+}
+
+impl FromStr for Notation {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "auto" => Ok(Notation::Auto),
+            "fixed" => Ok(Notation::Fixed),
+            "scientific" | "sci" => Ok(Notation::Scientific),
+            "engineering" | "eng" => Ok(Notation::Engineering),
+            _ => Err(format!("Unknown notation: {} (expected auto, fixed, scientific or engineering)", name)),
+        }
+    }
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Notation::Auto => "auto",
+            Notation::Fixed => "fixed",
+            Notation::Scientific => "scientific",
+            Notation::Engineering => "engineering",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Formats a number in a notation, rounded to `precision` significant digits or, in fixed
+/// notation, decimal places.
+fn format_number(value: f64, precision: Option<usize>, notation: Notation) -> String {
+    if !value.is_finite() {
+        return format!("{}", value);
+    }
+    match (notation, precision) {
+        (Notation::Auto, None) => format_value(value),
+        (Notation::Auto, Some(digits)) => format_value(round_significant(value, digits)),
+        (Notation::Fixed, None) => format!("{}", value),
+        (Notation::Fixed, Some(decimals)) => format!("{:.*}", decimals, value),
+        (Notation::Scientific, None) => format!("{:e}", value),
+        (Notation::Scientific, Some(digits)) => format!("{:.*e}", digits.max(1) - 1, value),
+        (Notation::Engineering, _) => {
+            // Rounding first lets 999.96 with 4 digits move up to the next exponent.
+            let value = round_significant(value, precision.unwrap_or(15));
+            let exponent = if value == 0.0 { 0 } else { (value.abs().log10().floor() as i32).div_euclid(3) * 3 };
+            // 15 digits remove the error of the division.
+            let mantissa = round_significant(value / 10f64.powi(exponent), precision.unwrap_or(15));
+            format!("{}e{}", mantissa, exponent)
+        }
+    }
+}
+
+/// Rounds a number to a count of significant digits, at least one.
+pub(crate) fn round_significant(value: f64, digits: usize) -> f64 {
+    format!("{:.*e}", digits.clamp(1, 17) - 1, value).parse().unwrap_or(value)
+}
+
+/// Formats a number, switching to scientific notation for very large or small magnitudes
+/// such as `6.62607015e-34`.
+fn format_value(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude != 0.0 && magnitude.is_finite() && !(1e-6..1e16).contains(&magnitude) {
+        format!("{:e}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// Formats a value with its uncertainty, e.g., `9.81 ± 0.02`.
+///
+/// The uncertainty keeps two significant figures when its leading digit is 1 and one
+/// otherwise, and the value is rounded to the same decimal place. Very large or small
+/// magnitudes share an exponent, as in `(6.674 ± 0.015)e-11`.
+fn format_uncertain(value: f64, uncertainty: f64) -> String {
+    if uncertainty == 0.0 || !uncertainty.is_finite() || !value.is_finite() {
+        return format!("{} ± {}", format_value(value), format_value(uncertainty));
+    }
+    let leading = uncertainty.log10().floor() as i32;
+    // 1.96 rounds to 2, which keeps one figure; the margin also absorbs error in the division.
+    let figures = if uncertainty / 10f64.powi(leading) < 1.95 { 2 } else { 1 };
+    let last_place = leading - figures + 1;
+    let magnitude = value.abs().max(uncertainty);
+    let exponent = if (1e-6..1e16).contains(&magnitude) { 0 } else { magnitude.log10().floor() as i32 };
+    let decimals = (exponent - last_place).max(0) as usize;
+    let (scale, step) = (10f64.powi(exponent), 10f64.powi(last_place - exponent));
+    let round = |x: f64| (x / scale / step).round() * step;
+    let (value, uncertainty) = (round(value), round(uncertainty));
+    match exponent {
+        0 => format!("{:.*} ± {:.*}", decimals, value, decimals, uncertainty),
+        _ => format!("({:.*} ± {:.*})e{}", decimals, value, decimals, uncertainty, exponent),
+    }
+}
+
+/// The unit of angles written as plain numbers, as in `sin(30)` or the result of `atan(1)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AngleMode {
+    /// Plain numbers are radians.
+    #[default]
+    Radians,
+    /// Plain numbers are degrees.
+    Degrees,
+}
+
+impl AngleMode {
+    /// Returns the unit plain numbers are taken to be in, `rad` or `deg`.
+    pub fn unit(self) -> &'static str {
+        match self {
+            AngleMode::Radians => "rad",
+            AngleMode::Degrees => "deg",
+        }
+    }
+}
+
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.unit())
+    }
+}
+
+impl FromStr for AngleMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "rad" | "radians" => Ok(AngleMode::Radians),
+            "deg" | "degrees" => Ok(AngleMode::Degrees),
+            _ => Err(format!("Unknown angle mode: {} (expected rad or deg)", name)),
+        }
+    }
+}
+
+/// Variable bindings used while evaluating an expression.
+///
+/// Binding a variable and evaluating the same AST repeatedly is how the solver
+/// samples an equation over its unknown. The context also holds the native functions
+/// registered by the host, collects warnings, such as an integral that did not
+/// converge, for the caller to report, and records a trace of the evaluation when asked.
+///
+/// The generator behind `rand()` and dice rolls is shared by a context and its clones, so
+/// evaluating in a copy advances the same sequence.
+#[derive(Clone, Default)]
+pub struct Context {
+    variables: HashMap<String, ValueWithUnit>,
+    functions: HashMap<String, Rc<dyn NativeFunction>>,
+    user_functions: HashMap<String, Rc<dyn UserFunction>>,
+    angle_mode: AngleMode,
+    rates: Option<Rc<Rates>>,
+    rng: Rc<RefCell<Option<Rng>>>,
+    warnings: RefCell<Vec<String>>,
+    tracer: Tracer,
+    deadline: Option<Instant>,
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("variables", &self.variables)
+            .field("functions", &self.functions.keys().collect::<Vec<_>>())
+            .field("user_functions", &self.user_functions.keys().collect::<Vec<_>>())
+            .field("angle_mode", &self.angle_mode)
+            .field("currencies", &self.rates.as_ref().map_or(0, |rates| rates.currencies().count()))
+            .field("warnings", &self.warnings)
+            .finish()
+    }
+}
+
+impl Context {
+    /// Creates an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a variable, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    /// * `value` - The value to bind.
+    pub fn set_variable(&mut self, name: &str, value: ValueWithUnit) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Returns the value bound to a variable, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    pub fn variable(&self, name: &str) -> Option<&ValueWithUnit> {
+        self.variables.get(name)
+    }
+
+    /// Unbinds a variable, returning its value if it was bound.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The variable name.
+    pub fn remove_variable(&mut self, name: &str) -> Option<ValueWithUnit> {
+        self.variables.remove(name)
+    }
+
+    /// Returns the bound variables and their values, in no particular order.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &ValueWithUnit)> {
+        self.variables.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Registers a native function, replacing any previous one of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name expressions call the function by.
+    /// * `function` - The implementation.
+    pub fn register_function(&mut self, name: &str, function: Rc<dyn NativeFunction>) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    /// Returns the native function registered under a name, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The function name.
+    pub fn function(&self, name: &str) -> Option<&dyn NativeFunction> {
+        self.functions.get(name).map(|function| function.as_ref())
+    }
+
+    /// Returns the native function registered under a name, shared, to keep beyond the context.
+    pub(crate) fn shared_function(&self, name: &str) -> Option<Rc<dyn NativeFunction>> {
+        self.functions.get(name).cloned()
+    }
+
+    /// Defines a function written in the calculator's own language, such as a script's
+    /// `fn`, replacing any previous one of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name expressions call the function by.
+    /// * `function` - The implementation.
+    pub fn define_function(&mut self, name: &str, function: Rc<dyn UserFunction>) {
+        self.user_functions.insert(name.to_string(), function);
+    }
+
+    /// Returns the user function defined under a name, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The function name.
+    pub fn user_function(&self, name: &str) -> Option<&dyn UserFunction> {
+        self.user_functions.get(name).map(|function| function.as_ref())
+    }
+
+    /// Sets the unit of angles written as plain numbers.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - Radians or degrees.
+    pub fn set_angle_mode(&mut self, mode: AngleMode) {
+        self.angle_mode = mode;
+    }
+
+    /// Returns the unit of angles written as plain numbers.
+    pub fn angle_mode(&self) -> AngleMode {
+        self.angle_mode
+    }
+
+    /// Sets the exchange rates that currency conversions report the date of. The currency
+    /// units themselves must be added with [`units::with_units`].
+    ///
+    /// # Arguments
+    ///
+    /// * `rates` - The exchange rates, or `None` for none.
+    pub fn set_rates(&mut self, rates: Option<Rc<Rates>>) {
+        self.rates = rates;
+    }
+
+    /// Returns the exchange rates, if any are set.
+    pub fn rates(&self) -> Option<&Rates> {
+        self.rates.as_deref()
+    }
+
+    /// Seeds the generator of random numbers, so that `rand()`, `randint` and dice rolls
+    /// repeat from run to run. Without a seed, the generator is seeded differently each run.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        *self.rng.borrow_mut() = Some(Rng::new(seed));
+    }
+
+    /// Draws from the generator of random numbers, seeding it on first use if no seed was set.
+    ///
+    /// # Arguments
+    ///
+    /// * `draw` - Takes what it needs from the generator.
+    pub fn with_rng<T>(&self, draw: impl FnOnce(&mut Rng) -> T) -> T {
+        draw(self.rng.borrow_mut().get_or_insert_with(Rng::from_entropy))
+    }
+
+    /// Records a warning produced during evaluation; a warning already recorded is not repeated.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The warning message.
+    pub fn warn(&self, message: String) {
+        let mut warnings = self.warnings.borrow_mut();
+        if !warnings.contains(&message) {
+            warnings.push(message);
+        }
+    }
+
+    /// Records which exchange rates a conversion between two units used, if it changed currency.
+    pub(crate) fn note_exchange(&self, from: &str, to: &str) {
+        if let Some(note) = self.rates().and_then(|rates| rates.describe(from, to)) {
+            self.warn(format!("Converted {}", note));
+        }
+    }
+
+    /// Removes and returns the warnings recorded so far.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.take()
+    }
+
+    /// Returns the tracer, which records the steps of evaluations in this context once started.
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    /// Sets the time after which evaluations in this context stop with an error. Sums,
+    /// integrals, the solver and script loops and calls check it as they go.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - The time to stop at, or `None` for no limit.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Checks that the deadline, if any, has not passed.
+    ///
+    /// # Returns
+    ///
+    /// * `Err(String)` - "Time limit exceeded" once the deadline has passed.
+    pub fn check_deadline(&self) -> Result<(), String> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err("Time limit exceeded".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An expression evaluated as a function of one of its variables (e.g., `sin(x) / x` of `x`).
+///
+/// The other variables keep the values bound in the context the function was created from.
+/// The expression is compiled once if it can be, see [`Program`], which makes sampling it
+/// many times, as plots and integrals do, several times faster.
+pub struct BoundFunction<'a> {
+    /// The expression to evaluate.
+    expr: &'a Expr,
+    /// The name of the variable the function is of.
+    var: String,
+    /// The unit the variable's values are given in, if any.
+    unit: Option<String>,
+    /// The surrounding bindings, plus the variable itself.
+    context: Context,
+    /// The expression compiled for the variable, unless it evaluates only by walking the tree.
+    program: Option<Program>,
+}
+
+impl<'a> BoundFunction<'a> {
+    /// Creates a function of `var` from `expr`.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The expression to evaluate.
+    /// * `var` - The name of the variable.
+    /// * `unit` - The unit of the variable's values, if any.
+    /// * `context` - The bindings of the other variables.
+    pub fn new(expr: &'a Expr, var: &str, unit: Option<String>, context: &Context) -> Self {
+        let program = Program::compile(expr, &[(var, unit.as_deref())], context).ok();
+        Self {
+            expr,
+            var: var.to_string(),
+            unit,
+            context: context.clone(),
+            program,
+        }
+    }
+
+    /// Evaluates the expression with the variable set to `x`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - The value of the variable, in the function's unit.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ValueWithUnit)` - The value of the expression.
+    /// * `Err(String)` - An error message if evaluation fails.
+    pub fn call(&mut self, x: f64) -> Result<ValueWithUnit, String> {
+        self.context.check_deadline()?;
+        if let Some(program) = &mut self.program {
+            return program.call(&[x]);
+        }
+        self.context.set_variable(&self.var, ValueWithUnit::new(x, self.unit.clone()));
+        evaluate_with(self.expr, &self.context)
+    }
 }
 
 /// Evaluates an expression AST and computes the result.
@@ -26,111 +568,189 @@ pub struct ValueWithUnit {
 /// * `Ok(ValueWithUnit)` - The computed value with unit.
 /// * `Err(String)` - An error message if evaluation fails.
 pub fn evaluate(expr: &Expr) -> Result<ValueWithUnit, String> {
+    evaluate_with(expr, &Context::default())
+}
+
+/// Evaluates an expression AST with the variables bound in `context`.
+///
+/// # Arguments
+///
+/// * `expr` - The expression AST to evaluate.
+/// * `context` - The variable bindings.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The computed value with unit.
+/// * `Err(String)` - An error message if evaluation fails.
+pub fn evaluate_with(expr: &Expr, context: &Context) -> Result<ValueWithUnit, String> {
+    if !context.tracer.is_active() {
+        return evaluate_node(expr, context);
+    }
+    context.tracer.enter();
+    let result = evaluate_node(expr, context);
+    context.tracer.leave(expr, &result);
+    result
+}
+
+/// Evaluates one node of an expression AST; its operands go through [`evaluate_with`], so
+/// they are traced as steps of their own.
+fn evaluate_node(expr: &Expr, context: &Context) -> Result<ValueWithUnit, String> {
     match expr {
-        Expr::Number(n) => Ok(ValueWithUnit {
-            value: *n,
-            unit: None,
-        }),
-        Expr::NumberWithUnit { value, unit } => Ok(ValueWithUnit {
-            value: *value,
-            unit: unit.clone(),
-        }),
+        Expr::Number(n) => Ok(ValueWithUnit::new(*n, None)),
+        // A name that is not a unit written after a number (e.g., `3x` or `2pi`) multiplies it.
+        Expr::NumberWithUnit { value, unit: Some(name) } if !units::is_unit(name) => {
+            // An SI prefix such as `4.7k` scales the number unless a variable or constant has
+            // that name, so a name means the same after a number as on its own.
+            let named = context.variable(name).is_some() || constants::lookup(name).is_some();
+            if let (false, Some(scale)) = (named, units::si_prefix(name)) {
+                return Ok(ValueWithUnit::new(value * scale, None));
+            }
+            let factor = evaluate_with(&Expr::Symbol(name.clone()), context)?.number("Implicit multiplication")?;
+            Ok(ValueWithUnit {
+                value: value * factor.value,
+                uncertainty: factor.uncertainty.map(|uncertainty| uncertainty * value.abs()),
+                ..factor
+            })
+        }
+        Expr::NumberWithUnit { value, unit } => Ok(ValueWithUnit::new(*value, unit.clone())),
+        Expr::Symbol(name) => match (context.variable(name), constants::lookup(name)) {
+            (Some(value), _) => Ok(value.clone()),
+            _ if name == "true" || name == "false" => Ok(ValueWithUnit::boolean(name == "true")),
+            // No constant is named like a unit, so `h` is an hour and `h_planck` Planck's constant.
+            (None, Some(constant)) => Ok(ValueWithUnit::new(constant.value, constant.unit.map(String::from))),
+            // A bare unit stands for one of that unit, so `9.81 m/s^2` divides by `s^2`.
+            _ if units::is_unit(name) => Ok(ValueWithUnit::new(1.0, Some(name.clone()))),
+            _ if currency::is_common_code(name) => Err(match context.rates() {
+                None => format!(
+                    "{} is a currency, but no exchange-rate snapshot is loaded; import one with 'calc rates import FILE' or pass --rates FILE",
+                    name
+                ),
+                Some(_) => format!("{} is a currency that the exchange-rate snapshot does not have", name),
+            }),
+            _ => Err(format!("Unknown variable: {}", name)),
+        },
+        Expr::UnaryOp { op: UnaryOp::Not, expr } => {
+            Ok(ValueWithUnit::boolean(!evaluate_with(expr, context)?.truth("not")?))
+        }
+        Expr::UnaryOp { op: UnaryOp::Factorial, expr } => {
+            let result = evaluate_with(expr, context)?.number("Factorial")?;
+            if result.unit.is_some() {
+                return Err("Factorial requires a unitless value".to_string());
+            }
+            let apply = |args: &[ValueWithUnit]| Ok(ValueWithUnit::new(factorial(args[0].value)?, None));
+            propagate(apply(std::slice::from_ref(&result))?, &[result], apply)
+        }
+        Expr::UnaryOp { op: UnaryOp::Percent, expr } => {
+            let result = evaluate_with(expr, context)?.number("'%'")?;
+            Ok(ValueWithUnit {
+                value: result.value / 100.0,
+                uncertainty: result.uncertainty.map(|uncertainty| uncertainty / 100.0),
+                ..result
+            })
+        }
         Expr::UnaryOp { op, expr } => {
-            let result = evaluate(expr)?;
+            let result = evaluate_with(expr, context)?.number(&format!("Unary {}", op))?;
             let value = match op {
-                UnaryOp::Plus => result.value,
                 UnaryOp::Minus => -result.value,
+                _ => result.value,
             };
-            Ok(ValueWithUnit {
-                value,
-                unit: result.unit,
-            })
+            Ok(ValueWithUnit { value, ..result })
         }
+        // `and` and `or` only evaluate the right-hand side when it decides the result.
+        Expr::BinaryOp { left, op: op @ (BinaryOp::And | BinaryOp::Or), right } => {
+            let name = op.to_string();
+            let left = evaluate_with(left, context)?.truth(name.trim())?;
+            if left == (*op == BinaryOp::Or) {
+                return Ok(ValueWithUnit::boolean(left));
+            }
+            Ok(ValueWithUnit::boolean(evaluate_with(right, context)?.truth(name.trim())?))
+        }
+        Expr::BinaryOp { left, op, right } if op.is_comparison() => {
+            let (left, right) = (evaluate_with(left, context)?, evaluate_with(right, context)?);
+            let units = (right.unit.clone(), left.unit.clone());
+            let result = compare(*op, left, right)?;
+            if let (Some(from), Some(to)) = units {
+                context.note_exchange(&from, &to);
+            }
+            Ok(result)
+        }
+        Expr::BinaryOp { left, op: BinaryOp::PlusMinus, right } => plus_minus(
+            evaluate_with(left, context)?.number("'±'")?,
+            evaluate_with(right, context)?.number("'±'")?,
+        ),
         Expr::BinaryOp { left, op, right } => {
-            let left_result = evaluate(left)?;
-            let right_result = evaluate(right)?;
-
-            // For simplicity, we'll assume units must match for addition and subtraction
-            match op {
-                BinaryOp::Add | BinaryOp::Subtract => {
-                    if left_result.unit != right_result.unit {
-                        return Err("Unit mismatch in addition or subtraction".to_string());
-                    }
-                    let value = match op {
-                        BinaryOp::Add => left_result.value + right_result.value,
-                        BinaryOp::Subtract => left_result.value - right_result.value,
-                        _ => unreachable!(),
-                    };
-                    Ok(ValueWithUnit {
-                        value,
-                        unit: left_result.unit.clone(),
-                    })
-                }
-                BinaryOp::Multiply | BinaryOp::Divide => {
-                    // For simplicity, we'll not handle unit multiplication/division in detail
-                    let value = match op {
-                        BinaryOp::Multiply => left_result.value * right_result.value,
-                        BinaryOp::Divide => {
-                            if right_result.value == 0.0 {
-                                return Err("Division by zero error".to_string());
-                            }
-                            left_result.value / right_result.value
-                        }
-                        _ => unreachable!(),
-                    };
-                    // Units handling can be more complex; we'll ignore units in multiplication/division
-                    Ok(ValueWithUnit {
-                        value,
-                        unit: None,
-                    })
-                }
-                BinaryOp::Power => {
-                    let value = left_result.value.powf(right_result.value);
-                    Ok(ValueWithUnit {
-                        value,
-                        unit: left_result.unit.clone(),
-                    })
-                }
+            let operation = format!("'{}'", op.to_string().trim());
+            let left_result = evaluate_with(left, context)?.number(&operation)?;
+            let right_result = evaluate_with(right, context)?.number(&operation)?;
+            let apply = |args: &[ValueWithUnit]| arithmetic(*op, &args[0], &args[1]);
+            propagate(apply(&[left_result.clone(), right_result.clone()])?, &[left_result, right_result], apply)
+        }
+        // Only the chosen branch is evaluated, so `x > 0 ? ln(x) : 0` never takes `ln(0)`.
+        Expr::Function { name, args } if name == "if" => match args.as_slice() {
+            [condition, then, otherwise] => match evaluate_with(condition, context)?.truth("if")? {
+                true => evaluate_with(then, context),
+                false => evaluate_with(otherwise, context),
+            },
+            _ => Err("if expects a condition and two values, e.g., if(x > 0, x, -x)".to_string()),
+        },
+        Expr::Function { name, .. } if name == "diff" => evaluate_with(&symbolic::expand_derivatives(expr)?, context),
+        Expr::Function { name, args } if name == "solve" => {
+            let roots = solver::solve_call(args, context)?;
+            match roots.as_slice() {
+                [root] => Ok(root.clone()),
+                [] => Err("solve found no roots".to_string()),
+                _ => Err(format!(
+                    "solve found {} roots; give a range with solve(equation, variable, low, high)",
+                    roots.len()
+                )),
             }
         }
-        Expr::Function { name, arg } => {
-            let arg_result = evaluate(arg)?;
-            let value = match name.as_str() {
-                "sin" => arg_result.value.sin(),
-                "cos" => arg_result.value.cos(),
-                "tan" => arg_result.value.tan(),
-                "asin" => arg_result.value.asin(),
-                "acos" => arg_result.value.acos(),
-                "atan" => arg_result.value.atan(),
-                "sqrt" => arg_result.value.sqrt(),
-                "log" => arg_result.value.log10(),
-                "ln" => arg_result.value.ln(),
-                "exp" => arg_result.value.exp(),
-                "abs" => arg_result.value.abs(),
-                "ceil" => arg_result.value.ceil(),
-                "floor" => arg_result.value.floor(),
-                "round" => arg_result.value.round(),
-                "trunc" => arg_result.value.trunc(),
-                "fract" => arg_result.value.fract(),
-                "radians" => arg_result.value.to_radians(),
-                "degrees" => arg_result.value.to_degrees(),
-                "pi" => PI,
-                "e" => std::f64::consts::E,
-                _ => return Err(format!("Unknown function: {}", name)),
+        Expr::Function { name, args } if calculus::is_calculus_function(name) => calculus::call(name, args, context),
+        // Random draws are not repeated to propagate uncertainties, and a user function
+        // of the same name takes precedence.
+        Expr::Function { name, args }
+            if probability::is_random_function(name) && context.user_function(name).is_none() && context.function(name).is_none() =>
+        {
+            probability::call_random(name, args, context)
+        }
+        // A function the user defined under the same name takes precedence.
+        Expr::Function { name, args }
+            if finance::is_finance_function(name) && context.user_function(name).is_none() && context.function(name).is_none() =>
+        {
+            finance::call(name, args, context)
+        }
+        Expr::Function { name, args } => {
+            let arg_results = args
+                .iter()
+                .map(|arg| evaluate_with(arg, context)?.number(name))
+                .collect::<Result<Vec<_>, _>>()?;
+            let apply = |args: &[ValueWithUnit]| match (context.user_function(name), context.function(name)) {
+                (Some(function), _) if function.arity() != args.len() => {
+                    Err(format!("{} expects {} arguments but got {}", name, function.arity(), args.len()))
+                }
+                (Some(function), _) => function.call(args, context),
+                (None, Some(function)) => call_native(name, function, args),
+                (None, None) => call_function(name, args, context.angle_mode),
             };
-            Ok(ValueWithUnit {
-                value,
-                unit: None, // Functions return unitless results
-            })
+            propagate(apply(&arg_results)?, &arg_results, apply)
         }
+        Expr::Equation { .. } => Err("Equations can only be used inside solve(equation, variable)".to_string()),
         Expr::Conversion { expr, target_unit } => {
-            let value_with_unit = evaluate(expr)?;
-            if let Some(source_unit) = value_with_unit.unit {
-                let converted_value = convert_units(value_with_unit.value, &source_unit, target_unit)?;
-                Ok(ValueWithUnit {
-                    value: converted_value,
-                    unit: Some(target_unit.clone()),
-                })
+            let mut value_with_unit = evaluate_with(expr, context)?.number("to")?;
+            // A plain number converted to an angle unit is an angle in the angle mode's unit.
+            if value_with_unit.unit.is_none() && units::is_angle(target_unit) {
+                value_with_unit.unit = Some(context.angle_mode.unit().to_string());
+            }
+            if let Some(source_unit) = &value_with_unit.unit {
+                let apply = |args: &[ValueWithUnit]| {
+                    let converted_value = units::convert(args[0].value, source_unit, target_unit)?;
+                    Ok(ValueWithUnit::new(converted_value, Some(target_unit.clone())))
+                };
+                let result =
+                    propagate(apply(std::slice::from_ref(&value_with_unit))?, std::slice::from_ref(&value_with_unit), apply)?;
+                // Only a conversion that succeeded used the exchange rates.
+                context.note_exchange(source_unit, target_unit);
+                Ok(result)
             } else {
                 Err("Cannot convert a unitless value".to_string())
             }
@@ -138,53 +758,530 @@ pub fn evaluate(expr: &Expr) -> Result<ValueWithUnit, String> {
     }
 }
 
-/// Converts a value from one unit to another.
+/// Attaches a standard uncertainty to a value, as in `9.81 ± 0.02`.
+///
+/// A unitless value takes the unit of its uncertainty, so `2 ± 0.1 m` is `(2 ± 0.1) m`, and
+/// an uncertainty in a compatible unit is converted as a difference, so `20 °C ± 1 K` is
+/// `20 ± 1 °C`. A value that is already uncertain combines both uncertainties in quadrature.
+///
+/// # Arguments
+///
+/// * `value` - The central value.
+/// * `uncertainty` - The standard uncertainty.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The measured value.
+/// * `Err(String)` - An error message if the uncertainty is negative or its unit does not fit the value.
+fn plus_minus(value: ValueWithUnit, uncertainty: ValueWithUnit) -> Result<ValueWithUnit, String> {
+    if !(uncertainty.value >= 0.0 && uncertainty.value.is_finite()) {
+        return Err(format!("The uncertainty after '±' must be a non-negative number, not {}", uncertainty));
+    }
+    let (sigma, unit) = match (&value.unit, &uncertainty.unit) {
+        (_, None) => (uncertainty.value, value.unit.clone()),
+        (None, Some(unit)) => (uncertainty.value, Some(unit.clone())),
+        (Some(unit), Some(from)) => (
+            units::convert(uncertainty.value, from, unit)? - units::convert(0.0, from, unit)?,
+            Some(unit.clone()),
+        ),
+    };
+    let sigma = value.uncertainty.map_or(sigma, |existing| existing.hypot(sigma));
+    Ok(ValueWithUnit::uncertain(value.value, sigma, unit))
+}
+
+/// Propagates the uncertainties of the arguments of an operation to its result.
+///
+/// This is first-order propagation assuming independent arguments: each partial derivative
+/// is estimated with a central difference, and the contributions add in quadrature.
+///
+/// # Arguments
+///
+/// * `result` - The result of the operation at the central values.
+/// * `args` - The arguments, some of which may be uncertain.
+/// * `apply` - The operation, applied to arguments with shifted central values.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The result, uncertain if any argument is.
+/// * `Err(String)` - An error message if the operation cannot be differentiated around the central values.
+fn propagate<F>(result: ValueWithUnit, args: &[ValueWithUnit], apply: F) -> Result<ValueWithUnit, String>
+where
+    F: Fn(&[ValueWithUnit]) -> Result<ValueWithUnit, String>,
+{
+    if args.iter().all(|arg| arg.uncertainty.is_none()) || result.is_boolean() {
+        return Ok(result);
+    }
+    let not_linear = |reason: String| {
+        format!("Cannot propagate the uncertainty to first order ({}); try --monte-carlo", reason)
+    };
+    let mut variance = 0.0;
+    for (index, arg) in args.iter().enumerate() {
+        let sigma = match arg.uncertainty {
+            Some(sigma) if sigma > 0.0 => sigma,
+            _ => continue,
+        };
+        // A step well inside the uncertainty, but not so small that rounding swamps it.
+        let step = (sigma * 1e-3).max(arg.value.abs() * 1e-9);
+        let shifted = |offset: f64| {
+            let mut shifted = args.to_vec();
+            shifted[index] = ValueWithUnit::new(arg.value + offset, arg.unit.clone());
+            apply(&shifted).map_err(not_linear)
+        };
+        let derivative = (shifted(step)?.value - shifted(-step)?.value) / (2.0 * step);
+        variance += (derivative * sigma).powi(2);
+    }
+    let uncertainty = variance.sqrt();
+    if !uncertainty.is_finite() {
+        return Err(not_linear("the result is not differentiable here".to_string()));
+    }
+    Ok(ValueWithUnit { uncertainty: Some(uncertainty), ..result })
+}
+
+/// Applies an arithmetic operator to the central values of two numbers.
 ///
 /// # Arguments
 ///
-/// * `value` - The numeric value to convert.
-/// * `from_unit` - The source unit.
-/// * `to_unit` - The target unit.
+/// * `op` - The operator; not a comparison, logical operator or `±`.
+/// * `left_result` - The left operand.
+/// * `right_result` - The right operand.
 ///
 /// # Returns
 ///
-/// * `Ok(f64)` - The converted value.
-/// * `Err(String)` - An error message if conversion fails.
-fn convert_units(value: f64, from_unit: &str, to_unit: &str) -> Result<f64, String> {
-    // We'll define a simple conversion map
-    let mut conversion_factors: HashMap<(&str, &str), f64> = HashMap::new();
+/// * `Ok(ValueWithUnit)` - The result, without an uncertainty.
+/// * `Err(String)` - An error message if the units do not fit the operator or a division is by zero.
+pub(crate) fn arithmetic(op: BinaryOp, left_result: &ValueWithUnit, right_result: &ValueWithUnit) -> Result<ValueWithUnit, String> {
+    match op {
+        BinaryOp::Add | BinaryOp::Subtract => {
+            // The right operand is converted into the unit of the left, so `1 km + 1 m` is 1.001 km.
+            let right = match (&left_result.unit, &right_result.unit) {
+                (None, None) => right_result.value,
+                // Exchange rates change daily, so a mix of currencies must be converted explicitly.
+                (Some(left), Some(right)) if left != right && units::is_currency(left) && units::is_currency(right) => {
+                    return Err(format!(
+                        "Cannot mix {} and {} in addition or subtraction; convert one with 'to' first",
+                        left, right
+                    ));
+                }
+                (Some(left), Some(right)) => match units::converter(right, left) {
+                    // Adding 10 °F to 20 °C is ambiguous between a temperature and a difference.
+                    Ok(units::Converter::Temperature { .. }) => {
+                        return Err(format!(
+                            "Cannot add or subtract {} and {}; convert one with 'to' first",
+                            left, right
+                        ));
+                    }
+                    Ok(converter) => converter.apply(right_result.value),
+                    Err(_) => return Err("Unit mismatch in addition or subtraction".to_string()),
+                },
+                _ => return Err("Unit mismatch in addition or subtraction".to_string()),
+            };
+            let value = match op {
+                BinaryOp::Add => left_result.value + right,
+                BinaryOp::Subtract => left_result.value - right,
+                _ => unreachable!(),
+            };
+            Ok(ValueWithUnit::new(value, left_result.unit.clone()))
+        }
+        BinaryOp::Multiply | BinaryOp::Divide => {
+            let (value, (scale, unit)) = match op {
+                BinaryOp::Multiply => (
+                    left_result.value * right_result.value,
+                    units::multiply(left_result.unit.as_deref(), right_result.unit.as_deref())?,
+                ),
+                BinaryOp::Divide => {
+                    if right_result.value == 0.0 {
+                        return Err("Division by zero error".to_string());
+                    }
+                    (
+                        left_result.value / right_result.value,
+                        units::divide(left_result.unit.as_deref(), right_result.unit.as_deref())?,
+                    )
+                }
+                _ => unreachable!(),
+            };
+            Ok(ValueWithUnit::new(value * scale, unit))
+        }
+        BinaryOp::FloorDivide => {
+            if right_result.value == 0.0 {
+                return Err("Division by zero error".to_string());
+            }
+            let (scale, unit) = units::divide(left_result.unit.as_deref(), right_result.unit.as_deref())?;
+            Ok(ValueWithUnit::new((left_result.value / right_result.value * scale).floor(), unit))
+        }
+        BinaryOp::Modulo => {
+            let divisor = match (&left_result.unit, &right_result.unit) {
+                (None, None) => right_result.value,
+                (Some(left_unit), Some(right_unit)) => units::convert(right_result.value, right_unit, left_unit)?,
+                _ => return Err("Unit mismatch in mod".to_string()),
+            };
+            if divisor == 0.0 {
+                return Err("Modulo by zero error".to_string());
+            }
+            // The result takes the sign of the divisor, so `-7 mod 3` is 2.
+            let value = left_result.value - divisor * (left_result.value / divisor).floor();
+            Ok(ValueWithUnit::new(value, left_result.unit.clone()))
+        }
+        BinaryOp::Power => {
+            if right_result.unit.is_some() {
+                return Err("Exponent must be unitless".to_string());
+            }
+            let value = left_result.value.powf(right_result.value);
+            let unit = match &left_result.unit {
+                Some(unit) if right_result.value.fract() == 0.0 => {
+                    units::power(unit, right_result.value as i32)?
+                }
+                Some(_) => return Err("Cannot raise a value with a unit to a non-integer power".to_string()),
+                None => None,
+            };
+            Ok(ValueWithUnit::new(value, unit))
+        }
+        _ => unreachable!("comparison, logical and ± operators are handled in evaluate_with"),
+    }
+}
+
+/// A built-in function, for listings and completion.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionInfo {
+    /// The name used in expressions (e.g., `sqrt`).
+    pub name: &'static str,
+    /// How the function is called, e.g., `sqrt(x)`.
+    pub usage: &'static str,
+    /// A short description.
+    pub description: &'static str,
+}
 
-    // Length units
-    conversion_factors.insert(("m", "ft"), 3.28084);
-    conversion_factors.insert(("ft", "m"), 0.3048);
-    conversion_factors.insert(("km", "mi"), 0.621371);
-    conversion_factors.insert(("mi", "km"), 1.60934);
+/// Built-in functions.
+static FUNCTIONS: &[FunctionInfo] = &[
+    // Functions of one number
+    FunctionInfo { name: "sin", usage: "sin(x)", description: "sine of an angle, in the angle mode's unit if plain" },
+    FunctionInfo { name: "cos", usage: "cos(x)", description: "cosine of an angle, in the angle mode's unit if plain" },
+    FunctionInfo { name: "tan", usage: "tan(x)", description: "tangent of an angle, in the angle mode's unit if plain" },
+    FunctionInfo { name: "asin", usage: "asin(x)", description: "arcsine, in the angle mode's unit" },
+    FunctionInfo { name: "acos", usage: "acos(x)", description: "arccosine, in the angle mode's unit" },
+    FunctionInfo { name: "atan", usage: "atan(x)", description: "arctangent, in the angle mode's unit" },
+    FunctionInfo { name: "sqrt", usage: "sqrt(x)", description: "square root, of the unit too" },
+    FunctionInfo { name: "log", usage: "log(x)", description: "base-10 logarithm" },
+    FunctionInfo { name: "ln", usage: "ln(x)", description: "natural logarithm" },
+    FunctionInfo { name: "exp", usage: "exp(x)", description: "e raised to the power x" },
+    FunctionInfo { name: "abs", usage: "abs(x)", description: "absolute value" },
+    FunctionInfo { name: "ceil", usage: "ceil(x)", description: "smallest integer not below x" },
+    FunctionInfo { name: "floor", usage: "floor(x)", description: "largest integer not above x" },
+    FunctionInfo { name: "round", usage: "round(x)", description: "nearest integer, halves away from zero" },
+    FunctionInfo { name: "trunc", usage: "trunc(x)", description: "integer part" },
+    FunctionInfo { name: "fract", usage: "fract(x)", description: "fractional part" },
+    FunctionInfo { name: "radians", usage: "radians(x)", description: "an angle, or a number of degrees, in radians" },
+    FunctionInfo { name: "degrees", usage: "degrees(x)", description: "an angle, or a number of radians, in degrees" },
+    FunctionInfo { name: "gamma", usage: "gamma(x)", description: "gamma function" },
+    // Probability distributions and counting
+    FunctionInfo { name: "normpdf", usage: "normpdf(x, mean, sd)", description: "normal probability density, standard if only x is given" },
+    FunctionInfo { name: "normcdf", usage: "normcdf(x, mean, sd)", description: "normal cumulative probability, standard if only x is given" },
+    FunctionInfo { name: "binom", usage: "binom(k, n, p)", description: "probability of k successes in n trials of probability p" },
+    FunctionInfo { name: "poisson", usage: "poisson(k, lambda)", description: "probability of k events at an average rate of lambda" },
+    FunctionInfo { name: "choose", usage: "choose(n, k)", description: "number of ways to choose k of n items" },
+    FunctionInfo { name: "perm", usage: "perm(n, k)", description: "number of ordered arrangements of k of n items" },
+    // Random numbers, drawn from the evaluation's generator
+    FunctionInfo { name: "rand", usage: "rand()", description: "random number from 0 up to 1" },
+    FunctionInfo { name: "randint", usage: "randint(a, b)", description: "random integer from a to b, inclusive" },
+    FunctionInfo { name: "dice", usage: "dice(n, sides)", description: "total of n rolls of a die, also written 3d6" },
+    // Forms that do not evaluate their arguments first
+    FunctionInfo { name: "if", usage: "if(condition, then, otherwise)", description: "chooses a value by a condition" },
+    FunctionInfo { name: "diff", usage: "diff(f, x)", description: "symbolic derivative of f with respect to x" },
+    FunctionInfo { name: "solve", usage: "solve(equation, x, low, high)", description: "roots of an equation, optionally within a range" },
+    FunctionInfo { name: "integrate", usage: "integrate(f, x, a, b)", description: "definite integral of f from a to b" },
+    FunctionInfo { name: "sum", usage: "sum(f, k, a, b)", description: "sum of f for k from a to b" },
+    FunctionInfo { name: "prod", usage: "prod(f, k, a, b)", description: "product of f for k from a to b" },
+    FunctionInfo { name: "limit", usage: "limit(f, x, a)", description: "limit of f as x approaches a" },
+    // Financial functions, which take lists of cash flows and name frequencies
+    FunctionInfo { name: "pmt", usage: "pmt(rate, periods, pv, fv, type)", description: "payment per period of a loan or annuity" },
+    FunctionInfo { name: "fv", usage: "fv(rate, periods, payment, pv, type)", description: "future value of payments and a present value" },
+    FunctionInfo { name: "pv", usage: "pv(rate, periods, payment, fv, type)", description: "present value of payments and a future value" },
+    FunctionInfo { name: "nper", usage: "nper(rate, payment, pv, fv, type)", description: "number of periods to pay off a loan" },
+    FunctionInfo { name: "npv", usage: "npv(rate, [cash flows])", description: "net present value of cash flows at the end of each period" },
+    FunctionInfo { name: "irr", usage: "irr([cash flows])", description: "internal rate of return of cash flows, the first one now" },
+    FunctionInfo { name: "compound", usage: "compound(principal, rate, time, frequency)", description: "principal grown by compound interest" },
+];
 
-    // Mass units
-    conversion_factors.insert(("kg", "lb"), 2.20462);
-    conversion_factors.insert(("lb", "kg"), 0.453592);
+/// Returns all built-in functions, those of one number first.
+pub fn functions() -> &'static [FunctionInfo] {
+    FUNCTIONS
+}
 
-    // Temperature units (special handling)
-    if from_unit == "C" && to_unit == "F" {
-        return Ok(value * 9.0 / 5.0 + 32.0);
-    } else if from_unit == "F" && to_unit == "C" {
-        return Ok((value - 32.0) * 5.0 / 9.0);
+/// Calls a native function, converting the arguments into the units of its signature.
+///
+/// # Arguments
+///
+/// * `name` - The name the function was called by.
+/// * `function` - The implementation.
+/// * `args` - The evaluated arguments.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The result, in the signature's result unit.
+/// * `Err(String)` - An error message if the arguments do not match the signature or the call fails.
+fn call_native(name: &str, function: &dyn NativeFunction, args: &[ValueWithUnit]) -> Result<ValueWithUnit, String> {
+    let signature = function.signature();
+    if args.len() != signature.arity() {
+        return Err(format!("{} expects {} arguments but got {}", name, signature.arity(), args.len()));
     }
+    let values = args
+        .iter()
+        .zip(&signature.params)
+        .enumerate()
+        .map(|(index, (arg, param))| match (&arg.unit, param) {
+            (None, None) => Ok(arg.value),
+            (Some(unit), Some(param)) => units::convert(arg.value, unit, param),
+            (_, Some(param)) => Err(format!("Argument {} of {} must be in {}", index + 1, name, param)),
+            (Some(unit), None) => Err(format!("Argument {} of {} must be unitless, not {}", index + 1, name, unit)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ValueWithUnit::new(function.call(&values)?, signature.returns))
+}
 
-    if let Some(factor) = conversion_factors.get(&(from_unit, to_unit)) {
-        Ok(value * factor)
+/// Calls a built-in function with already evaluated arguments.
+///
+/// Trigonometric functions take an angle in any angle unit, or a plain number in the unit
+/// of the angle mode, which is also the unit the inverse functions return. `sqrt` takes the
+/// root of the unit too, `abs` and the rounding functions keep it, and the other functions
+/// need a unitless argument.
+///
+/// # Arguments
+///
+/// * `name` - The name of the function.
+/// * `args` - The evaluated arguments.
+/// * `angle_mode` - The unit of angles written as plain numbers.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The result of the function.
+/// * `Err(String)` - An error message if the function is unknown, given the wrong number of
+///   arguments, or given an argument with a unit it cannot take.
+pub(crate) fn call_function(name: &str, args: &[ValueWithUnit], angle_mode: AngleMode) -> Result<ValueWithUnit, String> {
+    if probability::is_distribution(name) {
+        return probability::distribution(name, args);
+    }
+    let [arg] = args else {
+        return Err(format!("Unknown function: {} with {} arguments", name, args.len()));
+    };
+    let unitless = || match &arg.unit {
+        None => Ok(arg.value),
+        Some(unit) => Err(format!("{} expects a unitless value, not one in {}", name, unit)),
+    };
+    let angle_unit = || match &arg.unit {
+        None => Ok(angle_mode.unit()),
+        Some(unit) if units::is_angle(unit) => Ok(unit.as_str()),
+        Some(unit) => Err(format!("{} expects an angle, not a value in {}", name, unit)),
+    };
+    // Like the arguments of `sin`, results in degrees are exact at whole multiples of 15.
+    let from_radians = |radians: f64| {
+        let angle = units::convert(radians, "rad", angle_mode.unit())?;
+        let nearest = (angle / 15.0).round() * 15.0;
+        Ok::<_, String>(if angle_mode == AngleMode::Degrees && (angle - nearest).abs() < 1e-12 { nearest } else { angle })
+    };
+    let keep_unit = |value: f64| Ok(ValueWithUnit::new(value, arg.unit.clone()));
+    let value = match name {
+        "sin" | "cos" | "tan" => trigonometric(name, arg.value, angle_unit()?)?,
+        "asin" => from_radians(unitless()?.asin())?,
+        "acos" => from_radians(unitless()?.acos())?,
+        "atan" => from_radians(unitless()?.atan())?,
+        "sqrt" => {
+            let Some(unit) = &arg.unit else { return keep_unit(arg.value.sqrt()) };
+            let (scale, unit) = units::root(unit, 2)?;
+            return Ok(ValueWithUnit::new(arg.value.sqrt() * scale, unit));
+        }
+        "log" => unitless()?.log10(),
+        "ln" => unitless()?.ln(),
+        "exp" => unitless()?.exp(),
+        "abs" => return keep_unit(arg.value.abs()),
+        "ceil" => return keep_unit(arg.value.ceil()),
+        "floor" => return keep_unit(arg.value.floor()),
+        "round" => return keep_unit(arg.value.round()),
+        "trunc" => return keep_unit(arg.value.trunc()),
+        "fract" => return keep_unit(arg.value.fract()),
+        // A plain number is in degrees or radians whatever the angle mode, as the names say.
+        "radians" if arg.unit.is_none() => arg.value.to_radians(),
+        "radians" => units::convert(arg.value, angle_unit()?, "rad")?,
+        "degrees" if arg.unit.is_none() => arg.value.to_degrees(),
+        "degrees" => units::convert(arg.value, angle_unit()?, "deg")?,
+        "gamma" => gamma(unitless()?),
+        _ => return Err(format!("Unknown function: {}", name)),
+    };
+    Ok(ValueWithUnit::new(value, None))
+}
+
+/// Computes `sin`, `cos` or `tan` of an angle.
+///
+/// An angle that is not in radians is usually a whole multiple of 15 degrees, where the
+/// results 0, ±1/2 and ±1 are exact, so `sin(30 deg)` is 0.5 rather than 0.49999999999999994
+/// and `tan(90 deg)` is infinite.
+///
+/// # Arguments
+///
+/// * `name` - `sin`, `cos` or `tan`.
+/// * `value` - The angle.
+/// * `unit` - The angle unit of `value`.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The value of the function.
+/// * `Err(String)` - An error message if `unit` is not an angle unit.
+fn trigonometric(name: &str, value: f64, unit: &str) -> Result<f64, String> {
+    let (sin, cos) = units::convert(value, unit, "rad")?.sin_cos();
+    let steps = units::convert(value, unit, "deg")? / 15.0;
+    let exact = unit != "rad" && (steps - steps.round()).abs() < 1e-9;
+    let snap = |x: f64| {
+        let half = (x * 2.0).round() / 2.0;
+        if exact && (x - half).abs() < 1e-12 { half } else { x }
+    };
+    Ok(match name {
+        "sin" => snap(sin),
+        "cos" => snap(cos),
+        _ => snap(snap(sin) / snap(cos)),
+    })
+}
+
+/// Computes `n!`, exactly for small non-negative integers and as `gamma(n + 1)` otherwise.
+///
+/// # Arguments
+///
+/// * `n` - The operand.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The factorial.
+/// * `Err(String)` - An error message for negative integers, where the factorial is undefined,
+///   and for operands above about 170, whose factorial overflows an f64.
+pub(crate) fn factorial(n: f64) -> Result<f64, String> {
+    if n.fract() == 0.0 && n < 0.0 {
+        return Err(format!("Factorial is undefined for negative integers ({})", n));
+    }
+    let value = if n.fract() != 0.0 {
+        gamma(n + 1.0)
+    } else if n <= 170.0 {
+        (2..=n as u64).fold(1.0, |product, k| product * k as f64)
     } else {
-        Err(format!(
-            "Conversion from '{}' to '{}' not supported",
-            from_unit, to_unit
-        ))
+        // 171! overflows an f64.
+        f64::INFINITY
+    };
+    if value.is_infinite() || (n > 170.0 && value.is_nan()) {
+        return Err(format!("Factorial overflow: {}! is too large to represent", format_value(n)));
+    }
+    Ok(value)
+}
+
+/// Computes the gamma function with the Lanczos approximation (g = 7, 9 terms).
+///
+/// # Arguments
+///
+/// * `x` - The argument.
+///
+/// # Returns
+///
+/// * `gamma(x)`, accurate to about 15 significant digits; NaN at the poles `0, -1, -2, ...`.
+fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x <= 0.0 && x.fract() == 0.0 {
+        return f64::NAN;
     }
+    if x < 0.5 {
+        // Reflection formula: gamma(x) gamma(1 - x) = pi / sin(pi x).
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+    // t^(x + 0.5) alone overflows from x = 141, long before gamma does, so it is taken in halves.
+    let power = t.powf((x + 0.5) / 2.0);
+    (2.0 * PI).sqrt() * power * (-t).exp() * power * series
+}
+
+/// Compares two values, converting the right-hand side into the unit of the left.
+///
+/// Numbers within a relative tolerance of 1e-12 count as equal. Booleans can only be
+/// compared with `==` and `!=`.
+///
+/// # Arguments
+///
+/// * `op` - The comparison operator.
+/// * `left` - The left-hand value.
+/// * `right` - The right-hand value.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The boolean result.
+/// * `Err(String)` - An error message if the values cannot be compared.
+fn compare(op: BinaryOp, left: ValueWithUnit, right: ValueWithUnit) -> Result<ValueWithUnit, String> {
+    if left.is_boolean() || right.is_boolean() {
+        return match op {
+            BinaryOp::Equal | BinaryOp::NotEqual if left.kind == right.kind => {
+                Ok(ValueWithUnit::boolean((left.value == right.value) == (op == BinaryOp::Equal)))
+            }
+            _ => Err(format!("Cannot compare a boolean with '{}'", op.to_string().trim())),
+        };
+    }
+    let (a, b) = match (&left.unit, &right.unit) {
+        (None, None) => (left.value, right.value),
+        (Some(left_unit), Some(right_unit)) => (left.value, units::convert(right.value, right_unit, left_unit)?),
+        _ => return Err("Cannot compare a value with a unit to a unitless value".to_string()),
+    };
+    Ok(ValueWithUnit::boolean(compare_numbers(op, a, b)?))
+}
+
+/// Compares two numbers in the same unit, within the tolerance of `==`.
+///
+/// # Arguments
+///
+/// * `op` - The comparison operator.
+/// * `a` - The left-hand number.
+/// * `b` - The right-hand number.
+///
+/// # Returns
+///
+/// * `Ok(bool)` - Whether the comparison holds.
+/// * `Err(String)` - An error message if `op` is not a comparison.
+pub(crate) fn compare_numbers(op: BinaryOp, a: f64, b: f64) -> Result<bool, String> {
+    let equal = (a - b).abs() <= EQUALITY_TOLERANCE * a.abs().max(b.abs());
+    Ok(match op {
+        BinaryOp::Equal => equal,
+        BinaryOp::NotEqual => !equal,
+        BinaryOp::Less => a < b && !equal,
+        BinaryOp::LessEqual => a < b || equal,
+        BinaryOp::Greater => a > b && !equal,
+        BinaryOp::GreaterEqual => a > b || equal,
+        _ => return Err(format!("'{}' is not a comparison", op.to_string().trim())),
+    })
+}
+
+/// Checks whether a name refers to a built-in constant such as `pi` or `c`, or to `true` or `false`.
+///
+/// # Arguments
+///
+/// * `name` - The name to check.
+///
+/// # Returns
+///
+/// * `true` if the name is a built-in constant.
+pub fn is_constant(name: &str) -> bool {
+    matches!(name, "true" | "false") || constants::lookup(name).is_some()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ast::{Expr, UnaryOp, BinaryOp};
+    use crate::ast::{Expr, BinaryOp};
 
     #[test]
     fn test_evaluate_number() {
@@ -223,9 +1320,234 @@ mod tests {
     fn test_evaluate_function() {
         let expr = Expr::Function {
             name: "sin".to_string(),
-            arg: Box::new(Expr::Number(0.0)),
+            args: vec![Expr::Number(0.0)],
         };
         let result = evaluate(&expr).unwrap();
         assert_eq!(result.value, 0.0);
     }
+
+    #[test]
+    fn test_evaluate_with_bound_variable() {
+        let mut context = Context::new();
+        context.set_variable(
+            "x",
+            ValueWithUnit::new(4.0, None),
+        );
+        let expr = Expr::BinaryOp {
+            left: Box::new(Expr::NumberWithUnit {
+                value: 3.0,
+                unit: Some("x".to_string()),
+            }),
+            op: BinaryOp::Add,
+            right: Box::new(Expr::Symbol("x".to_string())),
+        };
+        assert_eq!(evaluate_with(&expr, &context).unwrap().value, 16.0);
+    }
+
+    #[test]
+    fn test_evaluate_unit_arithmetic() {
+        let expr = Expr::BinaryOp {
+            left: Box::new(Expr::NumberWithUnit {
+                value: 10.0,
+                unit: Some("N".to_string()),
+            }),
+            op: BinaryOp::Multiply,
+            right: Box::new(Expr::NumberWithUnit {
+                value: 2.0,
+                unit: Some("m".to_string()),
+            }),
+        };
+        let result = evaluate(&expr).unwrap();
+        assert_eq!(result.value, 20.0);
+        assert_eq!(result.unit.as_deref(), Some("J"));
+    }
+
+    #[test]
+    fn test_evaluate_mixed_unit_addition() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap());
+        assert_eq!(eval("1 km + 1 m").unwrap().to_string(), "1.001 km");
+        assert_eq!(eval("1 m - 50 cm").unwrap().to_string(), "0.5 m");
+        assert_eq!(eval("1 kg + 1 m").unwrap_err(), "Unit mismatch in addition or subtraction");
+        assert!(eval("20 C + 10 F").is_err());
+        assert!(eval("1 m + 1").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_comparisons() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap());
+        assert!(eval("1 mi > 1500 m").unwrap().is_boolean());
+        assert_eq!(eval("1 mi > 1500 m").unwrap().to_string(), "true");
+        assert_eq!(eval("0.1 + 0.2 == 0.3").unwrap().to_string(), "true");
+        assert_eq!(eval("not 2 <= 1 and (1 > 2 or true)").unwrap().to_string(), "true");
+        assert!(eval("1 kg < 1 m").is_err());
+        assert!(eval("(1 < 2) + 1").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_conditional() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap());
+        assert_eq!(eval("if(2 > 1, 10, ln(0))").unwrap().value, 10.0);
+        assert_eq!(eval("-1 > 0 ? 1 : -1 < 0 ? -1 : 0").unwrap().value, -1.0);
+        assert!(eval("if(1, 2, 3)").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_factorial_modulo_and_floor_division() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap());
+        assert_eq!(eval("5!").unwrap().value, 120.0);
+        assert!((eval("0.5!").unwrap().value - PI.sqrt() / 2.0).abs() < 1e-14);
+        assert!((eval("gamma(0.5)").unwrap().value - PI.sqrt()).abs() < 1e-14);
+        assert!(eval("(0 - 3)!").is_err());
+        assert!(eval("170!").unwrap().value.is_finite());
+        assert!(eval("171!").unwrap_err().contains("overflow"));
+        assert!(eval("170.5!").unwrap().value.is_finite());
+        assert!(eval("171.5!").unwrap_err().contains("overflow"));
+        assert_eq!(eval("17 mod 5").unwrap().value, 2.0);
+        assert_eq!(eval("(0 - 7) mod 3").unwrap().value, 2.0);
+        assert_eq!(eval("17 // 5").unwrap().value, 3.0);
+        assert_eq!(eval("1 h // 25 min").unwrap().value, 2.0);
+        assert_eq!(eval("1 km mod 375 m").unwrap().to_string(), "0.25 km");
+    }
+
+    #[test]
+    fn test_evaluate_implicit_multiplication() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap()).unwrap().value;
+        assert_eq!(eval("2pi"), 2.0 * PI);
+        assert_eq!(eval("3(4 + 5)"), 27.0);
+        assert_eq!(eval("2 sqrt(4)"), 4.0);
+        assert_eq!(evaluate(&crate::parser::parse_expression("2 m^2").unwrap()).unwrap().to_string(), "2 m^2");
+    }
+
+    #[test]
+    fn test_evaluate_number_literals() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap()).unwrap().value;
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^-2"), 0.25);
+        assert_eq!(eval("-7 mod 3"), 2.0);
+        assert_eq!(eval("4.7k"), 4700.0);
+        assert_eq!(eval("22p * 1M"), 22e-6);
+        assert_eq!(eval("2e"), 2.0 * std::f64::consts::E);
+        assert_eq!(evaluate(&crate::parser::parse_expression("5m").unwrap()).unwrap().to_string(), "5 m");
+
+        let mut context = Context::new();
+        context.set_variable("k", ValueWithUnit::new(3.0, None));
+        assert_eq!(evaluate_with(&crate::parser::parse_expression("2k").unwrap(), &context).unwrap().value, 6.0);
+    }
+
+    #[test]
+    fn test_evaluate_constants() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap()).unwrap();
+        assert_eq!(eval("c * 3 s to km").to_string(), "899377.374 km");
+        assert_eq!(eval("h_planck").to_string(), "6.62607015e-34 J*s");
+        assert_eq!(eval("100 km / 1 h").to_string(), "100 km/h");
+        assert!((eval("100 km/h to m/s").value - 250.0 / 9.0).abs() < 1e-9);
+        assert!((eval("60 mi/h to km/h").value - 96.56064).abs() < 1e-9);
+        assert_eq!(eval("atm to kPa").value, 101.325);
+        assert_eq!(eval("tau / 2").value, PI);
+
+        // After a number, a constant means what it does on its own; `G` is the giga prefix.
+        assert_eq!(eval("2 g0").to_string(), "19.6133 m/s^2");
+        assert_eq!(eval("3 G_newton").value, 3.0 * 6.67430e-11);
+        assert_eq!(eval("3G").value, 3e9);
+        assert_eq!(eval("1.5 N_A").value, 1.5 * 6.02214076e23);
+        assert_eq!(eval("2 c").value, 2.0 * 299792458.0);
+    }
+
+    #[test]
+    fn test_functions_table() {
+        let one = ValueWithUnit::new(0.5, None);
+        for info in functions() {
+            assert!(info.usage.starts_with(&format!("{}(", info.name)));
+            let special = matches!(info.name, "if" | "diff" | "solve")
+                || calculus::is_calculus_function(info.name)
+                || finance::is_finance_function(info.name)
+                || probability::is_random_function(info.name)
+                || matches!(info.name, "binom" | "poisson" | "choose" | "perm");
+            assert_eq!(call_function(info.name, std::slice::from_ref(&one), AngleMode::Radians).is_ok(), !special, "{}", info.name);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_functions_with_units() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap()).map(|value| value.to_string());
+        assert_eq!(eval("sqrt(16 m^2)").unwrap(), "4 m");
+        assert_eq!(eval("sqrt(9 m^2/s^2)").unwrap(), "3 m/s");
+        assert_eq!(eval("abs(-3 kg)").unwrap(), "3 kg");
+        assert_eq!(eval("round(2.6 m)").unwrap(), "3 m");
+        assert!(eval("sqrt(2 m)").unwrap_err().contains("Cannot take root 2"));
+        assert_eq!(eval("exp(2 m)").unwrap_err(), "exp expects a unitless value, not one in m");
+        assert!(eval("ln(1 s)").is_err());
+        assert_eq!(eval("sin(2 kg)").unwrap_err(), "sin expects an angle, not a value in kg");
+    }
+
+    #[test]
+    fn test_evaluate_angles() {
+        let mut context = Context::new();
+        let eval = |input: &str, context: &Context| {
+            evaluate_with(&crate::parser::parse_expression(input).unwrap(), context).unwrap().to_string()
+        };
+        assert_eq!(eval("sin(30 deg)", &context), "0.5");
+        assert_eq!(eval("cos(0.25 turn)", &context), "0");
+        assert_eq!(eval("tan(50 grad)", &context), "1");
+        assert_eq!(eval("atan(1) to deg", &context), "45 deg");
+        assert_eq!(eval("180 deg to rad", &context), format!("{} rad", PI));
+        assert_eq!(eval("degrees(0.5 turn)", &context), "180");
+        context.set_angle_mode(AngleMode::Degrees);
+        assert_eq!(eval("sin(30)", &context), "0.5");
+        assert_eq!(eval("asin(1)", &context), "90");
+        assert_eq!(eval("asin(0.5)", &context), "30");
+        assert_eq!(eval("acos(0) to rad", &context), format!("{} rad", PI / 2.0));
+        assert_eq!(eval("sin(pi rad / 2)", &context), "1");
+        assert_eq!("degrees".parse::<AngleMode>(), Ok(AngleMode::Degrees));
+    }
+
+    #[test]
+    fn test_evaluate_uncertainty_propagation() {
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap()).unwrap();
+        let product = eval("(2 ± 0.1) * (3 ± 0.2)");
+        assert_eq!(product.value, 6.0);
+        assert!((product.uncertainty.unwrap() - 0.5).abs() < 1e-9);
+        assert!((eval("sin(1 ± 0.01)").uncertainty.unwrap() - 0.01 * 1f64.cos()).abs() < 1e-9);
+        assert_eq!(eval("-(2 ± 0.1)").uncertainty, Some(0.1));
+        assert_eq!(eval("9.81 ± 0.02 m/s^2").unit.as_deref(), Some("m/s^2"));
+        assert!((eval("1 m ± 5 cm").uncertainty.unwrap() - 0.05).abs() < 1e-12);
+        assert!((eval("(3 ± 0.4) ± 0.3").uncertainty.unwrap() - 0.5).abs() < 1e-12);
+        assert!(eval("2 ± 0.1 < 3").is_boolean());
+        let eval = |input: &str| evaluate(&crate::parser::parse_expression(input).unwrap());
+        assert!(eval("1 ± -0.1").is_err());
+        assert!(eval("sqrt(0 ± 1)").unwrap_err().contains("--monte-carlo"));
+    }
+
+    #[test]
+    fn test_format_uncertain() {
+        let format = |value, uncertainty| ValueWithUnit::uncertain(value, uncertainty, None).to_string();
+        assert_eq!(format(9.8123, 0.0234), "9.81 ± 0.02");
+        assert_eq!(format(9.8123, 0.0146), "9.812 ± 0.015");
+        assert_eq!(format(1234.5, 56.0), "1230 ± 60");
+        assert_eq!(format(6.6743e-11, 1.5e-14), "(6.6743 ± 0.0015)e-11");
+        assert_eq!(format(2.0, 0.0), "2 ± 0");
+        assert_eq!(ValueWithUnit::uncertain(200.0, 10.0, Some("cm".to_string())).to_string(), "200 ± 10 cm");
+    }
+
+    #[test]
+    fn test_format_notation() {
+        let format = |value, precision, notation| ValueWithUnit::new(value, None).format(precision, notation);
+        assert_eq!(format(2.0 / 3.0, Some(4), Notation::Auto), "0.6667");
+        assert_eq!(format(6.02214076e23, Some(3), Notation::Auto), "6.02e23");
+        assert_eq!(format(1234.5678, Some(2), Notation::Fixed), "1234.57");
+        assert_eq!(format(1e20, None, Notation::Fixed), "100000000000000000000");
+        assert_eq!(format(1234.5, Some(3), Notation::Scientific), "1.23e3");
+        assert_eq!(format(0.00047, None, Notation::Engineering), "470e-6");
+        assert_eq!(format(999.96, Some(4), Notation::Engineering), "1e3");
+        assert_eq!(format(-12345.0, Some(3), Notation::Engineering), "-12.3e3");
+        assert_eq!(ValueWithUnit::boolean(true).format(Some(2), Notation::Scientific), "true");
+        assert_eq!("eng".parse::<Notation>(), Ok(Notation::Engineering));
+    }
+
+    #[test]
+    fn test_evaluate_symbols() {
+        let result = evaluate(&Expr::Symbol("pi".to_string())).unwrap();
+        assert_eq!(result.value, PI);
+        assert!(evaluate(&Expr::Symbol("x".to_string())).is_err());
+    }
 }
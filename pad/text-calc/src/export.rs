@@ -0,0 +1,729 @@
+//! Exporting sessions and scripts as Markdown, HTML or LaTeX documents.
+//!
+//! A [`Document`] is a list of blocks: prose from comments, calculations with their results
+//! or errors, and printed output. [`Document::render`] typesets it in a [`Format`], writing
+//! expressions, numbers and units in the notation of the format, e.g., `9.81 m/s²` in
+//! Markdown, `9.81 m/s<sup>2</sup>` in HTML and `\SI{9.81}{m.s^{-2}}` in LaTeX.
+
+use crate::ast::{BinaryOp, Expr, Statement, UnaryOp};
+use crate::calculator::{Calculator, Outcome, Settings};
+use crate::evaluator::{Notation, ValueWithUnit};
+use crate::script::{self, Output, Script, ScriptError};
+use crate::{parser, units};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The formats documents are exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Markdown, with calculations in tables.
+    Markdown,
+    /// A standalone HTML page.
+    Html,
+    /// A standalone LaTeX document, with units typeset by the siunitx package.
+    Latex,
+}
+
+impl Format {
+    /// Picks the format of a file from its extension, e.g., `.md`, `.html` or `.tex`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// * `None` if the extension is missing or not that of a format.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.to_lowercase().parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" | "htm" => Ok(Format::Html),
+            "latex" | "tex" => Ok(Format::Latex),
+            _ => Err(format!("Unknown export format: {} (expected markdown, html or latex)", name)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Markdown => "markdown",
+            Format::Html => "html",
+            Format::Latex => "latex",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A part of a document.
+#[derive(Debug, Clone)]
+pub enum Block {
+    /// A paragraph of prose, such as the comments of a script.
+    Text(String),
+    /// A line of input with its result or error.
+    Calculation {
+        /// The input, without its comment.
+        input: String,
+        /// The result or error message; `None` for a line that gives no result, such as a
+        /// line of a loop.
+        result: Option<Result<Outcome, String>>,
+    },
+    /// A line written by `print`.
+    Output(String),
+}
+
+/// A session or script to export.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    /// The blocks, in order.
+    pub blocks: Vec<Block>,
+}
+
+impl Document {
+    /// Creates an empty document, to which a session adds its blocks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs a script and makes a document of it: each line with its result, and the comment
+    /// lines as prose. Consecutive comment lines form a paragraph.
+    ///
+    /// Printed lines are placed before the next line with a result, as the lines of a
+    /// script print as they run.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The text of the script.
+    /// * `calculator` - The calculator to run the script in.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Document)` - The document. An error that stops the script is the result of the
+    ///   line it occurred in.
+    /// * `Err(ScriptError)` - An error in the structure of the script, such as an unclosed block.
+    pub fn from_script(source: &str, calculator: &mut Calculator) -> Result<Self, ScriptError> {
+        let script = Script::parse(source, calculator.settings().number_format)?;
+        let mut results: BTreeMap<usize, Result<Outcome, String>> = BTreeMap::new();
+        let mut printed: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        let mut pending = Vec::new();
+        let stopped = script.run(calculator, &mut |output| {
+            let (line, result) = match output {
+                Output::Result { line, outcome } => (line, Ok(outcome)),
+                Output::Error(e) => (e.line, Err(e.message)),
+                Output::Print(text) => return pending.push(text),
+                Output::Warning(_) => return,
+            };
+            printed.entry(line).or_default().append(&mut pending);
+            results.insert(line, result);
+        });
+        if let Err(e) = stopped {
+            printed.entry(e.line).or_default().append(&mut pending);
+            results.insert(e.line, Err(e.message));
+        }
+
+        let mut document = Document::new();
+        let mut paragraph = false;
+        for (index, raw) in source.lines().enumerate() {
+            let line = index + 1;
+            for text in printed.remove(&line).unwrap_or_default() {
+                document.blocks.push(Block::Output(text));
+            }
+            let code = script::strip_comment(raw).trim_end();
+            if !code.trim().is_empty() {
+                let input = if results.contains_key(&line) { code.trim() } else { code };
+                document.blocks.push(Block::Calculation { input: input.to_string(), result: results.remove(&line) });
+                paragraph = false;
+                continue;
+            }
+            let text = raw.trim().trim_start_matches('#').trim();
+            match document.blocks.last_mut() {
+                _ if text.is_empty() || raw.starts_with("#!") => paragraph = false,
+                Some(Block::Text(previous)) if paragraph => {
+                    previous.push(' ');
+                    previous.push_str(text);
+                }
+                _ => {
+                    document.blocks.push(Block::Text(text.to_string()));
+                    paragraph = true;
+                }
+            }
+        }
+        document.blocks.extend(pending.into_iter().map(Block::Output));
+        Ok(document)
+    }
+
+    /// Typesets the document.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format to write.
+    /// * `settings` - How inputs are read and results are written.
+    ///
+    /// # Returns
+    ///
+    /// The text of the document; HTML and LaTeX documents are complete, ready to open or compile.
+    pub fn render(&self, format: Format, settings: &Settings) -> String {
+        let mut out = String::from(match format {
+            Format::Markdown => "",
+            Format::Html => HTML_HEADER,
+            Format::Latex => LATEX_HEADER,
+        });
+        // Names assigned in the document are variables even where they are also units, like `h`.
+        let variables: HashSet<String> = self
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Calculation { input, .. } => match parser::parse_statement(input, settings.number_format) {
+                    Ok(Statement::Assignment { name, .. }) => Some(name),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        let mut index = 0;
+        while index < self.blocks.len() {
+            let group = Group::of(&self.blocks[index]);
+            let length = match group {
+                Group::Text => 1,
+                _ => self.blocks[index..].iter().take_while(|block| Group::of(block) == group).count(),
+            };
+            let blocks = &self.blocks[index..index + length];
+            index += length;
+            match group {
+                Group::Text => {
+                    let Block::Text(text) = &blocks[0] else { continue };
+                    out.push_str(&match format {
+                        // Prose is Markdown already.
+                        Format::Markdown => format!("{}\n\n", text),
+                        Format::Html => format!("<p>{}</p>\n", escape(text, format)),
+                        Format::Latex => format!("{}\n\n", escape(text, format)),
+                    });
+                }
+                Group::Results => out.push_str(&render_results(blocks, format, settings, &variables)),
+                Group::Code | Group::Output => {
+                    let lines: String = blocks
+                        .iter()
+                        .map(|block| match block {
+                            Block::Calculation { input, .. } | Block::Output(input) | Block::Text(input) => {
+                                format!("{}\n", input)
+                            }
+                        })
+                        .collect();
+                    out.push_str(&match (format, group) {
+                        (Format::Markdown, Group::Code) => format!("```\n{}```\n\n", lines),
+                        (Format::Markdown, _) => format!("```text\n{}```\n\n", lines),
+                        (Format::Html, Group::Code) => format!("<pre><code>{}</code></pre>\n", escape(&lines, format)),
+                        (Format::Html, _) => format!("<pre class=\"output\">{}</pre>\n", escape(&lines, format)),
+                        (Format::Latex, _) => format!("\\begin{{verbatim}}\n{}\\end{{verbatim}}\n\n", lines),
+                    });
+                }
+            }
+        }
+        out.push_str(match format {
+            Format::Markdown => "",
+            Format::Html => "</body>\n</html>\n",
+            Format::Latex => "\\end{document}\n",
+        });
+        out
+    }
+}
+
+/// The kinds of blocks that are typeset together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    Text,
+    /// Calculations with results, in a table.
+    Results,
+    /// Lines without results, as code.
+    Code,
+    Output,
+}
+
+impl Group {
+    fn of(block: &Block) -> Self {
+        match block {
+            Block::Text(_) => Group::Text,
+            Block::Calculation { result: Some(_), .. } => Group::Results,
+            Block::Calculation { result: None, .. } => Group::Code,
+            Block::Output(_) => Group::Output,
+        }
+    }
+}
+
+const HTML_HEADER: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Calculations</title>
+<style>
+body { font-family: sans-serif; max-width: 48em; margin: 2em auto; }
+table { border-collapse: collapse; }
+td { padding: 0.2em 1em; border-bottom: 1px solid #ddd; }
+td.result { font-weight: bold; }
+tr.error td.result { color: #c00; }
+pre.output { color: #555; }
+</style>
+</head>
+<body>
+";
+
+const LATEX_HEADER: &str = "\\documentclass{article}
+\\usepackage{amsmath}
+\\usepackage{siunitx}
+\\usepackage{xcolor}
+
+\\begin{document}
+
+";
+
+/// Typesets consecutive calculations with results, as a table or, in LaTeX, aligned equations.
+fn render_results(blocks: &[Block], format: Format, settings: &Settings, variables: &HashSet<String>) -> String {
+    let rows = blocks.iter().filter_map(|block| match block {
+        Block::Calculation { input, result: Some(result) } => Some((input_line(input, format, settings, variables), result)),
+        _ => None,
+    });
+    match format {
+        Format::Markdown => {
+            let mut out = String::from("| Input | Result |\n| --- | --- |\n");
+            for (input, result) in rows {
+                let result = match result {
+                    Ok(Outcome::Symbolic(expr)) => format!("`{}`", expr),
+                    Ok(outcome) => render_outcome(outcome, format, settings, variables),
+                    Err(message) => format!("**Error:** {}", message),
+                };
+                // A `|` would end the cell, even inside backticks, and a line break the row.
+                out.push_str(&format!("| `{}` | {} |\n", escape(&input, format), escape(&result, format)));
+            }
+            out + "\n"
+        }
+        Format::Html => {
+            let mut out = String::from("<table>\n");
+            for (input, result) in rows {
+                let (class, result) = match result {
+                    Ok(outcome) => ("", render_outcome(outcome, format, settings, variables)),
+                    Err(message) => (" class=\"error\"", format!("Error: {}", escape(message, format))),
+                };
+                out.push_str(&format!("<tr{}><td class=\"input\">{}</td><td class=\"result\">{}</td></tr>\n", class, input, result));
+            }
+            out + "</table>\n"
+        }
+        Format::Latex => {
+            let rows: Vec<String> = rows
+                .map(|(input, result)| {
+                    let result = match result {
+                        Ok(outcome) => render_outcome(outcome, format, settings, variables),
+                        Err(message) => format!("\\textcolor{{red}}{{\\text{{Error: {}}}}}", escape(message, format)),
+                    };
+                    format!("{} &\\Rightarrow {}", input, result)
+                })
+                .collect();
+            format!("\\begin{{align*}}\n{}\n\\end{{align*}}\n\n", rows.join(" \\\\\n"))
+        }
+    }
+}
+
+/// Typesets a line of input, or writes it as code if it does not parse.
+fn input_line(input: &str, format: Format, settings: &Settings, variables: &HashSet<String>) -> String {
+    match (parser::parse_statement(input, settings.number_format), format) {
+        (Ok(Statement::Assignment { name, expr }), _) => {
+            format!("{} = {}", symbol(&name, format), typeset(&expr, format, variables))
+        }
+        (Ok(Statement::Expression(expr)), _) => typeset(&expr, format, variables),
+        (Err(_), Format::Markdown) => input.to_string(),
+        (Err(_), Format::Html) => format!("<code>{}</code>", escape(input, format)),
+        (Err(_), Format::Latex) => format!("\\texttt{{{}}}", escape(input, format)),
+    }
+}
+
+/// Typesets the result of a line.
+fn render_outcome(outcome: &Outcome, format: Format, settings: &Settings, variables: &HashSet<String>) -> String {
+    match outcome {
+        Outcome::Value(value) => self::value(value, format, settings.precision, settings.notation),
+        Outcome::Symbolic(expr) => typeset(expr, format, variables),
+        Outcome::Roots(var, roots) => {
+            let roots: Vec<String> = roots
+                .iter()
+                .map(|root| format!("{} = {}", symbol(var, format), value(root, format, settings.precision, settings.notation)))
+                .collect();
+            roots.join(", ")
+        }
+    }
+}
+
+/// Typesets an expression, with the same parentheses it prints with.
+///
+/// # Arguments
+///
+/// * `expr` - The expression.
+/// * `format` - The format to write; Markdown writes the expression as it prints.
+pub fn expression(expr: &Expr, format: Format) -> String {
+    typeset(expr, format, &HashSet::new())
+}
+
+/// Typesets an expression in which the names that are units, other than `variables`, are
+/// units and written upright, as in `9.81 m / s^2`.
+fn typeset(expr: &Expr, format: Format, variables: &HashSet<String>) -> String {
+    if format == Format::Markdown {
+        return expr.to_string();
+    }
+    let expression = |expr: &Expr, format: Format| typeset(expr, format, variables);
+    let (first_parens, second_parens) = expr.operand_parentheses();
+    let operand = |operand: &Expr, parenthesize: bool| {
+        let text = expression(operand, format);
+        match (parenthesize, format) {
+            (false, _) => text,
+            (true, Format::Latex) => format!("\\left({}\\right)", text),
+            (true, _) => format!("({})", text),
+        }
+    };
+    match expr {
+        // Numbers in expressions print without exponents.
+        Expr::Number(value) | Expr::NumberWithUnit { value, unit: None } => value.to_string(),
+        Expr::NumberWithUnit { value, unit: Some(unit) } if units::resolve(unit).is_ok() => {
+            quantity(&value.to_string(), Some(unit), format)
+        }
+        // A number with a suffix that is not a unit, as in `2 x`, is a product.
+        Expr::NumberWithUnit { value, unit: Some(name) } => {
+            format!("{}{}", value, symbol(name, format))
+        }
+        Expr::Symbol(name) if units::is_unit(name) && !variables.contains(name) => match format {
+            Format::Latex => format!("\\si{{{}}}", unit(name, format)),
+            _ => unit(name, format),
+        },
+        Expr::Symbol(name) => symbol(name, format),
+        Expr::UnaryOp { op, expr: inner } => {
+            let inner = operand(inner, first_parens);
+            match (op, format) {
+                (UnaryOp::Plus, _) => format!("+{}", inner),
+                (UnaryOp::Minus, Format::Html) => format!("−{}", inner),
+                (UnaryOp::Minus, _) => format!("-{}", inner),
+                (UnaryOp::Not, Format::Latex) => format!("\\lnot {}", inner),
+                (UnaryOp::Not, _) => format!("not {}", inner),
+                (UnaryOp::Factorial, _) => format!("{}!", inner),
+                (UnaryOp::Percent, Format::Latex) => format!("{}\\%", inner),
+                (UnaryOp::Percent, _) => format!("{}%", inner),
+            }
+        }
+        Expr::BinaryOp { left, op, right } => match (op, format) {
+            // The fraction bar and the raised exponent group their operands.
+            (BinaryOp::Divide, Format::Latex) => {
+                format!("\\frac{{{}}}{{{}}}", expression(left, format), expression(right, format))
+            }
+            (BinaryOp::Power, Format::Latex) => format!("{}^{{{}}}", operand(left, first_parens), expression(right, format)),
+            (BinaryOp::Power, _) => format!("{}<sup>{}</sup>", operand(left, first_parens), expression(right, format)),
+            _ => format!("{}{}{}", operand(left, first_parens), operator(*op, format), operand(right, second_parens)),
+        },
+        Expr::Function { name, args } => {
+            let args: Vec<String> = args.iter().map(|arg| expression(arg, format)).collect();
+            let args = args.join(", ");
+            match (name.as_str(), format) {
+                ("sqrt", Format::Latex) => format!("\\sqrt{{{}}}", args),
+                ("abs", Format::Latex) => format!("\\left|{}\\right|", args),
+                ("sin" | "cos" | "tan" | "ln" | "log" | "exp", Format::Latex) => format!("\\{}\\left({}\\right)", name, args),
+                (_, Format::Latex) => format!("\\operatorname{{{}}}\\left({}\\right)", escape(name, format), args),
+                ("sqrt", _) => format!("√({})", args),
+                _ => format!("{}({})", escape(name, format), args),
+            }
+        }
+        Expr::Conversion { expr: inner, target_unit } => match format {
+            Format::Latex => format!("{} \\to \\si{{{}}}", operand(inner, first_parens), unit(target_unit, format)),
+            _ => format!("{} → {}", operand(inner, first_parens), unit(target_unit, format)),
+        },
+        Expr::Equation { left, right } => format!("{} = {}", expression(left, format), expression(right, format)),
+    }
+}
+
+/// Returns a binary operator with its spacing, other than the power and, in LaTeX, division.
+fn operator(op: BinaryOp, format: Format) -> String {
+    let text = match (op, format) {
+        (BinaryOp::Multiply, Format::Latex) => " \\cdot ",
+        (BinaryOp::Multiply, _) => " × ",
+        (BinaryOp::Subtract, Format::Html) => " − ",
+        (BinaryOp::FloorDivide, Format::Latex) => " \\mathbin{//} ",
+        (BinaryOp::Modulo, Format::Latex) => " \\bmod ",
+        (BinaryOp::PlusMinus, Format::Latex) => " \\pm ",
+        (BinaryOp::Equal, _) => " = ",
+        (BinaryOp::NotEqual, Format::Latex) => " \\neq ",
+        (BinaryOp::NotEqual, _) => " ≠ ",
+        (BinaryOp::Less, Format::Html) => " &lt; ",
+        (BinaryOp::LessEqual, Format::Latex) => " \\leq ",
+        (BinaryOp::LessEqual, _) => " ≤ ",
+        (BinaryOp::Greater, Format::Html) => " &gt; ",
+        (BinaryOp::GreaterEqual, Format::Latex) => " \\geq ",
+        (BinaryOp::GreaterEqual, _) => " ≥ ",
+        (BinaryOp::And, Format::Latex) => " \\land ",
+        (BinaryOp::Or, Format::Latex) => " \\lor ",
+        _ => return op.to_string(),
+    };
+    text.to_string()
+}
+
+/// Typesets the name of a variable, in italics, or `π` for `pi`.
+fn symbol(name: &str, format: Format) -> String {
+    match (format, name) {
+        (Format::Markdown, _) => name.to_string(),
+        (Format::Html, "pi") => "π".to_string(),
+        (Format::Html, _) => format!("<i>{}</i>", escape(name, format)),
+        (Format::Latex, "pi") => "\\pi".to_string(),
+        (Format::Latex, _) if name.chars().count() == 1 => name.to_string(),
+        (Format::Latex, _) => format!("\\mathit{{{}}}", escape(name, format)),
+    }
+}
+
+/// Typesets a value with its unit and uncertainty, e.g., `9.81 ± 0.02 m/s<sup>2</sup>` in
+/// HTML or `\SI{9.81 +- 0.02}{m.s^{-2}}` in LaTeX.
+///
+/// # Arguments
+///
+/// * `value` - The value.
+/// * `format` - The format to write.
+/// * `precision` - Significant digits, or decimal places in fixed notation, see
+///   [`ValueWithUnit::format`].
+/// * `notation` - How the number is written.
+pub fn value(value: &ValueWithUnit, format: Format, precision: Option<usize>, notation: Notation) -> String {
+    if value.is_boolean() {
+        return match format {
+            Format::Latex => format!("\\text{{{}}}", value),
+            _ => value.to_string(),
+        };
+    }
+    let number = ValueWithUnit { unit: None, ..value.clone() }.format(precision, notation);
+    quantity(&number, value.unit.as_deref(), format)
+}
+
+/// Typesets a number as it prints, such as `6.02e23` or `(6.674 ± 0.015)e-11`, with a unit.
+fn quantity(number: &str, unit: Option<&str>, format: Format) -> String {
+    if format == Format::Latex {
+        if !number.chars().any(|c| c.is_ascii_digit()) {
+            // siunitx does not read `inf` or `NaN`.
+            return format!("\\text{{{}}}", number);
+        }
+        let number = number.replace(['(', ')'], "").replace(" ± ", " +- ");
+        return match unit {
+            Some(unit) => format!("\\SI{{{}}}{{{}}}", number, self::unit(unit, format)),
+            None => format!("\\num{{{}}}", number),
+        };
+    }
+    // An exponent follows a digit or the parenthesis of an uncertain value.
+    let mut text = String::new();
+    let mut rest = number;
+    while let Some(index) = rest.find('e').filter(|&index| index > 0) {
+        let (mantissa, exponent) = (&rest[..index], &rest[index + 1..]);
+        let end = exponent.find(|c: char| !(c.is_ascii_digit() || c == '-')).unwrap_or(exponent.len());
+        text.push_str(&format!("{} × 10{}", mantissa, superscript(&exponent[..end], format)));
+        rest = &exponent[end..];
+    }
+    text.push_str(rest);
+    match unit {
+        Some(unit) => format!("{} {}", text, self::unit(unit, format)),
+        None => text,
+    }
+}
+
+/// Typesets a unit, e.g., `kg·m²/s²` in Markdown, or `kg.m^{2}.s^{-2}` for siunitx.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression, as units print.
+/// * `format` - The format to write.
+pub fn unit(unit: &str, format: Format) -> String {
+    let Ok(terms) = units::parse_terms(unit) else {
+        return escape(unit, format);
+    };
+    if format == Format::Latex {
+        let terms: Vec<String> = terms
+            .iter()
+            .map(|(symbol, exponent)| match exponent {
+                1 => latex_unit_symbol(symbol),
+                n => format!("{}^{{{}}}", latex_unit_symbol(symbol), n),
+            })
+            .collect();
+        return terms.join(".");
+    }
+    let power = |symbol: &str, exponent: i32| match exponent {
+        1 => escape(symbol, format),
+        n => format!("{}{}", escape(symbol, format), superscript(&n.to_string(), format)),
+    };
+    let numerator: Vec<String> =
+        terms.iter().filter(|(_, exponent)| *exponent > 0).map(|(symbol, exponent)| power(symbol, *exponent)).collect();
+    let mut text = if numerator.is_empty() { "1".to_string() } else { numerator.join("·") };
+    for (symbol, exponent) in terms.iter().filter(|(_, exponent)| *exponent < 0) {
+        text.push('/');
+        text.push_str(&power(symbol, -exponent));
+    }
+    text
+}
+
+/// Writes a unit symbol for siunitx, with the symbols LaTeX has no key for as macros.
+fn latex_unit_symbol(symbol: &str) -> String {
+    symbol
+        .chars()
+        .map(|c| match c {
+            'µ' | 'μ' => "\\micro ".to_string(),
+            '°' => "\\degree ".to_string(),
+            'Ω' => "\\ohm ".to_string(),
+            '%' => "\\percent ".to_string(),
+            _ => escape(&c.to_string(), Format::Latex),
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Raises an integer, written with Unicode superscripts in Markdown.
+fn superscript(exponent: &str, format: Format) -> String {
+    match format {
+        Format::Html => format!("<sup>{}</sup>", exponent),
+        Format::Latex => format!("^{{{}}}", exponent),
+        Format::Markdown => exponent
+            .chars()
+            .map(|c| match c {
+                '-' => '⁻',
+                '0' => '⁰',
+                '1' => '¹',
+                '2' => '²',
+                '3' => '³',
+                '4'..='9' => char::from_u32(0x2070 + c.to_digit(10).unwrap()).unwrap_or(c),
+                _ => c,
+            })
+            .collect(),
+    }
+}
+
+/// Escapes the characters of text that the format would read as markup.
+fn escape(text: &str, format: Format) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (format, c) {
+            (Format::Markdown, '|') => escaped.push_str("\\|"),
+            // Table rows are single lines.
+            (Format::Markdown, '\n') => escaped.push(' '),
+            (Format::Html, '&') => escaped.push_str("&amp;"),
+            (Format::Html, '<') => escaped.push_str("&lt;"),
+            (Format::Html, '>') => escaped.push_str("&gt;"),
+            (Format::Html, '"') => escaped.push_str("&quot;"),
+            (Format::Latex, '\\') => escaped.push_str("\\textbackslash{}"),
+            (Format::Latex, '<') => escaped.push_str("\\textless{}"),
+            (Format::Latex, '>') => escaped.push_str("\\textgreater{}"),
+            (Format::Latex, '|') => escaped.push_str("\\textbar{}"),
+            (Format::Latex, '~') => escaped.push_str("\\textasciitilde{}"),
+            (Format::Latex, '^') => escaped.push_str("\\textasciicircum{}"),
+            (Format::Latex, '{' | '}' | '$' | '&' | '#' | '_' | '%') => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Expr {
+        parser::parse_expression(input).unwrap()
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("report.md")), Some(Format::Markdown));
+        assert_eq!(Format::from_path(Path::new("out/Report.HTML")), Some(Format::Html));
+        assert_eq!(Format::from_path(Path::new("paper.tex")), Some(Format::Latex));
+        assert_eq!(Format::from_path(Path::new("notes.txt")), None);
+        assert!("pdf".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        let expr = parse("-(a + 2)^2 / sqrt(2 m) to km");
+        assert_eq!(expression(&expr, Format::Markdown), expr.to_string());
+        assert_eq!(expression(&expr, Format::Html), "−(<i>a</i> + 2)<sup>2</sup> / √(2 m) → km");
+        assert_eq!(
+            expression(&expr, Format::Latex),
+            "\\frac{-\\left(a + 2\\right)^{2}}{\\sqrt{\\SI{2}{m}}} \\to \\si{km}"
+        );
+        assert_eq!(expression(&parse("rate_max * 3 <= pi"), Format::Latex), "\\mathit{rate\\_max} \\cdot 3 \\leq \\pi");
+
+        // Units written on their own are upright, not variables.
+        let expr = parse("9.81 m / s^2");
+        assert_eq!(expression(&expr, Format::Html), "9.81 m / s<sup>2</sup>");
+        assert_eq!(expression(&expr, Format::Latex), "\\frac{\\SI{9.81}{m}}{\\si{s}^{2}}");
+    }
+
+    #[test]
+    fn test_render_units_and_special_characters() {
+        let mut document = Document::new();
+        let mut calculator = Calculator::new();
+        for input in ["h = 2 m", "h / s", "1 | 2 <> 3"] {
+            document.blocks.push(Block::Calculation { input: input.to_string(), result: Some(calculator.execute(input)) });
+        }
+        let html = document.render(Format::Html, calculator.settings());
+        assert!(html.contains("<td class=\"input\"><i>h</i> / s</td>"));
+        let markdown = document.render(Format::Markdown, calculator.settings());
+        assert!(markdown.contains("| `1 \\| 2 <> 3` | **Error:** Error parsing expression '1 \\| 2 <> 3'"));
+        assert_eq!(markdown.lines().filter(|line| line.starts_with("| `")).count(), 3);
+        let latex = document.render(Format::Latex, calculator.settings());
+        assert!(latex.contains("h = \\SI{2}{m} &\\Rightarrow \\SI{2}{m}"));
+        assert!(latex.contains("\\frac{h}{\\si{s}}"));
+        assert!(latex.contains("\\texttt{1 \\textbar{} 2 \\textless{}\\textgreater{} 3}"));
+    }
+
+    #[test]
+    fn test_value_and_unit() {
+        let acceleration = ValueWithUnit::uncertain(9.81, 0.02, Some("m/s^2".to_string()));
+        assert_eq!(value(&acceleration, Format::Markdown, None, Notation::Auto), "9.81 ± 0.02 m/s²");
+        assert_eq!(value(&acceleration, Format::Html, None, Notation::Auto), "9.81 ± 0.02 m/s<sup>2</sup>");
+        assert_eq!(value(&acceleration, Format::Latex, None, Notation::Auto), "\\SI{9.81 +- 0.02}{m.s^{-2}}");
+        let avogadro = ValueWithUnit::new(6.02214076e23, Some("1/mol".to_string()));
+        assert_eq!(value(&avogadro, Format::Markdown, Some(3), Notation::Scientific), "6.02 × 10²³ 1/mol");
+        assert_eq!(value(&avogadro, Format::Latex, Some(3), Notation::Scientific), "\\SI{6.02e23}{mol^{-1}}");
+        assert_eq!(unit("kg*m^2/s^2", Format::Html), "kg·m<sup>2</sup>/s<sup>2</sup>");
+        assert_eq!(unit("µm", Format::Latex), "\\micro m");
+        assert_eq!(value(&ValueWithUnit::boolean(true), Format::Latex, None, Notation::Auto), "\\text{true}");
+    }
+
+    #[test]
+    fn test_from_script() {
+        let source = "#!/usr/bin/env calc\n# Trip\n# by car\n\nd = 42 km  # one way\nfor i in 1..2 {\n    print(\"leg {i}\")\n}\nd / 2 h to km/h\nd + 1 s\n";
+        let document = Document::from_script(source, &mut Calculator::new()).unwrap();
+        let markdown = document.render(Format::Markdown, &Settings::default());
+        assert_eq!(
+            markdown,
+            "Trip by car\n\n| Input | Result |\n| --- | --- |\n| `d = 42 km` | 42 km |\n\n\
+             ```\nfor i in 1..2 {\n    print(\"leg {i}\")\n}\n```\n\n```text\nleg 1\nleg 2\n```\n\n\
+             | Input | Result |\n| --- | --- |\n| `d / (2 h) to km/h` | 21 km/h |\n\
+             | `d + 1 s` | **Error:** Error evaluating 'd + 1 s': Unit mismatch in addition or subtraction |\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html_and_latex() {
+        let mut document = Document::new();
+        document.blocks.push(Block::Text("Heat <loss> & gain".to_string()));
+        let mut calculator = Calculator::new();
+        for input in ["q = 3 W * 2 h", "q + 1 m"] {
+            document.blocks.push(Block::Calculation { input: input.to_string(), result: Some(calculator.execute(input)) });
+        }
+        let html = document.render(Format::Html, calculator.settings());
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>\n"));
+        assert!(html.contains("<p>Heat &lt;loss&gt; &amp; gain</p>"));
+        assert!(html.contains("<tr><td class=\"input\"><i>q</i> = 3 W × 2 h</td><td class=\"result\">6 W·h</td></tr>"));
+        assert!(html.contains("<tr class=\"error\"><td class=\"input\"><i>q</i> + 1 m</td><td class=\"result\">Error: "));
+        let latex = document.render(Format::Latex, calculator.settings());
+        assert!(latex.contains("\\usepackage{siunitx}") && latex.ends_with("\\end{document}\n"));
+        assert!(latex.contains("q = \\SI{3}{W} \\cdot \\SI{2}{h} &\\Rightarrow \\SI{6}{W.h} \\\\\n"));
+        assert!(latex.contains("q + \\SI{1}{m} &\\Rightarrow \\textcolor{red}{\\text{Error: "));
+    }
+}
@@ -0,0 +1,238 @@
+//! C API for using the calculator from other languages.
+//!
+//! The functions here wrap [`Calculator`] behind an opaque pointer and return results as
+//! plain C structs. Strings handed out by the library must be released with
+//! [`textcalc_result_free`]; the header is `include/textcalc.h`.
+
+use crate::calculator::{Calculator, Outcome};
+use crate::evaluator::ValueWithUnit;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// An opaque calculator context, created by [`textcalc_new`] and destroyed by [`textcalc_free`].
+pub struct TextCalc {
+    calculator: Calculator,
+}
+
+/// What an evaluation produced.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextCalcKind {
+    /// A number, in `value`, with its unit in `unit`.
+    Number = 0,
+    /// A boolean, in `value` as `1` or `0`.
+    Boolean = 1,
+    /// An expression with free symbols, simplified, in `text`.
+    Symbolic = 2,
+    /// The roots of a top-level `solve`, listed in `text`; `value` and `unit` hold the first.
+    Roots = 3,
+    /// The evaluation failed; the message is in `error`.
+    Error = 4,
+}
+
+/// The result of [`textcalc_eval`]. Unused string fields are null.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TextCalcResult {
+    /// What the evaluation produced.
+    pub kind: TextCalcKind,
+    /// The numeric value, or `NaN` when there is none.
+    pub value: f64,
+    /// The unit of `value`, or null for a unitless value.
+    pub unit: *mut c_char,
+    /// The result as the CLI prints it, e.g., `2.2 lb`, or null on error.
+    pub text: *mut c_char,
+    /// The error message, or null on success.
+    pub error: *mut c_char,
+}
+
+/// Converts a Rust string into a C string owned by the caller.
+fn into_c_string(text: &str) -> *mut c_char {
+    // Interior NUL bytes cannot appear in a C string, so they are dropped.
+    CString::new(text.replace('\0', "")).unwrap_or_default().into_raw()
+}
+
+/// Builds a result holding an error message.
+fn error_result(message: &str) -> TextCalcResult {
+    TextCalcResult {
+        kind: TextCalcKind::Error,
+        value: f64::NAN,
+        unit: ptr::null_mut(),
+        text: ptr::null_mut(),
+        error: into_c_string(message),
+    }
+}
+
+/// Builds a result holding a value, with the given kind and display text.
+fn value_result(kind: TextCalcKind, value: Option<&ValueWithUnit>, text: &str) -> TextCalcResult {
+    TextCalcResult {
+        kind,
+        value: value.map_or(f64::NAN, |value| value.value),
+        unit: value
+            .and_then(|value| value.unit.as_deref())
+            .map_or(ptr::null_mut(), into_c_string),
+        text: into_c_string(text),
+        error: ptr::null_mut(),
+    }
+}
+
+/// Converts an evaluation outcome into a C result.
+fn outcome_result(outcome: Outcome) -> TextCalcResult {
+    let text = outcome.to_string();
+    match &outcome {
+        Outcome::Value(value) if value.is_boolean() => value_result(TextCalcKind::Boolean, Some(value), &text),
+        Outcome::Value(value) => value_result(TextCalcKind::Number, Some(value), &text),
+        Outcome::Symbolic(_) => value_result(TextCalcKind::Symbolic, None, &text),
+        Outcome::Roots(_, roots) => value_result(TextCalcKind::Roots, roots.first(), &text),
+    }
+}
+
+/// Creates a calculator context with the default settings.
+///
+/// # Returns
+///
+/// * A new context, to be destroyed with [`textcalc_free`].
+#[no_mangle]
+pub extern "C" fn textcalc_new() -> *mut TextCalc {
+    Box::into_raw(Box::new(TextCalc {
+        calculator: Calculator::new(),
+    }))
+}
+
+/// Destroys a calculator context. Passing null does nothing.
+///
+/// # Safety
+///
+/// `context` must be null or a pointer returned by [`textcalc_new`] that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn textcalc_free(context: *mut TextCalc) {
+    if !context.is_null() {
+        drop(Box::from_raw(context));
+    }
+}
+
+/// Evaluates one expression, e.g., `1 kg to lb`, or assignment, e.g., `d = 5 km`. Assigned
+/// variables are kept in the context for later calls.
+///
+/// # Arguments
+///
+/// * `context` - The calculator context.
+/// * `expression` - A NUL-terminated UTF-8 string.
+///
+/// # Returns
+///
+/// * The result, whose strings must be released with [`textcalc_result_free`].
+///
+/// # Safety
+///
+/// `context` must be a live pointer from [`textcalc_new`] and `expression` a valid
+/// NUL-terminated string. A context must not be used from two threads at once.
+#[no_mangle]
+pub unsafe extern "C" fn textcalc_eval(context: *mut TextCalc, expression: *const c_char) -> TextCalcResult {
+    if context.is_null() || expression.is_null() {
+        return error_result("textcalc_eval was given a null pointer");
+    }
+    let expression = match CStr::from_ptr(expression).to_str() {
+        Ok(expression) => expression,
+        Err(_) => return error_result("The expression is not valid UTF-8"),
+    };
+    let calculator = &mut (*context).calculator;
+    // A panic must not unwind into the caller's C frames.
+    match panic::catch_unwind(AssertUnwindSafe(|| calculator.execute(expression))) {
+        Ok(Ok(outcome)) => outcome_result(outcome),
+        Ok(Err(message)) => error_result(&message),
+        Err(_) => error_result("Internal error while evaluating the expression"),
+    }
+}
+
+/// Releases the strings of a result and sets them to null. Passing null does nothing.
+///
+/// # Safety
+///
+/// `result` must be null or point to a result returned by [`textcalc_eval`] whose strings
+/// have not been released already.
+#[no_mangle]
+pub unsafe extern "C" fn textcalc_result_free(result: *mut TextCalcResult) {
+    if let Some(result) = result.as_mut() {
+        for field in [&mut result.unit, &mut result.text, &mut result.error] {
+            if !field.is_null() {
+                drop(CString::from_raw(*field));
+                *field = ptr::null_mut();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates through the C API and returns the kind, value and strings of the result.
+    fn eval(expression: &str) -> (TextCalcKind, f64, Option<String>, Option<String>, Option<String>) {
+        let read = |text: *mut c_char| (!text.is_null()).then(|| unsafe { CStr::from_ptr(text) }.to_string_lossy().into_owned());
+        let expression = CString::new(expression).unwrap();
+        unsafe {
+            let context = textcalc_new();
+            let mut result = textcalc_eval(context, expression.as_ptr());
+            let fields = (result.kind, result.value, read(result.unit), read(result.text), read(result.error));
+            textcalc_result_free(&mut result);
+            assert!(result.unit.is_null() && result.text.is_null() && result.error.is_null());
+            textcalc_free(context);
+            fields
+        }
+    }
+
+    #[test]
+    fn test_eval_number() {
+        let (kind, value, unit, text, error) = eval("5 kg + 3 kg");
+        assert_eq!(kind, TextCalcKind::Number);
+        assert_eq!(value, 8.0);
+        assert_eq!(unit.as_deref(), Some("kg"));
+        assert_eq!(text.as_deref(), Some("8 kg"));
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn test_eval_other_kinds() {
+        assert_eq!(eval("1 < 2").0, TextCalcKind::Boolean);
+        assert_eq!(eval("diff(x^2, x)").3.as_deref(), Some("2 * x"));
+        let (kind, value, ..) = eval("solve(x^2 = 4, x)");
+        assert_eq!((kind, value), (TextCalcKind::Roots, -2.0));
+    }
+
+    #[test]
+    fn test_eval_assignment() {
+        let read = |text: *mut c_char| unsafe { CStr::from_ptr(text) }.to_string_lossy().into_owned();
+        unsafe {
+            let context = textcalc_new();
+            let mut result = textcalc_eval(context, c"d = 5 km".as_ptr());
+            assert_eq!((result.kind, read(result.text)), (TextCalcKind::Number, "5 km".to_string()));
+            textcalc_result_free(&mut result);
+            let mut result = textcalc_eval(context, c"d * 2 to m".as_ptr());
+            assert_eq!((result.value, read(result.unit)), (10000.0, "m".to_string()));
+            textcalc_result_free(&mut result);
+            textcalc_free(context);
+        }
+    }
+
+    #[test]
+    fn test_eval_error() {
+        let (kind, value, unit, text, error) = eval("1 kg to m");
+        assert_eq!(kind, TextCalcKind::Error);
+        assert!(value.is_nan());
+        assert!(unit.is_none() && text.is_none());
+        assert!(error.unwrap().contains("not supported"));
+    }
+
+    #[test]
+    fn test_null_pointers() {
+        unsafe {
+            let mut result = textcalc_eval(ptr::null_mut(), ptr::null());
+            assert_eq!(result.kind, TextCalcKind::Error);
+            textcalc_result_free(&mut result);
+            textcalc_result_free(ptr::null_mut());
+            textcalc_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! Financial functions: loan payments, present and future values, net present value,
+//! internal rate of return and compound interest.
+//!
+//! `pmt`, `fv`, `pv` and `nper` follow the sign convention of spreadsheets: money paid out
+//! is negative and money received is positive, so the payment on a loan of 400000 is
+//! negative. Amounts may carry a currency or any other unit; they are converted to the unit
+//! of the first one, which is also the unit of the result.
+
+use crate::ast::Expr;
+use crate::evaluator::{evaluate_with, Context, ValueWithUnit};
+use crate::{solver, units};
+
+/// The lowest rate of return `irr` looks for, as a fraction.
+const IRR_LOW: f64 = -0.99;
+/// The highest rate of return `irr` looks for, as a fraction.
+const IRR_HIGH: f64 = 10.0;
+/// The most periods an amortization schedule may have.
+const MAX_PERIODS: f64 = 100_000.0;
+
+/// One period of an amortization schedule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Installment {
+    /// The period, counting from 1.
+    pub period: usize,
+    /// The amount paid in the period.
+    pub payment: f64,
+    /// The part of the payment that is interest.
+    pub interest: f64,
+    /// The part of the payment that repays the principal.
+    pub principal: f64,
+    /// The balance left after the payment.
+    pub balance: f64,
+}
+
+/// Checks whether a function name is one of the built-ins handled by this module.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+///
+/// # Returns
+///
+/// * `true` for `pmt`, `fv`, `pv`, `nper`, `npv`, `irr` and `compound`.
+pub fn is_finance_function(name: &str) -> bool {
+    matches!(name, "pmt" | "fv" | "pv" | "nper" | "npv" | "irr" | "compound")
+}
+
+/// Evaluates a call to one of the financial functions.
+///
+/// The arguments are evaluated here rather than by the caller, because the last argument
+/// of `compound` may name a compounding frequency such as `monthly`.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+/// * `args` - The unevaluated arguments.
+/// * `context` - The variable bindings of the surrounding expression.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The result, in the unit of the amounts for money.
+/// * `Err(String)` - An error message if the call is malformed or has no result.
+pub fn call(name: &str, args: &[Expr], context: &Context) -> Result<ValueWithUnit, String> {
+    if name == "compound" {
+        return compound_call(args, context);
+    }
+    let (arity, usage) = match name {
+        "pmt" => ("3 to 5", "pmt(rate, periods, present value, future value, type)"),
+        "fv" => ("3 to 5", "fv(rate, periods, payment, present value, type)"),
+        "pv" => ("3 to 5", "pv(rate, periods, payment, future value, type)"),
+        "nper" => ("3 to 5", "nper(rate, payment, present value, future value, type)"),
+        "npv" => ("at least 2", "npv(rate, [cash flows])"),
+        _ => ("at least 2", "irr([cash flows])"),
+    };
+    let fits = match name {
+        "npv" | "irr" => args.len() >= 2,
+        _ => (3..=5).contains(&args.len()),
+    };
+    if !fits {
+        return Err(format!("{} expects {} arguments, as in {}", name, arity, usage));
+    }
+    let values = args
+        .iter()
+        .map(|arg| evaluate_with(arg, context)?.number(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if name == "irr" {
+        let (flows, _) = amounts(name, &values, context)?;
+        return Ok(ValueWithUnit::new(internal_rate_of_return(&flows, context)?, None));
+    }
+    let rate = unitless(name, "rate", &values[0])?;
+    // At -100% or below, money vanishes or changes sign each period.
+    if !(rate > -1.0 && rate.is_finite()) {
+        return Err(format!("The rate of {} must be a number above -100%, not {}", name, rate));
+    }
+    if name == "npv" {
+        let (flows, unit) = amounts(name, &values[1..], context)?;
+        return Ok(ValueWithUnit::new(net_present_value(rate, &flows), unit));
+    }
+    // The optional type says whether payments fall at the end (0) or start (1) of each period.
+    let due = match values.get(4).map(|due| unitless(name, "type", due)).transpose()? {
+        None | Some(0.0) => false,
+        Some(1.0) => true,
+        Some(_) => {
+            return Err(format!("The type of {} must be 0 (payments at the end of each period) or 1 (at the start)", name))
+        }
+    };
+    let values = &values[..values.len().min(4)];
+    if name == "nper" {
+        let (money, _) = amounts(name, &values[1..], context)?;
+        let future = money.get(2).copied().unwrap_or(0.0);
+        return Ok(ValueWithUnit::new(periods_needed(rate, money[0], money[1], future, due)?, None));
+    }
+    let periods = unitless(name, "number of periods", &values[1])?;
+    if !periods.is_finite() || (name == "pmt" && periods == 0.0) {
+        return Err(format!("The number of periods of {} must be a finite number other than 0, not {}", name, periods));
+    }
+    let (money, unit) = amounts(name, &values[2..], context)?;
+    let other = money.get(1).copied().unwrap_or(0.0);
+    let result = match name {
+        "pmt" => payment(rate, periods, money[0], other, due),
+        "fv" => future_value(rate, periods, money[0], other, due),
+        _ => present_value(rate, periods, money[0], other, due),
+    };
+    if !result.is_finite() {
+        return Err(format!("{} has no finite result for these arguments", name));
+    }
+    Ok(ValueWithUnit::new(result, unit))
+}
+
+/// Returns the value of an argument that must be unitless, such as a rate.
+fn unitless(name: &str, what: &str, value: &ValueWithUnit) -> Result<f64, String> {
+    match &value.unit {
+        None => Ok(value.value),
+        Some(unit) => Err(format!("The {} of {} must be unitless, not in {}", what, name, unit)),
+    }
+}
+
+/// Converts amounts of money to the unit of the first one that has a unit; amounts without
+/// a unit, such as a future value of 0, are taken to be in that unit already.
+///
+/// # Returns
+///
+/// * `Ok((Vec<f64>, Option<String>))` - The amounts and their common unit.
+/// * `Err(String)` - An error message if two amounts cannot be converted to each other.
+fn amounts(name: &str, values: &[ValueWithUnit], context: &Context) -> Result<(Vec<f64>, Option<String>), String> {
+    let unit = values.iter().find_map(|value| value.unit.clone());
+    let amounts = values
+        .iter()
+        .map(|value| match (&value.unit, &unit) {
+            (Some(from), Some(to)) if from != to => {
+                context.note_exchange(from, to);
+                units::convert(value.value, from, to)
+                    .map_err(|_| format!("The amounts of {} must be in compatible units, not {} and {}", name, to, from))
+            }
+            _ => Ok(value.value),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((amounts, unit))
+}
+
+/// The growth of one unit of money over `periods` at `rate`, and the sum of the growths of
+/// one payment per period, which is `periods` when the rate is 0.
+fn growth(rate: f64, periods: f64, due: bool) -> (f64, f64) {
+    if rate == 0.0 {
+        return (1.0, periods);
+    }
+    let growth = (1.0 + rate).powf(periods);
+    let annuity = (growth - 1.0) / rate * if due { 1.0 + rate } else { 1.0 };
+    (growth, annuity)
+}
+
+/// The payment per period that turns `present` into `future`, as spreadsheets' `PMT`.
+fn payment(rate: f64, periods: f64, present: f64, future: f64, due: bool) -> f64 {
+    let (growth, annuity) = growth(rate, periods, due);
+    -(present * growth + future) / annuity
+}
+
+/// The value after `periods` of a present value and regular payments, as spreadsheets' `FV`.
+fn future_value(rate: f64, periods: f64, payment: f64, present: f64, due: bool) -> f64 {
+    let (growth, annuity) = growth(rate, periods, due);
+    -(present * growth + payment * annuity)
+}
+
+/// The present value of regular payments and a future value, as spreadsheets' `PV`.
+fn present_value(rate: f64, periods: f64, payment: f64, future: f64, due: bool) -> f64 {
+    let (growth, annuity) = growth(rate, periods, due);
+    -(future + payment * annuity) / growth
+}
+
+/// The number of periods the payments take to turn `present` into `future`, as spreadsheets' `NPER`.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The number of periods, which need not be whole.
+/// * `Err(String)` - An error message if the payments never get there, e.g., because they do
+///   not cover the interest.
+fn periods_needed(rate: f64, payment: f64, present: f64, future: f64, due: bool) -> Result<f64, String> {
+    let periods = if rate == 0.0 {
+        -(present + future) / payment
+    } else {
+        let payment = payment * if due { 1.0 + rate } else { 1.0 };
+        ((payment - future * rate) / (payment + present * rate)).ln() / (1.0 + rate).ln()
+    };
+    if periods.is_finite() && periods >= 0.0 {
+        Ok(periods)
+    } else {
+        Err("nper has no solution: the payments never reach the future value".to_string())
+    }
+}
+
+/// The net present value of cash flows at the end of each period, the first one period from now.
+fn net_present_value(rate: f64, flows: &[f64]) -> f64 {
+    flows.iter().enumerate().map(|(period, flow)| flow / (1.0 + rate).powi(period as i32 + 1)).sum()
+}
+
+/// Finds the rate at which the net present value of cash flows, the first one now, is zero.
+///
+/// The rate is bracketed on a grid between -99% and 1000% and refined with Brent's method.
+/// Cash flows that change sign more than once may have several such rates; then the one
+/// closest to zero is returned, with a warning.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The internal rate of return, as a fraction.
+/// * `Err(String)` - An error message if the cash flows have no rate of return in the range.
+fn internal_rate_of_return(flows: &[f64], context: &Context) -> Result<f64, String> {
+    if !flows.iter().any(|flow| *flow > 0.0) || !flows.iter().any(|flow| *flow < 0.0) {
+        return Err("irr needs at least one positive and one negative cash flow".to_string());
+    }
+    let present = |rate: f64| flows.iter().enumerate().map(|(period, flow)| flow / (1.0 + rate).powi(period as i32)).sum();
+    let roots = solver::find_roots(present, IRR_LOW, IRR_HIGH);
+    let Some(rate) = roots.iter().copied().min_by(|a, b| a.abs().total_cmp(&b.abs())) else {
+        return Err("irr found no rate of return between -99% and 1000%".to_string());
+    };
+    if roots.len() > 1 {
+        let all = roots.iter().map(|root| root.to_string()).collect::<Vec<_>>().join(", ");
+        context.warn(format!("irr found several rates of return ({}); using the one closest to 0", all));
+    }
+    Ok(rate)
+}
+
+/// Evaluates `compound(principal, annual rate, time, frequency)`.
+///
+/// The time is in years unless it has a time unit, and the frequency is a number of
+/// compounding periods per year or one of `annually`, `semiannually`, `quarterly`,
+/// `monthly`, `weekly`, `daily` and `continuously`; it defaults to `annually`.
+fn compound_call(args: &[Expr], context: &Context) -> Result<ValueWithUnit, String> {
+    let (principal, rate, time, frequency) = match args {
+        [principal, rate, time] => (principal, rate, time, None),
+        [principal, rate, time, frequency] => (principal, rate, time, Some(frequency)),
+        _ => {
+            return Err(
+                "compound expects a principal, an annual rate, a time and a frequency, e.g., compound(1000, 4.5%, 10 years, monthly)"
+                    .to_string(),
+            )
+        }
+    };
+    let principal = evaluate_with(principal, context)?.number("compound")?;
+    let rate = unitless("compound", "rate", &evaluate_with(rate, context)?.number("compound")?)?;
+    let time = evaluate_with(time, context)?.number("compound")?;
+    let years = match &time.unit {
+        None => time.value,
+        Some(unit) => units::convert(time.value, unit, "year")
+            .map_err(|_| format!("The time of compound must be a duration, not in {}", unit))?,
+    };
+    let growth = match frequency.map(|frequency| compounding_frequency(frequency, context)).transpose()?.flatten() {
+        None if frequency.is_some() => (rate * years).exp(),
+        None => (1.0 + rate).powf(years),
+        Some(per_year) => (1.0 + rate / per_year).powf(per_year * years),
+    };
+    if !growth.is_finite() {
+        return Err(format!("compound has no finite result at a rate of {} over {} years", rate, years));
+    }
+    Ok(ValueWithUnit::new(principal.value * growth, principal.unit))
+}
+
+/// Returns the number of compounding periods per year a frequency argument stands for, or
+/// `None` for continuous compounding.
+///
+/// # Returns
+///
+/// * `Ok(Option<f64>)` - The periods per year.
+/// * `Err(String)` - An error message if the frequency is not a name or a positive number.
+fn compounding_frequency(frequency: &Expr, context: &Context) -> Result<Option<f64>, String> {
+    if let Expr::Symbol(name) = frequency {
+        if context.variable(name).is_none() {
+            if let Some(per_year) = frequency_name(name) {
+                return Ok(per_year);
+            }
+        }
+    }
+    match evaluate_with(frequency, context)?.number("compound")? {
+        ValueWithUnit { value, unit: None, .. } if value > 0.0 && value.is_finite() => Ok(Some(value)),
+        value => Err(format!(
+            "The frequency of compound must be a positive number of periods per year or a name such as monthly, not {}",
+            value
+        )),
+    }
+}
+
+/// Returns the periods per year of a named compounding frequency: `Some(None)` for
+/// `continuously`, and `None` if the name is not a frequency.
+pub fn frequency_name(name: &str) -> Option<Option<f64>> {
+    match name {
+        "annually" | "yearly" => Some(Some(1.0)),
+        "semiannually" => Some(Some(2.0)),
+        "quarterly" => Some(Some(4.0)),
+        "monthly" => Some(Some(12.0)),
+        "weekly" => Some(Some(52.0)),
+        "daily" => Some(Some(365.0)),
+        "continuously" => Some(None),
+        _ => None,
+    }
+}
+
+/// Builds the amortization schedule of a loan repaid in equal payments at the end of each period.
+///
+/// # Arguments
+///
+/// * `rate` - The interest rate per period, as a fraction.
+/// * `periods` - The number of payments; a whole number.
+/// * `principal` - The amount borrowed.
+///
+/// # Returns
+///
+/// * `Ok(Vec<Installment>)` - One installment per period; the last one pays off the rounding
+///   left in the balance, so the balance ends at exactly 0.
+/// * `Err(String)` - An error message if the number of periods is not a positive whole number.
+pub fn schedule(rate: f64, periods: f64, principal: f64) -> Result<Vec<Installment>, String> {
+    if periods < 1.0 || periods.fract() != 0.0 || periods > MAX_PERIODS {
+        return Err(format!("The number of periods must be a whole number from 1 to {}", MAX_PERIODS));
+    }
+    if !(rate > -1.0 && rate.is_finite() && principal.is_finite()) {
+        return Err("The rate must be above -100% and the principal finite".to_string());
+    }
+    let payment = -payment(rate, periods, principal, 0.0, false);
+    let mut balance = principal;
+    let installments = (1..=periods as usize)
+        .map(|period| {
+            let interest = balance * rate;
+            let repaid = if period == periods as usize { balance } else { payment - interest };
+            balance -= repaid;
+            Installment { period, payment: interest + repaid, interest, principal: repaid, balance }
+        })
+        .collect();
+    Ok(installments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::evaluate;
+    use crate::parser::parse_expression;
+
+    fn eval(input: &str) -> Result<ValueWithUnit, String> {
+        evaluate(&parse_expression(input).unwrap())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9 * expected.abs().max(1.0), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_time_value_of_money() {
+        assert_close(eval("pmt(5%/12, 360, 400000)").unwrap().value, -2147.2864920485);
+        assert_close(eval("fv(5%, 10, -100)").unwrap().value, 1257.789253554883);
+        assert_close(eval("pv(5%, 10, -100)").unwrap().value, 772.1734929184818);
+        assert_close(eval("nper(5%/12, -2147.2864920485, 400000)").unwrap().value, 360.0);
+        assert_close(eval("pmt(0, 10, 1000)").unwrap().value, -100.0);
+        assert_close(eval("pmt(5%, 10, 1000, 0, 1)").unwrap().value, -123.337690443292);
+        assert!(eval("nper(5%, -10, 1000)").is_err());
+        assert!(eval("pmt(5%, 10)").unwrap_err().contains("3 to 5 arguments"));
+    }
+
+    #[test]
+    fn test_degenerate_arguments() {
+        assert!(eval("pmt(5%/12, 0, 1000)").unwrap_err().contains("number of periods"));
+        assert!(eval("pmt(0, 0, 1000)").is_err());
+        assert!(eval("pmt(-100%, 10, 1000)").unwrap_err().contains("above -100%"));
+        assert!(eval("fv(-150%, 10, -100)").is_err());
+        assert!(eval("npv(-100%, [100, 200])").is_err());
+        assert!(eval("nper(-1, -100, 1000)").is_err());
+        assert!(eval("pv(5%, 1e6, -100)").unwrap_err().contains("no finite result"));
+        assert_close(eval("fv(5%, 0, -100, -1000)").unwrap().value, 1000.0);
+    }
+
+    #[test]
+    fn test_npv_and_irr() {
+        assert_close(eval("npv(10%, [-1000, 300, 400, 500])").unwrap().value, -19.124376750222);
+        assert_close(eval("npv(10%, 100)").unwrap().value, 90.9090909091);
+        assert_close(eval("irr([-1000, 300, 400, 500])").unwrap().value, 0.0889633946933);
+        assert!(eval("irr([100, 200])").unwrap_err().contains("positive and one negative"));
+
+        // Cash flows with two sign changes have two rates of return.
+        let context = Context::new();
+        let rate = evaluate_with(&parse_expression("irr([-100, 230, -132])").unwrap(), &context).unwrap();
+        assert_close(rate.value, 0.1);
+        assert_eq!(context.take_warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_compound() {
+        assert_close(eval("compound(1000, 4.5%, 10 years, monthly)").unwrap().value, 1566.99277628178);
+        assert_close(eval("compound(1000, 4.5%, 10)").unwrap().value, 1552.9694217329);
+        assert_close(eval("compound(1000, 4.5%, 120 months, 12)").unwrap().value, 1566.99277628178);
+        assert_close(eval("compound(1000, 5%, 1, continuously)").unwrap().value, 1051.271096376);
+        assert!(eval("compound(1000, 5%, 2 kg)").is_err());
+        assert!(eval("compound(1000, 5%, 2, fortnightly)").is_err());
+        assert!(eval("compound(1000, -200%, 0.5)").unwrap_err().contains("no finite result"));
+    }
+
+    #[test]
+    fn test_amounts_with_units() {
+        let payment = eval("pmt(1%, 12, 1000 kg)").unwrap();
+        assert_eq!(payment.unit.as_deref(), Some("kg"));
+        assert_close(eval("fv(0, 10, -1 g, 1 kg)").unwrap().value, -990.0);
+        assert!(eval("pmt(1%, 12, 1000 kg, 5 m)").unwrap_err().contains("compatible units"));
+        assert!(eval("pmt(1%, 12 m, 1000)").unwrap_err().contains("unitless"));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let installments = schedule(0.01, 12.0, 1000.0).unwrap();
+        assert_eq!(installments.len(), 12);
+        assert_close(installments[0].interest, 10.0);
+        assert_close(installments[0].payment, 88.8487886783);
+        assert_eq!(installments[11].balance, 0.0);
+        assert!(schedule(0.01, 2.5, 1000.0).is_err());
+    }
+}
@@ -1,8 +1,42 @@
 //! Calculator library module.
 //!
 //! This module provides functionality to parse and evaluate mathematical expressions,
-//! including support for units, functions, and conversions.
+//! including support for units, functions, and conversions. Programs embedding the
+//! calculator should start from [`calculator::Calculator`].
+//!
+//! - [`ast`], [`parser`], [`evaluator`] and [`compile`]: reading and evaluating expressions.
+//! - [`units`], [`currency`], [`constants`] and [`uncertainty`]: what values are measured in.
+//! - [`symbolic`], [`solver`] and [`calculus`]: derivatives, equations, integrals, sums and limits.
+//! - [`finance`], [`random`] and [`probability`]: money, random numbers and distributions.
+//! - [`trace`], [`plot`] and [`batch`]: evaluation steps, charts and tables.
+//! - [`script`], [`spec`] and [`export`]: scripts, their checks and documents.
+//! - [`config`], [`worksheet`] and [`tui`]: the settings file and the full-screen worksheet.
+//! - [`server`], [`lsp`] and [`ffi`]: the HTTP server, the language server and the C API.
 
 pub mod ast;
 pub mod parser;
 pub mod evaluator;
+pub mod compile;
+pub mod symbolic;
+pub mod solver;
+pub mod calculus;
+pub mod units;
+pub mod plot;
+pub mod batch;
+pub mod constants;
+pub mod calculator;
+pub mod ffi;
+pub mod server;
+pub mod lsp;
+pub mod random;
+pub mod trace;
+pub mod uncertainty;
+pub mod script;
+pub mod currency;
+pub mod finance;
+pub mod probability;
+pub mod config;
+pub mod spec;
+pub mod worksheet;
+pub mod tui;
+pub mod export;
@@ -0,0 +1,569 @@
+//! Language server for calculator scripts.
+//!
+//! `calc lsp` speaks the Language Server Protocol over stdin and stdout. A script is run
+//! as a whole, functions, loops and conditions included, as `calc test` and `calc export`
+//! do, and the results of its lines drive diagnostics, hovers, completion, inlay hints and
+//! go-to-definition.
+
+use crate::calculator::{Calculator, Outcome, Settings};
+use crate::parser::{self, NumberFormat};
+use crate::script::{self, Output, Script, ScriptError};
+use crate::{ast::Statement, constants, evaluator, units};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, Diagnostic, DiagnosticSeverity, Documentation,
+    GotoDefinitionParams, Hover, HoverContents, HoverParams, HoverProviderCapability, InlayHint, InlayHintLabel,
+    InlayHintParams, Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::HashMap;
+use std::ops::Range as Span;
+
+/// The result of one line of a script at its top level.
+#[derive(Debug)]
+struct Line {
+    /// The line number, from 0.
+    number: u32,
+    /// The characters of the line holding the statement, without surrounding whitespace.
+    span: Span<usize>,
+    /// The value of the statement, or an error message and the characters it points at.
+    result: Result<Outcome, (String, Span<usize>)>,
+    /// Warnings recorded while evaluating, such as an integral that did not converge.
+    warnings: Vec<String>,
+    /// The variable the statement assigns and the characters of its name.
+    assigned: Option<(String, Span<usize>)>,
+}
+
+/// Returns the range of characters of a line holding a statement, without a trailing
+/// comment, or `None` for an empty line or a comment.
+fn statement_span(text: &str) -> Option<Span<usize>> {
+    let trimmed = script::strip_comment(text).trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    let start = text.chars().take_while(|c| c.is_whitespace()).count();
+    Some(start..start + trimmed.chars().count())
+}
+
+/// Returns the characters `span` of `text`.
+fn slice(text: &str, span: Span<usize>) -> &str {
+    let byte = |index: usize| text.char_indices().nth(index).map_or(text.len(), |(byte, _)| byte);
+    &text[byte(span.start)..byte(span.end)]
+}
+
+/// Runs the statements at the top level of a script that start before line `until`.
+///
+/// Only lines at the top level have results; lines inside blocks report an error that
+/// stops the script. An error in the structure of the script, such as an unclosed block,
+/// is the only result.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `calculator` - The calculator to run the script in; assignments bind its variables.
+/// * `until` - The number of the first line not to run.
+///
+/// # Returns
+///
+/// * The results of the lines, in the order they were run.
+fn run_lines(text: &str, calculator: &mut Calculator, until: u32) -> Vec<Line> {
+    let format = calculator.settings().number_format;
+    let script = match Script::parse(text, format) {
+        Ok(script) => script,
+        Err(error) => return script_line(text, format, error.line, Err(error)).into_iter().collect(),
+    };
+    let mut lines: Vec<Line> = Vec::new();
+    let stopped = script.run_before(until as usize + 1, calculator, &mut |output| match output {
+        Output::Result { line, outcome } => lines.extend(script_line(text, format, line, Ok(outcome))),
+        Output::Error(error) => lines.extend(script_line(text, format, error.line, Err(error))),
+        // Warnings follow the result of the line that recorded them.
+        Output::Warning(warning) => {
+            if let Some(line) = lines.last_mut() {
+                line.warnings.push(warning);
+            }
+        }
+        Output::Print(_) => (),
+    });
+    if let Err(error) = stopped {
+        lines.extend(script_line(text, format, error.line, Err(error)));
+    }
+    lines
+}
+
+/// Builds the result of line `number` of a script, counted from 1. A parse error points at
+/// the characters it is about, any other error at the statement from its column on.
+fn script_line(text: &str, format: NumberFormat, number: usize, result: Result<Outcome, ScriptError>) -> Option<Line> {
+    let number = number.checked_sub(1)?;
+    let line = text.lines().nth(number)?;
+    let span = statement_span(line)?;
+    let shift = |inner: Span<usize>| inner.start + span.start..inner.end + span.start;
+    let parsed = parser::check_statement(slice(line, span.clone()), format);
+    let assigned = match &parsed {
+        Ok(Statement::Assignment { name, .. }) => Some((name.clone(), shift(0..name.chars().count()))),
+        _ => None,
+    };
+    let result = result.map_err(|error| {
+        let start = error.column.saturating_sub(1);
+        let location = match parsed {
+            Err(e) if shift(e.span.clone()).start == start => shift(e.span),
+            _ => start.clamp(span.start, span.end)..span.end,
+        };
+        (error.message, location)
+    });
+    Some(Line { number: number as u32, span, result, warnings: Vec::new(), assigned })
+}
+
+/// Evaluates a whole script in a new calculator.
+fn analyze(text: &str, settings: Settings) -> Vec<Line> {
+    run_lines(text, &mut Calculator::with_settings(settings), u32::MAX)
+}
+
+/// Converts a column in UTF-16 code units, as positions are counted, into a character index.
+fn char_column(text: &str, column: u32) -> usize {
+    let mut units = 0;
+    text.chars()
+        .take_while(|c| {
+            units += c.len_utf16() as u32;
+            units <= column
+        })
+        .count()
+}
+
+/// Converts a character index into a column in UTF-16 code units.
+fn utf16_column(text: &str, index: usize) -> u32 {
+    text.chars().take(index).map(|c| c.len_utf16() as u32).sum()
+}
+
+/// Converts the characters `span` of line `number` into a protocol range.
+fn to_range(text: &str, number: u32, span: &Span<usize>) -> Range {
+    let line = text.lines().nth(number as usize).unwrap_or_default();
+    Range::new(
+        Position::new(number, utf16_column(line, span.start)),
+        Position::new(number, utf16_column(line, span.end)),
+    )
+}
+
+/// Reports parse and evaluation errors as errors, and evaluation warnings as warnings.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `settings` - The settings to evaluate with.
+///
+/// # Returns
+///
+/// * The diagnostics, in line order.
+pub fn diagnostics(text: &str, settings: Settings) -> Vec<Diagnostic> {
+    let diagnostic = |range: Range, severity: DiagnosticSeverity, message: String| Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("calc".to_string()),
+        message,
+        ..Diagnostic::default()
+    };
+    let mut diagnostics = Vec::new();
+    for line in analyze(text, settings) {
+        if let Err((message, span)) = &line.result {
+            diagnostics.push(diagnostic(to_range(text, line.number, span), DiagnosticSeverity::ERROR, message.clone()));
+        }
+        for warning in line.warnings {
+            diagnostics.push(diagnostic(to_range(text, line.number, &line.span), DiagnosticSeverity::WARNING, warning));
+        }
+    }
+    diagnostics
+}
+
+/// Shows the value of the innermost expression under the cursor that can be evaluated, or of
+/// the variable when the cursor is on the name an assignment binds.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `settings` - The settings to evaluate with.
+/// * `position` - The position of the cursor.
+///
+/// # Returns
+///
+/// * The hover, or `None` if there is nothing to evaluate at the position.
+pub fn hover(text: &str, settings: Settings, position: Position) -> Option<Hover> {
+    let line = text.lines().nth(position.line as usize)?;
+    let span = statement_span(line)?;
+    let column = char_column(line, position.character);
+    let mut calculator = Calculator::with_settings(settings);
+    let current = run_lines(text, &mut calculator, position.line + 1).pop()?;
+    if let (true, Some((name, name_span)), Ok(outcome)) = (current.number == position.line, &current.assigned, &current.result) {
+        if name_span.contains(&column) {
+            return Some(value_hover(text, current.number, name_span, name, outcome));
+        }
+    }
+
+    // Sub-expressions are evaluated with the variables bound before this line.
+    let mut calculator = Calculator::with_settings(settings);
+    run_lines(text, &mut calculator, position.line);
+    let source = slice(line, span.clone());
+    let inner_column = column.checked_sub(span.start)?;
+    parser::subexpressions_at(source, settings.number_format, inner_column)
+        .into_iter()
+        .find_map(|inner| {
+            let expression = slice(source, inner.clone());
+            let outcome = calculator.eval(expression).ok()?;
+            let inner = inner.start + span.start..inner.end + span.start;
+            Some(value_hover(text, position.line, &inner, expression, &outcome))
+        })
+}
+
+/// Builds a hover showing `expression = outcome` over the characters `span` of a line.
+fn value_hover(text: &str, number: u32, span: &Span<usize>, expression: &str, outcome: &Outcome) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("`{}` = `{}`", expression, outcome),
+        }),
+        range: Some(to_range(text, number, span)),
+    }
+}
+
+/// Offers the variables assigned above the cursor, and the built-in constants, functions
+/// and units. After `to`, only units are offered.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `settings` - The settings to evaluate with.
+/// * `position` - The position of the cursor.
+///
+/// # Returns
+///
+/// * The completion items; the editor filters them by what has been typed.
+pub fn completion(text: &str, settings: Settings, position: Position) -> Vec<CompletionItem> {
+    let item = |label: &str, kind: CompletionItemKind, detail: String, documentation: Option<&str>| CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail: Some(detail),
+        documentation: documentation.map(|text| Documentation::String(text.to_string())),
+        ..CompletionItem::default()
+    };
+    let unit_items = units::all().iter().map(|unit| {
+        let base = units::base_units(&unit.dimension).unwrap_or_default();
+        item(unit.symbol, CompletionItemKind::UNIT, format!("{} {}", unit.factor, base), None)
+    });
+
+    let line = text.lines().nth(position.line as usize).unwrap_or_default();
+    let before = slice(line, 0..char_column(line, position.character));
+    let before = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_').trim_end();
+    if before == "to" || before.ends_with(" to") {
+        return unit_items.collect();
+    }
+
+    let mut calculator = Calculator::with_settings(settings);
+    run_lines(text, &mut calculator, position.line);
+    let mut items: Vec<CompletionItem> = calculator
+        .variables()
+        .into_iter()
+        .map(|(name, value)| item(name, CompletionItemKind::VARIABLE, value.to_string(), None))
+        .collect();
+    items.extend(constants::all().iter().map(|constant| {
+        let value = evaluator::ValueWithUnit::new(constant.value, constant.unit.map(String::from));
+        item(constant.name, CompletionItemKind::CONSTANT, value.to_string(), Some(constant.description))
+    }));
+    items.extend(
+        evaluator::functions()
+            .iter()
+            .map(|function| item(function.name, CompletionItemKind::FUNCTION, function.usage.to_string(), Some(function.description))),
+    );
+    items.extend(unit_items);
+    items
+}
+
+/// Shows the result of each statement at the end of its line, e.g., `= 21 km/h`.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `settings` - The settings to evaluate with.
+/// * `range` - The lines to show hints for.
+///
+/// # Returns
+///
+/// * The hints, in line order.
+pub fn inlay_hints(text: &str, settings: Settings, range: Range) -> Vec<InlayHint> {
+    analyze(text, settings)
+        .into_iter()
+        .filter(|line| (range.start.line..=range.end.line).contains(&line.number))
+        .filter_map(|line| {
+            let outcome = line.result.ok()?;
+            let end = to_range(text, line.number, &line.span).end;
+            Some(InlayHint {
+                position: end,
+                label: InlayHintLabel::String(format!("= {}", outcome)),
+                kind: None,
+                text_edits: None,
+                tooltip: None,
+                padding_left: Some(true),
+                padding_right: None,
+                data: None,
+            })
+        })
+        .collect()
+}
+
+/// Finds the assignment that binds the variable under the cursor: the last one at or above
+/// the cursor's line.
+///
+/// # Arguments
+///
+/// * `text` - The script.
+/// * `settings` - The settings to evaluate with.
+/// * `uri` - The script's URI, for the returned location.
+/// * `position` - The position of the cursor.
+///
+/// # Returns
+///
+/// * The location of the assigned name, or `None` if the cursor is not on an assigned variable.
+pub fn definition(text: &str, settings: Settings, uri: &Url, position: Position) -> Option<Location> {
+    let line = text.lines().nth(position.line as usize)?;
+    let chars: Vec<char> = line.chars().collect();
+    let column = char_column(line, position.character);
+    let is_name = |index: &usize| chars.get(*index).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_');
+    if !is_name(&column) {
+        return None;
+    }
+    let start = (0..column).rev().take_while(is_name).last().unwrap_or(column);
+    let end = (column..chars.len()).take_while(is_name).last()? + 1;
+    let name: String = chars[start..end].iter().collect();
+
+    let mut calculator = Calculator::with_settings(settings);
+    run_lines(text, &mut calculator, position.line + 1)
+        .into_iter()
+        .rev()
+        .find_map(|line| match line.assigned {
+            Some((assigned, span)) if assigned == name => Some(Location::new(uri.clone(), to_range(text, line.number, &span))),
+            _ => None,
+        })
+}
+
+/// Runs the language server over stdin and stdout until the client shuts it down.
+///
+/// # Arguments
+///
+/// * `settings` - The settings to evaluate scripts with.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the client shut the server down.
+/// * `Err(String)` - An error message if the connection failed.
+pub fn run(settings: Settings) -> Result<(), String> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection, settings)?;
+    drop(connection);
+    io_threads.join().map_err(|e| format!("Language server I/O failed: {}", e))
+}
+
+/// Serves a client over a connection until it shuts the server down.
+///
+/// # Arguments
+///
+/// * `connection` - The connection to the client.
+/// * `settings` - The settings to evaluate scripts with.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the client shut the server down or closed the connection.
+/// * `Err(String)` - An error message if the protocol was violated or the connection failed.
+pub fn serve(connection: &Connection, settings: Settings) -> Result<(), String> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        definition_provider: Some(OneOf::Left(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).map_err(|e| e.to_string())?;
+    connection.initialize(capabilities).map_err(|e| format!("Language server initialization failed: {}", e))?;
+
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for message in &connection.receiver {
+        let reply = match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).map_err(|e| e.to_string())? {
+                    return Ok(());
+                }
+                Message::Response(handle_request(&documents, settings, request))
+            }
+            Message::Notification(notification) => match handle_notification(&mut documents, notification) {
+                Some(uri) => {
+                    let text = documents.get(&uri).map_or("", String::as_str);
+                    let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics(text, settings), None);
+                    Message::Notification(Notification::new("textDocument/publishDiagnostics".to_string(), params))
+                }
+                None => continue,
+            },
+            Message::Response(_) => continue,
+        };
+        connection.sender.send(reply).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Keeps the open documents up to date.
+///
+/// # Returns
+///
+/// * The URI of the document that was opened, changed or closed, whose diagnostics are stale.
+fn handle_notification(documents: &mut HashMap<Url, String>, notification: Notification) -> Option<Url> {
+    use lsp_types::{DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams};
+
+    match notification.method.as_str() {
+        "textDocument/didOpen" => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.insert(params.text_document.uri.clone(), params.text_document.text);
+            Some(params.text_document.uri)
+        }
+        "textDocument/didChange" => {
+            let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            // Documents are synchronized in full, so the last change holds the whole text.
+            let text = params.content_changes.into_iter().last()?.text;
+            documents.insert(params.text_document.uri.clone(), text);
+            Some(params.text_document.uri)
+        }
+        "textDocument/didClose" => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.remove(&params.text_document.uri);
+            Some(params.text_document.uri)
+        }
+        _ => None,
+    }
+}
+
+/// Answers a request about an open document.
+fn handle_request(documents: &HashMap<Url, String>, settings: Settings, request: Request) -> Response {
+    let text = |uri: &Url| documents.get(uri).map_or("", String::as_str);
+    match request.method.as_str() {
+        "textDocument/hover" => respond(request, |params: HoverParams| {
+            let document = params.text_document_position_params;
+            hover(text(&document.text_document.uri), settings, document.position)
+        }),
+        "textDocument/completion" => respond(request, |params: lsp_types::CompletionParams| {
+            let document = params.text_document_position;
+            completion(text(&document.text_document.uri), settings, document.position)
+        }),
+        "textDocument/inlayHint" => respond(request, |params: InlayHintParams| {
+            inlay_hints(text(&params.text_document.uri), settings, params.range)
+        }),
+        "textDocument/definition" => respond(request, |params: GotoDefinitionParams| {
+            let document = params.text_document_position_params;
+            let uri = &document.text_document.uri;
+            definition(text(uri), settings, uri, document.position)
+        }),
+        method => Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("Unsupported request: {}", method)),
+    }
+}
+
+/// Decodes the parameters of a request and answers it with the result of `f`.
+fn respond<P: serde::de::DeserializeOwned, R: serde::Serialize>(request: Request, f: impl FnOnce(P) -> R) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, f(params)),
+        Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "# Trip\nd = 42 km\nt = 2 h\n\n  d / t to km/h\n1 kg + 1 m\nx = 2 * * 3\n";
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = diagnostics(SCRIPT, Settings::default());
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(5, 0), Position::new(5, 10)));
+        assert!(diagnostics[0].message.contains("Unit mismatch"));
+        assert_eq!(diagnostics[1].range, Range::new(Position::new(6, 8), Position::new(6, 9)));
+        assert_eq!(diagnostics[1].severity, Some(DiagnosticSeverity::ERROR));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let script = "fn area(r) {\n    return r^2\n}\ntotal = 0\nfor k in 1..3 {\n    total += area(k)\n}\ntotal\nfn bad(x) {\n    return x + 1 m\n}\nbad(2)\n";
+        let diagnostics = diagnostics(script, Settings::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(11, 0), Position::new(11, 6)));
+        assert!(diagnostics[0].message.contains("in bad at line 10"));
+
+        let range = Range::new(Position::new(0, 0), Position::new(11, 0));
+        let labels: Vec<String> = inlay_hints(script, Settings::default(), range)
+            .into_iter()
+            .map(|hint| match hint.label {
+                InlayHintLabel::String(label) => label,
+                other => panic!("Unexpected label {:?}", other),
+            })
+            .collect();
+        assert_eq!(labels, ["= 0", "= 14"]);
+
+        let contents = match hover(script, Settings::default(), Position::new(7, 0)).unwrap().contents {
+            HoverContents::Markup(markup) => markup.value,
+            other => panic!("Unexpected hover {:?}", other),
+        };
+        assert_eq!(contents, "`total` = `14`");
+        assert!(hover(script, Settings::default(), Position::new(5, 4)).is_none());
+    }
+
+    #[test]
+    fn test_hover() {
+        let contents = |position| match hover(SCRIPT, Settings::default(), position).unwrap().contents {
+            HoverContents::Markup(markup) => markup.value,
+            other => panic!("Unexpected hover {:?}", other),
+        };
+        assert_eq!(contents(Position::new(4, 2)), "`d` = `42 km`");
+        assert_eq!(contents(Position::new(4, 4)), "`d / t` = `21 km/h`");
+        assert_eq!(contents(Position::new(4, 10)), "`d / t to km/h` = `21 km/h`");
+        assert_eq!(contents(Position::new(1, 0)), "`d` = `42 km`");
+        assert!(hover(SCRIPT, Settings::default(), Position::new(0, 2)).is_none());
+    }
+
+    #[test]
+    fn test_completion() {
+        let labels = |position| -> Vec<String> {
+            completion(SCRIPT, Settings::default(), position).into_iter().map(|item| item.label).collect()
+        };
+        let labels_at_end = labels(Position::new(5, 0));
+        assert!(["d", "t", "pi", "sqrt", "km"].iter().all(|label| labels_at_end.iter().any(|l| l == label)));
+        assert!(!labels(Position::new(2, 0)).contains(&"t".to_string()));
+        let after_to = labels(Position::new(4, 12));
+        assert!(after_to.contains(&"km".to_string()) && !after_to.contains(&"d".to_string()));
+    }
+
+    #[test]
+    fn test_inlay_hints() {
+        let range = Range::new(Position::new(0, 0), Position::new(4, 0));
+        let hints = inlay_hints(SCRIPT, Settings::default(), range);
+        let labels: Vec<String> = hints
+            .iter()
+            .map(|hint| match &hint.label {
+                InlayHintLabel::String(label) => label.clone(),
+                other => panic!("Unexpected label {:?}", other),
+            })
+            .collect();
+        assert_eq!(labels, ["= 42 km", "= 2 h", "= 21 km/h"]);
+        assert_eq!(hints[2].position, Position::new(4, 15));
+    }
+
+    #[test]
+    fn test_definition() {
+        let uri = Url::parse("file:///trip.calc").unwrap();
+        let location = definition(SCRIPT, Settings::default(), &uri, Position::new(4, 6)).unwrap();
+        assert_eq!(location.range, Range::new(Position::new(2, 0), Position::new(2, 1)));
+        assert!(definition(SCRIPT, Settings::default(), &uri, Position::new(4, 3)).is_none());
+        assert!(definition(SCRIPT, Settings::default(), &uri, Position::new(1, 9)).is_none());
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(char_column("π = 3", 1), 1);
+        assert_eq!(char_column("𝑥 = 3", 2), 1);
+        assert_eq!(utf16_column("𝑥 = 3", 1), 2);
+        assert_eq!(slice("𝑥 = 3", 4..5), "3");
+    }
+}
@@ -1,14 +1,20 @@
 //! Main program for the calculator.
 //!
 //! This module provides a command-line interface to input expressions and display results.
-//! Supports CLI mode, TUI mode, and scripting mode.
-
-mod ast;
-mod parser;
-mod evaluator;
+//! Supports CLI mode, TUI mode, scripting mode, an HTTP server, and a language server.
 
 use clap::{Parser, Subcommand};
-use evaluator::ValueWithUnit;
+use textcalculator::calculator::{Calculator, Outcome, Settings};
+use textcalculator::config::{self, Config};
+use textcalculator::currency::{self, Rates};
+use textcalculator::evaluator::{AngleMode, Notation, ValueWithUnit};
+use textcalculator::export::{Block, Document, Format};
+use textcalculator::parser::NumberFormat;
+use textcalculator::server::{Server, ServerOptions};
+use textcalculator::script::{Output, Script};
+use textcalculator::trace::TraceNode;
+use textcalculator::uncertainty::Propagation;
+use textcalculator::{batch, constants, lsp, plot, spec, tui};
 
 /// Command-line arguments for the calculator.
 #[derive(Parser, Debug)]
@@ -21,6 +27,42 @@ struct Args {
     #[arg()]
     expression: Option<String>,
 
+    /// Configuration file [default: textcalc/config.toml in the config directory]
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// How numbers are written: standard (1234.5), grouped (1,234.5) or decimal-comma (1.234,5) [default: standard]
+    #[arg(long, global = true)]
+    number_format: Option<NumberFormat>,
+
+    /// Unit of angles written as plain numbers, as in sin(30): rad or deg [default: rad]
+    #[arg(long, global = true)]
+    angle: Option<AngleMode>,
+
+    /// Significant digits of results, or decimal places with --notation fixed
+    #[arg(long, global = true, value_name = "DIGITS")]
+    precision: Option<usize>,
+
+    /// How results are written: auto, fixed, scientific or engineering [default: auto]
+    #[arg(long, global = true)]
+    notation: Option<Notation>,
+
+    /// Propagate uncertainties by drawing this many Monte Carlo samples instead of to first order
+    #[arg(long, global = true, value_name = "SAMPLES")]
+    monte_carlo: Option<usize>,
+
+    /// Seed for rand(), randint and dice rolls, so that results repeat from run to run
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Exchange-rate snapshot for currency units [default: rates.json in the data directory]
+    #[arg(long, global = true, value_name = "FILE")]
+    rates: Option<PathBuf>,
+
+    /// Print each evaluation step of the expression with its value and conversion factors
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    explain: Option<ExplainFormat>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -32,125 +74,706 @@ enum Commands {
     Eval {
         /// Path to the script file
         script: String,
+        /// Print the script with its results as a document: markdown, html or latex
+        #[arg(long, value_name = "FORMAT")]
+        export: Option<Format>,
+    },
+    /// Check script files against the results written in them as `# => expected` comments
+    Test {
+        /// Paths to the script files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Rewrite the expectations that fail with the actual results
+        #[arg(long)]
+        bless: bool,
+        /// Relative difference allowed between numbers, beyond the decimals an expectation is written with
+        #[arg(long, default_value_t = spec::DEFAULT_TOLERANCE)]
+        tolerance: f64,
+    },
+    /// Plot one or more expressions as functions of a variable
+    #[command(allow_negative_numbers = true)]
+    Plot {
+        /// Expressions to plot (e.g., "sin(x) / x")
+        #[arg(required = true)]
+        expressions: Vec<String>,
+        /// First value of the variable
+        #[arg(long, default_value_t = -10.0)]
+        from: f64,
+        /// Last value of the variable
+        #[arg(long, default_value_t = 10.0)]
+        to: f64,
+        /// Name of the variable the expressions are functions of
+        #[arg(long, default_value = "x")]
+        var: String,
+        /// Width of the chart in characters
+        #[arg(long, default_value_t = 72)]
+        width: usize,
+        /// Height of the chart in characters
+        #[arg(long, default_value_t = 20)]
+        height: usize,
+        /// Write the chart to an SVG file instead of the terminal
+        #[arg(long)]
+        svg: Option<String>,
+    },
+    /// Tabulate an expression over one or more stepped variables
+    Table {
+        /// Expression to evaluate (e.g., "x to ft")
+        expression: String,
+        /// Stepped variable as name=start..end or name=start..end:step (e.g., "x=1 m..10 m")
+        #[arg(long = "var", required = true, allow_hyphen_values = true)]
+        vars: Vec<String>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+    },
+    /// Evaluate an expression for every row of a CSV file and append the result as a column
+    Map {
+        /// Path to the CSV file; column headers may carry units, e.g., weight[kg]
+        file: String,
+        /// Expression to evaluate per row, using column names as variables
+        #[arg(long = "expr")]
+        expression: String,
+        /// Name of the new column
+        #[arg(long = "as", default_value = "result")]
+        column: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+    },
+    /// Print the amortization schedule of a loan repaid in equal payments
+    Amortize {
+        /// Interest rate per period (e.g., "5%/12")
+        rate: String,
+        /// Number of payments (e.g., "360")
+        periods: String,
+        /// Amount borrowed, optionally in a currency (e.g., "400000 USD")
+        principal: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+    },
+    /// Serve a JSON API for evaluating expressions over HTTP
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 7070)]
+        port: u16,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Seconds a session is kept after its last request
+        #[arg(long, default_value_t = 1800)]
+        session_timeout: u64,
+        /// Maximum number of sessions at once
+        #[arg(long, default_value_t = 1000)]
+        max_sessions: usize,
+        /// Maximum number of expressions per request
+        #[arg(long, default_value_t = 100)]
+        max_expressions: usize,
+        /// Milliseconds a request may spend evaluating
+        #[arg(long, default_value_t = 5000)]
+        time_limit: u64,
+    },
+    /// Run a language server for script files over stdin and stdout
+    Lsp,
+    /// Open a full-screen worksheet whose lines are evaluated as they are edited
+    Worksheet {
+        /// Script file to open, and to save to with Ctrl-S; created on saving if it does not exist
+        file: Option<PathBuf>,
+    },
+    /// Import or list the exchange rates that currencies are converted with
+    Rates {
+        #[command(subcommand)]
+        action: RatesAction,
+    },
+    /// Show the settings from the configuration file and command-line flags
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
     },
 }
 
+/// Actions of the `rates` subcommand.
+#[derive(Subcommand, Debug)]
+enum RatesAction {
+    /// Store a snapshot of exchange rates from a JSON or CSV file for later conversions
+    Import {
+        /// Path to the snapshot; CSV files need currency, rate and date columns
+        file: PathBuf,
+    },
+    /// List the stored exchange rates with their dates
+    Show,
+}
+
+/// Actions of the `config` subcommand.
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the settings in effect, as TOML, after command-line flags override the file
+    Show,
+}
+
+/// Output formats for `--explain`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ExplainFormat {
+    Text,
+    Json,
+}
+
+/// Output formats for `table`, `map` and `amortize`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum TableFormat {
+    Csv,
+    Markdown,
+}
+
 fn main() {
     let args = Args::parse();
+    let propagation = match args.monte_carlo {
+        Some(samples) => Propagation::MonteCarlo { samples },
+        None => Propagation::FirstOrder,
+    };
+    let config_path = args.config.clone().or_else(config::default_path);
+    let flags = Config {
+        precision: args.precision,
+        notation: args.notation,
+        angle: args.angle,
+        number_format: args.number_format,
+        rates: args.rates.clone(),
+        ..Config::default()
+    };
+    let config = flags.or(load_config(config_path.as_deref(), args.config.is_some()));
+    let settings = Settings { propagation, seed: args.seed, ..config.settings() };
+    let rates_path = config.rates.clone().or_else(currency::default_path);
+    let start = || {
+        let mut calculator = Calculator::with_settings(settings);
+        load_rates(&mut calculator, rates_path.as_deref(), config.rates.is_some());
+        for problem in config.load_definitions(&mut calculator) {
+            eprintln!("Warning: {}", problem);
+        }
+        calculator
+    };
+    let mut calculator = match args.command {
+        Some(Commands::Rates { .. } | Commands::Config { .. } | Commands::Test { .. }) => Calculator::with_settings(settings),
+        _ => start(),
+    };
 
     if let Some(command) = args.command {
         match command {
-            Commands::Eval { script } => {
+            Commands::Eval { script, export } => {
                 // Scripting Mode: Evaluate expressions from a script file
-                match run_script(&script) {
-                    Ok(_) => (),
+                let result = match export {
+                    Some(format) => export_script(&mut calculator, &script, format),
+                    None => run_script(&mut calculator, &script),
+                };
+                if let Err(e) = result {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            Commands::Test { files, bless, tolerance } => {
+                if !run_tests(&files, bless, tolerance, start) {
+                    std::process::exit(1);
+                }
+            }
+            Commands::Plot { expressions, from, to, var, width, height, svg } => {
+                if let Err(e) = run_plot(&calculator, &expressions, (from, to), &var, (width, height), svg.as_deref()) {
+                    eprintln!("Error: {}", e);
+                }
+                print_warnings(&calculator);
+            }
+            Commands::Table { expression, vars, format } => {
+                let result = vars
+                    .iter()
+                    .map(|spec| batch::parse_range(spec, &calculator))
+                    .collect::<Result<Vec<_>, _>>()
+                    .and_then(|ranges| batch::table(&expression, &ranges, &calculator));
+                print_table(result, format);
+                print_warnings(&calculator);
+            }
+            Commands::Map { file, expression, column, format } => {
+                let result = File::open(&file)
+                    .map_err(|e| format!("Failed to open '{}': {}", file, e))
+                    .and_then(|input| batch::map(input, &expression, &column, &calculator));
+                print_table(result, format);
+                print_warnings(&calculator);
+            }
+            Commands::Amortize { rate, periods, principal, format } => {
+                let value = |expression: &str| match calculator.eval(expression)? {
+                    Outcome::Value(value) => Ok(value),
+                    _ => Err(format!("'{}' is not a number", expression)),
+                };
+                let result = value(&rate).and_then(|rate| {
+                    batch::amortization(&rate, &value(&periods)?, &value(&principal)?).map(|table| (table, Vec::new()))
+                });
+                print_table(result, format);
+            }
+            Commands::Serve { port, host, session_timeout, max_sessions, max_expressions, time_limit } => {
+                let options = ServerOptions {
+                    settings: *calculator.settings(),
+                    session_timeout: Duration::from_secs(session_timeout),
+                    max_sessions,
+                    max_expressions,
+                    time_limit: Duration::from_millis(time_limit),
+                    ..ServerOptions::default()
+                };
+                match Server::bind(&format!("{}:{}", host, port), options) {
+                    Ok(server) => {
+                        println!("Listening on http://{}:{}", host, server.port().unwrap_or(port));
+                        server.run();
+                    }
                     Err(e) => eprintln!("Error: {}", e),
                 }
             }
+            Commands::Lsp => {
+                if let Err(e) = lsp::run(*calculator.settings()) {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            Commands::Worksheet { file } => {
+                if let Err(e) = tui::run(calculator, file.as_deref()) {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            Commands::Rates { action } => {
+                let Some(path) = rates_path else {
+                    eprintln!("Error: No place to store exchange rates; pass --rates FILE");
+                    return;
+                };
+                let result = match action {
+                    RatesAction::Import { file } => import_rates(&file, &path),
+                    RatesAction::Show => Rates::load(&path).map(|rates| print_rates(&rates)),
+                };
+                if let Err(e) = result {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            Commands::Config { action: ConfigAction::Show } => {
+                print_config(&config, config_path.as_deref(), rates_path);
+            }
         }
     } else if let Some(expression) = args.expression {
         // CLI Mode: Evaluate the expression provided as an argument
-        match evaluate_expression(&expression) {
-            Ok(result) => print_result(&result),
-            Err(e) => eprintln!("Error: {}", e),
+        if expression.trim() == ":const" {
+            print_constants();
+            return;
         }
+        if let Some(format) = args.explain {
+            match calculator.explain(&expression) {
+                Ok(trace) => print_trace(&trace, format),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        } else {
+            match calculator.eval(&expression) {
+                Ok(result) => print_result(&result, calculator.settings()),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        print_warnings(&calculator);
     } else {
         // TUI Mode: Enter interactive calculator mode
-        run_tui();
+        run_tui(&mut calculator);
     }
 }
 
-/// Evaluates a single expression string.
+/// Prints the result, including the unit if present.
 ///
 /// # Arguments
 ///
-/// * `expression` - The expression string to evaluate.
+/// * `result` - The result to print.
+/// * `settings` - The precision and notation of numbers.
+fn print_result(result: &Outcome, settings: &Settings) {
+    println!("Result: {}", result.format(settings.precision, settings.notation));
+}
+
+/// Prints the steps of an evaluation (`--explain`).
+///
+/// # Arguments
+///
+/// * `trace` - The trace of the evaluation.
+/// * `format` - Indented text or JSON.
+fn print_trace(trace: &TraceNode, format: ExplainFormat) {
+    match format {
+        ExplainFormat::Text => print!("{}", trace.render()),
+        ExplainFormat::Json => println!("{:#}", trace.to_json()),
+    }
+}
+
+/// Prints the warnings recorded while evaluating, such as an integral that did not converge.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator that evaluated the expressions.
+fn print_warnings(calculator: &Calculator) {
+    for warning in calculator.take_warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Reads the configuration file.
+///
+/// A missing file at the default location is not an error: the built-in defaults apply.
+///
+/// # Arguments
+///
+/// * `path` - The configuration file, if there is a place for one.
+/// * `explicit` - Whether the path was given with `--config`, so that it must exist.
 ///
 /// # Returns
 ///
-/// * `Ok(ValueWithUnit)` - The result of the evaluation.
-/// * `Err(String)` - An error message if evaluation fails.
-fn evaluate_expression(expression: &str) -> Result<ValueWithUnit, String> {
-    match parser::parse_expression(expression) {
-        Ok(ast) => evaluator::evaluate(&ast).map_err(|e| format!("Error evaluating '{}': {}", expression, e)),
-        Err(e) => Err(format!("Error parsing expression '{}': {}", expression, e)),
+/// The configuration, or an empty one after printing a warning if the file is invalid.
+fn load_config(path: Option<&Path>, explicit: bool) -> Config {
+    let Some(path) = path.filter(|path| explicit || path.exists()) else {
+        return Config::default();
+    };
+    Config::load(path).unwrap_or_else(|e| {
+        eprintln!("Warning: {}", e);
+        Config::default()
+    })
+}
+
+/// Prints the settings in effect as TOML (`config show`), with the defaults filled in.
+///
+/// # Arguments
+///
+/// * `config` - The configuration file under the command-line flags.
+/// * `path` - The configuration file, if there is a place for one.
+/// * `rates` - The exchange-rate snapshot in use.
+fn print_config(config: &Config, path: Option<&Path>, rates: Option<PathBuf>) {
+    match path {
+        Some(path) if path.exists() => println!("# Configuration file: {}", path.display()),
+        Some(path) => println!("# Configuration file: {} (not found)", path.display()),
+        None => println!("# No configuration file"),
+    }
+    let settings = config.settings();
+    let resolved = Config {
+        notation: Some(settings.notation),
+        angle: Some(settings.angle_mode),
+        number_format: Some(settings.number_format),
+        backend: Some(config.backend.unwrap_or_default()),
+        rates,
+        ..config.clone()
+    };
+    match toml::to_string(&resolved) {
+        Ok(text) => print!("{}", text),
+        Err(e) => eprintln!("Error: {}", e),
     }
 }
 
-/// Prints the result, including the unit if present.
+/// Adds the currencies of an exchange-rate snapshot to the calculator.
+///
+/// A missing snapshot at the default location is not an error: currencies are simply unknown.
 ///
 /// # Arguments
 ///
-/// * `result` - The result to print.
-fn print_result(result: &ValueWithUnit) {
-    if let Some(unit) = &result.unit {
-        println!("Result: {} {}", result.value, unit);
-    } else {
-        println!("Result: {}", result.value);
+/// * `calculator` - The calculator to add the currencies to.
+/// * `path` - The snapshot file, if there is a place for one.
+/// * `explicit` - Whether the path was given with `--rates`, so that it must exist.
+fn load_rates(calculator: &mut Calculator, path: Option<&Path>, explicit: bool) {
+    let Some(path) = path.filter(|path| explicit || path.exists()) else {
+        return;
+    };
+    if let Err(e) = Rates::load(path).and_then(|rates| calculator.set_rates(rates)) {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+/// Checks a snapshot of exchange rates and stores it as JSON (`rates import`).
+///
+/// # Arguments
+///
+/// * `file` - The snapshot to import, JSON or CSV.
+/// * `path` - Where to store it.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the snapshot was stored.
+/// * `Err(String)` - An error message if the snapshot is invalid or cannot be written.
+fn import_rates(file: &Path, path: &Path) -> Result<(), String> {
+    let rates = Rates::load(file)?;
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create '{}': {}", directory.display(), e))?;
+    }
+    std::fs::write(path, rates.to_json() + "\n").map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    println!("Imported {} exchange rates to {}", rates.currencies().count(), path.display());
+    Ok(())
+}
+
+/// Prints exchange rates with their dates (`rates show`).
+///
+/// # Arguments
+///
+/// * `rates` - The rates to print.
+fn print_rates(rates: &Rates) {
+    for (code, rate) in rates.currencies() {
+        println!("{}  {:>14}  {}", code, ValueWithUnit::new(rate.per_base, None).to_string(), rate.date);
+    }
+}
+
+/// Prints the built-in constants with their values, units and descriptions (`:const`).
+fn print_constants() {
+    let width = constants::all().iter().map(|constant| constant.name.len()).max().unwrap_or(0);
+    for constant in constants::all() {
+        let value = ValueWithUnit::new(constant.value, constant.unit.map(String::from));
+        println!("{:width$}  {:28}  {}", constant.name, value.to_string(), constant.description, width = width);
     }
 }
 
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-/// Runs the calculator in scripting mode, evaluating expressions from a file.
+/// Runs the calculator in scripting mode, evaluating a script file.
+///
+/// Assignments such as `x = 5 m` bind variables for the following lines, and blocks add
+/// loops, conditionals and functions (see [`Script`]). An error in a line at the top level
+/// is reported and the script goes on; an error inside a block stops it.
 ///
 /// # Arguments
 ///
+/// * `calculator` - The calculator to evaluate the expressions with.
 /// * `script_path` - The path to the script file.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the script was executed successfully.
-/// * `Err(String)` - An error message if execution fails.
-fn run_script(script_path: &str) -> Result<(), String> {
-    let file = File::open(script_path).map_err(|e| format!("Failed to open script file: {}", e))?;
-    let reader = BufReader::new(file);
-
-    for (line_number, line_result) in reader.lines().enumerate() {
-        let line_number = line_number + 1; // Line numbers start at 1
-        let line = line_result.map_err(|e| format!("Error reading script file: {}", e))?;
-        let expression = line.trim();
-
-        if expression.is_empty() || expression.starts_with('#') {
-            // Skip empty lines and comments
-            continue;
-        }
-
-        match evaluate_expression(expression) {
-            Ok(result) => {
-                print!("Line {}: ", line_number);
-                print_result(&result);
+/// * `Err(String)` - An error message if the script cannot be read or parsed, or stops with an error.
+fn run_script(calculator: &mut Calculator, script_path: &str) -> Result<(), String> {
+    let source = std::fs::read_to_string(script_path).map_err(|e| format!("Failed to open script file: {}", e))?;
+    let script = Script::parse(&source, calculator.settings().number_format).map_err(|e| e.to_string())?;
+    let settings = *calculator.settings();
+    script
+        .run(calculator, &mut |output| match output {
+            Output::Result { line, outcome } => {
+                print!("Line {}: ", line);
+                print_result(&outcome, &settings);
             }
+            Output::Print(text) => println!("{}", text),
+            Output::Error(e) => eprintln!("Error on line {}, column {}: {}", e.line, e.column, e.message),
+            Output::Warning(warning) => eprintln!("Warning: {}", warning),
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// Runs a script file and prints it as a document (`eval --export`).
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to evaluate the expressions with.
+/// * `script_path` - The path to the script file.
+/// * `format` - The format of the document.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the document was printed; errors in lines are part of it.
+/// * `Err(String)` - An error message if the script cannot be read or parsed.
+fn export_script(calculator: &mut Calculator, script_path: &str, format: Format) -> Result<(), String> {
+    let source = std::fs::read_to_string(script_path).map_err(|e| format!("Failed to open script file: {}", e))?;
+    let document = Document::from_script(&source, calculator).map_err(|e| e.to_string())?;
+    print!("{}", document.render(format, calculator.settings()));
+    Ok(())
+}
+
+/// Checks script files against their `# => expected` comments (`test`), printing each
+/// failure and a summary per file.
+///
+/// # Arguments
+///
+/// * `files` - The script files.
+/// * `bless` - Whether to rewrite the failed expectations with the actual results.
+/// * `tolerance` - The relative difference allowed between numbers.
+/// * `start` - Creates the calculator each file runs in.
+///
+/// # Returns
+///
+/// Whether every check passed, or was blessed.
+fn run_tests(files: &[PathBuf], bless: bool, tolerance: f64, start: impl Fn() -> Calculator) -> bool {
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let name = file.display();
+        let report = std::fs::read_to_string(file)
+            .map_err(|e| format!("Failed to open '{}': {}", name, e))
+            .and_then(|source| {
+                let report = spec::check(&source, &mut start(), tolerance).map_err(|e| format!("{}: {}", name, e))?;
+                Ok((source, report))
+            });
+        let (source, report) = match report {
+            Ok(report) => report,
             Err(e) => {
-                eprintln!("Error on line {}: {}", line_number, e);
+                eprintln!("Error: {}", e);
+                failed += 1;
+                continue;
             }
+        };
+        let mut failures = report.failures().count();
+        for check in report.failures() {
+            println!("FAIL {}:{}: {}", name, check.line, check.code);
+            if let Some(expected) = &check.expected {
+                println!("  expected: {}", expected);
+            }
+            println!("  actual:   {}", check.actual.as_deref().unwrap_or("no result"));
         }
+        if bless && failures > 0 {
+            let (blessed, count) = spec::bless(&source, &report);
+            match std::fs::write(file, blessed) {
+                Ok(()) => {
+                    println!("{}: blessed {} expectation{}", name, count, if count == 1 { "" } else { "s" });
+                    failures -= count;
+                }
+                Err(e) => eprintln!("Error: Failed to write '{}': {}", name, e),
+            }
+        }
+        println!("{}: {} passed, {} failed", name, report.passed(), failures);
+        passed += report.passed();
+        failed += failures;
+    }
+    if files.len() > 1 {
+        println!("Total: {} passed, {} failed", passed, failed);
+    }
+    failed == 0
+}
+
+/// Plots expressions in the terminal, or writes them to an SVG file.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator the expressions are evaluated with.
+/// * `expressions` - The expressions to plot.
+/// * `range` - The first and last value of the variable.
+/// * `var` - The variable the expressions are functions of.
+/// * `size` - The width and height of the terminal chart in characters.
+/// * `svg_path` - The file to write an SVG chart to, if any.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the chart was drawn.
+/// * `Err(String)` - An error message if sampling or writing fails.
+fn run_plot(
+    calculator: &Calculator,
+    expressions: &[String],
+    range: (f64, f64),
+    var: &str,
+    size: (usize, usize),
+    svg_path: Option<&str>,
+) -> Result<(), String> {
+    use std::io::IsTerminal;
+
+    if !range.0.is_finite() || !range.1.is_finite() || range.0 >= range.1 {
+        return Err(format!("--from ({}) must be less than --to ({})", range.0, range.1));
     }
+    // An SVG gets enough samples for a smooth curve; the terminal one per Braille dot column.
+    let samples = if svg_path.is_some() { 1000 } else { size.0 * 2 };
+    let series = expressions
+        .iter()
+        .map(|expression| plot::sample(expression, var, range, samples, calculator))
+        .collect::<Result<Vec<_>, _>>()?;
 
+    match svg_path {
+        Some(path) => {
+            let svg = plot::render_svg(&series)?;
+            std::fs::write(path, svg).map_err(|e| format!("Failed to write '{}': {}", path, e))?;
+            println!("Wrote {}", path);
+        }
+        None => print!("{}", plot::render_braille(&series, size.0, size.1, std::io::stdout().is_terminal())?),
+    }
     Ok(())
 }
 
-/// Runs the calculator in interactive mode (TUI).
-fn run_tui() {
-    use reedline::{DefaultPrompt, Reedline};
+/// Prints a table produced by `table` or `map`, with the errors of failed rows on stderr.
+///
+/// # Arguments
+///
+/// * `result` - The table and row errors, or an error message.
+/// * `format` - The output format.
+fn print_table(result: Result<(batch::Table, Vec<String>), String>, format: TableFormat) {
+    let (table, errors) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+    for error in errors {
+        eprintln!("Error: {}", error);
+    }
+    match format {
+        TableFormat::Csv => match table.to_csv() {
+            Ok(csv) => print!("{}", csv),
+            Err(e) => eprintln!("Error: {}", e),
+        },
+        TableFormat::Markdown => print!("{}", table.to_markdown()),
+    }
+}
+
+/// Writes an interactive session as a document (`:export`), in the format of the file's extension.
+///
+/// # Arguments
+///
+/// * `session` - The notes and calculations of the session.
+/// * `path` - The file to write: `.md`, `.html` or `.tex`.
+/// * `settings` - How inputs are read and results are written.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the file was written.
+/// * `Err(String)` - An error message if the extension is not that of a format or the file cannot be written.
+fn export_session(session: &Document, path: &Path, settings: &Settings) -> Result<(), String> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| format!("Cannot tell the format of '{}': use .md, .html or .tex", path.display()))?;
+    std::fs::write(path, session.render(format, settings)).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// Runs the calculator in interactive mode (TUI). Assignments bind variables for later lines,
+/// lines starting with `#` are notes, and `:export FILE` writes the session as a document.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to evaluate the expressions with.
+fn run_tui(calculator: &mut Calculator) {
+    use reedline::{DefaultPrompt, Reedline, Signal};
     let mut line_editor = Reedline::create();
     let prompt = DefaultPrompt::default();
+    let mut session = Document::new();
 
     loop {
         let sig = line_editor.read_line(&prompt);
         match sig {
-            Ok(input) => {
+            Ok(Signal::Success(input)) => {
                 let expression = input.trim();
                 if expression.eq_ignore_ascii_case("exit") || expression.eq_ignore_ascii_case("quit") {
                     break;
                 }
+                if expression == ":const" {
+                    print_constants();
+                    continue;
+                }
+                if let Some(mode) = expression.strip_prefix(":angle ") {
+                    match mode.trim().parse() {
+                        Ok(mode) => calculator.settings_mut().angle_mode = mode,
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    continue;
+                }
+                if let Some(path) = expression.strip_prefix(":export ") {
+                    match export_session(&session, Path::new(path.trim()), calculator.settings()) {
+                        Ok(()) => println!("Exported the session to {}", path.trim()),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    continue;
+                }
+                if let Some(note) = expression.strip_prefix('#') {
+                    session.blocks.push(Block::Text(note.trim().to_string()));
+                    continue;
+                }
 
-                match evaluate_expression(expression) {
-                    Ok(result) => print_result(&result),
+                let result = calculator.execute(expression);
+                match &result {
+                    Ok(result) => print_result(result, calculator.settings()),
                     Err(e) => eprintln!("{}", e),
                 }
+                print_warnings(calculator);
+                if !expression.is_empty() {
+                    session.blocks.push(Block::Calculation { input: expression.to_string(), result: Some(result) });
+                }
             }
+            Ok(_) => break,
             Err(err) => {
                 eprintln!("Error reading input: {}", err);
                 break;
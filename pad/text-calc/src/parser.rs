@@ -2,18 +2,19 @@
 //!
 //! This module uses Pest to parse input strings into an AST.
 
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
 use pest::Parser;
 use pest::iterators::Pair;
-use pest::error::Error as PestError;
-use crate::ast::{Expr, UnaryOp, BinaryOp};
+use pest::error::{Error as PestError, InputLocation};
+use crate::ast::{Expr, Statement, UnaryOp, BinaryOp};
 
 /// Pest parser for the calculator, based on the grammar defined in `calculator.pest`.
 #[derive(pest_derive::Parser)]
 #[grammar = "calculator.pest"]
 pub struct CalculatorParser;
 
-use crate::parser::Rule;
-
 /// Parses an expression string into an AST.
 ///
 /// # Arguments
@@ -25,13 +26,284 @@ use crate::parser::Rule;
 /// * `Ok(Expr)` - The parsed expression as an AST.
 /// * `Err(String)` - An error message if parsing fails.
 pub fn parse_expression(expression: &str) -> Result<Expr, String> {
-    let parse_result = CalculatorParser::parse(Rule::expression, expression);
+    let parse_result = CalculatorParser::parse(Rule::calculation, expression);
     match parse_result {
         Ok(mut pairs) => build_expr(pairs.next().unwrap()),
         Err(e) => Err(format_pest_error(e)),
     }
 }
 
+/// Parses a line of a session, which may assign a variable (e.g., `x = 5 m`).
+///
+/// # Arguments
+///
+/// * `line` - The line to parse.
+/// * `format` - How the numbers in the line are grouped and punctuated.
+///
+/// # Returns
+///
+/// * `Ok(Statement)` - The parsed assignment or expression.
+/// * `Err(String)` - An error message if parsing fails.
+pub fn parse_statement(line: &str, format: NumberFormat) -> Result<Statement, String> {
+    let normalized = normalize_numbers(line, format);
+    let mut pairs = CalculatorParser::parse(Rule::statement, &normalized).map_err(format_pest_error)?;
+    build_statement(pairs.next().unwrap())
+}
+
+/// A syntax error, with the characters of the input it points at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// A one-line description, e.g., `Parsing error: expected expression`.
+    pub message: String,
+    /// The range of characters of the input the error points at.
+    pub span: Range<usize>,
+}
+
+/// Parses a line of a session like [`parse_statement`], locating any error in the line.
+///
+/// # Arguments
+///
+/// * `line` - The line to parse.
+/// * `format` - How the numbers in the line are grouped and punctuated.
+///
+/// # Returns
+///
+/// * `Ok(Statement)` - The parsed assignment or expression.
+/// * `Err(SyntaxError)` - The error and where it is; errors found after parsing span the whole line.
+pub fn check_statement(line: &str, format: NumberFormat) -> Result<Statement, SyntaxError> {
+    let (normalized, offsets) = normalize_with_offsets(line, format);
+    let length = offsets[offsets.len() - 1];
+    match CalculatorParser::parse(Rule::statement, &normalized) {
+        Ok(mut pairs) => build_statement(pairs.next().unwrap()).map_err(|message| SyntaxError { message, span: 0..length }),
+        Err(error) => {
+            let (start, end) = match error.location {
+                InputLocation::Pos(position) => (position, position),
+                InputLocation::Span(span) => span,
+            };
+            let start = offsets[start];
+            Err(SyntaxError {
+                message: format!("Parsing error: {}", error.variant.message()),
+                span: start..offsets[end].max(start + 1).min(length),
+            })
+        }
+    }
+}
+
+/// Finds the sub-expressions of a line that contain a character, innermost first, e.g.,
+/// `2 m`, then `2 m + 3 m`, then `(2 m + 3 m) * 2` for the `m` of `2 m`.
+///
+/// # Arguments
+///
+/// * `line` - The line to search.
+/// * `format` - How the numbers in the line are grouped and punctuated.
+/// * `column` - The index of the character.
+///
+/// # Returns
+///
+/// * The ranges of characters of the sub-expressions, or nothing if the line does not parse.
+pub fn subexpressions_at(line: &str, format: NumberFormat, column: usize) -> Vec<Range<usize>> {
+    let (normalized, offsets) = normalize_with_offsets(line, format);
+    let mut pending: Vec<Pair<Rule>> = match CalculatorParser::parse(Rule::statement, &normalized) {
+        Ok(pairs) => pairs.collect(),
+        Err(_) => return Vec::new(),
+    };
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    // Sibling pairs do not overlap, so at most one pair per level contains the column, and
+    // they are found from the outermost in.
+    while let Some(pair) = pending.pop() {
+        // A pair may end in whitespace that an optional part after it skipped.
+        let start = pair.as_span().start();
+        let span = offsets[start]..offsets[start + pair.as_str().trim_end().len()];
+        if !span.contains(&column) {
+            continue;
+        }
+        let operand = matches!(
+            pair.as_rule(),
+            Rule::expression
+                | Rule::disjunction
+                | Rule::conjunction
+                | Rule::negation
+                | Rule::comparison
+                | Rule::converted
+                | Rule::sum
+                | Rule::term
+                | Rule::factor
+                | Rule::unary
+                | Rule::power
+                | Rule::postfix
+                | Rule::number_with_unit
+                | Rule::dice
+                | Rule::function
+                | Rule::symbol
+                | Rule::grouping
+        );
+        if operand && spans.last() != Some(&span) {
+            spans.push(span);
+        }
+        pending.extend(pair.into_inner());
+    }
+    spans.reverse();
+    spans
+}
+
+/// Builds a statement from the pair matched by the `statement` rule.
+///
+/// # Arguments
+///
+/// * `pair` - An `assignment` or `expression` pair.
+///
+/// # Returns
+///
+/// * `Ok(Statement)` - The assignment or expression.
+/// * `Err(String)` - An error message if the assignment target is reserved or AST construction fails.
+fn build_statement(pair: Pair<Rule>) -> Result<Statement, String> {
+    match pair.as_rule() {
+        Rule::assignment => {
+            let mut inner_rules = pair.into_inner();
+            let name = inner_rules.next().unwrap().as_str().to_string();
+            if matches!(name.as_str(), "true" | "false") {
+                return Err(format!("Cannot assign to {}", name));
+            }
+            let expr = build_expr(inner_rules.next().unwrap())?;
+            Ok(Statement::Assignment { name, expr })
+        }
+        _ => Ok(Statement::Expression(build_expr(pair)?)),
+    }
+}
+
+/// How digit grouping and the decimal separator are written in number literals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// `1234.5` or `1_234.5`; commas only separate function arguments.
+    #[default]
+    Standard,
+    /// `1,234.5`: a comma followed by exactly three digits groups thousands.
+    Grouped,
+    /// `1.234,5`: a point followed by exactly three digits groups thousands, a comma between
+    /// digits is the decimal separator and `;` separates function arguments.
+    DecimalComma,
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "standard" => Ok(NumberFormat::Standard),
+            "grouped" => Ok(NumberFormat::Grouped),
+            "decimal-comma" => Ok(NumberFormat::DecimalComma),
+            _ => Err(format!("Unknown number format: {} (expected standard, grouped or decimal-comma)", name)),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NumberFormat::Standard => "standard",
+            NumberFormat::Grouped => "grouped",
+            NumberFormat::DecimalComma => "decimal-comma",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses an expression string whose numbers are written in the given format.
+///
+/// # Arguments
+///
+/// * `expression` - The expression string to parse.
+/// * `format` - How the numbers in the expression are grouped and punctuated.
+///
+/// # Returns
+///
+/// * `Ok(Expr)` - The parsed expression as an AST.
+/// * `Err(String)` - An error message if parsing fails.
+pub fn parse_expression_with(expression: &str, format: NumberFormat) -> Result<Expr, String> {
+    parse_expression(&normalize_numbers(expression, format))
+}
+
+/// Rewrites the numbers in an expression into the standard format, e.g., `1.234,5` into `1234.5`.
+///
+/// Digits inside names such as `x1` are left alone.
+///
+/// # Arguments
+///
+/// * `expression` - The expression string to rewrite.
+/// * `format` - How the numbers in the expression are grouped and punctuated.
+///
+/// # Returns
+///
+/// * The expression with grouping removed and `.` as the decimal separator.
+pub fn normalize_numbers(expression: &str, format: NumberFormat) -> String {
+    normalize_with_offsets(expression, format).0
+}
+
+/// Rewrites the numbers in an expression into the standard format, and maps each byte of
+/// the result back to the character of `expression` it came from.
+///
+/// # Arguments
+///
+/// * `expression` - The expression string to rewrite.
+/// * `format` - How the numbers in the expression are grouped and punctuated.
+///
+/// # Returns
+///
+/// * The rewritten expression, and for each of its bytes and one past the end, a character
+///   index into `expression`.
+fn normalize_with_offsets(expression: &str, format: NumberFormat) -> (String, Vec<usize>) {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut normalized = String::with_capacity(expression.len());
+    let mut offsets = Vec::with_capacity(expression.len() + 1);
+    let mut push = |c: char, index: usize| {
+        normalized.push(c);
+        offsets.extend(std::iter::repeat_n(index, c.len_utf8()));
+    };
+    let (group, decimal) = match format {
+        NumberFormat::Standard => (None, '.'),
+        NumberFormat::Grouped => (Some(','), '.'),
+        NumberFormat::DecimalComma => (Some('.'), ','),
+    };
+    let digit_at = |index: usize| chars.get(index).is_some_and(char::is_ascii_digit);
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if group.is_none() {
+            push(c, index);
+            index += 1;
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '_') {
+                push(chars[index], index);
+                index += 1;
+            }
+            continue;
+        }
+        if !c.is_ascii_digit() {
+            push(if c == ';' && format == NumberFormat::DecimalComma { ',' } else { c }, index);
+            index += 1;
+            continue;
+        }
+        let mut fraction = false;
+        while let Some(&c) = chars.get(index) {
+            if c.is_ascii_digit() || c == '_' {
+                push(c, index);
+            } else if Some(c) == group && !fraction && (1..=3).all(|k| digit_at(index + k)) && !digit_at(index + 4) {
+                // A group separator is dropped.
+            } else if c == decimal && !fraction && digit_at(index + 1) {
+                push('.', index);
+                fraction = true;
+            } else {
+                break;
+            }
+            index += 1;
+        }
+    }
+    offsets.push(chars.len());
+    (normalized, offsets)
+}
+
 /// Formats a Pest parsing error into a string.
 ///
 /// # Arguments
@@ -45,6 +317,96 @@ fn format_pest_error(error: PestError<Rule>) -> String {
     format!("Parsing error: {}", error)
 }
 
+/// Maps an operator rule to the binary operator it denotes.
+///
+/// # Arguments
+///
+/// * `rule` - The operator rule matched by the grammar.
+///
+/// # Returns
+///
+/// * `Ok(BinaryOp)` - The corresponding binary operator.
+/// * `Err(String)` - An error message if the rule is not a binary operator.
+fn binary_op(rule: Rule) -> Result<BinaryOp, String> {
+    match rule {
+        Rule::add => Ok(BinaryOp::Add),
+        Rule::subtract => Ok(BinaryOp::Subtract),
+        Rule::multiply => Ok(BinaryOp::Multiply),
+        Rule::divide => Ok(BinaryOp::Divide),
+        Rule::floor_divide => Ok(BinaryOp::FloorDivide),
+        Rule::modulo => Ok(BinaryOp::Modulo),
+        Rule::pow => Ok(BinaryOp::Power),
+        Rule::plus_minus => Ok(BinaryOp::PlusMinus),
+        Rule::equal => Ok(BinaryOp::Equal),
+        Rule::not_equal => Ok(BinaryOp::NotEqual),
+        Rule::less => Ok(BinaryOp::Less),
+        Rule::less_equal => Ok(BinaryOp::LessEqual),
+        Rule::greater => Ok(BinaryOp::Greater),
+        Rule::greater_equal => Ok(BinaryOp::GreaterEqual),
+        Rule::and => Ok(BinaryOp::And),
+        Rule::or => Ok(BinaryOp::Or),
+        _ => Err(format!("Unknown operator: {:?}", rule)),
+    }
+}
+
+/// Converts a number literal such as `1_000.5` or `6.022e23` to its value.
+///
+/// # Arguments
+///
+/// * `literal` - The text matched by the `number` rule.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The value of the literal.
+/// * `Err(String)` - An error message if the literal is not a valid number.
+fn parse_number(literal: &str) -> Result<f64, String> {
+    literal.replace('_', "").parse::<f64>().map_err(|e| format!("Invalid number {}: {}", literal, e))
+}
+
+/// Checks whether a pair is a bare number with a unit or name suffix, such as `3x` or `2 m`.
+///
+/// # Arguments
+///
+/// * `pair` - The operand of a power or factorial.
+///
+/// # Returns
+///
+/// * `true` if the operand is a `number_with_unit` with a suffix and no sign or brackets.
+fn is_quantity(pair: &Pair<Rule>) -> bool {
+    let mut current = pair.clone();
+    loop {
+        let mut inner = current.clone().into_inner();
+        match (current.as_rule(), inner.next(), inner.next()) {
+            (Rule::number_with_unit, _, Some(_)) => return true,
+            (Rule::unary | Rule::postfix | Rule::primary, Some(only), None) => current = only,
+            _ => return false,
+        }
+    }
+}
+
+/// Applies an operator to the suffix of a quantity, so `3x^2` is `3 * x^2` and `2 m^2` is
+/// two square metres, rather than the power of the whole quantity.
+///
+/// # Arguments
+///
+/// * `operand` - The built operand.
+/// * `quantity` - Whether the operand was written as a number with a suffix.
+/// * `apply` - Builds the operator node around its operand.
+///
+/// # Returns
+///
+/// * The operator node, multiplied by the number when the operand is a quantity.
+fn apply_to_suffix(operand: Expr, quantity: bool, apply: impl FnOnce(Expr) -> Expr) -> Expr {
+    match operand {
+        Expr::NumberWithUnit { value, unit: Some(unit) } if quantity => Expr::BinaryOp {
+            left: Box::new(Expr::Number(value)),
+            op: BinaryOp::Multiply,
+            right: Box::new(apply(Expr::Symbol(unit))),
+        },
+        operand => apply(operand),
+    }
+}
+
 /// Recursively builds the AST from the parsed pairs.
 ///
 /// # Arguments
@@ -57,100 +419,105 @@ fn format_pest_error(error: PestError<Rule>) -> String {
 /// * `Err(String)` - An error message if AST construction fails.
 fn build_expr(pair: Pair<Rule>) -> Result<Expr, String> {
     match pair.as_rule() {
-        Rule::conversion => {
+        Rule::expression => {
+            let mut inner_rules = pair.into_inner();
+            let condition = build_expr(inner_rules.next().unwrap())?;
+            match (inner_rules.next(), inner_rules.next()) {
+                // `c ? a : b` is the same as `if(c, a, b)`.
+                (Some(then), Some(otherwise)) => Ok(Expr::Function {
+                    name: "if".to_string(),
+                    args: vec![condition, build_expr(then)?, build_expr(otherwise)?],
+                }),
+                _ => Ok(condition),
+            }
+        }
+        Rule::converted => {
             let mut inner_rules = pair.into_inner();
             let value_expr = build_expr(inner_rules.next().unwrap())?;
-            let target_unit = inner_rules.next().unwrap().as_str().to_string();
-            Ok(Expr::Conversion {
-                expr: Box::new(value_expr),
-                target_unit,
-            })
+            match inner_rules.next() {
+                Some(conversion) => {
+                    // The conversion pair holds the `to` keyword followed by the target unit.
+                    let target_unit = conversion.into_inner().last().unwrap().as_str().to_string();
+                    Ok(Expr::Conversion {
+                        expr: Box::new(value_expr),
+                        target_unit,
+                    })
+                }
+                None => Ok(value_expr),
+            }
         }
-        Rule::expression | Rule::term | Rule::factor | Rule::power | Rule::unary | Rule::primary | Rule::grouping => {
+        Rule::sum | Rule::term | Rule::factor | Rule::disjunction | Rule::conjunction | Rule::comparison => {
             let mut inner_rules = pair.into_inner();
-            let first = build_expr(inner_rules.next().unwrap())?;
-
-            let mut result = first;
-            while let Some(next_pair) = inner_rules.next() {
-                let operator = next_pair.as_str();
-                let next_expr = build_expr(inner_rules.next().unwrap())?;
-                result = match operator {
-                    "+" => Expr::BinaryOp {
-                        left: Box::new(result),
-                        op: BinaryOp::Add,
-                        right: Box::new(next_expr),
-                    },
-                    "-" => Expr::BinaryOp {
-                        left: Box::new(result),
-                        op: BinaryOp::Subtract,
-                        right: Box::new(next_expr),
-                    },
-                    "*" => Expr::BinaryOp {
-                        left: Box::new(result),
-                        op: BinaryOp::Multiply,
-                        right: Box::new(next_expr),
-                    },
-                    "/" => Expr::BinaryOp {
-                        left: Box::new(result),
-                        op: BinaryOp::Divide,
-                        right: Box::new(next_expr),
-                    },
-                    "^" => Expr::BinaryOp {
-                        left: Box::new(result),
-                        op: BinaryOp::Power,
-                        right: Box::new(next_expr),
-                    },
-                    _ => return Err(format!("Unknown operator: {}", operator)),
+            let mut result = build_expr(inner_rules.next().unwrap())?;
+            while let Some(operator) = inner_rules.next() {
+                // An implicit factor is its own operand, multiplied by juxtaposition.
+                let (op, next_expr) = match operator.as_rule() {
+                    Rule::implicit_factor => (BinaryOp::Multiply, build_expr(operator)?),
+                    rule => (binary_op(rule)?, build_expr(inner_rules.next().unwrap())?),
+                };
+                result = Expr::BinaryOp {
+                    left: Box::new(result),
+                    op,
+                    right: Box::new(next_expr),
                 };
             }
             Ok(result)
         }
-        Rule::number_with_unit => {
+        Rule::power => {
             let mut inner_rules = pair.into_inner();
-            let number_pair = inner_rules.next().unwrap();
-            let value = number_pair.as_str().parse::<f64>().map_err(|e| e.to_string())?;
-            let unit = if let Some(unit_pair) = inner_rules.next() {
-                Some(unit_pair.as_str().to_string())
-            } else {
-                None
-            };
-            Ok(Expr::NumberWithUnit { value, unit })
-        }
-        Rule::number => {
-            let num = pair.as_str().parse::<f64>().map_err(|e| e.to_string())?;
-            Ok(Expr::Number(num))
+            let base_pair = inner_rules.next().unwrap();
+            let quantity = is_quantity(&base_pair);
+            let base = build_expr(base_pair)?;
+            match inner_rules.next() {
+                Some(operator) => {
+                    let op = binary_op(operator.as_rule())?;
+                    let exponent = build_expr(inner_rules.next().unwrap())?;
+                    Ok(apply_to_suffix(base, quantity, |suffix| Expr::BinaryOp {
+                        left: Box::new(suffix),
+                        op,
+                        right: Box::new(exponent),
+                    }))
+                }
+                None => Ok(base),
+            }
         }
-        Rule::function => {
+        Rule::postfix => {
             let mut inner_rules = pair.into_inner();
-            let name = inner_rules.next().unwrap().as_str().to_string();
-            let arg = build_expr(inner_rules.next().unwrap())?;
-            Ok(Expr::Function {
-                name,
-                arg: Box::new(arg),
-            })
+            let primary = inner_rules.next().unwrap();
+            let quantity = is_quantity(&primary);
+            let mut result = build_expr(primary)?;
+            for operator in inner_rules {
+                result = match operator.as_rule() {
+                    Rule::percent => Expr::UnaryOp {
+                        op: UnaryOp::Percent,
+                        expr: Box::new(result),
+                    },
+                    _ => apply_to_suffix(result, quantity, |operand| Expr::UnaryOp {
+                        op: UnaryOp::Factorial,
+                        expr: Box::new(operand),
+                    }),
+                };
+            }
+            Ok(result)
         }
-        Rule::unary => {
+        Rule::unary | Rule::negation => {
             let mut inner_rules = pair.into_inner();
             let mut op_signs = Vec::new();
 
             while let Some(next) = inner_rules.peek() {
-                if next.as_rule() == Rule::primary
-                    || next.as_rule() == Rule::grouping
-                    || next.as_rule() == Rule::number_with_unit
-                {
+                if !matches!(next.as_rule(), Rule::plus | Rule::minus | Rule::not) {
                     break;
                 }
-                op_signs.push(inner_rules.next().unwrap().as_str());
+                op_signs.push(inner_rules.next().unwrap().as_rule());
             }
 
-            let expr = build_expr(inner_rules.next().unwrap())?;
-
-            let mut result = expr;
+            let mut result = build_expr(inner_rules.next().unwrap())?;
             for op in op_signs.into_iter().rev() {
                 let op = match op {
-                    "+" => UnaryOp::Plus,
-                    "-" => UnaryOp::Minus,
-                    _ => return Err(format!("Unknown unary operator: {}", op)),
+                    Rule::plus => UnaryOp::Plus,
+                    Rule::minus => UnaryOp::Minus,
+                    Rule::not => UnaryOp::Not,
+                    _ => return Err(format!("Unknown unary operator: {:?}", op)),
                 };
                 result = Expr::UnaryOp {
                     op,
@@ -159,8 +526,59 @@ fn build_expr(pair: Pair<Rule>) -> Result<Expr, String> {
             }
             Ok(result)
         }
-        Rule::primary => build_expr(pair.into_inner().next().unwrap()),
-        Rule::grouping => build_expr(pair.into_inner().next().unwrap()),
+        Rule::number_with_unit => {
+            let mut inner_rules = pair.into_inner();
+            let value = parse_number(inner_rules.next().unwrap().as_str())?;
+            match inner_rules.next() {
+                Some(unit_pair) => Ok(Expr::NumberWithUnit {
+                    value,
+                    unit: Some(unit_pair.as_str().to_string()),
+                }),
+                None => Ok(Expr::Number(value)),
+            }
+        }
+        Rule::number => Ok(Expr::Number(parse_number(pair.as_str())?)),
+        // `3d6` is a roll of three six-sided dice, `dice(3, 6)`.
+        Rule::dice => {
+            let (count, sides) = pair.as_str().split_once('d').unwrap();
+            Ok(Expr::Function {
+                name: "dice".to_string(),
+                args: vec![Expr::Number(parse_number(count)?), Expr::Number(parse_number(sides)?)],
+            })
+        }
+        Rule::function => {
+            let mut inner_rules = pair.into_inner();
+            let name = inner_rules.next().unwrap().as_str().to_string();
+            let mut args = Vec::new();
+            for argument in inner_rules {
+                // The values of a list are arguments of their own.
+                match argument.clone().into_inner().next() {
+                    Some(list) if list.as_rule() == Rule::list => {
+                        for value in list.into_inner() {
+                            args.push(build_expr(value)?);
+                        }
+                    }
+                    _ => args.push(build_expr(argument)?),
+                }
+            }
+            if name == "if" && args.len() != 3 {
+                return Err("if expects a condition and two values, e.g., if(x > 0, x, -x)".to_string());
+            }
+            Ok(Expr::Function { name, args })
+        }
+        Rule::argument => {
+            let mut inner_rules = pair.into_inner();
+            let left = build_expr(inner_rules.next().unwrap())?;
+            match inner_rules.next() {
+                Some(right) => Ok(Expr::Equation {
+                    left: Box::new(left),
+                    right: Box::new(build_expr(right)?),
+                }),
+                None => Ok(left),
+            }
+        }
+        Rule::symbol => Ok(Expr::Symbol(pair.as_str().to_string())),
+        Rule::primary | Rule::grouping | Rule::implicit_factor => build_expr(pair.into_inner().next().unwrap()),
         _ => Err(format!("Unhandled rule: {:?}", pair.as_rule())),
     }
 }
@@ -203,6 +621,187 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_symbol() {
+        let expr = parse_expression("x").unwrap();
+        assert_eq!(expr, Expr::Symbol("x".to_string()));
+    }
+
+    #[test]
+    fn test_parse_multiple_arguments() {
+        let expr = parse_expression("diff(x^3 * sin(x), x)").unwrap();
+        if let Expr::Function { name, args } = expr {
+            assert_eq!(name, "diff");
+            assert_eq!(args.len(), 2);
+            assert_eq!(args[1], Expr::Symbol("x".to_string()));
+        } else {
+            panic!("Expected Expr::Function");
+        }
+    }
+
+    #[test]
+    fn test_parse_equation_argument() {
+        let expr = parse_expression("solve(x^2 + 3x = 10, x)").unwrap();
+        if let Expr::Function { args, .. } = expr {
+            assert!(matches!(args[0], Expr::Equation { .. }));
+        } else {
+            panic!("Expected Expr::Function");
+        }
+        assert!(parse_expression("x = 10").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_trailing_input() {
+        assert!(parse_expression("1 + 2 )").is_err());
+    }
+
+    #[test]
+    fn test_parse_comparison_and_logic() {
+        let expr = parse_expression("not x > 1 mi to m or y == 2 and z != 3").unwrap();
+        assert_eq!(expr.to_string(), "not x > 1 mi to m or y == 2 and z != 3");
+        if let Expr::BinaryOp { left, op, .. } = expr {
+            assert_eq!(op, BinaryOp::Or);
+            assert!(matches!(*left, Expr::UnaryOp { op: UnaryOp::Not, .. }));
+        } else {
+            panic!("Expected Expr::BinaryOp");
+        }
+        assert!(parse_expression("1 < x < 2").is_err());
+        assert!(parse_expression("5 and").is_err());
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let expr = parse_expression("x < 0 ? -x : x > 10 ? 10 : x").unwrap();
+        assert_eq!(expr.to_string(), "if(x < 0, -x, if(x > 10, 10, x))");
+        assert_eq!(parse_expression("if(x < 0, -x, x)").unwrap(), parse_expression("x < 0 ? -x : x").unwrap());
+        assert!(parse_expression("if(x < 0, -x)").is_err());
+    }
+
+    #[test]
+    fn test_parse_implicit_multiplication() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert_eq!(print("3(4 + 5)"), "3 * (4 + 5)");
+        assert_eq!(print("2 sqrt(2)"), "2 * sqrt(2)");
+        assert_eq!(print("(1 + 2)(3 + 4) x"), "(1 + 2) * (3 + 4) * x");
+        assert_eq!(print("3x^2"), "3 * x^2");
+        assert_eq!(print("2 m^2"), "2 * m^2");
+        assert_eq!(print("2 - 3"), "2 - 3");
+        // A name with digits or underscores is never split into a unit and a factor.
+        assert_eq!(print("2 x1"), "2 * x1");
+        assert_eq!(print("2 m2"), "2 * m2");
+        assert_eq!(print("2 g0"), "2 * g0");
+        assert_eq!(print("2 k_B"), "2 * k_B");
+        assert_eq!(parse_expression("2pi").unwrap(), Expr::NumberWithUnit { value: 2.0, unit: Some("pi".to_string()) });
+    }
+
+    #[test]
+    fn test_parse_factorial_modulo_and_floor_division() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert_eq!(print("5! + 17 mod 5 * 2"), "5! + 17 mod 5 * 2");
+        assert_eq!(print("17 // 5"), "17 // 5");
+        assert_eq!(print("(x + 1)! / 2x!"), "(x + 1)! / (2 * x!)");
+        assert!(matches!(parse_expression("5 != 3").unwrap(), Expr::BinaryOp { op: BinaryOp::NotEqual, .. }));
+    }
+
+    #[test]
+    fn test_parse_number_literals() {
+        let number = |input: &str| match parse_expression(input).unwrap() {
+            Expr::Number(n) => n,
+            other => panic!("Expected Expr::Number, got {:?}", other),
+        };
+        assert_eq!(number("1_000_000"), 1e6);
+        assert_eq!(number(".5"), 0.5);
+        assert_eq!(number("6.022E23"), 6.022e23);
+        assert_eq!(number("1e-9"), 1e-9);
+        assert_eq!(number("2."), 2.0);
+        assert_eq!(parse_expression("2e").unwrap(), Expr::NumberWithUnit { value: 2.0, unit: Some("e".to_string()) });
+        assert_eq!(number("1_000.000_1"), 1000.0001);
+    }
+
+    #[test]
+    fn test_parse_sign_precedence() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert!(matches!(parse_expression("-2^2").unwrap(), Expr::UnaryOp { op: UnaryOp::Minus, .. }));
+        assert_eq!(print("-2^2"), "-2^2");
+        assert_eq!(print("(-2)^2"), "(-2)^2");
+        assert_eq!(print("2^-2"), "2^-2");
+        assert_eq!(print("2^-x^2"), "2^-x^2");
+        assert_eq!(print("-(2 * x)"), "-(2 * x)");
+        assert_eq!(print("2 * -3"), "2 * -3");
+    }
+
+    #[test]
+    fn test_parse_plus_minus() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert_eq!(parse_expression("2 +/- 0.1").unwrap(), parse_expression("2 ± 0.1").unwrap());
+        assert!(matches!(parse_expression("2 ± 0.1 m").unwrap(), Expr::BinaryOp { op: BinaryOp::PlusMinus, .. }));
+        assert_eq!(print("9.81 ± 0.02 m/s^2"), "9.81 ± 0.02 m / s^2");
+        assert_eq!(print("(2 ± 0.1) * 3"), "2 ± 0.1 * 3");
+        assert_eq!(print("(2 ± 0.1)^2"), "(2 ± 0.1)^2");
+        assert!(parse_expression("1 ± 2 ± 3").is_err());
+    }
+
+    #[test]
+    fn test_parse_percent_and_lists() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert!(matches!(parse_expression("5%").unwrap(), Expr::UnaryOp { op: UnaryOp::Percent, .. }));
+        assert_eq!(print("5%/12"), "5% / 12");
+        assert_eq!(print("(2 + 3)%"), "(2 + 3)%");
+        assert_eq!(print("npv(10%, [-1000, 300, 400])"), "npv(10%, -1000, 300, 400)");
+        assert_eq!(print("max([2], 3)"), "max(2, 3)");
+        assert!(parse_expression("[1, 2] + 3").is_err());
+    }
+
+    #[test]
+    fn test_parse_dice() {
+        let print = |input: &str| parse_expression(input).unwrap().to_string();
+        assert_eq!(print("3d6 + 2"), "dice(3, 6) + 2");
+        assert!(matches!(parse_expression("d6").unwrap(), Expr::Symbol(_)));
+        assert!(matches!(parse_expression("3d6.5").unwrap(), Expr::BinaryOp { .. }));
+    }
+
+    #[test]
+    fn test_parse_number_formats() {
+        assert_eq!(normalize_numbers("max(1,234.5, 2,000)", NumberFormat::Grouped), "max(1234.5, 2000)");
+        assert_eq!(normalize_numbers("1.234,5 + x1,5", NumberFormat::DecimalComma), "1234.5 + x1,5");
+        assert_eq!(normalize_numbers("max(1,5; 2)", NumberFormat::DecimalComma), "max(1.5, 2)");
+        assert_eq!(normalize_numbers("1,234", NumberFormat::Standard), "1,234");
+        assert_eq!(parse_expression_with("1,000,000", NumberFormat::Grouped).unwrap(), Expr::Number(1e6));
+        assert_eq!("decimal-comma".parse::<NumberFormat>(), Ok(NumberFormat::DecimalComma));
+        assert!("french".parse::<NumberFormat>().is_err());
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        let statement = parse_statement("x = 5 m", NumberFormat::Standard).unwrap();
+        assert_eq!(statement, Statement::Assignment { name: "x".to_string(), expr: parse_expression("5 m").unwrap() });
+        let statement = parse_statement("x == 5", NumberFormat::Standard).unwrap();
+        assert!(matches!(statement, Statement::Expression(Expr::BinaryOp { op: BinaryOp::Equal, .. })));
+        assert!(parse_statement("true = 1", NumberFormat::Standard).is_err());
+        assert!(parse_statement("2 = x", NumberFormat::Standard).is_err());
+    }
+
+    #[test]
+    fn test_check_statement() {
+        let error = check_statement("x = 2 * * 3", NumberFormat::Standard).unwrap_err();
+        assert_eq!(error.span, 8..9);
+        assert!(error.message.starts_with("Parsing error: expected"));
+        // Offsets count characters of the line as written, before grouping is removed.
+        assert_eq!(check_statement("1,000,000 + )", NumberFormat::Grouped).unwrap_err().span, 12..13);
+        assert_eq!(check_statement("true = 1", NumberFormat::Standard).unwrap_err().span, 0..8);
+        assert!(check_statement("y = 1,5 * 2", NumberFormat::DecimalComma).is_ok());
+    }
+
+    #[test]
+    fn test_subexpressions_at() {
+        let line = "(2 m + 3 m) * 2";
+        let spans: Vec<&str> = subexpressions_at(line, NumberFormat::Standard, 3).into_iter().map(|span| &line[span]).collect();
+        assert_eq!(spans, ["2 m", "2 m + 3 m", "(2 m + 3 m)", "(2 m + 3 m) * 2"]);
+        let spans = subexpressions_at("d = 1,5 km", NumberFormat::DecimalComma, 5);
+        assert_eq!((spans.len(), spans.first()), (1, Some(&(4..10))));
+        assert!(subexpressions_at("1 +", NumberFormat::Standard, 0).is_empty());
+    }
+
     #[test]
     fn test_parse_function() {
         let expr = parse_expression("sin(pi / 2)").unwrap();
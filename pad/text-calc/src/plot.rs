@@ -0,0 +1,428 @@
+//! Plotting of expressions as functions of one variable.
+//!
+//! Expressions are sampled over a range and drawn either as a Braille chart for the
+//! terminal or as an SVG document.
+
+use crate::calculator::Calculator;
+use crate::evaluator::BoundFunction;
+use crate::symbolic;
+use std::fmt::Write;
+
+/// ANSI colours for successive series in the terminal chart.
+const ANSI_COLORS: [&str; 6] = ["\x1b[34m", "\x1b[31m", "\x1b[32m", "\x1b[35m", "\x1b[33m", "\x1b[36m"];
+/// Resets the terminal colour.
+const ANSI_RESET: &str = "\x1b[0m";
+/// Stroke colours for successive series in SVG output.
+const SVG_COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd", "#ff7f0e", "#17becf"];
+/// Width and height of an exported SVG chart, in pixels.
+const SVG_SIZE: (f64, f64) = (640.0, 400.0);
+/// Space around the SVG plot area for labels: left, right, top, bottom.
+const SVG_MARGINS: (f64, f64, f64, f64) = (70.0, 20.0, 20.0, 40.0);
+
+/// A function sampled over a range, ready to be drawn.
+#[derive(Debug, Clone)]
+pub struct Series {
+    /// The legend label, the expression as written.
+    pub label: String,
+    /// The sampled `(x, y)` points; `y` is NaN where the function is undefined.
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Samples an expression as a function of `var` at evenly spaced points.
+///
+/// Points where evaluation fails are kept with a NaN value so the line breaks there.
+///
+/// # Arguments
+///
+/// * `expression` - The expression to plot (e.g., `sin(x) / x`).
+/// * `var` - The variable the expression is a function of.
+/// * `range` - The first and last value of the variable.
+/// * `samples` - The number of points to sample, at least 2.
+/// * `calculator` - The calculator the expression is evaluated with; `var` shadows its variables.
+///
+/// # Returns
+///
+/// * `Ok(Series)` - The sampled points.
+/// * `Err(String)` - An error message if the expression cannot be parsed or is undefined everywhere.
+pub fn sample(
+    expression: &str,
+    var: &str,
+    range: (f64, f64),
+    samples: usize,
+    calculator: &Calculator,
+) -> Result<Series, String> {
+    let ast = symbolic::expand_derivatives(&calculator.parse(expression)?)?;
+    let (from, to) = range;
+    let samples = samples.max(2);
+    let mut first_error = None;
+    let points = calculator.with_context(|context| {
+        let unknown = symbolic::free_symbols(&ast)
+            .into_iter()
+            .find(|name| name != var && context.variable(name).is_none());
+        if let Some(unknown) = unknown {
+            return Err(format!(
+                "Unknown variable '{}' in '{}'; the plot is a function of '{}'",
+                unknown, expression, var
+            ));
+        }
+        let mut function = BoundFunction::new(&ast, var, None, context);
+        Ok((0..samples)
+            .map(|i| {
+                let x = from + (to - from) * i as f64 / (samples - 1) as f64;
+                let y = match function.call(x) {
+                    Ok(result) => result.value,
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                        f64::NAN
+                    }
+                };
+                (x, y)
+            })
+            .collect::<Vec<(f64, f64)>>())
+    })?;
+
+    if points.iter().all(|(_, y)| !y.is_finite()) {
+        let reason = first_error.unwrap_or_else(|| "no finite values".to_string());
+        return Err(format!("'{}' cannot be plotted over [{}, {}]: {}", expression, from, to, reason));
+    }
+    Ok(Series {
+        label: expression.to_string(),
+        points,
+    })
+}
+
+/// Returns the smallest and largest finite `y` over all series, widened when they are equal.
+fn y_range(series: &[Series]) -> Option<(f64, f64)> {
+    let values = series.iter().flat_map(|s| s.points.iter().map(|(_, y)| *y)).filter(|y| y.is_finite());
+    let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), y| (low.min(y), high.max(y)));
+    if low > high {
+        return None;
+    }
+    if low == high {
+        let pad = if low == 0.0 { 1.0 } else { low.abs() / 10.0 };
+        return Some((low - pad, high + pad));
+    }
+    Some((low, high))
+}
+
+/// Returns the smallest and largest `x` over all series.
+fn x_range(series: &[Series]) -> (f64, f64) {
+    let xs = series.iter().flat_map(|s| s.points.iter().map(|(x, _)| *x));
+    xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), x| (low.min(x), high.max(x)))
+}
+
+/// Formats an axis label with at most 4 significant digits.
+fn format_tick(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let scale = 10f64.powi(3 - value.abs().log10().floor() as i32);
+    let rounded = (value * scale).round() / scale;
+    // Rounding can leave `-0`, which should print as `0`.
+    format!("{}", rounded + 0.0)
+}
+
+/// A grid of Braille cells, each holding 2 × 4 dots.
+struct Canvas {
+    width: usize,
+    height: usize,
+    dots: Vec<u8>,
+    colors: Vec<Option<usize>>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            dots: vec![0; width * height],
+            colors: vec![None; width * height],
+        }
+    }
+
+    /// Sets the dot at pixel `(px, py)`, counted from the top left, in the colour of `series`.
+    fn set(&mut self, px: usize, py: usize, series: Option<usize>) {
+        const BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        if px >= self.width * 2 || py >= self.height * 4 {
+            return;
+        }
+        let cell = (py / 4) * self.width + px / 2;
+        self.dots[cell] |= BITS[px % 2][py % 4];
+        if series.is_some() {
+            self.colors[cell] = series;
+        }
+    }
+
+    /// Draws a straight line between two pixels.
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), series: usize) {
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let px = from.0 + (to.0 - from.0) * t;
+            let py = from.1 + (to.1 - from.1) * t;
+            self.set(px.round() as usize, py.round() as usize, Some(series));
+        }
+    }
+
+    /// Returns the character for a cell, coloured when `color` is set.
+    fn cell(&self, column: usize, row: usize, color: bool) -> String {
+        let index = row * self.width + column;
+        let glyph = char::from_u32(0x2800 + self.dots[index] as u32).unwrap_or(' ');
+        match self.colors[index] {
+            Some(series) if color => format!("{}{}{}", ANSI_COLORS[series % ANSI_COLORS.len()], glyph, ANSI_RESET),
+            _ => glyph.to_string(),
+        }
+    }
+}
+
+/// Renders one or more series as a Braille chart with axis labels and a legend.
+///
+/// The y-axis is scaled to the finite values of all series. Dotted axes are drawn
+/// through zero when it lies in the plotted range.
+///
+/// # Arguments
+///
+/// * `series` - The series to draw, sharing the same axes.
+/// * `width` - The width of the plot area in characters.
+/// * `height` - The height of the plot area in characters.
+/// * `color` - Whether to colour each series with ANSI escape codes.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The chart, one line per text row.
+/// * `Err(String)` - An error message if there is nothing to plot.
+pub fn render_braille(series: &[Series], width: usize, height: usize, color: bool) -> Result<String, String> {
+    let (y_low, y_high) = y_range(series).ok_or("Nothing to plot")?;
+    let (x_low, x_high) = x_range(series);
+    let (width, height) = (width.max(8), height.max(2));
+    let (pixels_x, pixels_y) = ((width * 2 - 1) as f64, (height * 4 - 1) as f64);
+    let to_pixel = |x: f64, y: f64| {
+        let px = if x_high > x_low { (x - x_low) / (x_high - x_low) * pixels_x } else { 0.0 };
+        (px, (y_high - y) / (y_high - y_low) * pixels_y)
+    };
+
+    let mut canvas = Canvas::new(width, height);
+    if y_low <= 0.0 && y_high >= 0.0 {
+        let (_, py) = to_pixel(x_low, 0.0);
+        for px in (0..width * 2).step_by(2) {
+            canvas.set(px, py.round() as usize, None);
+        }
+    }
+    if x_low <= 0.0 && x_high >= 0.0 {
+        let (px, _) = to_pixel(0.0, y_low);
+        for py in (0..height * 4).step_by(2) {
+            canvas.set(px.round() as usize, py, None);
+        }
+    }
+    for (index, s) in series.iter().enumerate() {
+        for pair in s.points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if y0.is_finite() && y1.is_finite() {
+                canvas.line(to_pixel(x0, y0), to_pixel(x1, y1), index);
+            }
+        }
+    }
+
+    let top = format_tick(y_high);
+    // Only charts taller than two rows have a row between the top and bottom labels.
+    let middle = if height > 2 { format_tick((y_high + y_low) / 2.0) } else { String::new() };
+    let bottom = format_tick(y_low);
+    let label_width = top.len().max(middle.len()).max(bottom.len());
+
+    let mut output = String::new();
+    for row in 0..height {
+        let label = match row {
+            0 => top.as_str(),
+            _ if row == height - 1 => bottom.as_str(),
+            _ if row == height / 2 => middle.as_str(),
+            _ => "",
+        };
+        let tick = if label.is_empty() { '│' } else { '┤' };
+        let _ = write!(output, "{:>w$} {}", label, tick, w = label_width);
+        for column in 0..width {
+            output.push_str(&canvas.cell(column, row, color));
+        }
+        output.push('\n');
+    }
+    let _ = writeln!(output, "{:>w$} └{}", "", "─".repeat(width), w = label_width);
+    let (left, right) = (format_tick(x_low), format_tick(x_high));
+    let gap = (width + 1).saturating_sub(left.len() + right.len()).max(1);
+    let _ = writeln!(output, "{:>w$} {}{}{}", "", left, " ".repeat(gap), right, w = label_width);
+
+    for (index, s) in series.iter().enumerate() {
+        let marker = if color {
+            format!("{}⣿{}", ANSI_COLORS[index % ANSI_COLORS.len()], ANSI_RESET)
+        } else {
+            "⣿".to_string()
+        };
+        let _ = writeln!(output, "{:>w$} {} {}", "", marker, s.label, w = label_width);
+    }
+    Ok(output)
+}
+
+/// Escapes text for use inside an SVG document.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Renders one or more series as a standalone SVG document with axes and a legend.
+///
+/// # Arguments
+///
+/// * `series` - The series to draw, sharing the same axes.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The SVG document.
+/// * `Err(String)` - An error message if there is nothing to plot.
+pub fn render_svg(series: &[Series]) -> Result<String, String> {
+    let (y_low, y_high) = y_range(series).ok_or("Nothing to plot")?;
+    let (x_low, x_high) = x_range(series);
+    let (width, height) = SVG_SIZE;
+    let (left, right, top, bottom) = SVG_MARGINS;
+    let (plot_width, plot_height) = (width - left - right, height - top - bottom);
+    let to_point = |x: f64, y: f64| {
+        let px = if x_high > x_low { left + (x - x_low) / (x_high - x_low) * plot_width } else { left };
+        (px, top + (y_high - y) / (y_high - y_low) * plot_height)
+    };
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+        w = width,
+        h = height
+    );
+    let _ = writeln!(svg, r#"<rect width="{}" height="{}" fill="white"/>"#, width, height);
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#999"/>"##,
+        left, top, plot_width, plot_height
+    );
+    if y_low <= 0.0 && y_high >= 0.0 {
+        let (_, y) = to_point(x_low, 0.0);
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{}" y1="{y:.2}" x2="{}" y2="{y:.2}" stroke="#ccc" stroke-dasharray="4 4"/>"##,
+            left,
+            left + plot_width
+        );
+    }
+    if x_low <= 0.0 && x_high >= 0.0 {
+        let (x, _) = to_point(0.0, y_low);
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{x:.2}" y1="{}" x2="{x:.2}" y2="{}" stroke="#ccc" stroke-dasharray="4 4"/>"##,
+            top,
+            top + plot_height
+        );
+    }
+
+    // Axis labels at the corners of the plot area.
+    let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#, left - 6.0, top + 4.0, format_tick(y_high));
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+        left - 6.0,
+        top + plot_height + 4.0,
+        format_tick(y_low)
+    );
+    let _ = writeln!(svg, r#"<text x="{}" y="{}">{}</text>"#, left, height - bottom + 18.0, format_tick(x_low));
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+        left + plot_width,
+        height - bottom + 18.0,
+        format_tick(x_high)
+    );
+
+    for (index, s) in series.iter().enumerate() {
+        let color = SVG_COLORS[index % SVG_COLORS.len()];
+        // Undefined points split the series into separate polylines.
+        for segment in s.points.split(|(_, y)| !y.is_finite()).filter(|segment| segment.len() > 1) {
+            let points: Vec<String> = segment
+                .iter()
+                .map(|(x, y)| {
+                    let (px, py) = to_point(*x, *y);
+                    format!("{:.2},{:.2}", px, py)
+                })
+                .collect();
+            let _ = writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+                points.join(" "),
+                color
+            );
+        }
+        let legend_y = top + 16.0 + index as f64 * 16.0;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{}" y1="{y}" x2="{}" y2="{y}" stroke="{}" stroke-width="2"/>"#,
+            left + 10.0,
+            left + 30.0,
+            color,
+            y = legend_y - 4.0
+        );
+        let _ = writeln!(svg, r#"<text x="{}" y="{}">{}</text>"#, left + 36.0, legend_y, escape_xml(&s.label));
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::{AngleMode, ValueWithUnit};
+
+    #[test]
+    fn test_sample() {
+        let series = sample("x^2", "x", (-2.0, 2.0), 5, &Calculator::new()).unwrap();
+        let ys: Vec<f64> = series.points.iter().map(|(_, y)| *y).collect();
+        assert_eq!(ys, vec![4.0, 1.0, 0.0, 1.0, 4.0]);
+    }
+
+    #[test]
+    fn test_sample_marks_undefined_points() {
+        let series = sample("sin(x) / x", "x", (-1.0, 1.0), 3, &Calculator::new()).unwrap();
+        assert!(series.points[1].1.is_nan());
+        assert!((series.points[0].1 - 0.8414709848).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_rejects_other_variables() {
+        assert!(sample("x * y", "x", (0.0, 1.0), 10, &Calculator::new()).is_err());
+    }
+
+    #[test]
+    fn test_sample_uses_calculator() {
+        let mut calculator = Calculator::new();
+        calculator.settings_mut().angle_mode = AngleMode::Degrees;
+        calculator.set_variable("a", ValueWithUnit::new(3.0, None));
+        let series = sample("a * sin(x)", "x", (0.0, 90.0), 2, &calculator).unwrap();
+        assert_eq!(series.points[1], (90.0, 3.0));
+    }
+
+    #[test]
+    fn test_render_braille() {
+        let series = sample("x", "x", (0.0, 1.0), 17, &Calculator::new()).unwrap();
+        let chart = render_braille(&[series], 8, 2, false).unwrap();
+        let lines: Vec<&str> = chart.lines().collect();
+        assert!(lines[0].starts_with("1 ┤"));
+        assert!(lines[1].starts_with("0 ┤"));
+        // The diagonal ends in the top dots of the top right cell.
+        assert!(lines[0].ends_with('⠉'));
+        assert!(lines[2].starts_with("  └────────"));
+        assert!(lines.last().unwrap().ends_with("⣿ x"));
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let first = sample("sin(x)", "x", (0.0, 6.0), 50, &Calculator::new()).unwrap();
+        let second = sample("1 / (x - 3)", "x", (0.0, 6.0), 51, &Calculator::new()).unwrap();
+        let svg = render_svg(&[first, second]).unwrap();
+        assert!(svg.starts_with("<svg"));
+        // The pole at x = 3 splits the second series in two.
+        assert_eq!(svg.matches("<polyline").count(), 3);
+        assert!(svg.contains(">sin(x)</text>"));
+    }
+}
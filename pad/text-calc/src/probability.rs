@@ -0,0 +1,328 @@
+//! Random numbers, dice and probability distributions.
+//!
+//! `rand`, `randint` and `dice` draw from the generator of the evaluation context, which
+//! is seeded with `--seed` for reproducible results. The distributions and counting
+//! functions (`normpdf`, `normcdf`, `binom`, `poisson`, `choose` and `perm`) are
+//! deterministic and evaluate like any other function.
+
+use crate::ast::Expr;
+use crate::evaluator::{evaluate_with, Context, ValueWithUnit};
+use crate::units;
+use std::f64::consts::{PI, SQRT_2};
+
+/// The most dice one roll may throw.
+const MAX_DICE: f64 = 1_000_000.0;
+/// The largest whole number an `f64` holds exactly.
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// Checks whether a function name draws random numbers.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+///
+/// # Returns
+///
+/// * `true` for `rand`, `randint` and `dice`.
+pub fn is_random_function(name: &str) -> bool {
+    matches!(name, "rand" | "randint" | "dice")
+}
+
+/// Checks whether a function name is a probability distribution or counting function.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+///
+/// # Returns
+///
+/// * `true` for `normpdf`, `normcdf`, `binom`, `poisson`, `choose` and `perm`.
+pub fn is_distribution(name: &str) -> bool {
+    matches!(name, "normpdf" | "normcdf" | "binom" | "poisson" | "choose" | "perm")
+}
+
+/// Evaluates a call to `rand`, `randint` or `dice`.
+///
+/// The arguments are evaluated once and the draw is not repeated, so random functions do
+/// not take part in uncertainty propagation.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+/// * `args` - The unevaluated arguments.
+/// * `context` - The variable bindings and the generator to draw from.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The random number.
+/// * `Err(String)` - An error message if the arguments are not whole numbers in range.
+pub fn call_random(name: &str, args: &[Expr], context: &Context) -> Result<ValueWithUnit, String> {
+    let values = args
+        .iter()
+        .map(|arg| match evaluate_with(arg, context)?.number(name)? {
+            ValueWithUnit { value, unit: None, .. } => Ok(value),
+            ValueWithUnit { unit: Some(unit), .. } => Err(format!("{} expects unitless values, not one in {}", name, unit)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let value = match (name, values.as_slice()) {
+        ("rand", []) => context.with_rng(|rng| rng.next_f64()),
+        ("rand", _) => return Err("rand takes no arguments, as in rand()".to_string()),
+        ("randint", [low, high]) => {
+            if low.fract() != 0.0 || high.fract() != 0.0 || low > high || high - low >= MAX_EXACT {
+                return Err(format!("randint expects whole numbers a ≤ b, not {} and {}", low, high));
+            }
+            low + context.with_rng(|rng| rng.below((high - low) as u64 + 1)) as f64
+        }
+        ("randint", _) => return Err("randint expects two arguments, as in randint(1, 6)".to_string()),
+        (_, [count, sides]) => {
+            if count.fract() != 0.0 || *count < 1.0 || *count > MAX_DICE {
+                return Err(format!("The number of dice must be a whole number from 1 to {}, not {}", MAX_DICE, count));
+            }
+            if sides.fract() != 0.0 || *sides < 1.0 || *sides >= MAX_EXACT {
+                return Err(format!("A die must have a whole number of sides, at least 1, not {}", sides));
+            }
+            context.with_rng(|rng| (0..*count as u64).map(|_| rng.below(*sides as u64) as f64 + 1.0).sum())
+        }
+        _ => return Err("dice expects a number of dice and their sides, as in dice(3, 6) or 3d6".to_string()),
+    };
+    Ok(ValueWithUnit::new(value, None))
+}
+
+/// Evaluates a probability distribution or counting function.
+///
+/// `normpdf` and `normcdf` take `x` alone for the standard normal distribution, or `x`, the
+/// mean and the standard deviation, which may share a unit; the density is then per that
+/// unit. The other functions take unitless arguments.
+///
+/// # Arguments
+///
+/// * `name` - The function name.
+/// * `args` - The evaluated arguments.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The probability, density or count.
+/// * `Err(String)` - An error message if the arguments are of the wrong number, unit or range.
+pub fn distribution(name: &str, args: &[ValueWithUnit]) -> Result<ValueWithUnit, String> {
+    if matches!(name, "normpdf" | "normcdf") {
+        return normal(name, args);
+    }
+    let values = args
+        .iter()
+        .map(|arg| match &arg.unit {
+            None => Ok(arg.value),
+            Some(unit) => Err(format!("{} expects unitless values, not one in {}", name, unit)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let value = match (name, values.as_slice()) {
+        ("binom", [k, n, p]) => {
+            let n = whole(name, "n", *n)?;
+            if !(0.0..=1.0).contains(p) {
+                return Err(format!("The probability p of binom must be from 0 to 1, not {}", p));
+            }
+            let k = whole(name, "k", *k)?;
+            if k > n {
+                0.0
+            } else if *p == 0.0 || *p == 1.0 {
+                // Every trial fails, or every trial succeeds.
+                let certain = if *p == 0.0 { 0.0 } else { n };
+                if k == certain { 1.0 } else { 0.0 }
+            } else {
+                (ln_choose(n, k) + k * p.ln() + (n - k) * (1.0 - p).ln()).exp()
+            }
+        }
+        ("poisson", [k, lambda]) => {
+            let k = whole(name, "k", *k)?;
+            if !(*lambda >= 0.0 && lambda.is_finite()) {
+                return Err(format!("The rate lambda of poisson must be a non-negative number, not {}", lambda));
+            }
+            if *lambda == 0.0 {
+                if k == 0.0 { 1.0 } else { 0.0 }
+            } else {
+                (k * lambda.ln() - lambda - ln_factorial(k)).exp()
+            }
+        }
+        ("choose", [n, k]) => {
+            let (n, k) = (whole(name, "n", *n)?, whole(name, "k", *k)?);
+            if k > n { 0.0 } else { choose_exact(n, k).unwrap_or_else(|| ln_choose(n, k).exp()) }
+        }
+        ("perm", [n, k]) => {
+            let (n, k) = (whole(name, "n", *n)?, whole(name, "k", *k)?);
+            if k > n { 0.0 } else { ((n - k + 1.0) as u64..=n as u64).fold(1.0, |product, i| product * i as f64) }
+        }
+        _ => {
+            let usage = match name {
+                "binom" => "binom(k, n, p)",
+                "poisson" => "poisson(k, lambda)",
+                "choose" => "choose(n, k)",
+                _ => "perm(n, k)",
+            };
+            return Err(format!("{} expects {} arguments, as in {}", name, usage.matches(',').count() + 1, usage));
+        }
+    };
+    Ok(ValueWithUnit::new(value, None))
+}
+
+/// Evaluates `normpdf` or `normcdf`.
+fn normal(name: &str, args: &[ValueWithUnit]) -> Result<ValueWithUnit, String> {
+    let (x, mean, sd, unit) = match args {
+        [ValueWithUnit { value, unit: None, .. }] => (*value, 0.0, 1.0, None),
+        [ValueWithUnit { unit: Some(unit), .. }] => {
+            return Err(format!("The standard normal distribution takes a unitless x, not one in {}", unit))
+        }
+        [x, mean, sd] => {
+            // The value and the mean are converted to the unit of the standard deviation.
+            let in_unit = |arg: &ValueWithUnit| match (&arg.unit, &sd.unit) {
+                (None, None) => Ok(arg.value),
+                (Some(from), Some(to)) => units::convert(arg.value, from, to),
+                _ => Err(format!("The arguments of {} must all have the same unit or none", name)),
+            };
+            (in_unit(x)?, in_unit(mean)?, sd.value, sd.unit.clone())
+        }
+        _ => return Err(format!("{} expects x, or x, a mean and a standard deviation, as in {}(1.96, 0, 1)", name, name)),
+    };
+    if !(sd > 0.0 && sd.is_finite()) {
+        return Err(format!("The standard deviation of {} must be a positive number, not {}", name, sd));
+    }
+    let z = (x - mean) / sd;
+    if name == "normcdf" {
+        return Ok(ValueWithUnit::new(0.5 * erfc(-z / SQRT_2), None));
+    }
+    let density = (-0.5 * z * z).exp() / (sd * (2.0 * PI).sqrt());
+    // A density is per unit of the variable.
+    let (scale, unit) = units::divide(None, unit.as_deref())?;
+    Ok(ValueWithUnit::new(density * scale, unit))
+}
+
+/// Returns `value` if it is a non-negative whole number, and an error naming the argument otherwise.
+fn whole(name: &str, argument: &str, value: f64) -> Result<f64, String> {
+    if value >= 0.0 && value.fract() == 0.0 && value < MAX_EXACT {
+        Ok(value)
+    } else {
+        Err(format!("The argument {} of {} must be a non-negative whole number, not {}", argument, name, value))
+    }
+}
+
+/// Computes `n choose k` with integer arithmetic, or `None` if an intermediate value overflows.
+fn choose_exact(n: f64, k: f64) -> Option<f64> {
+    let (n, k) = (n as u64, k.min(n - k) as u64);
+    let mut result: u64 = 1;
+    for i in 0..k {
+        // The running product of i + 1 consecutive numbers divides by (i + 1)! exactly.
+        result = result.checked_mul(n - i)? / (i + 1);
+    }
+    Some(result as f64)
+}
+
+/// The natural logarithm of `n choose k`.
+fn ln_choose(n: f64, k: f64) -> f64 {
+    let k = k.min(n - k);
+    (0..k as u64).map(|i| ((n - i as f64) / (i as f64 + 1.0)).ln()).sum()
+}
+
+/// The natural logarithm of `k!`.
+fn ln_factorial(k: f64) -> f64 {
+    (2..=k as u64).map(|i| (i as f64).ln()).sum()
+}
+
+/// The complementary error function, to about 14 significant digits.
+///
+/// Uses the Taylor series of `erf` near zero and a continued fraction in the tails, where
+/// the series would lose precision.
+fn erfc(x: f64) -> f64 {
+    if x.is_nan() {
+        return f64::NAN;
+    }
+    if x.abs() < 2.0 {
+        // erf(x) = 2/sqrt(pi) * sum of (-1)^n x^(2n+1) / (n! (2n+1))
+        let mut term = x;
+        let mut sum = x;
+        for n in 1..60 {
+            term *= -x * x / n as f64;
+            sum += term / (2 * n + 1) as f64;
+        }
+        return 1.0 - 2.0 / PI.sqrt() * sum;
+    }
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+    // erfc(x) = exp(-x^2)/sqrt(pi) / (x + (1/2)/(x + 1/(x + (3/2)/(x + 2/(x + ...)))))
+    let fraction = (1..80).rev().fold(x, |tail, n| x + n as f64 / 2.0 / tail);
+    (-x * x).exp() / PI.sqrt() / fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    fn eval(input: &str) -> Result<ValueWithUnit, String> {
+        let mut context = Context::new();
+        context.set_seed(42);
+        evaluate_with(&parse_expression(input).unwrap(), &context)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12 * expected.abs().max(1.0), "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_seeded_draws_repeat() {
+        let draws = |seed: u64| {
+            let mut context = Context::new();
+            context.set_seed(seed);
+            let expr = parse_expression("rand() + randint(1, 6) + 3d6").unwrap();
+            (0..5).map(|_| evaluate_with(&expr, &context).unwrap().value).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+        // A clone shares the generator, so it continues the sequence rather than repeating it.
+        let context = Context::new();
+        let expr = parse_expression("rand()").unwrap();
+        let first = evaluate_with(&expr, &context.clone()).unwrap().value;
+        assert_ne!(evaluate_with(&expr, &context).unwrap().value, first);
+    }
+
+    #[test]
+    fn test_random_ranges() {
+        let mut context = Context::new();
+        context.set_seed(7);
+        let expr = parse_expression("randint(-2, 2) + 10 * 2d6").unwrap();
+        for _ in 0..1000 {
+            let value = evaluate_with(&expr, &context).unwrap().value;
+            let (dice, offset) = ((value + 2.0).div_euclid(10.0), (value + 2.0).rem_euclid(10.0));
+            assert!((2.0..=12.0).contains(&dice) && (0.0..=4.0).contains(&offset), "{}", value);
+        }
+        assert!(eval("randint(6, 1)").is_err());
+        assert!(eval("randint(1.5, 2)").is_err());
+        assert!(eval("0d6").is_err());
+        assert!(eval("rand(1)").is_err());
+    }
+
+    #[test]
+    fn test_normal_distribution() {
+        assert_close(eval("normcdf(1.96)").unwrap().value, 0.9750021048517795);
+        assert_close(eval("normcdf(-3)").unwrap().value, 0.0013498980316301);
+        assert_close(eval("normcdf(0)").unwrap().value, 0.5);
+        assert_close(eval("normpdf(0)").unwrap().value, 0.3989422804014327);
+        assert_close(eval("normcdf(180 cm, 1.7 m, 10 cm)").unwrap().value, 0.8413447460685429);
+        let density = eval("normpdf(1.7 m, 1.7 m, 0.1 m)").unwrap();
+        assert_eq!(density.unit.as_deref(), Some("1/m"));
+        assert_close(density.value, 3.989422804014327);
+        assert!(eval("normpdf(1, 0, 0)").is_err());
+    }
+
+    #[test]
+    fn test_discrete_distributions() {
+        assert_close(eval("binom(3, 10, 0.5)").unwrap().value, 0.1171875);
+        assert_close(eval("binom(0, 5, 0)").unwrap().value, 1.0);
+        assert_close(eval("binom(5, 5, 1)").unwrap().value, 1.0);
+        assert_eq!(eval("binom(1, 5, 0)").unwrap().value, 0.0);
+        assert_close(eval("poisson(2, 3)").unwrap().value, 0.22404180765538775);
+        assert_eq!(eval("choose(52, 5)").unwrap().value, 2598960.0);
+        assert_eq!(eval("choose(5, 7)").unwrap().value, 0.0);
+        assert_eq!(eval("perm(10, 3)").unwrap().value, 720.0);
+        assert!(eval("choose(5.5, 2)").is_err());
+        assert!(eval("binom(1, 5, 2)").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Pseudo-random numbers.
+//!
+//! A small, seedable generator (SplitMix64) so that sampled results, such as Monte Carlo
+//! uncertainties, are reproducible from run to run, and so are `rand()` and dice rolls
+//! when a seed is given.
+
+use std::f64::consts::TAU;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A seedable pseudo-random number generator.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator; the same seed always gives the same sequence.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed.
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Creates a generator with a different seed in every run, taken from the clock and
+    /// the per-process keys of the standard library's hash maps.
+    pub fn from_entropy() -> Self {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos()));
+        Self::new(hasher.finish())
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number drawn uniformly from `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an integer drawn uniformly from `0..bound`, without the bias of a plain modulo.
+    ///
+    /// # Arguments
+    ///
+    /// * `bound` - The number of possible values; must not be 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Lemire's method: the high half of a 128-bit product, rejecting the few low halves
+        // that would make some values more likely than others.
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let product = self.next_u64() as u128 * bound as u128;
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+
+    /// Returns a number drawn from a normal distribution, using the Box-Muller transform.
+    ///
+    /// # Arguments
+    ///
+    /// * `mean` - The mean of the distribution.
+    /// * `standard_deviation` - The standard deviation of the distribution.
+    pub fn normal(&mut self, mean: f64, standard_deviation: f64) -> f64 {
+        // 1 - u lies in (0, 1], so the logarithm is finite.
+        let radius = (-2.0 * (1.0 - self.next_f64()).ln()).sqrt();
+        mean + standard_deviation * radius * (TAU * self.next_f64()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn test_uniform_range() {
+        let mut rng = Rng::new(7);
+        let samples: Vec<f64> = (0..10_000).map(|_| rng.next_f64()).collect();
+        assert!(samples.iter().all(|x| (0.0..1.0).contains(x)));
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!((mean - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_below() {
+        let mut rng = Rng::new(7);
+        let mut counts = [0; 6];
+        for _ in 0..6_000 {
+            counts[rng.below(6) as usize] += 1;
+        }
+        assert!(counts.iter().all(|count| (800..1200).contains(count)), "{:?}", counts);
+        assert_eq!(rng.below(1), 0);
+    }
+
+    #[test]
+    fn test_normal_moments() {
+        let mut rng = Rng::new(7);
+        let samples: Vec<f64> = (0..20_000).map(|_| rng.normal(10.0, 2.0)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!((mean - 10.0).abs() < 0.05);
+        assert!((variance.sqrt() - 2.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,861 @@
+//! Scripts with control flow.
+//!
+//! A script is a sequence of lines, each an assignment or an expression as in a session,
+//! or a statement that opens a block. Blocks close with `}` on a line of their own:
+//!
+//! ```text
+//! fn payment(principal, rate, months) {
+//!     return principal * rate / (1 - (1 + rate)^-months)
+//! }
+//! balance = 1000
+//! for month in 1..12 {
+//!     balance -= payment(1000, 0.01, 12) - balance * 0.01
+//!     if balance < 500 {
+//!         print "Month {month}: {balance:.2}"
+//!     }
+//! }
+//! ```
+//!
+//! Lines at the top level print their results as before; inside blocks only `print` writes
+//! output.
+
+use crate::ast::{BinaryOp, Expr, Statement};
+use crate::calculator::{Calculator, Outcome, UserFunction};
+use crate::evaluator::{self, Context, ValueWithUnit};
+use crate::parser::{self, NumberFormat};
+use crate::{symbolic, units};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+/// The most iterations a loop may run, so that a runaway `while` stops with an error.
+const MAX_ITERATIONS: usize = 1_000_000;
+
+/// The deepest that calls of script functions may nest.
+const MAX_CALL_DEPTH: usize = 1000;
+/// Stack a call needs left before it runs; each takes about 20 KiB in a debug build.
+const CALL_STACK_RED_ZONE: usize = 128 * 1024;
+/// Stack added at a time once a call runs short, so deep recursion does not overflow.
+const CALL_STACK_GROWTH: usize = 2 * 1024 * 1024;
+
+thread_local! {
+    /// The number of script function calls in progress.
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// An error in a script, with the line and column it occurred at, both starting at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// The line number.
+    pub line: usize,
+    /// The column, in characters.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// What running a script produces, in order.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// The result of a line at the top level of the script.
+    Result {
+        /// The line number.
+        line: usize,
+        /// The result.
+        outcome: Outcome,
+    },
+    /// A line written by `print`.
+    Print(String),
+    /// An error in a line at the top level, after which the script goes on.
+    Error(ScriptError),
+    /// A warning, such as an integral that did not converge.
+    Warning(String),
+}
+
+/// A parsed script.
+#[derive(Debug, Clone)]
+pub struct Script {
+    statements: Vec<Stmt>,
+}
+
+/// A statement and where it starts.
+#[derive(Debug, Clone)]
+struct Stmt {
+    line: usize,
+    column: usize,
+    kind: Kind,
+}
+
+/// The kinds of statements.
+#[derive(Debug, Clone)]
+enum Kind {
+    /// An assignment or expression; `text` is the line, for messages.
+    Line { text: String, statement: Statement },
+    /// A line that does not parse. At the top level, the error is reported when the line is reached.
+    Invalid(String),
+    /// `print "text {value}"` or `print value`.
+    Print(Vec<Piece>),
+    /// `if condition { ... } else { ... }`; `else if` is an `if` inside `otherwise`.
+    If { condition: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    /// `while condition { ... }`.
+    While { condition: Expr, body: Vec<Stmt> },
+    /// `for name in start..end:step { ... }`.
+    For { var: String, start: Expr, end: Expr, step: Option<Expr>, body: Vec<Stmt> },
+    /// `fn name(params) { ... }`.
+    Function { name: String, params: Vec<String>, body: Rc<Vec<Stmt>> },
+    /// `return value`, inside a function.
+    Return(Expr),
+}
+
+/// Part of the text `print` writes.
+#[derive(Debug, Clone)]
+enum Piece {
+    /// Literal text.
+    Text(String),
+    /// A value, optionally with a fixed number of decimals, as in `{balance:.2}`.
+    Value { expr: Expr, decimals: Option<usize> },
+}
+
+impl Script {
+    /// Parses a script.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The text of the script.
+    /// * `format` - How the numbers in the script are grouped and punctuated.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Script)` - The parsed script. Lines at the top level that do not parse are kept
+    ///   and reported when they are reached, so the rest of the script still runs.
+    /// * `Err(ScriptError)` - An error in the structure of the script, such as an unclosed block.
+    pub fn parse(source: &str, format: NumberFormat) -> Result<Self, ScriptError> {
+        let mut parser = ScriptParser { lines: source.lines().collect(), next: 0, format };
+        let (statements, end) = parser.block(0, false)?;
+        match end {
+            End::Eof => Ok(Self { statements }),
+            End::Close { line, column } => Err(error(line, column, "Unexpected '}' without an open block")),
+            End::Else { line, column, .. } => Err(error(line, column, "'else' without 'if'")),
+        }
+    }
+
+    /// Runs the script. Its functions are defined in the calculator first, so they may be
+    /// called before the line that defines them.
+    ///
+    /// # Arguments
+    ///
+    /// * `calculator` - The calculator whose variables the script reads and assigns.
+    /// * `output` - Receives results, printed text, errors and warnings as they occur.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the script ran to its end. Errors in lines at the top level are
+    ///   passed to `output` and do not stop the script.
+    /// * `Err(ScriptError)` - The error that stopped the script, from inside a block.
+    pub fn run(&self, calculator: &mut Calculator, output: &mut dyn FnMut(Output)) -> Result<(), ScriptError> {
+        self.run_before(usize::MAX, calculator, output)
+    }
+
+    /// Runs the statements at the top level of the script that start before a line, as an
+    /// editor does to find the variables bound above the cursor. All functions are defined.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The number of the first line not to run, from 1.
+    /// * `calculator` - The calculator whose variables the script reads and assigns.
+    /// * `output` - Receives results, printed text, errors and warnings as they occur.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the statements ran to their end.
+    /// * `Err(ScriptError)` - The error that stopped the script, from inside a block.
+    pub fn run_before(&self, line: usize, calculator: &mut Calculator, output: &mut dyn FnMut(Output)) -> Result<(), ScriptError> {
+        let prints = Rc::new(RefCell::new(Vec::new()));
+        for statement in &self.statements {
+            if let Kind::Function { name, params, body } = &statement.kind {
+                let function = ScriptFunction {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    prints: prints.clone(),
+                };
+                calculator
+                    .define_function(name, Rc::new(function))
+                    .map_err(|message| error(statement.line, statement.column, message))?;
+            }
+        }
+        let count = self.statements.iter().take_while(|statement| statement.line < line).count();
+        let mut runner = Runner { prints, output: Some(output) };
+        runner.block(&self.statements[..count], calculator, true).map(|_| ())
+    }
+}
+
+/// Creates an error at a line and column.
+fn error(line: usize, column: usize, message: impl Into<String>) -> ScriptError {
+    ScriptError { line, column, message: message.into() }
+}
+
+/// How a block ended.
+enum End {
+    /// At the end of the script.
+    Eof,
+    /// At a `}` line.
+    Close { line: usize, column: usize },
+    /// At `} else {` or `} else if condition {`.
+    Else { line: usize, column: usize, condition: Option<Expr> },
+}
+
+/// Splits a script into statements, line by line.
+struct ScriptParser<'a> {
+    lines: Vec<&'a str>,
+    next: usize,
+    format: NumberFormat,
+}
+
+impl<'a> ScriptParser<'a> {
+    /// Parses statements until the end of the script or of the enclosing block.
+    fn block(&mut self, depth: usize, in_function: bool) -> Result<(Vec<Stmt>, End), ScriptError> {
+        let mut statements = Vec::new();
+        while self.next < self.lines.len() {
+            let raw = self.lines[self.next];
+            self.next += 1;
+            let line = self.next;
+            let text = strip_comment(raw).trim();
+            if text.is_empty() {
+                continue;
+            }
+            let column = column_of(raw, text);
+            if let Some(rest) = text.strip_prefix('}') {
+                let rest = rest.trim_start();
+                if rest.is_empty() {
+                    return Ok((statements, End::Close { line, column }));
+                }
+                let header = keyword(rest, "else")
+                    .and_then(|rest| rest.strip_suffix('{'))
+                    .map(str::trim_end)
+                    .ok_or_else(|| error(line, column_of(raw, rest), "Expected '} else {' or a new line after '}'"))?;
+                let condition = match (header.is_empty(), keyword(header, "if")) {
+                    (true, _) => None,
+                    (false, Some(condition)) => Some(self.expression(raw, condition, line)?),
+                    (false, None) => return Err(error(line, column_of(raw, header), "Expected 'if' or '{' after 'else'")),
+                };
+                return Ok((statements, End::Else { line, column, condition }));
+            }
+            statements.push(self.statement(raw, text, line, column, depth, in_function)?);
+        }
+        Ok((statements, End::Eof))
+    }
+
+    /// Parses one statement, and the block it opens, if any.
+    fn statement(
+        &mut self,
+        raw: &str,
+        text: &str,
+        line: usize,
+        column: usize,
+        depth: usize,
+        in_function: bool,
+    ) -> Result<Stmt, ScriptError> {
+        let stmt = |kind| Stmt { line, column, kind };
+        if let Some(header) = text.strip_suffix('{').map(str::trim_end) {
+            if let Some(condition) = keyword(header, "if") {
+                let condition = self.expression(raw, condition, line)?;
+                let (then, end) = self.body(line, column, depth, in_function)?;
+                let otherwise = self.otherwise(end, depth, in_function)?;
+                return Ok(stmt(Kind::If { condition, then, otherwise }));
+            }
+            if let Some(condition) = keyword(header, "while") {
+                let condition = self.expression(raw, condition, line)?;
+                let body = self.closed_body(line, column, depth, in_function)?;
+                return Ok(stmt(Kind::While { condition, body }));
+            }
+            if let Some(rest) = keyword(header, "for") {
+                let (var, range) = rest
+                    .split_once(char::is_whitespace)
+                    .and_then(|(var, rest)| Some((var, keyword(rest.trim_start(), "in")?)))
+                    .filter(|(var, _)| is_identifier(var))
+                    .ok_or_else(|| error(line, column_of(raw, rest), "Expected 'for name in start..end {'"))?;
+                let (start, rest) = range
+                    .split_once("..")
+                    .ok_or_else(|| error(line, column_of(raw, range), "Expected a range such as 1..10 or 0..1:0.1"))?;
+                let (end, step) = match rest.split_once(':') {
+                    Some((end, step)) => (end, Some(self.expression(raw, step, line)?)),
+                    None => (rest, None),
+                };
+                let (start, end) = (self.expression(raw, start, line)?, self.expression(raw, end, line)?);
+                let body = self.closed_body(line, column, depth, in_function)?;
+                return Ok(stmt(Kind::For { var: var.to_string(), start, end, step, body }));
+            }
+            if let Some(rest) = keyword(header, "fn") {
+                if depth > 0 {
+                    return Err(error(line, column, "Functions can only be defined at the top level of a script"));
+                }
+                let (name, params) = self.signature(raw, rest, line)?;
+                let body = self.closed_body(line, column, depth, true)?;
+                return Ok(stmt(Kind::Function { name, params, body: Rc::new(body) }));
+            }
+        }
+        let assigns = |rest: &str| rest.starts_with('=') && !rest.starts_with("==");
+        if let Some(rest) = keyword(text, "return").filter(|rest| !assigns(rest)) {
+            if !in_function {
+                return Err(error(line, column, "'return' outside a function"));
+            }
+            return Ok(stmt(Kind::Return(self.expression(raw, rest, line)?)));
+        }
+        if let Some(rest) = keyword(text, "print").filter(|rest| !assigns(rest)) {
+            return Ok(stmt(Kind::Print(self.template(raw, rest, line)?)));
+        }
+        if keyword(text, "else").is_some() {
+            return Err(error(line, column, "'else' must follow '}' on the same line"));
+        }
+        if let Some((name, op, value)) = compound_assignment(text) {
+            let value = self.expression(raw, value, line)?;
+            let expr = Expr::BinaryOp { left: Box::new(Expr::Symbol(name.to_string())), op, right: Box::new(value) };
+            let statement = Statement::Assignment { name: name.to_string(), expr };
+            return Ok(stmt(Kind::Line { text: text.to_string(), statement }));
+        }
+        match parser::check_statement(text, self.format) {
+            Ok(statement) => Ok(stmt(Kind::Line { text: text.to_string(), statement })),
+            Err(e) => Ok(Stmt { line, column: column + e.span.start, kind: Kind::Invalid(e.message) }),
+        }
+    }
+
+    /// Parses the body of a block opened on `line`, which must be closed.
+    fn body(&mut self, line: usize, column: usize, depth: usize, in_function: bool) -> Result<(Vec<Stmt>, End), ScriptError> {
+        match self.block(depth + 1, in_function)? {
+            (_, End::Eof) => Err(error(line, column, "This block is never closed with '}'")),
+            body => Ok(body),
+        }
+    }
+
+    /// Parses the body of a block that cannot be followed by `else`.
+    fn closed_body(&mut self, line: usize, column: usize, depth: usize, in_function: bool) -> Result<Vec<Stmt>, ScriptError> {
+        match self.body(line, column, depth, in_function)? {
+            (_, End::Else { line, column, .. }) => Err(error(line, column, "'else' without 'if'")),
+            (body, _) => Ok(body),
+        }
+    }
+
+    /// Parses what follows the block of an `if`: nothing, an `else` block or an `else if`.
+    fn otherwise(&mut self, end: End, depth: usize, in_function: bool) -> Result<Vec<Stmt>, ScriptError> {
+        match end {
+            End::Else { line, column, condition: None } => self.closed_body(line, column, depth, in_function),
+            End::Else { line, column, condition: Some(condition) } => {
+                let (then, end) = self.body(line, column, depth, in_function)?;
+                let otherwise = self.otherwise(end, depth, in_function)?;
+                Ok(vec![Stmt { line, column, kind: Kind::If { condition, then, otherwise } }])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Parses `name(a, b)` of a function definition.
+    fn signature(&self, raw: &str, text: &str, line: usize) -> Result<(String, Vec<String>), ScriptError> {
+        let malformed = || error(line, column_of(raw, text), "Expected 'fn name(parameters) {'");
+        let (name, params) = text.strip_suffix(')').and_then(|text| text.split_once('(')).ok_or_else(malformed)?;
+        let name = name.trim_end();
+        let params: Vec<String> = match params.trim() {
+            "" => Vec::new(),
+            params => params.split(',').map(|param| param.trim().to_string()).collect(),
+        };
+        if !is_identifier(name) || !params.iter().all(|param| is_identifier(param)) {
+            return Err(malformed());
+        }
+        if let Some(repeated) = params.iter().enumerate().find_map(|(i, param)| params[..i].contains(param).then_some(param)) {
+            return Err(error(line, column_of(raw, text), format!("The parameter '{}' is repeated", repeated)));
+        }
+        Ok((name.to_string(), params))
+    }
+
+    /// Parses an expression that is part of `raw`, locating errors in the line.
+    fn expression(&self, raw: &str, part: &str, line: usize) -> Result<Expr, ScriptError> {
+        let part = part.trim();
+        let column = column_of(raw, part);
+        match parser::check_statement(part, self.format) {
+            _ if part.is_empty() => Err(error(line, column, "Expected an expression")),
+            Ok(Statement::Expression(expr)) => Ok(expr),
+            Ok(Statement::Assignment { .. }) => Err(error(line, column, "Expected an expression; compare with '=='")),
+            Err(e) => Err(error(line, column + e.span.start, e.message)),
+        }
+    }
+
+    /// Parses what `print` writes: a string with `{expression}` placeholders, or an expression.
+    fn template(&self, raw: &str, text: &str, line: usize) -> Result<Vec<Piece>, ScriptError> {
+        let text = text.trim();
+        let string = text
+            .strip_prefix('(')
+            .and_then(|text| text.strip_suffix(')'))
+            .map(str::trim)
+            .filter(|inner| inner.starts_with('"'))
+            .unwrap_or(text);
+        let Some(content) = string.strip_prefix('"') else {
+            let expr = self.expression(raw, text, line)?;
+            return Ok(vec![Piece::Value { expr, decimals: None }]);
+        };
+        let content = content
+            .strip_suffix('"')
+            .ok_or_else(|| error(line, column_of(raw, string), "The string is not closed with '\"'"))?;
+
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = content;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push(c);
+                rest = &rest[2..];
+            } else if c == '{' {
+                let end = matching_brace(rest)
+                    .ok_or_else(|| error(line, column_of(raw, rest), "The placeholder is not closed with '}'"))?;
+                let placeholder = &rest[1..end];
+                let (expr, decimals) = match placeholder.rsplit_once(":.") {
+                    Some((expr, decimals)) if !decimals.is_empty() && decimals.bytes().all(|b| b.is_ascii_digit()) => {
+                        (expr, decimals.parse().ok())
+                    }
+                    _ => (placeholder, None),
+                };
+                pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                pieces.push(Piece::Value { expr: self.expression(raw, expr, line)?, decimals });
+                rest = &rest[end + 1..];
+            } else {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        pieces.push(Piece::Text(literal));
+        pieces.retain(|piece| !matches!(piece, Piece::Text(text) if text.is_empty()));
+        Ok(pieces)
+    }
+}
+
+/// Removes a `#` comment from a line, unless the `#` is inside a string.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Returns the column, starting at 1, at which `part`, a slice of `line`, starts.
+fn column_of(line: &str, part: &str) -> usize {
+    let offset = (part.as_ptr() as usize).saturating_sub(line.as_ptr() as usize).min(line.len());
+    line[..offset].chars().count() + 1
+}
+
+/// Returns what follows `word` at the start of `text`, if `text` starts with that word.
+fn keyword<'t>(text: &'t str, word: &str) -> Option<&'t str> {
+    let rest = text.strip_prefix(word)?;
+    match rest.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => None,
+        _ => Some(rest.trim_start()),
+    }
+}
+
+/// Checks whether a name can be a variable, parameter or function name.
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits `name += value` and the other compound assignments.
+fn compound_assignment(text: &str) -> Option<(&str, BinaryOp, &str)> {
+    let (target, value) = text.split_once('=')?;
+    let target = target.trim_end();
+    let op = match target.chars().last()? {
+        '+' => BinaryOp::Add,
+        '-' => BinaryOp::Subtract,
+        '*' => BinaryOp::Multiply,
+        '/' => BinaryOp::Divide,
+        _ => return None,
+    };
+    let name = target[..target.len() - 1].trim_end();
+    (is_identifier(name) && !value.starts_with('=')).then_some((name, op, value))
+}
+
+/// Returns the byte index of the `}` that closes the `{` at the start of `text`.
+fn matching_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Where statements read and assign variables: the calculator at the top level of a script,
+/// or a copy of the caller's variables inside a function.
+trait Scope {
+    /// Executes an assignment or expression.
+    fn execute(&mut self, text: &str, statement: &Statement) -> Result<Outcome, String>;
+    /// Evaluates an expression to a value.
+    fn evaluate(&self, expr: &Expr) -> Result<ValueWithUnit, String>;
+    /// Binds a variable.
+    fn assign(&mut self, name: &str, value: ValueWithUnit);
+    /// Removes and returns the warnings recorded so far.
+    fn take_warnings(&self) -> Vec<String>;
+    /// Checks that the time limit of the evaluation has not run out.
+    fn check_deadline(&self) -> Result<(), String>;
+}
+
+impl Scope for Calculator {
+    fn execute(&mut self, text: &str, statement: &Statement) -> Result<Outcome, String> {
+        self.execute_statement(text, statement.clone())
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<ValueWithUnit, String> {
+        match self.evaluate_ast(&expr.to_string(), expr)? {
+            Outcome::Value(value) => Ok(value),
+            Outcome::Symbolic(expr) => {
+                let unknown = symbolic::free_symbols(&expr).into_iter().next().unwrap_or_default();
+                Err(format!("Unknown variable: {}", unknown))
+            }
+            Outcome::Roots(..) => Err("Expected a single value, not the roots of an equation".to_string()),
+        }
+    }
+
+    fn assign(&mut self, name: &str, value: ValueWithUnit) {
+        self.set_variable(name, value);
+    }
+
+    fn take_warnings(&self) -> Vec<String> {
+        Calculator::take_warnings(self)
+    }
+
+    fn check_deadline(&self) -> Result<(), String> {
+        Calculator::check_deadline(self)
+    }
+}
+
+impl Scope for Context {
+    fn execute(&mut self, _text: &str, statement: &Statement) -> Result<Outcome, String> {
+        match statement {
+            Statement::Expression(expr) => self.evaluate(expr).map(Outcome::Value),
+            Statement::Assignment { name, expr } => {
+                let value = self.evaluate(expr)?;
+                self.set_variable(name, value.clone());
+                Ok(Outcome::Value(value))
+            }
+        }
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<ValueWithUnit, String> {
+        evaluator::evaluate_with(expr, self)
+    }
+
+    fn assign(&mut self, name: &str, value: ValueWithUnit) {
+        self.set_variable(name, value);
+    }
+
+    fn take_warnings(&self) -> Vec<String> {
+        Context::take_warnings(self)
+    }
+
+    fn check_deadline(&self) -> Result<(), String> {
+        Context::check_deadline(self)
+    }
+}
+
+/// What a statement leaves to do next.
+enum Flow {
+    /// Go on with the next statement.
+    Next,
+    /// Leave the function with a value.
+    Return(ValueWithUnit),
+}
+
+/// Executes statements.
+struct Runner<'a> {
+    /// Text written by `print`, including from inside functions, not yet passed on.
+    prints: Rc<RefCell<Vec<String>>>,
+    /// Receives the output; `None` inside functions, whose prints are passed on by the caller.
+    output: Option<&'a mut dyn FnMut(Output)>,
+}
+
+impl Runner<'_> {
+    /// Passes output on, if this runner has somewhere to pass it.
+    fn emit(&mut self, item: Output) {
+        if let Some(output) = self.output.as_mut() {
+            output(item);
+        }
+    }
+
+    /// Passes on the text printed and the warnings recorded so far.
+    fn flush(&mut self, scope: &dyn Scope) {
+        if self.output.is_none() {
+            return;
+        }
+        let prints = self.prints.take();
+        for text in prints {
+            self.emit(Output::Print(text));
+        }
+        for warning in scope.take_warnings() {
+            self.emit(Output::Warning(warning));
+        }
+    }
+
+    /// Executes a block of statements.
+    fn block(&mut self, statements: &[Stmt], scope: &mut dyn Scope, top: bool) -> Result<Flow, ScriptError> {
+        for statement in statements {
+            let flow = self.statement(statement, scope, top);
+            self.flush(scope);
+            if let Flow::Return(value) = flow? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Executes one statement; errors in lines at the top level are reported and skipped.
+    fn statement(&mut self, statement: &Stmt, scope: &mut dyn Scope, top: bool) -> Result<Flow, ScriptError> {
+        let fail = |message: String| error(statement.line, statement.column, message);
+        match &statement.kind {
+            Kind::Line { text, statement: line } => match scope.execute(text, line) {
+                Ok(outcome) if top => self.emit(Output::Result { line: statement.line, outcome }),
+                Ok(_) => {}
+                Err(e) if top => self.emit(Output::Error(fail(e))),
+                Err(e) => return Err(fail(e)),
+            },
+            Kind::Invalid(message) if top => self.emit(Output::Error(fail(message.clone()))),
+            Kind::Invalid(message) => return Err(fail(message.clone())),
+            Kind::Print(pieces) => {
+                let text = pieces
+                    .iter()
+                    .map(|piece| render(piece, scope))
+                    .collect::<Result<String, _>>()
+                    .map_err(fail)?;
+                self.prints.borrow_mut().push(text);
+            }
+            Kind::If { condition, then, otherwise } => {
+                let branch = if truth(scope, condition).map_err(fail)? { then } else { otherwise };
+                return self.block(branch, scope, false);
+            }
+            Kind::While { condition, body } => {
+                let mut iterations = 0;
+                while truth(scope, condition).map_err(fail)? {
+                    iterations += 1;
+                    if iterations > MAX_ITERATIONS {
+                        return Err(fail(format!("The loop ran more than {} times", MAX_ITERATIONS)));
+                    }
+                    scope.check_deadline().map_err(fail)?;
+                    if let Flow::Return(value) = self.block(body, scope, false)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            Kind::For { var, start, end, step, body } => {
+                let (start, step, count) = range(scope, start, end, step.as_ref()).map_err(fail)?;
+                for i in 0..count {
+                    scope.check_deadline().map_err(fail)?;
+                    let value = ValueWithUnit::new(start.value + step * i as f64, start.unit.clone());
+                    scope.assign(var, value);
+                    if let Flow::Return(value) = self.block(body, scope, false)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            // Functions are defined before the script runs.
+            Kind::Function { .. } => {}
+            Kind::Return(expr) => return Ok(Flow::Return(scope.evaluate(expr).map_err(fail)?)),
+        }
+        Ok(Flow::Next)
+    }
+}
+
+/// Evaluates a condition, which must be a boolean.
+fn truth(scope: &dyn Scope, condition: &Expr) -> Result<bool, String> {
+    let value = scope.evaluate(condition)?;
+    if !value.is_boolean() {
+        return Err(format!("Expected a condition that is true or false, not {}", value));
+    }
+    Ok(value.value != 0.0)
+}
+
+/// Evaluates the bounds of a `for` loop into its first value, step and number of values.
+///
+/// As with `calc table`, the end is included and the step defaults to 1 in the unit of the start.
+fn range(scope: &dyn Scope, start: &Expr, end: &Expr, step: Option<&Expr>) -> Result<(ValueWithUnit, f64, usize), String> {
+    let start = scope.evaluate(start)?;
+    let in_start_unit = |expr: &Expr| -> Result<f64, String> {
+        let value = scope.evaluate(expr)?;
+        match (&start.unit, &value.unit) {
+            (None, None) => Ok(value.value),
+            (Some(to), Some(from)) => units::convert(value.value, from, to),
+            _ => Err("The range must use the same units throughout".to_string()),
+        }
+    };
+    let end = in_start_unit(end)?;
+    let step = match step {
+        Some(step) => in_start_unit(step)?,
+        None => 1.0,
+    };
+    if step.is_nan() || step <= 0.0 || !end.is_finite() || !start.value.is_finite() {
+        return Err("The range needs finite bounds and a positive step".to_string());
+    }
+    let count = ((end - start.value) / step + 1e-9).floor() + 1.0;
+    if count > MAX_ITERATIONS as f64 {
+        return Err(format!("The range has more than {} values", MAX_ITERATIONS));
+    }
+    Ok((start, step, count.max(0.0) as usize))
+}
+
+/// Writes one piece of `print` output.
+fn render(piece: &Piece, scope: &dyn Scope) -> Result<String, String> {
+    let (expr, decimals) = match piece {
+        Piece::Text(text) => return Ok(text.clone()),
+        Piece::Value { expr, decimals } => (expr, decimals),
+    };
+    let value = scope.evaluate(expr)?;
+    let Some(decimals) = decimals.filter(|_| !value.is_boolean()) else {
+        return Ok(value.to_string());
+    };
+    let number = match value.uncertainty {
+        Some(uncertainty) => format!("{:.*} ± {:.*}", decimals, value.value, decimals, uncertainty),
+        None => format!("{:.*}", decimals, value.value),
+    };
+    Ok(match value.unit {
+        Some(unit) => format!("{} {}", number, unit),
+        None => number,
+    })
+}
+
+/// A function defined with `fn` in a script.
+struct ScriptFunction {
+    name: String,
+    params: Vec<String>,
+    body: Rc<Vec<Stmt>>,
+    /// Where `print` inside the function writes, shared with the script.
+    prints: Rc<RefCell<Vec<String>>>,
+}
+
+impl UserFunction for ScriptFunction {
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    fn call(&self, args: &[ValueWithUnit], context: &Context) -> Result<ValueWithUnit, String> {
+        let depth = CALL_DEPTH.with(|depth| {
+            depth.set(depth.get() + 1);
+            depth.get()
+        });
+        let result = if depth > MAX_CALL_DEPTH {
+            Err(format!("Calls of {} nest more than {} deep", self.name, MAX_CALL_DEPTH))
+        } else {
+            stacker::maybe_grow(CALL_STACK_RED_ZONE, CALL_STACK_GROWTH, || self.run(args, context))
+        };
+        CALL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        result
+    }
+}
+
+impl ScriptFunction {
+    /// Runs the body with the parameters bound, on a copy of the caller's variables.
+    fn run(&self, args: &[ValueWithUnit], context: &Context) -> Result<ValueWithUnit, String> {
+        context.check_deadline()?;
+        let mut scope = context.clone();
+        for (param, arg) in self.params.iter().zip(args) {
+            scope.set_variable(param, arg.clone());
+        }
+        let mut runner = Runner { prints: self.prints.clone(), output: None };
+        match runner.block(&self.body, &mut scope, false) {
+            Ok(Flow::Return(value)) => Ok(value),
+            Ok(Flow::Next) => Err(format!("{} ended without returning a value", self.name)),
+            // A recursive call fails at the same place at every level; name each place once.
+            Err(e) => {
+                let frame = format!("in {} at line {}, column {}", self.name, e.line, e.column);
+                if e.message.contains(&frame) {
+                    Err(e.message)
+                } else {
+                    Err(format!("{}: {}", frame, e.message))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ScriptFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptFunction").field("name", &self.name).field("params", &self.params).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a script and collects its output as text, one entry per item.
+    fn run(source: &str) -> Result<Vec<String>, ScriptError> {
+        let script = Script::parse(source, NumberFormat::Standard)?;
+        let mut calculator = Calculator::new();
+        let mut lines = Vec::new();
+        script.run(&mut calculator, &mut |output| {
+            lines.push(match output {
+                Output::Result { line, outcome } => format!("{}: {}", line, outcome),
+                Output::Print(text) => text,
+                Output::Error(e) => format!("error {}", e),
+                Output::Warning(warning) => format!("warning {}", warning),
+            })
+        })?;
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_loops() {
+        let source = "total = 0\nfor k in 1..4 {\n    total += k\n}\ntotal\nn = 1\nwhile n < 100 {\n    n *= 3\n}\nn";
+        assert_eq!(run(source).unwrap(), ["1: 0", "5: 10", "6: 1", "10: 243"]);
+        assert_eq!(run("for d in 1 km..1500 m:250 m {\n    print d to m\n}").unwrap(), ["1000 m", "1250 m", "1500 m"]);
+        assert!(run("for k in 3..1 {\n    print k\n}").unwrap().is_empty());
+        assert!(run("while true {\n}").unwrap_err().message.contains("more than"));
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let source = "for x in -1..1 {\n  if x < 0 {\n    print \"{x} negative\"\n  } else if x == 0 {\n    print \"zero\"\n  } else {\n    print \"{x} positive\"\n  }\n}";
+        assert_eq!(run(source).unwrap(), ["-1 negative", "zero", "1 positive"]);
+        // A one-line conditional is still an expression.
+        assert_eq!(run("if(2 > 1, 10, 20)").unwrap(), ["1: 10"]);
+        assert!(run("if 2 {\n}").unwrap_err().message.contains("true or false"));
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "print fact(5)\nfn fact(n) {\n    if n <= 1 {\n        return 1\n    }\n    return n * fact(n - 1)\n}\nfn speed(d, t) {\n    return d / t to km/h\n}\nspeed(42 km, 2 h)";
+        assert_eq!(run(source).unwrap(), ["120", "11: 21 km/h"]);
+        assert!(run("fn f(x) {\n    x\n}\nf(1)").unwrap()[0].contains("f ended without returning a value"));
+        assert!(run("fn f(x) {\n    return f(x)\n}\nf(1)").unwrap()[0].contains("nest more than"));
+        assert_eq!(run("fn f(x) {\n    return f(x)\n}\nf(1)").unwrap()[0].matches("in f at").count(), 1);
+        let source = "fn depth(n) {\n    if n == 0 {\n        return 0\n    }\n    return 1 + depth(n - 1)\n}\ndepth(500)";
+        assert_eq!(run(source).unwrap(), ["7: 500"]);
+        assert_eq!(run("return 1").unwrap_err().message, "'return' outside a function");
+    }
+
+    #[test]
+    fn test_print_formatting() {
+        let source = "rate = 0.05\nprint \"Rate: {rate * 100:.1}%, {{literal}}, {2 m + 3 m}\" # comment\nprint(\"#{1 < 2}\")";
+        assert_eq!(run(source).unwrap(), ["1: 0.05", "Rate: 5.0%, {literal}, 5 m", "#true"]);
+    }
+
+    #[test]
+    fn test_errors_have_positions() {
+        assert_eq!(run("x = 1\nfor k in 1..2 {\n  y = k +\n}").unwrap_err(), error(3, 10, "Parsing error: expected unary"));
+        assert_eq!(run("if 1 < 2 {\n  print 1").unwrap_err(), error(1, 1, "This block is never closed with '}'"));
+        assert_eq!(run("}").unwrap_err(), error(1, 1, "Unexpected '}' without an open block"));
+        let lines = run("1 +\n  1 kg + 1 m\n2").unwrap();
+        assert!(lines[0].starts_with("error line 1, column 4"), "{}", lines[0]);
+        assert!(lines[1].starts_with("error line 2, column 3"), "{}", lines[1]);
+        assert_eq!(lines[2], "3: 2");
+    }
+}
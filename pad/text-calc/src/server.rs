@@ -0,0 +1,441 @@
+//! HTTP server exposing the calculator as a JSON API.
+//!
+//! `POST /eval` evaluates one or more expressions, optionally in a named session that keeps
+//! variables between requests; `GET /units` and `GET /functions` list what expressions can
+//! use. Requests are handled one at a time by [`Service`], which does not touch the network,
+//! and [`Server`] connects it to a listening socket.
+
+use crate::calculator::{Calculator, Outcome, Settings};
+use crate::evaluator::{self, ValueWithUnit};
+use crate::units;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Longest session id accepted.
+const MAX_SESSION_ID_LENGTH: usize = 64;
+
+/// Limits and settings of a server.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Settings of the calculators that evaluate requests.
+    pub settings: Settings,
+    /// How long a session is kept after its last request.
+    pub session_timeout: Duration,
+    /// How many sessions may exist at once.
+    pub max_sessions: usize,
+    /// How many expressions one request may contain.
+    pub max_expressions: usize,
+    /// How long an expression may be, in bytes.
+    pub max_expression_length: usize,
+    /// How long one request may spend evaluating. An expression still running when the time
+    /// is up stops with an error, as do the expressions after it.
+    pub time_limit: Duration,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            settings: Settings::default(),
+            session_timeout: Duration::from_secs(30 * 60),
+            max_sessions: 1000,
+            max_expressions: 100,
+            max_expression_length: 1000,
+            time_limit: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The body of `POST /eval`: one expression or a list of them, and an optional session id.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EvalRequest {
+    expression: Option<String>,
+    expressions: Option<Vec<String>>,
+    session: Option<String>,
+}
+
+/// A calculator kept between requests.
+#[derive(Debug)]
+struct Session {
+    calculator: Calculator,
+    last_used: Instant,
+}
+
+/// A response to a request: an HTTP status code and a JSON body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The JSON body.
+    pub body: Value,
+}
+
+impl Response {
+    /// Creates a response with status 200.
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    /// Creates an error response with a message, e.g., `{"error": "Not found"}`.
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+/// Handles requests and keeps the sessions.
+#[derive(Debug, Default)]
+pub struct Service {
+    options: ServerOptions,
+    sessions: HashMap<String, Session>,
+}
+
+impl Service {
+    /// Creates a service with no sessions.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The limits and settings to apply.
+    pub fn new(options: ServerOptions) -> Self {
+        Self {
+            options,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of live sessions.
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Handles one request.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method, e.g., `POST`.
+    /// * `path` - The request path; a query string is ignored.
+    /// * `body` - The request body.
+    ///
+    /// # Returns
+    ///
+    /// * The response to send.
+    pub fn handle(&mut self, method: &str, path: &str, body: &str) -> Response {
+        self.prune_sessions();
+        let path = path.split('?').next().unwrap_or_default();
+        match (method, path) {
+            ("POST", "/eval") => self.eval(body),
+            ("GET", "/units") => Response::ok(units_listing()),
+            ("GET", "/functions") => Response::ok(functions_listing()),
+            (_, "/eval" | "/units" | "/functions") => Response::error(405, "Method not allowed"),
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    /// Drops the sessions that have not been used within the session timeout.
+    fn prune_sessions(&mut self) {
+        let timeout = self.options.session_timeout;
+        self.sessions.retain(|_, session| session.last_used.elapsed() < timeout);
+    }
+
+    /// Handles `POST /eval`.
+    fn eval(&mut self, body: &str) -> Response {
+        let request: EvalRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(e) => return Response::error(400, &format!("Invalid request: {}", e)),
+        };
+        let expressions = match (request.expression, request.expressions) {
+            (Some(expression), None) => vec![expression],
+            (None, Some(expressions)) => expressions,
+            _ => return Response::error(400, "Give either \"expression\" or \"expressions\""),
+        };
+        if expressions.len() > self.options.max_expressions {
+            let message = format!("Too many expressions (at most {})", self.options.max_expressions);
+            return Response::error(400, &message);
+        }
+
+        let (max_length, deadline) = (self.options.max_expression_length, Instant::now() + self.options.time_limit);
+        let mut scratch;
+        let calculator = match &request.session {
+            Some(id) => match self.session(id) {
+                Ok(calculator) => calculator,
+                Err(response) => return response,
+            },
+            None => {
+                scratch = Calculator::with_settings(self.options.settings);
+                &mut scratch
+            }
+        };
+        calculator.set_deadline(Some(deadline));
+        let results: Vec<Value> = expressions
+            .iter()
+            .map(|expression| {
+                if expression.len() > max_length {
+                    let message = format!("Expression too long (at most {} bytes)", max_length);
+                    return json!({ "expression": expression, "error": message });
+                }
+                if Instant::now() >= deadline {
+                    return json!({ "expression": expression, "error": "Time limit exceeded" });
+                }
+                let mut result = match calculator.execute(expression) {
+                    Ok(outcome) => outcome_json(expression, &outcome),
+                    Err(e) => json!({ "expression": expression, "error": e }),
+                };
+                let warnings = calculator.take_warnings();
+                if !warnings.is_empty() {
+                    result["warnings"] = json!(warnings);
+                }
+                result
+            })
+            .collect();
+        calculator.set_deadline(None);
+        Response::ok(json!({ "session": request.session, "results": results }))
+    }
+
+    /// Returns the calculator of a session, creating the session if needed.
+    fn session(&mut self, id: &str) -> Result<&mut Calculator, Response> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_SESSION_ID_LENGTH
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Response::error(400, "Session ids are 1 to 64 letters, digits, '-' or '_'"));
+        }
+        if !self.sessions.contains_key(id) && self.sessions.len() >= self.options.max_sessions {
+            return Err(Response::error(503, "Too many sessions"));
+        }
+        let settings = self.options.settings;
+        let session = self.sessions.entry(id.to_string()).or_insert_with(|| Session {
+            calculator: Calculator::with_settings(settings),
+            last_used: Instant::now(),
+        });
+        session.last_used = Instant::now();
+        Ok(&mut session.calculator)
+    }
+}
+
+/// Converts a value to JSON as `{"value": 2.5, "unit": "km"}`, with an `"uncertainty"` for a measured value.
+fn value_json(value: &ValueWithUnit) -> Value {
+    if value.is_boolean() {
+        return json!({ "value": value.value != 0.0, "unit": null });
+    }
+    let mut json = json!({ "value": value.value, "unit": value.unit });
+    if let Some(uncertainty) = value.uncertainty {
+        json["uncertainty"] = json!(uncertainty);
+    }
+    json
+}
+
+/// Converts the outcome of an expression to JSON, with the result as the CLI prints it.
+fn outcome_json(expression: &str, outcome: &Outcome) -> Value {
+    let mut result = json!({ "expression": expression, "result": outcome.to_string() });
+    match outcome {
+        Outcome::Value(value) => {
+            result["kind"] = json!(if value.is_boolean() { "boolean" } else { "number" });
+            let value = value_json(value);
+            result["value"] = value["value"].clone();
+            result["unit"] = value["unit"].clone();
+            if let Some(uncertainty) = value.get("uncertainty") {
+                result["uncertainty"] = uncertainty.clone();
+            }
+        }
+        Outcome::Symbolic(_) => result["kind"] = json!("symbolic"),
+        Outcome::Roots(var, roots) => {
+            result["kind"] = json!("roots");
+            result["variable"] = json!(var);
+            result["roots"] = roots.iter().map(value_json).collect();
+        }
+    }
+    result
+}
+
+/// Lists the built-in units with their size and dimension in SI base units.
+fn units_listing() -> Value {
+    let units: Vec<Value> = units::all()
+        .iter()
+        .map(|unit| json!({ "symbol": unit.symbol, "factor": unit.factor, "base": units::base_units(&unit.dimension) }))
+        .collect();
+    json!({ "units": units })
+}
+
+/// Lists the built-in functions with their usage and description.
+fn functions_listing() -> Value {
+    let functions: Vec<Value> = evaluator::functions()
+        .iter()
+        .map(|function| json!({ "name": function.name, "usage": function.usage, "description": function.description }))
+        .collect();
+    json!({ "functions": functions })
+}
+
+/// A [`Service`] listening on a TCP port.
+///
+/// Requests are served one at a time on the thread that calls [`Server::run`].
+pub struct Server {
+    http: tiny_http::Server,
+    service: Service,
+}
+
+impl Server {
+    /// Starts listening on an address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address to listen on, e.g., `127.0.0.1:7070`; port `0` picks a free port.
+    /// * `options` - The limits and settings to apply.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Server)` - The server, ready to [`run`](Server::run).
+    /// * `Err(String)` - An error message if the address cannot be bound.
+    pub fn bind(address: &str, options: ServerOptions) -> Result<Self, String> {
+        let http = tiny_http::Server::http(address).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+        Ok(Self {
+            http,
+            service: Service::new(options),
+        })
+    }
+
+    /// Returns the port the server listens on.
+    pub fn port(&self) -> Option<u16> {
+        self.http.server_addr().to_ip().map(|address| address.port())
+    }
+
+    /// Serves requests until the process ends.
+    pub fn run(mut self) {
+        for mut request in self.http.incoming_requests() {
+            let response = match read_body(&mut request) {
+                Ok(body) => self.service.handle(request.method().as_str(), request.url(), &body),
+                Err(response) => response,
+            };
+            let header = tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap();
+            let http_response = tiny_http::Response::from_string(response.body.to_string())
+                .with_status_code(response.status)
+                .with_header(header);
+            // The client may have gone away; there is nobody left to tell.
+            let _ = request.respond(http_response);
+        }
+    }
+}
+
+/// Reads the body of a request as UTF-8, up to [`MAX_BODY_SIZE`] bytes.
+fn read_body(request: &mut tiny_http::Request) -> Result<String, Response> {
+    let too_large = || Response::error(413, &format!("Request body too large (at most {} bytes)", MAX_BODY_SIZE));
+    if request.body_length().is_some_and(|length| length > MAX_BODY_SIZE) {
+        return Err(too_large());
+    }
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE as u64 + 1)
+        .read_to_string(&mut body)
+        .map_err(|_| Response::error(400, "Request body is not valid UTF-8"))?;
+    if body.len() > MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `POST /eval` with a JSON body.
+    fn post(service: &mut Service, body: Value) -> Response {
+        service.handle("POST", "/eval", &body.to_string())
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut service = Service::default();
+        let response = post(&mut service, json!({ "expression": "1.5 km * 2 to m" }));
+        assert_eq!(response.status, 200);
+        let result = &response.body["results"][0];
+        assert_eq!((result["kind"].as_str(), result["value"].as_f64()), (Some("number"), Some(3000.0)));
+        assert_eq!((result["unit"].as_str(), result["result"].as_str()), (Some("m"), Some("3000 m")));
+        assert_eq!(response.body["session"], Value::Null);
+
+        let response = post(&mut service, json!({ "expressions": ["x = 2", "x > 1", "diff(y^2, y)", "1 +"] }));
+        let results = &response.body["results"];
+        assert_eq!(results[1]["value"], json!(true));
+        assert_eq!(results[2]["kind"], json!("symbolic"));
+        assert!(results[3]["error"].as_str().unwrap().contains("Error parsing"));
+        assert_eq!(service.session_count(), 0);
+
+        let response = post(&mut service, json!({ "expression": "2 ± 0.1 m" }));
+        let result = &response.body["results"][0];
+        assert_eq!((result["value"].as_f64(), result["uncertainty"].as_f64()), (Some(2.0), Some(0.1)));
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut service = Service::default();
+        post(&mut service, json!({ "expression": "d = 5 km", "session": "a" }));
+        let response = post(&mut service, json!({ "expression": "d to m", "session": "a" }));
+        assert_eq!(response.body["results"][0]["result"], json!("5000 m"));
+        assert_eq!(response.body["session"], json!("a"));
+        let response = post(&mut service, json!({ "expression": "d to m", "session": "b" }));
+        assert!(response.body["results"][0]["error"].as_str().unwrap().contains("Unknown variable: d"));
+        assert_eq!(service.session_count(), 2);
+        assert_eq!(post(&mut service, json!({ "expression": "1", "session": "no spaces" })).status, 400);
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let options = ServerOptions { session_timeout: Duration::ZERO, max_sessions: 1, ..ServerOptions::default() };
+        let mut service = Service::new(options);
+        post(&mut service, json!({ "expression": "x = 1", "session": "a" }));
+        // The session expired before the next request.
+        let response = post(&mut service, json!({ "expression": "x", "session": "a" }));
+        assert!(response.body["results"][0]["error"].as_str().unwrap().contains("Unknown variable: x"));
+
+        let options = ServerOptions { max_sessions: 1, ..ServerOptions::default() };
+        let mut service = Service::new(options);
+        assert_eq!(post(&mut service, json!({ "expression": "1", "session": "a" })).status, 200);
+        assert_eq!(post(&mut service, json!({ "expression": "1", "session": "b" })).status, 503);
+    }
+
+    #[test]
+    fn test_evaluation_limits() {
+        let options = ServerOptions { max_expressions: 2, max_expression_length: 5, ..ServerOptions::default() };
+        let mut service = Service::new(options);
+        assert_eq!(post(&mut service, json!({ "expressions": ["1", "2", "3"] })).status, 400);
+        let response = post(&mut service, json!({ "expressions": ["1 + 1", "1 + 1 + 1"] }));
+        assert_eq!(response.body["results"][0]["value"], json!(2.0));
+        assert!(response.body["results"][1]["error"].as_str().unwrap().contains("too long"));
+
+        let mut service = Service::new(ServerOptions { time_limit: Duration::ZERO, ..ServerOptions::default() });
+        let response = post(&mut service, json!({ "expression": "1" }));
+        assert_eq!(response.body["results"][0]["error"], json!("Time limit exceeded"));
+
+        // A single long expression is cut off, and a session works again afterwards.
+        let options = ServerOptions { time_limit: Duration::from_millis(200), ..ServerOptions::default() };
+        let mut service = Service::new(options);
+        let start = Instant::now();
+        let expression = "sum(sum(k*j, k, 1, 100000), j, 1, 100000)";
+        let response = post(&mut service, json!({ "expression": expression, "session": "a" }));
+        assert!(response.body["results"][0]["error"].as_str().unwrap().ends_with("Time limit exceeded"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let response = post(&mut service, json!({ "expression": "1 + 1", "session": "a" }));
+        assert_eq!(response.body["results"][0]["value"], json!(2.0));
+    }
+
+    #[test]
+    fn test_errors_and_listings() {
+        let mut service = Service::default();
+        assert_eq!(service.handle("POST", "/eval", "not json").status, 400);
+        assert_eq!(post(&mut service, json!({ "expression": "1", "expressions": ["2"] })).status, 400);
+        assert_eq!(post(&mut service, json!({ "expresion": "1" })).status, 400);
+        assert_eq!(service.handle("GET", "/eval", "").status, 405);
+        assert_eq!(service.handle("GET", "/nowhere", "").status, 404);
+
+        let units = service.handle("GET", "/units", "").body;
+        assert!(units["units"].as_array().unwrap().contains(&json!({ "symbol": "km", "factor": 1000.0, "base": "m" })));
+        let functions = service.handle("GET", "/functions?verbose", "").body;
+        assert_eq!(functions["functions"][0]["name"], json!("sin"));
+    }
+}
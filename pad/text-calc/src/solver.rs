@@ -0,0 +1,417 @@
+//! Numeric equation solving and root finding.
+//!
+//! Roots are bracketed by scanning the search range for sign changes and refined with
+//! Brent's method. Newton's method picks up roots where the function touches zero
+//! without crossing it, and serves as a fallback when no bracket is found.
+
+use crate::ast::Expr;
+use crate::evaluator::{evaluate_with, BoundFunction, Context, ValueWithUnit};
+use crate::units;
+
+/// Search range used by `solve` when none is given.
+const DEFAULT_RANGE: (f64, f64) = (-1.0e6, 1.0e6);
+/// Relative tolerance on the location of a root.
+const TOLERANCE: f64 = 1e-12;
+/// Iteration limit for Brent's and Newton's methods.
+const MAX_ITERATIONS: usize = 200;
+/// Number of evenly spaced samples taken across the search range.
+const LINEAR_SAMPLES: usize = 200;
+/// Number of logarithmically spaced samples per decade, on each side of zero.
+const SAMPLES_PER_DECADE: usize = 20;
+/// Smallest magnitude covered by the logarithmic samples.
+const SMALLEST_SCALE: f64 = 1e-6;
+/// Points at which `solve` checks that both sides of an equation have comparable units.
+const PROBES: [f64; 4] = [1.0, 0.5, 2.0, -1.0];
+/// Starting points tried by Newton's method when scanning finds nothing.
+const NEWTON_STARTS: [f64; 6] = [1.0, -1.0, 10.0, -10.0, 0.1, -0.1];
+
+/// Finds a root of `f` in `[a, b]` with Brent's method.
+///
+/// # Arguments
+///
+/// * `f` - The function whose root is sought.
+/// * `a` - One end of the bracket.
+/// * `b` - The other end of the bracket.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The root.
+/// * `Err(String)` - An error message if `f(a)` and `f(b)` have the same sign.
+pub fn brent<F: FnMut(f64) -> f64>(mut f: F, mut a: f64, mut b: f64) -> Result<f64, String> {
+    let mut fa = f(a);
+    let mut fb = f(b);
+    if fa == 0.0 {
+        return Ok(a);
+    }
+    if fb == 0.0 {
+        return Ok(b);
+    }
+    if fa.signum() == fb.signum() {
+        return Err(format!("Root is not bracketed in [{}, {}]", a, b));
+    }
+    if fa.abs() < fb.abs() {
+        std::mem::swap(&mut a, &mut b);
+        std::mem::swap(&mut fa, &mut fb);
+    }
+
+    let (mut c, mut fc) = (a, fa);
+    let mut d = c;
+    let mut bisected = true;
+    for _ in 0..MAX_ITERATIONS {
+        let tolerance = TOLERANCE * b.abs().max(1.0);
+        if fb == 0.0 || (b - a).abs() < tolerance {
+            return Ok(b);
+        }
+
+        let mut s = if fa != fc && fb != fc {
+            // Inverse quadratic interpolation.
+            a * fb * fc / ((fa - fb) * (fa - fc)) + b * fa * fc / ((fb - fa) * (fb - fc))
+                + c * fa * fb / ((fc - fa) * (fc - fb))
+        } else {
+            // Secant step.
+            b - fb * (b - a) / (fb - fa)
+        };
+
+        let bound = (3.0 * a + b) / 4.0;
+        let outside = !((s > bound.min(b)) && (s < bound.max(b)));
+        let slow = if bisected {
+            (s - b).abs() >= (b - c).abs() / 2.0 || (b - c).abs() < tolerance
+        } else {
+            (s - b).abs() >= (c - d).abs() / 2.0 || (c - d).abs() < tolerance
+        };
+        bisected = outside || slow;
+        if bisected {
+            s = (a + b) / 2.0;
+        }
+
+        let fs = f(s);
+        d = c;
+        c = b;
+        fc = fb;
+        if fa.signum() != fs.signum() {
+            b = s;
+            fb = fs;
+        } else {
+            a = s;
+            fa = fs;
+        }
+        if fa.abs() < fb.abs() {
+            std::mem::swap(&mut a, &mut b);
+            std::mem::swap(&mut fa, &mut fb);
+        }
+    }
+    Ok(b)
+}
+
+/// Finds a root of `f` near `x0` with Newton's method, using a numeric derivative.
+///
+/// Steps that increase `|f|` are halved until they make progress, so the iteration
+/// falls back to cautious descent instead of diverging.
+///
+/// # Arguments
+///
+/// * `f` - The function whose root is sought.
+/// * `x0` - The starting point.
+///
+/// # Returns
+///
+/// * `Some(f64)` - The root, if the iteration converged to a zero of `f`.
+/// * `None` - If the iteration stalled or diverged.
+pub fn newton<F: FnMut(f64) -> f64>(mut f: F, x0: f64) -> Option<f64> {
+    let mut x = x0;
+    let mut fx = f(x);
+    let initial = fx.abs().max(1.0);
+    for _ in 0..MAX_ITERATIONS {
+        if !fx.is_finite() {
+            return None;
+        }
+        if fx == 0.0 {
+            return Some(x);
+        }
+        let h = 1e-7 * x.abs().max(1.0);
+        let slope = (f(x + h) - f(x - h)) / (2.0 * h);
+        if slope == 0.0 || !slope.is_finite() {
+            return None;
+        }
+
+        let mut step = fx / slope;
+        let mut next = x - step;
+        let mut f_next = f(next);
+        let mut halvings = 0;
+        while (f_next.is_nan() || f_next.abs() >= fx.abs()) && halvings < 30 {
+            step /= 2.0;
+            next = x - step;
+            f_next = f(next);
+            halvings += 1;
+        }
+        x = next;
+        fx = f_next;
+        if step.abs() <= TOLERANCE * x.abs().max(1.0) {
+            break;
+        }
+    }
+    (fx.abs() <= 1e-9 * initial).then_some(x)
+}
+
+/// Finds the roots of `f` in `[low, high]`.
+///
+/// # Arguments
+///
+/// * `f` - The function whose roots are sought.
+/// * `low` - The lower end of the search range.
+/// * `high` - The upper end of the search range.
+///
+/// # Returns
+///
+/// * The roots in ascending order, without duplicates.
+pub fn find_roots<F: FnMut(f64) -> f64>(mut f: F, low: f64, high: f64) -> Vec<f64> {
+    let grid = sample_grid(low, high);
+    let values: Vec<f64> = grid.iter().map(|&x| f(x)).collect();
+    let mut roots = Vec::new();
+
+    for i in 0..grid.len() {
+        if values[i] == 0.0 {
+            roots.push(grid[i]);
+            continue;
+        }
+        if i + 1 < grid.len() && values[i].is_finite() && values[i + 1].is_finite() && values[i] * values[i + 1] < 0.0 {
+            // A pole such as that of tan(x) also changes sign; there |f| grows past both ends of the bracket.
+            if let Ok(root) = brent(&mut f, grid[i], grid[i + 1]) {
+                if f(root).abs() <= values[i].abs().max(values[i + 1].abs()) {
+                    roots.push(root);
+                }
+            }
+        }
+        // A local minimum of |f| without a sign change may be a root that only touches zero.
+        if i > 0 && i + 1 < grid.len() {
+            let (previous, current, next) = (values[i - 1], values[i], values[i + 1]);
+            let touches = current.abs() < previous.abs()
+                && current.abs() < next.abs()
+                && previous.signum() == current.signum()
+                && next.signum() == current.signum();
+            if touches {
+                if let Some(root) = newton(&mut f, grid[i]).filter(|r| *r >= grid[i - 1] && *r <= grid[i + 1]) {
+                    roots.push(root);
+                }
+            }
+        }
+    }
+
+    if roots.is_empty() {
+        roots.extend(NEWTON_STARTS.iter().filter_map(|&x0| newton(&mut f, x0)).filter(|r| *r >= low && *r <= high));
+    }
+
+    let mut roots: Vec<f64> = roots.into_iter().map(|root| polish(&mut f, root)).collect();
+    roots.sort_by(f64::total_cmp);
+    roots.dedup_by(|a, b| (*a - *b).abs() <= 1e-9 * a.abs().max(1.0));
+    roots
+}
+
+/// Rounds a root to 12 significant digits when that does not make the residual worse,
+/// so that `2` is reported rather than `1.9999999999999998`.
+fn polish<F: FnMut(f64) -> f64>(f: &mut F, root: f64) -> f64 {
+    if root == 0.0 || !root.is_finite() {
+        return root;
+    }
+    let scale = 10f64.powi(11 - root.abs().log10().floor() as i32);
+    let rounded = (root * scale).round() / scale;
+    if f(rounded).abs() <= f(root).abs() {
+        rounded
+    } else {
+        root
+    }
+}
+
+/// Builds the sample points for scanning `[low, high]`: an even grid plus a
+/// logarithmic grid on each side of zero, so that both large and small roots are bracketed.
+fn sample_grid(low: f64, high: f64) -> Vec<f64> {
+    let mut grid: Vec<f64> = (0..=LINEAR_SAMPLES)
+        .map(|i| low + (high - low) * i as f64 / LINEAR_SAMPLES as f64)
+        .collect();
+
+    let largest = low.abs().max(high.abs());
+    if largest > SMALLEST_SCALE {
+        let decades = (largest / SMALLEST_SCALE).log10();
+        let count = (decades * SAMPLES_PER_DECADE as f64).ceil() as usize;
+        for i in 0..=count {
+            let magnitude = SMALLEST_SCALE * 10f64.powf(decades * i as f64 / count as f64);
+            grid.extend([magnitude, -magnitude].into_iter().filter(|x| *x >= low && *x <= high));
+        }
+    }
+    if low < 0.0 && high > 0.0 {
+        grid.push(0.0);
+    }
+
+    grid.sort_by(f64::total_cmp);
+    grid.dedup();
+    grid
+}
+
+/// Evaluates the arguments of a `solve` call and finds the roots of its equation.
+///
+/// Accepts `solve(equation, variable)` and `solve(equation, variable, low, high)`.
+/// An expression without `=` is solved for zero. Both sides of the equation must have
+/// compatible units; the right-hand side is converted into the unit of the left.
+///
+/// # Arguments
+///
+/// * `args` - The unevaluated arguments of the call.
+/// * `context` - The variable bindings of the surrounding expression.
+///
+/// # Returns
+///
+/// * `Ok(Vec<ValueWithUnit>)` - The roots in ascending order, in the unit of the variable.
+/// * `Err(String)` - An error message if the call is malformed or no roots exist.
+pub fn solve_call(args: &[Expr], context: &Context) -> Result<Vec<ValueWithUnit>, String> {
+    let (equation, var, range) = match args {
+        [equation, Expr::Symbol(var)] => (equation, var, None),
+        [equation, Expr::Symbol(var), low, high] => (equation, var, Some((low, high))),
+        _ => return Err("solve expects an equation and a variable, e.g., solve(x^2 = 4, x)".to_string()),
+    };
+    let zero = Expr::Number(0.0);
+    let (left, right) = match equation {
+        Expr::Equation { left, right } => (left.as_ref(), right.as_ref()),
+        other => (other, &zero),
+    };
+
+    let mut candidates = Vec::new();
+    let (low, high) = match range {
+        Some((low, high)) => {
+            let low = evaluate_with(low, context)?;
+            let high = evaluate_with(high, context)?;
+            let high_value = match (&low.unit, &high.unit) {
+                (Some(low_unit), Some(high_unit)) => units::convert(high.value, high_unit, low_unit)?,
+                _ => high.value,
+            };
+            if !(low.value.is_finite() && high_value.is_finite() && low.value < high_value) {
+                return Err(format!(
+                    "solve expects finite bounds with the low one first, not {} and {}",
+                    low, high
+                ));
+            }
+            candidates.push(low.unit.clone());
+            (low.value, high_value)
+        }
+        None => {
+            candidates.push(None);
+            literal_units(equation, &mut candidates);
+            DEFAULT_RANGE
+        }
+    };
+
+    let mut first_error = None;
+    for unit in candidates {
+        let mut left_side = BoundFunction::new(left, var, unit.clone(), context);
+        let mut right_side = BoundFunction::new(right, var, unit.clone(), context);
+        let mut residual_at = |x: f64| residual(left_side.call(x)?, right_side.call(x)?);
+        // The unknown takes the first candidate unit that makes both sides comparable.
+        if !PROBES.iter().any(|&x| residual_at(x).is_ok()) {
+            first_error.get_or_insert(residual_at(PROBES[0]).unwrap_err());
+            continue;
+        }
+        let roots = find_roots(|x| residual_at(x).unwrap_or(f64::NAN), low, high);
+        // Samples taken after the deadline read as NaN, so report the deadline rather than no roots.
+        context.check_deadline()?;
+        if roots.is_empty() {
+            return Err(format!("No roots found for {} in [{}, {}]", var, low, high));
+        }
+        return Ok(roots
+            .into_iter()
+            .map(|value| ValueWithUnit::new(value, unit.clone()))
+            .collect());
+    }
+    Err(first_error.unwrap_or_else(|| "Cannot solve equation".to_string()))
+}
+
+/// Computes `left - right`, converting the right-hand side into the unit of the left.
+fn residual(left: ValueWithUnit, right: ValueWithUnit) -> Result<f64, String> {
+    match (&left.unit, &right.unit) {
+        (None, None) => Ok(left.value - right.value),
+        (Some(left_unit), Some(right_unit)) => Ok(left.value - units::convert(right.value, right_unit, left_unit)?),
+        _ => Err("Both sides of the equation must have compatible units".to_string()),
+    }
+}
+
+/// Collects the distinct units written as literals in `expr`, as candidate units for the unknown.
+fn literal_units(expr: &Expr, units_found: &mut Vec<Option<String>>) {
+    match expr {
+        Expr::NumberWithUnit { unit: Some(unit), .. }
+            if units::is_unit(unit) && !units_found.contains(&Some(unit.clone())) =>
+        {
+            units_found.push(Some(unit.clone()));
+        }
+        Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => literal_units(expr, units_found),
+        Expr::BinaryOp { left, right, .. } | Expr::Equation { left, right } => {
+            literal_units(left, units_found);
+            literal_units(right, units_found);
+        }
+        Expr::Function { args, .. } => args.iter().for_each(|arg| literal_units(arg, units_found)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    /// Parses a `solve` call and returns its roots.
+    fn solve(input: &str) -> Result<Vec<ValueWithUnit>, String> {
+        match parse_expression(input).unwrap() {
+            Expr::Function { args, .. } => solve_call(&args, &Context::new()),
+            _ => panic!("Expected Expr::Function"),
+        }
+    }
+
+    #[test]
+    fn test_brent() {
+        let root = brent(|x| x * x - 2.0, 0.0, 2.0).unwrap();
+        assert!((root - 2f64.sqrt()).abs() < 1e-12);
+        assert!(brent(|x| x * x + 1.0, -1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_newton_double_root() {
+        let root = newton(|x| (x - 3.0).powi(2), 1.0).unwrap();
+        assert!((root - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_solve_multiple_roots() {
+        let roots: Vec<f64> = solve("solve(x^2 + 3x = 10, x)").unwrap().iter().map(|r| r.value).collect();
+        assert_eq!(roots, vec![-5.0, 2.0]);
+        let roots = solve("solve(x^2 + 3x = 10, x, 0, 10)").unwrap();
+        assert_eq!(roots.len(), 1);
+    }
+
+    #[test]
+    fn test_solve_skips_poles() {
+        assert!(solve("solve(tan(x) = 0, x, 1, 2)").is_err());
+        let roots: Vec<f64> = solve("solve(tan(x) = 0, x, 2, 4)").unwrap().iter().map(|r| r.value).collect();
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0] - std::f64::consts::PI).abs() < 1e-9);
+        assert!(solve("solve(1/x = 0, x, -1, 1)").is_err());
+    }
+
+    #[test]
+    fn test_solve_rejects_bad_bounds() {
+        assert!(solve("solve(x = 1, x, 0, 1e400)").is_err());
+        assert!(solve("solve(x = 1, x, 2, 0)").is_err());
+        assert!(solve("solve(x = 1, x, 1, 1)").is_err());
+    }
+
+    #[test]
+    fn test_solve_compound_interest() {
+        let roots = solve("solve(1000 * (1 + r)^5 = 1500, r)").unwrap();
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0].value - 0.0844717712).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_solve_with_units() {
+        let roots = solve("solve(x + 1 m = 10 ft, x)").unwrap();
+        assert_eq!(roots[0].unit.as_deref(), Some("m"));
+        assert!((roots[0].value - 2.048).abs() < 1e-9);
+        assert!(solve("solve(x + 1 m = 10 kg, x)").is_err());
+    }
+}
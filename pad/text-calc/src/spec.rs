@@ -0,0 +1,285 @@
+//! Scripts as tests.
+//!
+//! A comment of the form `# => expected` after a line of a script states the line's result:
+//!
+//! ```text
+//! 5 kg to lb          # => 11.0231 lb
+//! pi                  # => 3.1416
+//! solve(x^2 = 4, x)   # => x = -2, x = 2
+//! 2 m + 3 s           # => error: Unit mismatch
+//! ```
+//!
+//! [`check`] runs the script and compares the result of each such line with its
+//! expectation. A number matches if the result rounds to it at the decimals it is written
+//! with, so `3.1416` accepts pi, or if it is within a relative tolerance; the unit must be
+//! the one the result is written in. An expected error matches an error whose message
+//! contains the given text. Every other line at the top level must run without error.
+
+use crate::calculator::Calculator;
+use crate::script::{self, Output, Script, ScriptError};
+use std::collections::BTreeMap;
+
+/// The relative difference allowed between an expected and an actual number by default.
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// The outcome of checking one line of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The code of the line, without its comment.
+    pub code: String,
+    /// The expected result, or `None` for a line that states none.
+    pub expected: Option<String>,
+    /// The result of the line, or its error as `error: message`; `None` if the line gave no
+    /// result, as lines inside blocks do not.
+    pub actual: Option<String>,
+    /// Whether the line met its expectation.
+    pub passed: bool,
+}
+
+/// The checks of a script: every line with an expectation, and every line that failed
+/// without one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// The checks, in line order.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns the number of checks that passed.
+    pub fn passed(&self) -> usize {
+        self.checks.iter().filter(|check| check.passed).count()
+    }
+
+    /// Returns the checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}
+
+/// An expectation in a line of a script.
+struct Expectation {
+    /// Where the expected text starts in the line, after `=>`.
+    offset: usize,
+    /// The expected text.
+    text: String,
+}
+
+/// Runs a script and checks the results of its lines against their expectations.
+///
+/// # Arguments
+///
+/// * `source` - The text of the script.
+/// * `calculator` - The calculator to run the script in.
+/// * `tolerance` - The relative difference allowed between numbers, beyond the decimals an
+///   expectation is written with.
+///
+/// # Returns
+///
+/// * `Ok(Report)` - The checks. An error that stops the script fails the line it occurred in.
+/// * `Err(ScriptError)` - An error in the structure of the script, such as an unclosed block.
+pub fn check(source: &str, calculator: &mut Calculator, tolerance: f64) -> Result<Report, ScriptError> {
+    let script = Script::parse(source, calculator.settings().number_format)?;
+    let mut results: BTreeMap<usize, Result<String, String>> = BTreeMap::new();
+    let stopped = script.run(calculator, &mut |output| match output {
+        Output::Result { line, outcome } => {
+            results.insert(line, Ok(outcome.to_string()));
+        }
+        Output::Error(e) => {
+            results.insert(e.line, Err(e.message));
+        }
+        Output::Print(_) | Output::Warning(_) => (),
+    });
+    if let Err(e) = stopped {
+        results.insert(e.line, Err(e.message));
+    }
+
+    let mut checks = Vec::new();
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let expected = expectation(raw).map(|expectation| expectation.text);
+        let result = results.remove(&line);
+        if expected.is_none() && !matches!(result, Some(Err(_))) {
+            continue;
+        }
+        let passed = match (&expected, &result) {
+            (Some(expected), Some(result)) => matches(expected, result, tolerance),
+            _ => false,
+        };
+        checks.push(Check {
+            line,
+            code: script::strip_comment(raw).trim().to_string(),
+            expected,
+            actual: result.map(|result| result.unwrap_or_else(|message| format!("error: {}", message))),
+            passed,
+        });
+    }
+    Ok(Report { checks })
+}
+
+/// Rewrites the failed expectations of a script with the actual results (`calc test --bless`).
+///
+/// # Arguments
+///
+/// * `source` - The text of the script that was checked.
+/// * `report` - The checks of the script, from [`check`].
+///
+/// # Returns
+///
+/// The new text of the script, and the number of expectations rewritten. Lines that failed
+/// without an expectation, or gave no result, are left alone.
+pub fn bless(source: &str, report: &Report) -> (String, usize) {
+    let actual: BTreeMap<usize, &str> = report
+        .failures()
+        .filter(|check| check.expected.is_some())
+        .filter_map(|check| Some((check.line, check.actual.as_deref()?)))
+        .collect();
+    let mut blessed = String::with_capacity(source.len());
+    for (index, raw) in source.split_inclusive('\n').enumerate() {
+        let content = raw.trim_end_matches(['\n', '\r']);
+        match (actual.get(&(index + 1)), expectation(content)) {
+            (Some(actual), Some(expectation)) => {
+                blessed.push_str(&content[..expectation.offset]);
+                blessed.push(' ');
+                blessed.push_str(actual);
+                blessed.push_str(&raw[content.len()..]);
+            }
+            _ => blessed.push_str(raw),
+        }
+    }
+    (blessed, actual.len())
+}
+
+/// Finds the `# => expected` comment of a line, if it has one.
+fn expectation(line: &str) -> Option<Expectation> {
+    let code = script::strip_comment(line);
+    let comment = line[code.len()..].strip_prefix('#')?;
+    let arrow = comment.trim_start().strip_prefix("=>")?;
+    Some(Expectation { offset: line.len() - arrow.len(), text: arrow.trim().to_string() })
+}
+
+/// Checks whether a result matches an expectation.
+///
+/// # Arguments
+///
+/// * `expected` - The expected result, e.g., `11.0231 lb`, `x = -2, x = 2` or `error: Unknown unit`.
+/// * `actual` - The result as it prints, or the error message.
+/// * `tolerance` - The relative difference allowed between numbers.
+pub fn matches(expected: &str, actual: &Result<String, String>, tolerance: f64) -> bool {
+    match actual {
+        Ok(actual) if expected == actual => true,
+        Ok(actual) => {
+            let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.split(", ").collect(), actual.split(", ").collect());
+            expected.len() == actual.len() && expected.iter().zip(&actual).all(|(e, a)| item_matches(e, a, tolerance))
+        }
+        Err(message) => match expected.strip_prefix("error") {
+            Some(text) => message.contains(text.trim_start_matches(':').trim()),
+            None => false,
+        },
+    }
+}
+
+/// Checks whether one value matches, e.g., `1.41 m` and `1.4142135623730951 m`, or a root
+/// such as `x = 2`.
+fn item_matches(expected: &str, actual: &str, tolerance: f64) -> bool {
+    if expected == actual {
+        return true;
+    }
+    let (expected, actual) = match (expected.split_once(" = "), actual.split_once(" = ")) {
+        (Some((name, expected)), Some((other, actual))) if name == other => (expected, actual),
+        (None, None) => (expected, actual),
+        _ => return false,
+    };
+    let (Some((expected, resolution, expected_unit)), Some((actual, _, actual_unit))) =
+        (split_number(expected), split_number(actual))
+    else {
+        return false;
+    };
+    let unit = |unit: &str| unit.split_whitespace().collect::<String>();
+    unit(expected_unit) == unit(actual_unit)
+        && (expected - actual).abs() <= (tolerance * expected.abs()).max(resolution) * (1.0 + 1e-12)
+}
+
+/// Splits a value such as `11.0231 lb` into its number, half a unit in the number's last
+/// decimal, and its unit.
+fn split_number(text: &str) -> Option<(f64, f64, &str)> {
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+        .unwrap_or(text.len());
+    (1..=end).rev().find_map(|length| {
+        let number = &text[..length];
+        let value: f64 = number.parse().ok()?;
+        Some((value, resolution(number), text[length..].trim()))
+    })
+}
+
+/// Returns half a unit in the last decimal of a number, e.g., `0.005` for `3.14` and `5e20`
+/// for `6.02e23`, or zero for an integer, which must match exactly.
+fn resolution(number: &str) -> f64 {
+    let (mantissa, exponent) = match number.find(['e', 'E']) {
+        Some(index) => (&number[..index], number[index + 1..].parse().unwrap_or(0)),
+        None => (number, 0),
+    };
+    match mantissa.split_once('.') {
+        Some((_, decimals)) => 0.5 * 10f64.powi(exponent - decimals.len() as i32),
+        None if exponent != 0 => 0.5 * 10f64.powi(exponent),
+        None => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let ok = |actual: &str| Ok(actual.to_string());
+        assert!(matches("3.1416", &ok("3.141592653589793"), DEFAULT_TOLERANCE));
+        assert!(!matches("3.1415", &ok("3.141592653589793"), DEFAULT_TOLERANCE));
+        assert!(matches("11.0231 lb", &ok("11.023113109243878 lb"), DEFAULT_TOLERANCE));
+        assert!(!matches("11.0231 kg", &ok("11.023113109243878 lb"), DEFAULT_TOLERANCE));
+        assert!(!matches("2", &ok("2.4"), DEFAULT_TOLERANCE));
+        assert!(matches("2", &ok("2.01"), 0.01));
+        assert!(matches("6.02e23", &ok("6.02214076e23"), DEFAULT_TOLERANCE));
+        assert!(matches("9.8 m/s^2", &ok("9.80665 m / s^2"), DEFAULT_TOLERANCE));
+    }
+
+    #[test]
+    fn test_matches_roots_text_and_errors() {
+        let ok = |actual: &str| Ok(actual.to_string());
+        assert!(matches("x = -1.414, x = 1.414", &ok("x = -1.4142135623730951, x = 1.4142135623730951"), 0.0));
+        assert!(!matches("x = 2", &ok("x = -2, x = 2"), 0.0));
+        assert!(!matches("y = 2", &ok("x = 2"), 0.0));
+        assert!(matches("2 * x", &ok("2 * x"), 0.0));
+        assert!(matches("true", &ok("true"), 0.0));
+        assert!(matches("9.81 ± 0.02 m", &ok("9.81 ± 0.02 m"), 0.0));
+        assert!(matches("error: Unknown unit", &Err("Unknown unit 'parsec'".to_string()), 0.0));
+        assert!(!matches("2", &Err("Unknown unit 'parsec'".to_string()), 0.0));
+    }
+
+    #[test]
+    fn test_check() {
+        let source = "x = 5 kg  # => 5 kg\nx to lb # => 11.02 lb\nx * 2 # => 11 kg\nfor i in 1..2 {\n    x += 1 kg # => 6 kg\n}\nx + 1 m\n";
+        let report = check(source, &mut Calculator::new(), DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(report.passed(), 2);
+        let failures: Vec<_> = report.failures().map(|check| (check.line, check.actual.clone())).collect();
+        assert_eq!(failures[0], (3, Some("10 kg".to_string())));
+        assert_eq!(failures[1], (5, None));
+        assert_eq!(failures[2].0, 7);
+        assert!(failures[2].1.as_ref().unwrap().starts_with("error: "));
+        assert_eq!(report.checks[1].code, "x to lb");
+        assert!(check("if 1 {\n", &mut Calculator::new(), DEFAULT_TOLERANCE).is_err());
+    }
+
+    #[test]
+    fn test_bless() {
+        let source = "2 + 2   # => 5\r\n1 / 3 # => 0.333\n3 * 3 #=>1\n1 m + 1 s\n";
+        let report = check(source, &mut Calculator::new(), DEFAULT_TOLERANCE).unwrap();
+        let (blessed, count) = bless(source, &report);
+        assert_eq!(count, 2);
+        assert_eq!(blessed, "2 + 2   # => 4\r\n1 / 3 # => 0.333\n3 * 3 #=> 9\n1 m + 1 s\n");
+        let report = check(&blessed, &mut Calculator::new(), DEFAULT_TOLERANCE).unwrap();
+        assert_eq!(report.failures().map(|check| check.line).collect::<Vec<_>>(), [4]);
+    }
+}
@@ -0,0 +1,646 @@
+//! Symbolic manipulation of the calculator AST.
+//!
+//! This module differentiates expressions with respect to a free symbol and simplifies
+//! the result (constant folding, algebraic identities and like-term collection).
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::{evaluator, finance, probability, units};
+use std::collections::BTreeSet;
+
+/// Upper bound on simplification passes, guarding against rewrites that never settle.
+const MAX_SIMPLIFY_PASSES: usize = 32;
+
+/// Returns the free symbols of an expression, excluding built-in constants and units.
+///
+/// A number followed by a name that is not a known unit (e.g., `3x`) counts as a
+/// product with the symbol `x`.
+///
+/// # Arguments
+///
+/// * `expr` - The expression to inspect.
+///
+/// # Returns
+///
+/// * The names of all free symbols, in sorted order.
+pub fn free_symbols(expr: &Expr) -> BTreeSet<String> {
+    let mut symbols = BTreeSet::new();
+    collect_symbols(expr, &mut symbols);
+    symbols
+}
+
+/// Accumulates the free symbols of `expr` into `symbols`.
+fn collect_symbols(expr: &Expr, symbols: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Symbol(name) if !evaluator::is_constant(name) && !units::is_unit(name) => {
+            symbols.insert(name.clone());
+        }
+        // A suffix such as `x` in `3x` is a symbol, but an SI prefix such as `k` in `4.7k` is not.
+        Expr::NumberWithUnit { unit: Some(name), .. }
+            if !evaluator::is_constant(name) && !units::is_unit(name) && units::si_prefix(name).is_none() =>
+        {
+            symbols.insert(name.clone());
+        }
+        Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => collect_symbols(expr, symbols),
+        Expr::BinaryOp { left, right, .. } => {
+            collect_symbols(left, symbols);
+            collect_symbols(right, symbols);
+        }
+        Expr::Function { name, args } => match bound_variable(name, args) {
+            Some(var) => {
+                // The bound variable is local to the call, e.g., `x` in `solve(x^2 = 4, x)`.
+                let mut inner = BTreeSet::new();
+                args.iter().for_each(|arg| collect_symbols(arg, &mut inner));
+                inner.remove(var);
+                symbols.extend(inner);
+            }
+            // The frequency of `compound` may be a name such as `monthly`.
+            None if name == "compound" => args.iter().enumerate().for_each(|(index, arg)| match arg {
+                Expr::Symbol(frequency) if index == 3 && finance::frequency_name(frequency).is_some() => {}
+                _ => collect_symbols(arg, symbols),
+            }),
+            None => args.iter().for_each(|arg| collect_symbols(arg, symbols)),
+        },
+        Expr::Equation { left, right } => {
+            collect_symbols(left, symbols);
+            collect_symbols(right, symbols);
+        }
+        _ => {}
+    }
+}
+
+/// Returns the variable a call binds for its own use, such as `x` in `solve(x^2 = 4, x)`.
+fn bound_variable<'a>(name: &str, args: &'a [Expr]) -> Option<&'a str> {
+    match (name, args) {
+        ("solve" | "integrate" | "sum" | "prod" | "limit", [_, Expr::Symbol(var), ..]) => Some(var),
+        _ => None,
+    }
+}
+
+/// Rewrites a number suffixed with something other than a unit (e.g., `3x`) as a product.
+///
+/// # Arguments
+///
+/// * `expr` - The expression to rewrite.
+///
+/// # Returns
+///
+/// * The expression with symbolic suffixes turned into multiplications.
+fn lower(expr: &Expr) -> Expr {
+    match expr {
+        Expr::NumberWithUnit { value, unit: Some(name) } if !units::is_unit(name) => {
+            multiply(Expr::Number(*value), Expr::Symbol(name.clone()))
+        }
+        Expr::UnaryOp { op, expr } => Expr::UnaryOp {
+            op: *op,
+            expr: Box::new(lower(expr)),
+        },
+        Expr::BinaryOp { left, op, right } => binary(lower(left), *op, lower(right)),
+        Expr::Function { name, args } => Expr::Function {
+            name: name.clone(),
+            args: args.iter().map(lower).collect(),
+        },
+        Expr::Conversion { expr, target_unit } => Expr::Conversion {
+            expr: Box::new(lower(expr)),
+            target_unit: target_unit.clone(),
+        },
+        Expr::Equation { left, right } => Expr::Equation {
+            left: Box::new(lower(left)),
+            right: Box::new(lower(right)),
+        },
+        _ => expr.clone(),
+    }
+}
+
+/// Replaces every `diff(f, x)` call in an expression with the simplified derivative of `f`.
+///
+/// # Arguments
+///
+/// * `expr` - The expression that may contain `diff` calls.
+///
+/// # Returns
+///
+/// * `Ok(Expr)` - The expression with all derivatives taken.
+/// * `Err(String)` - An error message if a `diff` call is malformed or cannot be differentiated.
+pub fn expand_derivatives(expr: &Expr) -> Result<Expr, String> {
+    match expr {
+        Expr::Function { name, args } if name == "diff" => {
+            let (body, var) = match args.as_slice() {
+                [body, Expr::Symbol(var)] => (body, var),
+                _ => return Err("diff expects an expression and a variable, e.g., diff(x^2, x)".to_string()),
+            };
+            let body = expand_derivatives(body)?;
+            Ok(simplify(&differentiate(&body, var)?))
+        }
+        Expr::UnaryOp { op, expr } => Ok(Expr::UnaryOp {
+            op: *op,
+            expr: Box::new(expand_derivatives(expr)?),
+        }),
+        Expr::BinaryOp { left, op, right } => Ok(binary(expand_derivatives(left)?, *op, expand_derivatives(right)?)),
+        Expr::Function { name, args } => Ok(Expr::Function {
+            name: name.clone(),
+            args: args.iter().map(expand_derivatives).collect::<Result<_, _>>()?,
+        }),
+        Expr::Conversion { expr, target_unit } => Ok(Expr::Conversion {
+            expr: Box::new(expand_derivatives(expr)?),
+            target_unit: target_unit.clone(),
+        }),
+        Expr::Equation { left, right } => Ok(Expr::Equation {
+            left: Box::new(expand_derivatives(left)?),
+            right: Box::new(expand_derivatives(right)?),
+        }),
+        _ => Ok(expr.clone()),
+    }
+}
+
+/// Differentiates an expression with respect to a variable.
+///
+/// The result is not simplified; pass it through [`simplify`] for a readable form.
+///
+/// # Arguments
+///
+/// * `expr` - The expression to differentiate.
+/// * `var` - The name of the variable to differentiate with respect to.
+///
+/// # Returns
+///
+/// * `Ok(Expr)` - The derivative.
+/// * `Err(String)` - An error message if the expression cannot be differentiated.
+pub fn differentiate(expr: &Expr, var: &str) -> Result<Expr, String> {
+    let expr = lower(expr);
+    if !depends_on(&expr, var) {
+        return Ok(Expr::Number(0.0));
+    }
+    match &expr {
+        Expr::Symbol(_) => Ok(Expr::Number(1.0)),
+        Expr::UnaryOp { op: UnaryOp::Not, .. } => Err("Cannot differentiate a logical expression".to_string()),
+        Expr::UnaryOp { op: UnaryOp::Factorial, .. } => Err("Cannot differentiate a factorial".to_string()),
+        Expr::UnaryOp { op, expr } => Ok(Expr::UnaryOp {
+            op: *op,
+            expr: Box::new(differentiate(expr, var)?),
+        }),
+        Expr::BinaryOp { op, .. } if op.is_comparison() || op.is_logical() => {
+            Err("Cannot differentiate a comparison or logical expression".to_string())
+        }
+        Expr::BinaryOp { op: op @ (BinaryOp::Modulo | BinaryOp::FloorDivide | BinaryOp::PlusMinus), .. } => {
+            Err(format!("Cannot differentiate '{}'", op.to_string().trim()))
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let d_left = differentiate(left, var)?;
+            let d_right = differentiate(right, var)?;
+            let (u, v) = (left.as_ref().clone(), right.as_ref().clone());
+            match op {
+                BinaryOp::Add | BinaryOp::Subtract => Ok(binary(d_left, *op, d_right)),
+                // (u v)' = u' v + u v'
+                BinaryOp::Multiply => Ok(add(multiply(d_left, v), multiply(u, d_right))),
+                // (u / v)' = (u' v - u v') / v^2
+                BinaryOp::Divide => Ok(divide(
+                    subtract(multiply(d_left, v.clone()), multiply(u, d_right)),
+                    power(v, Expr::Number(2.0)),
+                )),
+                BinaryOp::Power if !depends_on(&v, var) => {
+                    // (u^n)' = n u^(n - 1) u'
+                    let exponent = subtract(v.clone(), Expr::Number(1.0));
+                    Ok(multiply(multiply(v, power(u, exponent)), d_left))
+                }
+                BinaryOp::Power if !depends_on(&u, var) => {
+                    // (a^v)' = a^v ln(a) v'
+                    Ok(multiply(multiply(expr.clone(), call("ln", u)), d_right))
+                }
+                BinaryOp::Power => {
+                    // (u^v)' = u^v (v' ln(u) + v u' / u)
+                    let inner = add(multiply(d_right, call("ln", u.clone())), divide(multiply(v, d_left), u));
+                    Ok(multiply(expr.clone(), inner))
+                }
+                _ => unreachable!("the other operators are rejected above"),
+            }
+        }
+        // A piecewise expression is differentiated branch by branch.
+        Expr::Function { name, args } if name == "if" && args.len() == 3 => Ok(Expr::Function {
+            name: name.clone(),
+            args: vec![args[0].clone(), differentiate(&args[1], var)?, differentiate(&args[2], var)?],
+        }),
+        Expr::Function { name, args } => {
+            let arg = match args.as_slice() {
+                [arg] => arg.clone(),
+                _ => return Err(format!("Cannot differentiate function '{}' with {} arguments", name, args.len())),
+            };
+            let outer = match name.as_str() {
+                "sin" => call("cos", arg.clone()),
+                "cos" => negate(call("sin", arg.clone())),
+                "tan" => divide(Expr::Number(1.0), power(call("cos", arg.clone()), Expr::Number(2.0))),
+                "asin" => divide(Expr::Number(1.0), call("sqrt", subtract(Expr::Number(1.0), square(arg.clone())))),
+                "acos" => negate(divide(
+                    Expr::Number(1.0),
+                    call("sqrt", subtract(Expr::Number(1.0), square(arg.clone()))),
+                )),
+                "atan" => divide(Expr::Number(1.0), add(Expr::Number(1.0), square(arg.clone()))),
+                "sqrt" => divide(Expr::Number(1.0), multiply(Expr::Number(2.0), call("sqrt", arg.clone()))),
+                "ln" => divide(Expr::Number(1.0), arg.clone()),
+                "log" => divide(Expr::Number(1.0), multiply(arg.clone(), call("ln", Expr::Number(10.0)))),
+                "exp" => call("exp", arg.clone()),
+                "abs" => divide(arg.clone(), call("abs", arg.clone())),
+                "fract" => Expr::Number(1.0),
+                "radians" => divide(Expr::Symbol("pi".to_string()), Expr::Number(180.0)),
+                "degrees" => divide(Expr::Number(180.0), Expr::Symbol("pi".to_string())),
+                _ => return Err(format!("Cannot differentiate function '{}'", name)),
+            };
+            Ok(multiply(outer, differentiate(&arg, var)?))
+        }
+        Expr::Conversion { .. } => Err("Cannot differentiate a unit conversion".to_string()),
+        Expr::Equation { .. } => Err("Cannot differentiate an equation".to_string()),
+        Expr::Number(_) | Expr::NumberWithUnit { .. } => Ok(Expr::Number(0.0)),
+    }
+}
+
+/// Checks whether an expression mentions the given variable.
+fn depends_on(expr: &Expr, var: &str) -> bool {
+    match expr {
+        Expr::Symbol(name) => name == var,
+        Expr::UnaryOp { expr, .. } | Expr::Conversion { expr, .. } => depends_on(expr, var),
+        Expr::BinaryOp { left, right, .. } | Expr::Equation { left, right } => {
+            depends_on(left, var) || depends_on(right, var)
+        }
+        Expr::Function { args, .. } => args.iter().any(|arg| depends_on(arg, var)),
+        Expr::Number(_) | Expr::NumberWithUnit { .. } => false,
+    }
+}
+
+/// Simplifies an expression.
+///
+/// Folds constant subexpressions, applies identities such as `x + 0`, `x * 1` and `x^1`,
+/// merges repeated factors into powers and collects like terms in sums.
+///
+/// # Arguments
+///
+/// * `expr` - The expression to simplify.
+///
+/// # Returns
+///
+/// * The simplified expression.
+pub fn simplify(expr: &Expr) -> Expr {
+    let mut current = lower(expr);
+    for _ in 0..MAX_SIMPLIFY_PASSES {
+        let next = simplify_node(&current);
+        if next == current {
+            break;
+        }
+        current = next;
+    }
+    current
+}
+
+/// Performs one bottom-up simplification pass.
+fn simplify_node(expr: &Expr) -> Expr {
+    match expr {
+        Expr::NumberWithUnit { value, unit: None } => Expr::Number(*value),
+        Expr::UnaryOp { op: UnaryOp::Plus, expr } => simplify_node(expr),
+        Expr::UnaryOp { op: UnaryOp::Minus, expr } => match simplify_node(expr) {
+            Expr::Number(n) => Expr::Number(-n),
+            Expr::UnaryOp { op: UnaryOp::Minus, expr } => *expr,
+            inner => negate(inner),
+        },
+        Expr::UnaryOp { op, expr } => Expr::UnaryOp {
+            op: *op,
+            expr: Box::new(simplify_node(expr)),
+        },
+        Expr::BinaryOp { left, op, right } => {
+            let node = binary(simplify_node(left), *op, simplify_node(right));
+            match op {
+                BinaryOp::Add | BinaryOp::Subtract => build_sum(collect_terms(&node)),
+                BinaryOp::Multiply | BinaryOp::Divide => build_product(collect_factors(&node)),
+                BinaryOp::Power => simplify_power(node),
+                _ => node,
+            }
+        }
+        Expr::Function { name, args } => {
+            let args: Vec<Expr> = args.iter().map(simplify_node).collect();
+            fold_function(name, args)
+        }
+        Expr::Conversion { expr, target_unit } => Expr::Conversion {
+            expr: Box::new(simplify_node(expr)),
+            target_unit: target_unit.clone(),
+        },
+        Expr::Equation { left, right } => Expr::Equation {
+            left: Box::new(simplify_node(left)),
+            right: Box::new(simplify_node(right)),
+        },
+        _ => expr.clone(),
+    }
+}
+
+/// Simplifies a power whose operands are already simplified.
+fn simplify_power(node: Expr) -> Expr {
+    let Expr::BinaryOp { left, right, .. } = &node else {
+        return node;
+    };
+    match (left.as_ref(), right.as_ref()) {
+        (_, Expr::Number(n)) if *n == 0.0 => Expr::Number(1.0),
+        (base, Expr::Number(n)) if *n == 1.0 => base.clone(),
+        (Expr::Number(b), Expr::Number(n)) if b.powf(*n).is_finite() => Expr::Number(b.powf(*n)),
+        (Expr::Number(b), _) if *b == 1.0 => Expr::Number(1.0),
+        // (u^a)^b = u^(a b) for numeric exponents.
+        (Expr::BinaryOp { left: base, op: BinaryOp::Power, right: inner }, Expr::Number(n)) => match inner.as_ref() {
+            Expr::Number(m) => power(base.as_ref().clone(), Expr::Number(m * n)),
+            _ => node,
+        },
+        _ => node,
+    }
+}
+
+/// Evaluates a function of numeric arguments when the result is exact (an integer).
+fn fold_function(name: &str, args: Vec<Expr>) -> Expr {
+    let call_expr = Expr::Function {
+        name: name.to_string(),
+        args,
+    };
+    if let Expr::Function { args, .. } = &call_expr {
+        // A random draw is not a constant, even of constant arguments.
+        if !probability::is_random_function(name) && args.iter().all(|arg| matches!(arg, Expr::Number(_))) {
+            if let Ok(result) = evaluator::evaluate(&call_expr) {
+                if result.value.is_finite() && result.value.fract() == 0.0 {
+                    return Expr::Number(result.value);
+                }
+            }
+        }
+    }
+    call_expr
+}
+
+/// A product in normal form: a numeric coefficient times bases raised to exponents.
+struct Product {
+    coefficient: f64,
+    factors: Vec<(Expr, Expr)>,
+}
+
+/// Flattens a product or quotient into its coefficient and `(base, exponent)` factors.
+///
+/// Repeated bases are merged by adding their exponents, so `x * x^2` becomes `x^3`.
+fn collect_factors(expr: &Expr) -> Product {
+    let mut product = Product {
+        coefficient: 1.0,
+        factors: Vec::new(),
+    };
+    push_factors(expr, 1.0, &mut product);
+
+    let mut merged: Vec<(Expr, Expr)> = Vec::new();
+    for (base, exponent) in product.factors {
+        match merged.iter_mut().find(|(existing, _)| *existing == base) {
+            Some((_, total)) => *total = simplify_node(&add(total.clone(), exponent)),
+            None => merged.push((base, exponent)),
+        }
+    }
+    merged.retain(|(_, exponent)| *exponent != Expr::Number(0.0));
+    merged.sort_by_key(|(base, _)| factor_rank(base));
+    product.factors = merged;
+    product
+}
+
+/// Adds the factors of `expr`, raised to `sign` (1 for numerators, -1 for denominators).
+fn push_factors(expr: &Expr, sign: f64, product: &mut Product) {
+    match expr {
+        Expr::Number(n) if sign > 0.0 || *n != 0.0 => product.coefficient *= n.powf(sign),
+        Expr::UnaryOp { op: UnaryOp::Minus, expr } => {
+            product.coefficient = -product.coefficient;
+            push_factors(expr, sign, product);
+        }
+        Expr::BinaryOp { left, op: BinaryOp::Multiply, right } => {
+            push_factors(left, sign, product);
+            push_factors(right, sign, product);
+        }
+        Expr::BinaryOp { left, op: BinaryOp::Divide, right } if !is_zero(right) => {
+            push_factors(left, sign, product);
+            push_factors(right, -sign, product);
+        }
+        Expr::BinaryOp { left, op: BinaryOp::Power, right } => {
+            let exponent = match right.as_ref() {
+                Expr::Number(n) => Expr::Number(n * sign),
+                other if sign < 0.0 => negate(other.clone()),
+                other => other.clone(),
+            };
+            product.factors.push((left.as_ref().clone(), exponent));
+        }
+        other => product.factors.push((other.clone(), Expr::Number(sign))),
+    }
+}
+
+/// Orders factors so that symbols come before function calls and other terms.
+fn factor_rank(base: &Expr) -> u8 {
+    match base {
+        Expr::Symbol(_) => 0,
+        Expr::Function { .. } => 1,
+        _ => 2,
+    }
+}
+
+/// Checks whether an expression is the literal zero.
+fn is_zero(expr: &Expr) -> bool {
+    *expr == Expr::Number(0.0)
+}
+
+/// Rebuilds an expression from a product in normal form.
+fn build_product(product: Product) -> Expr {
+    if product.coefficient == 0.0 {
+        return Expr::Number(0.0);
+    }
+    let mut numerator: Option<Expr> = None;
+    let mut denominator: Option<Expr> = None;
+    for (base, exponent) in product.factors {
+        let (target, exponent) = match exponent {
+            Expr::Number(n) if n < 0.0 => (&mut denominator, Expr::Number(-n)),
+            exponent => (&mut numerator, exponent),
+        };
+        let factor = simplify_power(power(base, exponent));
+        *target = Some(match target.take() {
+            Some(existing) => multiply(existing, factor),
+            None => factor,
+        });
+    }
+
+    // The sign goes on the numerator, so `-x / y` rather than `-(x / y)`.
+    let magnitude = product.coefficient.abs();
+    let numerator = match numerator {
+        Some(expr) if magnitude == 1.0 && product.coefficient < 0.0 => negate(expr),
+        Some(expr) if magnitude == 1.0 => expr,
+        Some(expr) if product.coefficient < 0.0 => negate(multiply(Expr::Number(magnitude), expr)),
+        Some(expr) => multiply(Expr::Number(magnitude), expr),
+        None => Expr::Number(product.coefficient),
+    };
+    match denominator {
+        Some(expr) => divide(numerator, expr),
+        None => numerator,
+    }
+}
+
+/// Flattens a sum or difference into `(coefficient, term)` pairs with like terms combined.
+///
+/// Constants are collected under the term `1` and placed last.
+fn collect_terms(expr: &Expr) -> Vec<(f64, Expr)> {
+    let mut flat = Vec::new();
+    push_terms(expr, 1.0, &mut flat);
+
+    let mut merged: Vec<(f64, Expr)> = Vec::new();
+    for (coefficient, term) in flat {
+        match merged.iter_mut().find(|(_, existing)| *existing == term) {
+            Some((total, _)) => *total += coefficient,
+            None => merged.push((coefficient, term)),
+        }
+    }
+    merged.retain(|(coefficient, _)| *coefficient != 0.0);
+    // Constants go last, so `2 - (3 - x)` reads `x - 1`.
+    merged.sort_by_key(|(_, term)| *term == Expr::Number(1.0));
+    merged
+}
+
+/// Adds the terms of `expr`, scaled by `sign`, to `terms`.
+fn push_terms(expr: &Expr, sign: f64, terms: &mut Vec<(f64, Expr)>) {
+    match expr {
+        Expr::BinaryOp { left, op: BinaryOp::Add, right } => {
+            push_terms(left, sign, terms);
+            push_terms(right, sign, terms);
+        }
+        Expr::BinaryOp { left, op: BinaryOp::Subtract, right } => {
+            push_terms(left, sign, terms);
+            push_terms(right, -sign, terms);
+        }
+        Expr::UnaryOp { op: UnaryOp::Minus, expr } => push_terms(expr, -sign, terms),
+        other => {
+            let mut product = collect_factors(other);
+            let coefficient = product.coefficient * sign;
+            product.coefficient = 1.0;
+            let term = if product.factors.is_empty() {
+                Expr::Number(1.0)
+            } else {
+                build_product(product)
+            };
+            terms.push((coefficient, term));
+        }
+    }
+}
+
+/// Rebuilds an expression from `(coefficient, term)` pairs.
+fn build_sum(terms: Vec<(f64, Expr)>) -> Expr {
+    let mut result: Option<Expr> = None;
+    for (coefficient, term) in terms {
+        let is_constant = term == Expr::Number(1.0);
+        let magnitude = if is_constant {
+            Expr::Number(coefficient.abs())
+        } else {
+            build_product(Product {
+                coefficient: coefficient.abs(),
+                factors: vec![(term, Expr::Number(1.0))],
+            })
+        };
+        result = Some(match result {
+            None if is_constant => Expr::Number(coefficient),
+            None if coefficient < 0.0 => negate(magnitude),
+            None => magnitude,
+            Some(sum) if coefficient < 0.0 => subtract(sum, magnitude),
+            Some(sum) => add(sum, magnitude),
+        });
+    }
+    result.unwrap_or(Expr::Number(0.0))
+}
+
+/// Builds a binary operation node.
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    Expr::BinaryOp {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+fn add(left: Expr, right: Expr) -> Expr {
+    binary(left, BinaryOp::Add, right)
+}
+
+fn subtract(left: Expr, right: Expr) -> Expr {
+    binary(left, BinaryOp::Subtract, right)
+}
+
+fn multiply(left: Expr, right: Expr) -> Expr {
+    binary(left, BinaryOp::Multiply, right)
+}
+
+fn divide(left: Expr, right: Expr) -> Expr {
+    binary(left, BinaryOp::Divide, right)
+}
+
+fn power(base: Expr, exponent: Expr) -> Expr {
+    binary(base, BinaryOp::Power, exponent)
+}
+
+fn square(expr: Expr) -> Expr {
+    power(expr, Expr::Number(2.0))
+}
+
+fn negate(expr: Expr) -> Expr {
+    Expr::UnaryOp {
+        op: UnaryOp::Minus,
+        expr: Box::new(expr),
+    }
+}
+
+/// Builds a single-argument function call.
+fn call(name: &str, arg: Expr) -> Expr {
+    Expr::Function {
+        name: name.to_string(),
+        args: vec![arg],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    /// Parses, differentiates with respect to `x` and prints the simplified result.
+    fn derivative(input: &str) -> String {
+        let expr = parse_expression(input).unwrap();
+        simplify(&differentiate(&expr, "x").unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_differentiate_product() {
+        assert_eq!(derivative("x^3 * sin(x)"), "3 * x^2 * sin(x) + x^3 * cos(x)");
+    }
+
+    #[test]
+    fn test_differentiate_chain_rule() {
+        assert_eq!(derivative("exp(2 * x)"), "2 * exp(2 * x)");
+        assert_eq!(derivative("cos(x)"), "-sin(x)");
+        assert_eq!(derivative("1 / x"), "-1 / x^2");
+    }
+
+    #[test]
+    fn test_simplify_collects_like_terms() {
+        let expr = parse_expression("x + 2 * x - 3 + x * x + 5").unwrap();
+        assert_eq!(simplify(&expr).to_string(), "3 * x + x^2 + 2");
+    }
+
+    #[test]
+    fn test_simplify_identities() {
+        let expr = parse_expression("(x + 0) * 1 ^ y + 0 * z - (x^1)").unwrap();
+        assert_eq!(simplify(&expr), Expr::Number(0.0));
+    }
+
+    #[test]
+    fn test_expand_derivatives() {
+        let expr = parse_expression("diff(diff(x^3, x), x)").unwrap();
+        assert_eq!(expand_derivatives(&expr).unwrap().to_string(), "6 * x");
+        assert!(expand_derivatives(&parse_expression("diff(x^2)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_differentiate_piecewise() {
+        assert_eq!(derivative("x < 0 ? 2 * x^2 : x^3"), "if(x < 0, 4 * x, 3 * x^2)");
+        assert!(differentiate(&parse_expression("x > 1").unwrap(), "x").is_err());
+    }
+
+    #[test]
+    fn test_free_symbols() {
+        let expr = parse_expression("3y + sin(pi * x) + 2 kg").unwrap();
+        let symbols: Vec<String> = free_symbols(&expr).into_iter().collect();
+        assert_eq!(symbols, vec!["x".to_string(), "y".to_string()]);
+    }
+}
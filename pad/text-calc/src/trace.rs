@@ -0,0 +1,238 @@
+//! Evaluation traces.
+//!
+//! With tracing started on a [`Context`](crate::evaluator::Context), the evaluator records
+//! a [`TraceNode`] for every expression it reduces, with the intermediate value and the
+//! unit conversion factors used. `--explain` prints the tree as indented text or JSON.
+
+use crate::ast::{BinaryOp, Expr};
+use crate::evaluator::ValueWithUnit;
+use crate::units;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+
+/// One reduction step: an expression, its result and the steps it was computed from.
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    /// The expression, as the parser understood it.
+    pub expression: String,
+    /// The value of the expression, or the error that stopped evaluation there.
+    pub result: Result<ValueWithUnit, String>,
+    /// Unit conversion factors applied in this step, e.g., `1 kg = 2.204622621849 lb`.
+    pub notes: Vec<String>,
+    /// The steps for the operands, in the order they were evaluated.
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    /// Renders the trace as indented text, one step per line and operands below their
+    /// expression. Literals, whose value is their own text, are left out.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        self.render_into(&mut text, 0);
+        text
+    }
+
+    fn render_into(&self, text: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        match &self.result {
+            Ok(value) => text.push_str(&format!("{}{} = {}\n", indent, self.expression, value)),
+            Err(e) => text.push_str(&format!("{}{}: error: {}\n", indent, self.expression, e)),
+        }
+        for child in self.children.iter().filter(|child| !child.is_literal()) {
+            child.render_into(text, depth + 1);
+        }
+        for note in &self.notes {
+            text.push_str(&format!("{}  conversion: {}\n", indent, note));
+        }
+    }
+
+    /// Checks whether the step is a literal that evaluated to its own text, like `5 kg`.
+    fn is_literal(&self) -> bool {
+        self.children.is_empty()
+            && self.notes.is_empty()
+            && matches!(&self.result, Ok(value) if value.to_string() == self.expression)
+    }
+
+    /// Converts the trace to JSON, with the fields of each step named as in the HTTP API.
+    pub fn to_json(&self) -> Value {
+        let mut step = json!({ "expression": self.expression });
+        match &self.result {
+            Ok(value) => {
+                step["result"] = json!(value.to_string());
+                step["value"] = if value.is_boolean() { json!(value.value != 0.0) } else { json!(value.value) };
+                step["unit"] = json!(value.unit);
+                if let Some(uncertainty) = value.uncertainty {
+                    step["uncertainty"] = json!(uncertainty);
+                }
+            }
+            Err(e) => step["error"] = json!(e),
+        }
+        if !self.notes.is_empty() {
+            step["notes"] = json!(self.notes);
+        }
+        step["children"] = self.children.iter().map(TraceNode::to_json).collect();
+        step
+    }
+}
+
+/// Records the steps of an evaluation while tracing is on.
+///
+/// Copies of a context do not trace, so the many evaluations behind `integrate`, `solve`
+/// or Monte Carlo sampling, which run on copies, show up as a single step.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    /// The finished steps at each open level, innermost last; `None` when not tracing.
+    levels: RefCell<Option<Vec<Vec<TraceNode>>>>,
+}
+
+impl Clone for Tracer {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Tracer {
+    /// Starts recording, discarding any earlier trace.
+    pub fn start(&self) {
+        *self.levels.borrow_mut() = Some(vec![Vec::new()]);
+    }
+
+    /// Checks whether steps are being recorded.
+    pub fn is_active(&self) -> bool {
+        self.levels.borrow().is_some()
+    }
+
+    /// Stops recording and returns the trace of the outermost expression evaluated, if any.
+    pub fn finish(&self) -> Option<TraceNode> {
+        self.levels.take()?.into_iter().next()?.pop()
+    }
+
+    /// Opens a step, to which the steps evaluated until [`Tracer::leave`] belong.
+    pub(crate) fn enter(&self) {
+        if let Some(levels) = self.levels.borrow_mut().as_mut() {
+            levels.push(Vec::new());
+        }
+    }
+
+    /// Closes the innermost step with the expression it evaluated and its result.
+    pub(crate) fn leave(&self, expr: &Expr, result: &Result<ValueWithUnit, String>) {
+        let mut levels = self.levels.borrow_mut();
+        let Some(levels) = levels.as_mut() else { return };
+        let children = levels.pop().unwrap_or_default();
+        let node = TraceNode {
+            expression: expr.to_string(),
+            result: result.clone(),
+            notes: conversion_notes(expr, &children),
+            children,
+        };
+        match levels.last_mut() {
+            Some(parent) => parent.push(node),
+            None => levels.push(vec![node]),
+        }
+    }
+}
+
+/// Describes the unit conversions an expression applied to the values of its operands.
+fn conversion_notes(expr: &Expr, children: &[TraceNode]) -> Vec<String> {
+    let unit = |index: usize| match children.get(index).map(|child| &child.result) {
+        Some(Ok(value)) => value.unit.clone(),
+        _ => None,
+    };
+    let note = match expr {
+        Expr::Conversion { target_unit, .. } => unit(0).and_then(|from| factor_note(&from, target_unit)),
+        Expr::BinaryOp { op: op @ (BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::FloorDivide), .. } => {
+            let (left, right) = (unit(0), unit(1));
+            let combined = match op {
+                BinaryOp::Multiply => units::multiply(left.as_deref(), right.as_deref()),
+                _ => units::divide(left.as_deref(), right.as_deref()),
+            };
+            match (left, right, combined) {
+                (Some(left), Some(right), Ok((scale, unit))) if scale != 1.0 => {
+                    let scaled = ValueWithUnit::new(scale, unit);
+                    Some(format!("1 {}{}{} = {}", left, if *op == BinaryOp::Multiply { "*" } else { "/" }, right, scaled))
+                }
+                _ => None,
+            }
+        }
+        // These convert the right-hand operand into the unit of the left.
+        Expr::BinaryOp { op, .. } if *op == BinaryOp::Modulo || *op == BinaryOp::PlusMinus || op.is_comparison() => {
+            match (unit(0), unit(1)) {
+                (Some(left), Some(right)) => factor_note(&right, &left),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    note.into_iter().collect()
+}
+
+/// Describes the conversion from one unit to another, or `None` if the units are the same.
+fn factor_note(from: &str, to: &str) -> Option<String> {
+    if from == to {
+        return None;
+    }
+    let zero = units::convert(0.0, from, to).ok()?;
+    let factor = units::convert(1.0, from, to).ok()? - zero;
+    // Going through the base unit leaves rounding error, as in 1.7999999999999972 for C to F.
+    let number = |x: f64| {
+        let rounded = format!("{:.12e}", x).parse().unwrap_or(x);
+        ValueWithUnit::new(rounded, None).to_string()
+    };
+    if zero == 0.0 {
+        Some(format!("1 {} = {} {}", from, number(factor), to))
+    } else {
+        Some(format!("{} to {}: multiply by {} and add {}", from, to, number(factor), number(zero)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::{evaluate_with, Context};
+    use crate::parser::parse_expression;
+
+    fn trace(input: &str) -> TraceNode {
+        let context = Context::new();
+        context.tracer().start();
+        let _ = evaluate_with(&parse_expression(input).unwrap(), &context);
+        context.tracer().finish().unwrap()
+    }
+
+    #[test]
+    fn test_trace_tree() {
+        let root = trace("(5 kg + 3 kg) to lb");
+        // A conversion applies to the whole sum, so no parentheses are needed.
+        assert_eq!(root.expression, "5 kg + 3 kg to lb");
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.children[0].result.as_ref().unwrap().to_string(), "8 kg");
+        assert_eq!(root.children[0].children.len(), 2);
+        assert_eq!(root.notes, ["1 kg = 2.204622621849 lb"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let text = trace("(5 kg + 3 kg) to lb").render();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "  5 kg + 3 kg = 8 kg");
+        assert_eq!(lines[2], "  conversion: 1 kg = 2.204622621849 lb");
+        assert_eq!(lines.len(), 3);
+        assert!(trace("1 kg + 1 m").render().starts_with("1 kg + 1 m: error: Unit mismatch"));
+    }
+
+    #[test]
+    fn test_conversion_notes() {
+        assert_eq!(trace("100 C to F").notes, ["C to F: multiply by 1.8 and add 32"]);
+        assert_eq!(trace("2 km * 3 m").notes, ["1 km*m = 0.001 km^2"]);
+        assert_eq!(trace("1 mi > 1500 m").notes, ["1 m = 0.0006213711922373 mi"]);
+        assert!(trace("2 * 3").notes.is_empty());
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = trace("(5 kg + 3 kg) to lb").to_json();
+        assert_eq!(json["unit"], json!("lb"));
+        assert_eq!(json["children"][0]["result"], json!("8 kg"));
+        assert_eq!(json["children"][0]["children"][1]["value"], json!(3.0));
+        assert_eq!(trace("1 kg + 1 m").to_json()["error"], json!("Unit mismatch in addition or subtraction"));
+    }
+}
@@ -0,0 +1,508 @@
+//! The full-screen terminal interface (`calc worksheet`).
+//!
+//! The screen shows a [`Worksheet`] on the left, each line with its result beside it, and
+//! a panel on the right with the variables, the history of results, or a searchable
+//! browser of units and functions. Every edit evaluates the worksheet again, which
+//! recomputes only the lines the edit affects.
+
+use crate::calculator::Calculator;
+use crate::worksheet::Worksheet;
+use crate::{evaluator, units};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::{Path, PathBuf};
+
+/// The most entries the history keeps.
+const HISTORY_LIMIT: usize = 1000;
+
+/// The lines Page Up and Page Down move by.
+const PAGE: usize = 10;
+
+/// Runs the interface until the user quits with Ctrl-Q.
+///
+/// # Arguments
+///
+/// * `calculator` - The calculator to evaluate the worksheet with.
+/// * `path` - The file the worksheet is read from and saved to with Ctrl-S, if any; a file
+///   that does not exist yet starts an empty worksheet.
+///
+/// # Returns
+///
+/// * `Ok(())` - When the user quits.
+/// * `Err(String)` - An error message if the file cannot be read or the terminal fails.
+pub fn run(calculator: Calculator, path: Option<&Path>) -> Result<(), String> {
+    let source = match path {
+        Some(path) if path.exists() => {
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?
+        }
+        _ => String::new(),
+    };
+    let mut app = App::new(Worksheet::new(calculator, &source), path.map(Path::to_path_buf));
+    let mut terminal = ratatui::try_init().map_err(|e| format!("Failed to start the terminal interface: {}", e))?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// The panels beside the worksheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Panel {
+    Variables,
+    History,
+    Browser,
+}
+
+impl Panel {
+    const ALL: [Panel; 3] = [Panel::Variables, Panel::History, Panel::Browser];
+
+    fn title(self) -> &'static str {
+        match self {
+            Panel::Variables => "Variables",
+            Panel::History => "History",
+            Panel::Browser => "Units & functions",
+        }
+    }
+
+    fn index(self) -> usize {
+        Panel::ALL.iter().position(|panel| *panel == self).unwrap_or(0)
+    }
+
+    /// Returns the panel `offset` places to the right, wrapping around.
+    fn cycle(self, offset: usize) -> Self {
+        Panel::ALL[(self.index() + offset) % Panel::ALL.len()]
+    }
+}
+
+/// Which part of the screen receives keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Worksheet,
+    Panel,
+}
+
+/// An entry of a panel.
+struct Entry {
+    /// The name or value shown first.
+    label: String,
+    /// What follows it, e.g., a value or a description.
+    detail: String,
+    /// The text Enter inserts into the worksheet.
+    insert: String,
+}
+
+/// The state of the interface.
+struct App {
+    worksheet: Worksheet,
+    path: Option<PathBuf>,
+    modified: bool,
+    /// The line the cursor is on, starting at 0.
+    row: usize,
+    /// The character the cursor is before, starting at 0.
+    column: usize,
+    /// The first line on screen.
+    scroll: usize,
+    focus: Focus,
+    panel: Panel,
+    selection: ListState,
+    /// Lines and their results, as they were when Enter left them.
+    history: Vec<(String, String)>,
+    search: String,
+    /// A message for the status line, until the next key.
+    status: Option<String>,
+    quit: bool,
+}
+
+impl App {
+    fn new(worksheet: Worksheet, path: Option<PathBuf>) -> Self {
+        Self {
+            worksheet,
+            path,
+            modified: false,
+            row: 0,
+            column: 0,
+            scroll: 0,
+            focus: Focus::Worksheet,
+            panel: Panel::Variables,
+            selection: ListState::default().with_selected(Some(0)),
+            history: Vec::new(),
+            search: String::new(),
+            status: None,
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), String> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame)).map_err(|e| format!("Failed to draw: {}", e))?;
+            match event::read().map_err(|e| format!("Failed to read a key: {}", e))? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => self.handle_key(key),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        self.status = None;
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') if control => self.quit = true,
+            KeyCode::Char('s') if control => self.save(),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Worksheet => Focus::Panel,
+                    Focus::Panel => Focus::Worksheet,
+                }
+            }
+            KeyCode::F(2) => self.switch_panel(self.panel.cycle(1)),
+            _ if control => (),
+            _ => match self.focus {
+                Focus::Worksheet => self.edit(key.code),
+                Focus::Panel => self.browse(key.code),
+            },
+        }
+    }
+
+    /// Handles a key in the worksheet.
+    fn edit(&mut self, code: KeyCode) {
+        let mut line: Vec<char> = self.worksheet.line(self.row).chars().collect();
+        let last = self.worksheet.len() - 1;
+        self.column = self.column.min(line.len());
+        match code {
+            KeyCode::Char(c) => {
+                line.insert(self.column, c);
+                self.column += 1;
+                self.replace_line(self.row, line.into_iter().collect());
+            }
+            KeyCode::Backspace if self.column > 0 => {
+                line.remove(self.column - 1);
+                self.column -= 1;
+                self.replace_line(self.row, line.into_iter().collect());
+            }
+            KeyCode::Backspace if self.row > 0 => {
+                let text = self.worksheet.remove_line(self.row);
+                self.row -= 1;
+                self.column = self.worksheet.line(self.row).chars().count();
+                self.replace_line(self.row, format!("{}{}", self.worksheet.line(self.row), text));
+            }
+            KeyCode::Delete if self.column < line.len() => {
+                line.remove(self.column);
+                self.replace_line(self.row, line.into_iter().collect());
+            }
+            KeyCode::Delete if self.row < last => {
+                let text = self.worksheet.remove_line(self.row + 1);
+                self.replace_line(self.row, format!("{}{}", self.worksheet.line(self.row), text));
+            }
+            KeyCode::Enter => {
+                self.record(self.row);
+                let after: String = line.split_off(self.column).into_iter().collect();
+                self.worksheet.set_line(self.row, &line.into_iter().collect::<String>());
+                self.worksheet.insert_line(self.row + 1, &after);
+                self.row += 1;
+                self.column = 0;
+                self.changed();
+            }
+            KeyCode::Left if self.column > 0 => self.column -= 1,
+            KeyCode::Left if self.row > 0 => {
+                self.row -= 1;
+                self.column = self.worksheet.line(self.row).chars().count();
+            }
+            KeyCode::Right if self.column < line.len() => self.column += 1,
+            KeyCode::Right if self.row < last => {
+                self.row += 1;
+                self.column = 0;
+            }
+            KeyCode::Up => self.row = self.row.saturating_sub(1),
+            KeyCode::Down => self.row = (self.row + 1).min(last),
+            KeyCode::PageUp => self.row = self.row.saturating_sub(PAGE),
+            KeyCode::PageDown => self.row = (self.row + PAGE).min(last),
+            KeyCode::Home => self.column = 0,
+            KeyCode::End => self.column = line.len(),
+            _ => (),
+        }
+    }
+
+    /// Handles a key in the panel.
+    fn browse(&mut self, code: KeyCode) {
+        let count = self.entries().len();
+        let selected = self.selection.selected().unwrap_or(0);
+        match code {
+            KeyCode::Up => self.selection.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => self.selection.select(Some((selected + 1).min(count.saturating_sub(1)))),
+            KeyCode::PageUp => self.selection.select(Some(selected.saturating_sub(PAGE))),
+            KeyCode::PageDown => self.selection.select(Some((selected + PAGE).min(count.saturating_sub(1)))),
+            KeyCode::Left => self.switch_panel(self.panel.cycle(Panel::ALL.len() - 1)),
+            KeyCode::Right => self.switch_panel(self.panel.cycle(1)),
+            KeyCode::Esc => self.focus = Focus::Worksheet,
+            KeyCode::Enter => {
+                if let Some(entry) = self.entries().into_iter().nth(selected) {
+                    self.insert(&entry.insert);
+                    self.focus = Focus::Worksheet;
+                }
+            }
+            KeyCode::Char(c) if self.panel == Panel::Browser => {
+                self.search.push(c);
+                self.selection.select(Some(0));
+            }
+            KeyCode::Backspace if self.panel == Panel::Browser => {
+                self.search.pop();
+                self.selection.select(Some(0));
+            }
+            _ => (),
+        }
+    }
+
+    fn switch_panel(&mut self, panel: Panel) {
+        self.panel = panel;
+        // The history opens at its latest entry.
+        let selected = if panel == Panel::History { self.history.len().saturating_sub(1) } else { 0 };
+        self.selection.select(Some(selected));
+    }
+
+    /// Inserts text at the cursor.
+    fn insert(&mut self, text: &str) {
+        let mut line: Vec<char> = self.worksheet.line(self.row).chars().collect();
+        self.column = self.column.min(line.len());
+        line.splice(self.column..self.column, text.chars());
+        self.column += text.chars().count();
+        self.replace_line(self.row, line.into_iter().collect());
+    }
+
+    fn replace_line(&mut self, row: usize, text: String) {
+        self.worksheet.set_line(row, &text);
+        self.changed();
+    }
+
+    fn changed(&mut self) {
+        self.modified = true;
+        self.worksheet.evaluate();
+    }
+
+    /// Adds a line and its result to the history, if it has a result.
+    fn record(&mut self, row: usize) {
+        if let Some(Ok(outcome)) = self.worksheet.result(row) {
+            let result = self.worksheet.calculator().format(outcome);
+            self.history.push((self.worksheet.line(row).trim().to_string(), result));
+            if self.history.len() > HISTORY_LIMIT {
+                self.history.remove(0);
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        let Some(path) = &self.path else {
+            self.status = Some("No file to save to; open one with `calc worksheet FILE`".to_string());
+            return;
+        };
+        self.status = Some(match std::fs::write(path, self.worksheet.source()) {
+            Ok(()) => {
+                self.modified = false;
+                format!("Saved {}", path.display())
+            }
+            Err(e) => format!("Failed to write '{}': {}", path.display(), e),
+        });
+    }
+
+    /// Returns the entries of the current panel; the browser's are those matching the search.
+    fn entries(&self) -> Vec<Entry> {
+        let calculator = self.worksheet.calculator();
+        match self.panel {
+            Panel::Variables => self
+                .worksheet
+                .variables()
+                .into_iter()
+                .map(|(name, value)| Entry {
+                    label: name.to_string(),
+                    detail: value.format(calculator.settings().precision, calculator.settings().notation),
+                    insert: name.to_string(),
+                })
+                .collect(),
+            Panel::History => self
+                .history
+                .iter()
+                .map(|(line, result)| Entry { label: line.clone(), detail: format!("= {}", result), insert: result.clone() })
+                .collect(),
+            Panel::Browser => {
+                let search = self.search.to_lowercase();
+                let units = units::all().iter().map(|unit| Entry {
+                    label: unit.symbol.to_string(),
+                    detail: match units::base_units(&unit.dimension) {
+                        Some(base) => format!("unit, {} {}", unit.factor, base),
+                        None => format!("unit, {}", unit.factor),
+                    },
+                    insert: unit.symbol.to_string(),
+                });
+                let functions = evaluator::functions().iter().map(|function| Entry {
+                    label: function.usage.to_string(),
+                    detail: function.description.to_string(),
+                    insert: format!("{}(", function.name),
+                });
+                units
+                    .chain(functions)
+                    .filter(|entry| {
+                        entry.label.to_lowercase().contains(&search) || entry.detail.to_lowercase().contains(&search)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [sheet, panel] = Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(main);
+        self.draw_worksheet(frame, sheet);
+        self.draw_panel(frame, panel);
+        let warning = self.worksheet.warnings(self.row).first().map(|warning| format!("Warning: {}", warning));
+        let help = "Ctrl-S save · Ctrl-Q quit · Tab worksheet/panel · F2 next panel · Enter in panel inserts";
+        let text = self.status.clone().or(warning).unwrap_or_else(|| help.to_string());
+        frame.render_widget(Paragraph::new(text).style(Style::new().add_modifier(Modifier::REVERSED)), status);
+    }
+
+    fn draw_worksheet(&mut self, frame: &mut Frame, area: Rect) {
+        let name = self.path.as_ref().map_or("Worksheet".to_string(), |path| path.display().to_string());
+        let title = format!(" {}{} ", name, if self.modified { " *" } else { "" });
+        let block = Block::bordered().title(title).border_style(self.border(Focus::Worksheet));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let height = usize::from(inner.height).max(1);
+        self.row = self.row.min(self.worksheet.len() - 1);
+        self.scroll = self.scroll.min(self.row).max((self.row + 1).saturating_sub(height));
+        let gutter = self.worksheet.len().to_string().len() + 1;
+        let width = usize::from(inner.width).saturating_sub(gutter) * 3 / 5;
+        let calculator = self.worksheet.calculator();
+        let lines: Vec<Line> = (self.scroll..self.worksheet.len().min(self.scroll + height))
+            .map(|index| {
+                let (result, color) = match self.worksheet.result(index) {
+                    Some(Ok(outcome)) => (format!("= {}", calculator.format(outcome)), Color::Green),
+                    Some(Err(e)) => (format!("! {}", e), Color::Red),
+                    None => (String::new(), Color::Reset),
+                };
+                let color = if self.worksheet.warnings(index).is_empty() { color } else { Color::Yellow };
+                let text: String = self.worksheet.line(index).chars().chain(std::iter::repeat(' ')).take(width).collect();
+                Line::from(vec![
+                    Span::styled(format!("{:>1$} ", index + 1, gutter - 1), Style::new().fg(Color::DarkGray)),
+                    Span::raw(text),
+                    Span::styled(result, Style::new().fg(color)),
+                ])
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), inner);
+        if self.focus == Focus::Worksheet {
+            let column = self.column.min(self.worksheet.line(self.row).chars().count()).min(width);
+            let (x, y) = (gutter + column, self.row - self.scroll);
+            frame.set_cursor_position(Position::new(inner.x + x as u16, inner.y + y as u16));
+        }
+    }
+
+    fn draw_panel(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().border_style(self.border(Focus::Panel));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let search_height = if self.panel == Panel::Browser { 1 } else { 0 };
+        let [tabs, search, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Length(search_height), Constraint::Min(0)]).areas(inner);
+
+        let titles = Panel::ALL.map(Panel::title);
+        let highlight = Style::new().add_modifier(Modifier::BOLD | Modifier::REVERSED);
+        frame.render_widget(Tabs::new(titles).select(self.panel.index()).highlight_style(highlight), tabs);
+        if self.panel == Panel::Browser {
+            frame.render_widget(Paragraph::new(format!("Search: {}", self.search)), search);
+            if self.focus == Focus::Panel {
+                let x = "Search: ".len() + self.search.chars().count();
+                frame.set_cursor_position(Position::new(search.x + x as u16, search.y));
+            }
+        }
+        let items: Vec<ListItem> = self
+            .entries()
+            .into_iter()
+            .map(|entry| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(entry.label, Style::new().add_modifier(Modifier::BOLD)),
+                    Span::raw("  "),
+                    Span::styled(entry.detail, Style::new().fg(Color::DarkGray)),
+                ]))
+            })
+            .collect();
+        let list_widget = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list_widget, list, &mut self.selection);
+    }
+
+    /// Returns the border style of a part of the screen, highlighted if it has the focus.
+    fn border(&self, part: Focus) -> Style {
+        if self.focus == part {
+            Style::new().fg(Color::Cyan)
+        } else {
+            Style::new().fg(Color::DarkGray)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn press(app: &mut App, codes: &[KeyCode]) {
+        for code in codes {
+            app.handle_key(KeyEvent::new(*code, KeyModifiers::NONE));
+        }
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        press(app, &text.chars().map(KeyCode::Char).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut app = App::new(Worksheet::new(Calculator::new(), ""), None);
+        type_text(&mut app, "x = 2");
+        press(&mut app, &[KeyCode::Enter]);
+        type_text(&mut app, "x * 3");
+        assert_eq!(app.worksheet.source(), "x = 2\nx * 3\n");
+        assert_eq!(app.worksheet.result(1).unwrap().as_ref().unwrap().to_string(), "6");
+        assert_eq!(app.history, [("x = 2".to_string(), "2".to_string())]);
+
+        // Backspace at the start of a line joins it to the previous one.
+        press(&mut app, &[KeyCode::Home, KeyCode::Backspace, KeyCode::Char(';')]);
+        assert_eq!((app.row, app.column), (0, 6));
+        assert_eq!(app.worksheet.source(), "x = 2;x * 3\n");
+        assert!(app.worksheet.result(0).unwrap().is_err());
+        assert!(app.modified);
+    }
+
+    #[test]
+    fn test_panels() {
+        let mut app = App::new(Worksheet::new(Calculator::new(), "speed = 3 m/s\n"), None);
+        press(&mut app, &[KeyCode::Tab, KeyCode::Enter]);
+        assert_eq!(app.worksheet.line(0), "speedspeed = 3 m/s");
+
+        app.worksheet.set_line(0, "");
+        press(&mut app, &[KeyCode::Tab, KeyCode::F(2), KeyCode::F(2)]);
+        assert_eq!(app.panel, Panel::Browser);
+        type_text(&mut app, "normcdf");
+        assert_eq!(app.entries().len(), 1);
+        press(&mut app, &[KeyCode::Enter]);
+        assert_eq!((app.worksheet.line(0), app.focus), ("normcdf(", Focus::Worksheet));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut app = App::new(Worksheet::new(Calculator::new(), "d = 42 km\nd / 2 h to km/h\n1 m + 1 s\n"), None);
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let rows: Vec<String> = (0..buffer.area.height)
+            .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect())
+            .collect();
+        assert!(rows[2].contains("2 d / 2 h to km/h") && rows[2].contains("= 21 km/h"), "{:?}", rows);
+        assert!(rows[3].contains("! Error"), "{:?}", rows);
+        assert!(rows[2].contains("d  42 km"), "{:?}", rows);
+        assert!(rows[11].starts_with("Ctrl-S save"), "{:?}", rows);
+    }
+}
@@ -0,0 +1,171 @@
+//! Uncertainty propagation.
+//!
+//! By default the evaluator propagates uncertainties to first order as it goes. For
+//! strongly non-linear expressions, [`monte_carlo`] instead samples every uncertain input
+//! from a normal distribution and reports the mean and spread of the results.
+
+use crate::ast::{BinaryOp, Expr};
+use crate::evaluator::{self, Context, ValueWithUnit};
+use crate::random::Rng;
+
+/// The seed of the Monte Carlo sampler, so that results are reproducible.
+pub const MONTE_CARLO_SEED: u64 = 0x5EED;
+
+/// How uncertainties are carried through an expression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Propagation {
+    /// Linearized propagation with partial derivatives, assuming independent inputs.
+    #[default]
+    FirstOrder,
+    /// Sampling of the uncertain inputs.
+    MonteCarlo {
+        /// The number of samples to draw.
+        samples: usize,
+    },
+}
+
+/// An uncertain input of an expression: a `±` term or an uncertain variable.
+struct Input {
+    /// The name the input is bound to while sampling.
+    name: String,
+    /// The central value, uncertainty and unit.
+    value: ValueWithUnit,
+}
+
+/// Evaluates an expression by sampling its uncertain inputs.
+///
+/// Every `a ± b` term and every variable with an uncertainty is an input. A variable is
+/// drawn once per sample, so `x - x` is exactly zero. Samples that cannot be evaluated,
+/// such as `sqrt` of a negative draw, are skipped with a warning in `context`.
+///
+/// # Arguments
+///
+/// * `expr` - The expression to evaluate.
+/// * `context` - The variable bindings.
+/// * `samples` - The number of samples to draw.
+/// * `rng` - The random number generator.
+///
+/// # Returns
+///
+/// * `Ok(ValueWithUnit)` - The mean of the samples, with their standard deviation as its uncertainty.
+/// * `Err(String)` - An error message if too few samples could be evaluated or the result is a boolean.
+pub fn monte_carlo(expr: &Expr, context: &Context, samples: usize, rng: &mut Rng) -> Result<ValueWithUnit, String> {
+    let mut inputs: Vec<Input> = context
+        .variables()
+        .filter(|(_, value)| value.uncertainty.is_some())
+        .map(|(name, value)| Input { name: name.to_string(), value: value.clone() })
+        .collect();
+    let expr = extract_inputs(expr, context, &mut inputs);
+    if inputs.is_empty() {
+        return evaluator::evaluate_with(&expr, context);
+    }
+
+    let mut sampling = context.clone();
+    let mut values = Vec::with_capacity(samples);
+    let (mut unit, mut failure) = (None, None);
+    for _ in 0..samples {
+        for input in &inputs {
+            let sigma = input.value.uncertainty.unwrap_or(0.0);
+            let draw = rng.normal(input.value.value, sigma);
+            sampling.set_variable(&input.name, ValueWithUnit::new(draw, input.value.unit.clone()));
+        }
+        match evaluator::evaluate_with(&expr, &sampling) {
+            Ok(result) if result.is_boolean() => {
+                return Err("Monte Carlo propagation needs a numeric result, not a boolean".to_string())
+            }
+            Ok(result) if result.value.is_finite() => {
+                unit = result.unit;
+                values.push(result.value);
+            }
+            Ok(result) => failure = failure.or(Some(format!("the result was {}", result))),
+            Err(e) => failure = failure.or(Some(e)),
+        }
+    }
+    if values.len() < 2 {
+        return Err(format!("Too few Monte Carlo samples could be evaluated: {}", failure.unwrap_or_default()));
+    }
+    if let Some(failure) = failure {
+        context.warn(format!(
+            "{} of {} Monte Carlo samples were skipped ({})",
+            samples - values.len(),
+            samples,
+            failure
+        ));
+    }
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (count - 1.0);
+    Ok(ValueWithUnit::uncertain(mean, variance.sqrt(), unit))
+}
+
+/// Replaces each `a ± b` term that can be evaluated on its own with a symbol, recording it as an input.
+///
+/// Terms that depend on a variable bound inside the expression, such as the `x` of an
+/// integral, are left in place and propagate to first order.
+fn extract_inputs(expr: &Expr, context: &Context, inputs: &mut Vec<Input>) -> Expr {
+    let mut recurse = |expr: &Expr| Box::new(extract_inputs(expr, context, inputs));
+    match expr {
+        Expr::Function { name, args } => Expr::Function {
+            name: name.clone(),
+            args: args.iter().map(|arg| *recurse(arg)).collect(),
+        },
+        Expr::BinaryOp { op: BinaryOp::PlusMinus, .. } => match evaluator::evaluate_with(expr, context) {
+            Ok(value) => {
+                let name = format!("±{}", inputs.len());
+                inputs.push(Input { name: name.clone(), value });
+                Expr::Symbol(name)
+            }
+            Err(_) => expr.clone(),
+        },
+        Expr::UnaryOp { op, expr } => Expr::UnaryOp { op: *op, expr: recurse(expr) },
+        Expr::BinaryOp { left, op, right } => Expr::BinaryOp { left: recurse(left), op: *op, right: recurse(right) },
+        Expr::Conversion { expr, target_unit } => {
+            Expr::Conversion { expr: recurse(expr), target_unit: target_unit.clone() }
+        }
+        Expr::Equation { left, right } => Expr::Equation { left: recurse(left), right: recurse(right) },
+        Expr::Number(_) | Expr::NumberWithUnit { .. } | Expr::Symbol(_) => expr.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    fn sample(input: &str, context: &Context) -> ValueWithUnit {
+        let mut rng = Rng::new(MONTE_CARLO_SEED);
+        monte_carlo(&parse_expression(input).unwrap(), context, 20_000, &mut rng).unwrap()
+    }
+
+    #[test]
+    fn test_monte_carlo_non_linear() {
+        // To first order x^2 at 0 has no uncertainty, but its mean is the variance, 1.
+        let result = sample("(0 ± 1)^2", &Context::new());
+        assert!((result.value - 1.0).abs() < 0.05, "{}", result.value);
+        assert!((result.uncertainty.unwrap() - 2f64.sqrt()).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_monte_carlo_correlated_variable() {
+        let mut context = Context::new();
+        context.set_variable("x", ValueWithUnit::uncertain(5.0, 0.5, Some("m".to_string())));
+        let result = sample("x - x", &context);
+        assert_eq!((result.value, result.uncertainty), (0.0, Some(0.0)));
+        assert_eq!(result.unit.as_deref(), Some("m"));
+    }
+
+    #[test]
+    fn test_monte_carlo_matches_linear_case() {
+        let result = sample("(10 ± 0.3 m) + (5 ± 0.4 m)", &Context::new());
+        assert!((result.value - 15.0).abs() < 0.02);
+        assert!((result.uncertainty.unwrap() - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_monte_carlo_skips_failed_samples() {
+        let context = Context::new();
+        let result = sample("sqrt(0.1 ± 0.1)", &context);
+        assert!(result.value > 0.0);
+        assert!(context.take_warnings()[0].contains("Monte Carlo samples were skipped"));
+    }
+}
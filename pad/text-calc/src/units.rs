@@ -0,0 +1,713 @@
+//! Unit definitions and conversions.
+//!
+//! Every unit is described by its dimension and its size in SI base units, so any two
+//! units of the same dimension can be converted into each other. Compound units such as
+//! `kg*m/s^2` are written as `*`- and `/`-separated symbols with optional integer powers.
+//! Angles are dimensionless, as in SI, with the radian as their base unit.
+//! Programs embedding the calculator can add their own units with [`with_units`].
+
+use std::cell::RefCell;
+
+/// Exponents of the SI base dimensions: length, mass, time, electric current,
+/// temperature, amount of substance and luminous intensity, followed by money.
+pub type Dimension = [i32; 8];
+
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0, 0];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0, 0];
+const DIMENSIONLESS: Dimension = [0; 8];
+/// The dimension of currencies, whose units come from exchange rates (see [`crate::currency`]).
+pub const CURRENCY: Dimension = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// A named unit.
+#[derive(Debug, Clone, Copy)]
+pub struct UnitDef {
+    /// The symbol used in expressions (e.g., `km`).
+    pub symbol: &'static str,
+    /// The size of one unit in SI base units (e.g., `1000.0` for `km`).
+    pub factor: f64,
+    /// The dimension of the unit.
+    pub dimension: Dimension,
+}
+
+/// Built-in units.
+static UNITS: &[UnitDef] = &[
+    // Length units
+    UnitDef { symbol: "m", factor: 1.0, dimension: LENGTH },
+    UnitDef { symbol: "km", factor: 1000.0, dimension: LENGTH },
+    UnitDef { symbol: "cm", factor: 0.01, dimension: LENGTH },
+    UnitDef { symbol: "mm", factor: 0.001, dimension: LENGTH },
+    UnitDef { symbol: "ft", factor: 0.3048, dimension: LENGTH },
+    UnitDef { symbol: "yd", factor: 0.9144, dimension: LENGTH },
+    UnitDef { symbol: "mi", factor: 1609.344, dimension: LENGTH },
+    // Mass units
+    UnitDef { symbol: "kg", factor: 1.0, dimension: MASS },
+    UnitDef { symbol: "g", factor: 0.001, dimension: MASS },
+    UnitDef { symbol: "lb", factor: 0.45359237, dimension: MASS },
+    UnitDef { symbol: "oz", factor: 0.028349523125, dimension: MASS },
+    // Time units
+    UnitDef { symbol: "s", factor: 1.0, dimension: TIME },
+    UnitDef { symbol: "min", factor: 60.0, dimension: TIME },
+    UnitDef { symbol: "h", factor: 3600.0, dimension: TIME },
+    UnitDef { symbol: "day", factor: 86400.0, dimension: TIME },
+    UnitDef { symbol: "days", factor: 86400.0, dimension: TIME },
+    UnitDef { symbol: "week", factor: 604800.0, dimension: TIME },
+    UnitDef { symbol: "weeks", factor: 604800.0, dimension: TIME },
+    // A year is a Julian year of 365.25 days, and a month a twelfth of it.
+    UnitDef { symbol: "month", factor: 2629800.0, dimension: TIME },
+    UnitDef { symbol: "months", factor: 2629800.0, dimension: TIME },
+    UnitDef { symbol: "year", factor: 31557600.0, dimension: TIME },
+    UnitDef { symbol: "years", factor: 31557600.0, dimension: TIME },
+    // Electric current and amount of substance
+    UnitDef { symbol: "A", factor: 1.0, dimension: CURRENT },
+    UnitDef { symbol: "mol", factor: 1.0, dimension: AMOUNT },
+    // Temperature units (factors apply to temperature differences)
+    UnitDef { symbol: "K", factor: 1.0, dimension: TEMPERATURE },
+    UnitDef { symbol: "C", factor: 1.0, dimension: TEMPERATURE },
+    UnitDef { symbol: "F", factor: 5.0 / 9.0, dimension: TEMPERATURE },
+    // Volume units
+    UnitDef { symbol: "L", factor: 0.001, dimension: VOLUME },
+    UnitDef { symbol: "mL", factor: 1e-6, dimension: VOLUME },
+    // Derived units
+    UnitDef { symbol: "Hz", factor: 1.0, dimension: FREQUENCY },
+    UnitDef { symbol: "N", factor: 1.0, dimension: FORCE },
+    UnitDef { symbol: "kN", factor: 1000.0, dimension: FORCE },
+    UnitDef { symbol: "Pa", factor: 1.0, dimension: PRESSURE },
+    UnitDef { symbol: "kPa", factor: 1000.0, dimension: PRESSURE },
+    UnitDef { symbol: "J", factor: 1.0, dimension: ENERGY },
+    UnitDef { symbol: "kJ", factor: 1000.0, dimension: ENERGY },
+    UnitDef { symbol: "W", factor: 1.0, dimension: POWER },
+    UnitDef { symbol: "kW", factor: 1000.0, dimension: POWER },
+    // Angle units
+    UnitDef { symbol: "rad", factor: 1.0, dimension: DIMENSIONLESS },
+    UnitDef { symbol: "deg", factor: std::f64::consts::PI / 180.0, dimension: DIMENSIONLESS },
+    UnitDef { symbol: "grad", factor: std::f64::consts::PI / 200.0, dimension: DIMENSIONLESS },
+    UnitDef { symbol: "turn", factor: std::f64::consts::TAU, dimension: DIMENSIONLESS },
+];
+
+/// Named units that products and quotients are simplified to, e.g., `N*m` to `J`.
+static DERIVED_NAMES: &[&str] = &["N", "kN", "Pa", "kPa", "J", "kJ", "W", "kW"];
+
+/// Temperature scales as `(symbol, degrees per degree Celsius, value at 0 °C)`.
+///
+/// Absolute temperatures convert through Celsius so that whole-degree values stay exact.
+static TEMPERATURE_SCALES: &[(&str, f64, f64)] = &[("C", 1.0, 0.0), ("F", 1.8, 32.0), ("K", 1.0, 273.15)];
+
+/// A unit defined at runtime, e.g., by a program embedding the calculator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomUnit {
+    /// The symbol used in expressions (e.g., `furlong`).
+    pub symbol: String,
+    /// The size of one unit in SI base units.
+    pub factor: f64,
+    /// The dimension of the unit.
+    pub dimension: Dimension,
+}
+
+impl CustomUnit {
+    /// Defines a unit as a multiple of an existing unit expression, e.g., `furlong` as `201.168 m`.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The new unit's symbol; letters only, and not already a built-in unit.
+    /// * `factor` - How many `base` units one new unit is.
+    /// * `base` - A unit expression of built-in units, e.g., `m` or `kg*m/s^2`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(CustomUnit)` - The unit definition.
+    /// * `Err(String)` - An error message if the symbol is taken or invalid, or `base` is unknown.
+    pub fn new(symbol: &str, factor: f64, base: &str) -> Result<Self, String> {
+        if symbol.is_empty() || !symbol.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("Invalid unit symbol '{}': use letters only", symbol));
+        }
+        if lookup(symbol).is_some() {
+            return Err(format!("'{}' is already a unit", symbol));
+        }
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(format!("The size of unit '{}' must be a positive number", symbol));
+        }
+        let (base_factor, dimension) = resolve(base)?;
+        Ok(Self {
+            symbol: symbol.to_string(),
+            factor: factor * base_factor,
+            dimension,
+        })
+    }
+}
+
+thread_local! {
+    /// Units added by [`with_units`] for the duration of a call.
+    static CUSTOM_UNITS: RefCell<Vec<CustomUnit>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Puts the previous custom units back when a [`with_units`] call ends, even by panicking.
+struct RestoreUnits(Vec<CustomUnit>);
+
+impl Drop for RestoreUnits {
+    fn drop(&mut self) {
+        CUSTOM_UNITS.with(|custom| custom.replace(std::mem::take(&mut self.0)));
+    }
+}
+
+/// Runs `f` with extra units known to every function in this module.
+///
+/// The units are only visible on the current thread and only until `f` returns.
+///
+/// # Arguments
+///
+/// * `units` - The units to add.
+/// * `f` - The code to run.
+///
+/// # Returns
+///
+/// * The result of `f`.
+pub fn with_units<R>(units: &[CustomUnit], f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreUnits(CUSTOM_UNITS.with(|custom| custom.replace(units.to_vec())));
+    f()
+}
+
+/// Looks up a built-in unit by its symbol.
+///
+/// # Arguments
+///
+/// * `symbol` - The unit symbol.
+///
+/// # Returns
+///
+/// * `Some(&UnitDef)` - The unit definition, if the symbol is known.
+pub fn lookup(symbol: &str) -> Option<&'static UnitDef> {
+    UNITS.iter().find(|unit| unit.symbol == symbol)
+}
+
+/// Returns all built-in units, grouped by dimension.
+pub fn all() -> &'static [UnitDef] {
+    UNITS
+}
+
+/// Writes a dimension in SI base units, e.g., `m*kg/s^2` for a force.
+///
+/// # Arguments
+///
+/// * `dimension` - The dimension to write.
+///
+/// # Returns
+///
+/// * `None` if the dimension is dimensionless.
+pub fn base_units(dimension: &Dimension) -> Option<String> {
+    // Currencies have no fixed base, so money is written with the generic currency sign.
+    const BASE_SYMBOLS: [&str; 8] = ["m", "kg", "s", "A", "K", "mol", "cd", "¤"];
+    let terms: Vec<(String, i32)> = BASE_SYMBOLS
+        .iter()
+        .zip(dimension)
+        .filter(|(_, exponent)| **exponent != 0)
+        .map(|(symbol, exponent)| (symbol.to_string(), *exponent))
+        .collect();
+    format_terms(&terms)
+}
+
+/// Returns the size in SI base units and the dimension of a built-in or custom unit symbol.
+fn find(symbol: &str) -> Option<(f64, Dimension)> {
+    match lookup(symbol) {
+        Some(def) => Some((def.factor, def.dimension)),
+        None => CUSTOM_UNITS.with(|custom| {
+            custom
+                .borrow()
+                .iter()
+                .find(|unit| unit.symbol == symbol)
+                .map(|unit| (unit.factor, unit.dimension))
+        }),
+    }
+}
+
+/// Checks whether a name is a known unit.
+///
+/// # Arguments
+///
+/// * `symbol` - The name to check.
+///
+/// # Returns
+///
+/// * `true` if the name is a built-in unit or a custom unit added by [`with_units`].
+pub fn is_unit(symbol: &str) -> bool {
+    find(symbol).is_some()
+}
+
+/// Checks whether a unit measures angles, like `deg` or `rad`.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression to check.
+///
+/// # Returns
+///
+/// * `true` if the unit is known and dimensionless.
+pub fn is_angle(unit: &str) -> bool {
+    resolve(unit).is_ok_and(|(_, dimension)| dimension == DIMENSIONLESS)
+}
+
+/// Checks whether a unit is a currency, like `USD`.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression to check.
+///
+/// # Returns
+///
+/// * `true` if the unit is known and measures money.
+pub fn is_currency(unit: &str) -> bool {
+    resolve(unit).is_ok_and(|(_, dimension)| dimension == CURRENCY)
+}
+
+/// Returns the scale of an SI prefix written on its own after a number, as in `4.7k`.
+///
+/// Letters that are also units, such as `m` (metre) or `h` (hour), are never prefixes.
+///
+/// # Arguments
+///
+/// * `symbol` - The suffix to check.
+///
+/// # Returns
+///
+/// * `Some(f64)` - The factor the prefix stands for, if the suffix is an unambiguous prefix.
+pub fn si_prefix(symbol: &str) -> Option<f64> {
+    let scale = match symbol {
+        "T" => 1e12,
+        "G" => 1e9,
+        "M" => 1e6,
+        "k" => 1e3,
+        "u" => 1e-6,
+        "n" => 1e-9,
+        "p" => 1e-12,
+        _ => return None,
+    };
+    (!is_unit(symbol)).then_some(scale)
+}
+
+/// Splits a unit expression such as `kg*m/s^2` into `(symbol, exponent)` terms.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression.
+///
+/// # Returns
+///
+/// * `Ok(Vec<(String, i32)>)` - The terms, in order of appearance.
+/// * `Err(String)` - An error message if a term is malformed.
+pub(crate) fn parse_terms(unit: &str) -> Result<Vec<(String, i32)>, String> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut rest = unit.trim();
+    loop {
+        let end = rest.find(['*', '/']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        let (symbol, exponent) = match term.split_once('^') {
+            Some((symbol, exponent)) => (
+                symbol.trim(),
+                exponent
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid exponent in unit '{}'", unit))?,
+            ),
+            None => (term, 1),
+        };
+        // A leading `1` allows reciprocal units such as `1/s`.
+        if symbol != "1" || !terms.is_empty() || sign != 1 {
+            if symbol.is_empty() {
+                return Err(format!("Invalid unit '{}'", unit));
+            }
+            terms.push((symbol.to_string(), sign * exponent));
+        }
+        if end == rest.len() {
+            return Ok(terms);
+        }
+        sign = if rest[end..].starts_with('/') { -1 } else { 1 };
+        rest = &rest[end + 1..];
+    }
+}
+
+/// Writes `(symbol, exponent)` terms as a unit expression, e.g., `kg*m/s^2`.
+///
+/// # Returns
+///
+/// * `None` if there are no terms (the quantity is dimensionless).
+fn format_terms(terms: &[(String, i32)]) -> Option<String> {
+    if terms.is_empty() {
+        return None;
+    }
+    let power = |symbol: &str, exponent: i32| match exponent {
+        1 => symbol.to_string(),
+        n => format!("{}^{}", symbol, n),
+    };
+    let numerator: Vec<String> = terms
+        .iter()
+        .filter(|(_, exponent)| *exponent > 0)
+        .map(|(symbol, exponent)| power(symbol, *exponent))
+        .collect();
+    let mut text = if numerator.is_empty() {
+        "1".to_string()
+    } else {
+        numerator.join("*")
+    };
+    for (symbol, exponent) in terms.iter().filter(|(_, exponent)| *exponent < 0) {
+        text.push('/');
+        text.push_str(&power(symbol, -exponent));
+    }
+    Some(text)
+}
+
+/// Resolves a unit expression to its size in SI base units and its dimension.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression, e.g., `km/h`.
+///
+/// # Returns
+///
+/// * `Ok((f64, Dimension))` - The factor to SI and the dimension.
+/// * `Err(String)` - An error message if a symbol is unknown.
+pub fn resolve(unit: &str) -> Result<(f64, Dimension), String> {
+    let mut factor = 1.0;
+    let mut dimension = DIMENSIONLESS;
+    for (symbol, exponent) in parse_terms(unit)? {
+        let (unit_factor, unit_dimension) = find(&symbol).ok_or_else(|| format!("Unknown unit '{}'", symbol))?;
+        factor *= unit_factor.powi(exponent);
+        for (total, base) in dimension.iter_mut().zip(unit_dimension) {
+            *total += base * exponent;
+        }
+    }
+    Ok((factor, dimension))
+}
+
+/// Checks whether two units measure the same dimension.
+///
+/// # Arguments
+///
+/// * `a` - The first unit expression.
+/// * `b` - The second unit expression.
+///
+/// # Returns
+///
+/// * `true` if both units are known and share a dimension.
+pub fn compatible(a: &str, b: &str) -> bool {
+    match (resolve(a), resolve(b)) {
+        (Ok((_, a)), Ok((_, b))) => a == b,
+        _ => false,
+    }
+}
+
+/// Combines two units raised to the given powers, as in a product (`1`) or quotient (`-1`).
+///
+/// Terms of the same dimension are merged into the unit written first, units that cancel
+/// are dropped, and a result matching a named unit (e.g., `N*m`) is given that name (`J`).
+///
+/// # Arguments
+///
+/// * `left` - The unit of the left operand, if any.
+/// * `right` - The unit of the right operand, if any.
+/// * `right_power` - `1` to multiply by `right`, `-1` to divide by it.
+///
+/// # Returns
+///
+/// * `Ok((f64, Option<String>))` - The factor to multiply the numeric value by, and the resulting unit.
+/// * `Err(String)` - An error message if a unit is unknown.
+fn combine(left: Option<&str>, right: Option<&str>, right_power: i32) -> Result<(f64, Option<String>), String> {
+    let mut terms = match left {
+        Some(unit) => parse_terms(unit)?,
+        None => Vec::new(),
+    };
+    if let Some(unit) = right {
+        terms.extend(parse_terms(unit)?.into_iter().map(|(symbol, exponent)| (symbol, exponent * right_power)));
+    }
+
+    let mut scale = 1.0;
+    let mut merged: Vec<(String, i32)> = Vec::new();
+    for (symbol, exponent) in terms {
+        let (factor, dimension) = find(&symbol).ok_or_else(|| format!("Unknown unit '{}'", symbol))?;
+        let existing = merged
+            .iter_mut()
+            .find(|(other, _)| find(other).is_some_and(|(_, other)| other == dimension));
+        match existing {
+            Some((other, total)) => {
+                // Exchange rates change daily, so a mix of currencies must be converted explicitly.
+                if *other != symbol && is_currency(&symbol) {
+                    return Err(format!(
+                        "Cannot mix {} and {} in multiplication or division; convert one with 'to' first",
+                        other, symbol
+                    ));
+                }
+                scale *= (factor / find(other).unwrap().0).powi(exponent);
+                *total += exponent;
+            }
+            None => merged.push((symbol, exponent)),
+        }
+    }
+    merged.retain(|(_, exponent)| *exponent != 0);
+
+    if merged.len() > 1 {
+        let text = format_terms(&merged).unwrap();
+        let (factor, dimension) = resolve(&text)?;
+        let named = DERIVED_NAMES
+            .iter()
+            .filter_map(|name| lookup(name))
+            .find(|def| def.dimension == dimension && ((def.factor - factor) / factor).abs() < 1e-12);
+        if let Some(def) = named {
+            return Ok((scale, Some(def.symbol.to_string())));
+        }
+    }
+    Ok((scale, format_terms(&merged)))
+}
+
+/// Multiplies two units.
+///
+/// # Arguments
+///
+/// * `left` - The unit of the left operand, if any.
+/// * `right` - The unit of the right operand, if any.
+///
+/// # Returns
+///
+/// * `Ok((f64, Option<String>))` - The factor to multiply the numeric value by, and the resulting unit.
+/// * `Err(String)` - An error message if a unit is unknown.
+pub fn multiply(left: Option<&str>, right: Option<&str>) -> Result<(f64, Option<String>), String> {
+    combine(left, right, 1)
+}
+
+/// Divides one unit by another.
+///
+/// # Arguments
+///
+/// * `left` - The unit of the dividend, if any.
+/// * `right` - The unit of the divisor, if any.
+///
+/// # Returns
+///
+/// * `Ok((f64, Option<String>))` - The factor to multiply the numeric value by, and the resulting unit.
+/// * `Err(String)` - An error message if a unit is unknown.
+pub fn divide(left: Option<&str>, right: Option<&str>) -> Result<(f64, Option<String>), String> {
+    combine(left, right, -1)
+}
+
+/// Raises a unit to an integer power.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression.
+/// * `exponent` - The power.
+///
+/// # Returns
+///
+/// * `Ok(Option<String>)` - The resulting unit, or `None` for a zero power.
+/// * `Err(String)` - An error message if the unit is malformed.
+pub fn power(unit: &str, exponent: i32) -> Result<Option<String>, String> {
+    let terms: Vec<(String, i32)> = parse_terms(unit)?
+        .into_iter()
+        .map(|(symbol, power)| (symbol, power * exponent))
+        .filter(|(_, power)| *power != 0)
+        .collect();
+    Ok(format_terms(&terms))
+}
+
+/// Takes a root of a unit, as in the square root of `m^2`.
+///
+/// Terms of the same dimension are merged first, so `km*m` has the square root `km` with
+/// a factor of `1/sqrt(1000)`.
+///
+/// # Arguments
+///
+/// * `unit` - The unit expression.
+/// * `degree` - The root to take, e.g., `2` for the square root.
+///
+/// # Returns
+///
+/// * `Ok((f64, Option<String>))` - The factor to multiply the root of the numeric value by, and the resulting unit.
+/// * `Err(String)` - An error message if a unit is unknown or a power does not divide by `degree`.
+pub fn root(unit: &str, degree: i32) -> Result<(f64, Option<String>), String> {
+    let (scale, merged) = multiply(Some(unit), None)?;
+    let terms = match &merged {
+        Some(merged) => parse_terms(merged)?,
+        None => Vec::new(),
+    };
+    if terms.iter().any(|(_, power)| power % degree != 0) {
+        return Err(format!("Cannot take root {} of the unit {}", degree, unit));
+    }
+    let terms: Vec<(String, i32)> = terms.into_iter().map(|(symbol, power)| (symbol, power / degree)).collect();
+    Ok((scale.powf(1.0 / degree as f64), format_terms(&terms)))
+}
+
+/// Converts a value from one unit to another.
+///
+/// # Arguments
+///
+/// * `value` - The numeric value to convert.
+/// * `from_unit` - The source unit.
+/// * `to_unit` - The target unit.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The converted value.
+/// * `Err(String)` - An error message if conversion fails.
+pub fn convert(value: f64, from_unit: &str, to_unit: &str) -> Result<f64, String> {
+    Ok(converter(from_unit, to_unit)?.apply(value))
+}
+
+/// A conversion between two units, resolved once to convert many values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Converter {
+    /// The units are the same.
+    Identity,
+    /// Multiplies by the size of the source unit and divides by that of the target.
+    Scale {
+        /// The size of the source unit in SI base units.
+        from: f64,
+        /// The size of the target unit in SI base units.
+        to: f64,
+    },
+    /// Converts between temperature scales, whose zeros differ.
+    Temperature {
+        /// The degrees of the source scale per degree Celsius, and its value at 0 °C.
+        from: (f64, f64),
+        /// The same for the target unit.
+        to: (f64, f64),
+    },
+}
+
+impl Converter {
+    /// Converts a value, exactly as [`convert`] does.
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Converter::Identity => value,
+            Converter::Scale { from, to } => value * from / to,
+            Converter::Temperature { from: (from_scale, from_zero), to: (to_scale, to_zero) } => {
+                (value - from_zero) / from_scale * to_scale + to_zero
+            }
+        }
+    }
+}
+
+/// Resolves the conversion from one unit to another.
+///
+/// # Arguments
+///
+/// * `from_unit` - The source unit.
+/// * `to_unit` - The target unit.
+///
+/// # Returns
+///
+/// * `Ok(Converter)` - The conversion.
+/// * `Err(String)` - An error message if the units are unknown or measure different dimensions.
+pub fn converter(from_unit: &str, to_unit: &str) -> Result<Converter, String> {
+    if from_unit == to_unit && resolve(from_unit).is_ok() {
+        return Ok(Converter::Identity);
+    }
+    if !compatible(from_unit, to_unit) {
+        return Err(format!(
+            "Conversion from '{}' to '{}' not supported",
+            from_unit, to_unit
+        ));
+    }
+
+    let scale = |symbol: &str| TEMPERATURE_SCALES.iter().find(|(name, _, _)| *name == symbol);
+    if let (Some((_, from_scale, from_zero)), Some((_, to_scale, to_zero))) = (scale(from_unit), scale(to_unit)) {
+        return Ok(Converter::Temperature { from: (*from_scale, *from_zero), to: (*to_scale, *to_zero) });
+    }
+
+    let (from_factor, _) = resolve(from_unit)?;
+    let (to_factor, _) = resolve(to_unit)?;
+    Ok(Converter::Scale { from: from_factor, to: to_factor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_length() {
+        assert!((convert(10.0, "km", "mi").unwrap() - 6.21371).abs() < 0.00001);
+        assert!((convert(1.0, "ft", "m").unwrap() - 0.3048).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_convert_temperature() {
+        assert_eq!(convert(100.0, "C", "F").unwrap(), 212.0);
+        assert_eq!(convert(212.0, "F", "C").unwrap(), 100.0);
+        assert!((convert(0.0, "C", "K").unwrap() - 273.15).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_convert_incompatible() {
+        assert!(convert(1.0, "kg", "m").is_err());
+        assert!(convert(1.0, "kg", "furlong").is_err());
+    }
+
+    #[test]
+    fn test_convert_compound() {
+        assert!((convert(36.0, "km/h", "m/s").unwrap() - 10.0).abs() < 1e-12);
+        assert!((convert(1.0, "kN*m", "J").unwrap() - 1000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_multiply_and_divide() {
+        assert_eq!(multiply(Some("N"), Some("m")).unwrap(), (1.0, Some("J".to_string())));
+        assert_eq!(multiply(Some("m"), Some("m")).unwrap(), (1.0, Some("m^2".to_string())));
+        assert_eq!(divide(Some("km"), Some("h")).unwrap(), (1.0, Some("km/h".to_string())));
+        assert_eq!(divide(None, Some("s")).unwrap(), (1.0, Some("1/s".to_string())));
+        let (scale, unit) = divide(Some("km"), Some("m")).unwrap();
+        assert_eq!((scale, unit), (1000.0, None));
+    }
+
+    #[test]
+    fn test_power() {
+        assert_eq!(power("m/s", 2).unwrap(), Some("m^2/s^2".to_string()));
+        assert_eq!(power("m", 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_root() {
+        assert_eq!(root("m^2", 2).unwrap(), (1.0, Some("m".to_string())));
+        assert_eq!(root("m^3/s^6", 3).unwrap(), (1.0, Some("m/s^2".to_string())));
+        let (scale, unit) = root("km*m", 2).unwrap();
+        assert!((scale - 1000f64.sqrt().recip()).abs() < 1e-12);
+        assert_eq!(unit.as_deref(), Some("km"));
+        assert!(root("m^3", 2).is_err());
+    }
+
+    #[test]
+    fn test_angles() {
+        assert!((convert(180.0, "deg", "rad").unwrap() - std::f64::consts::PI).abs() < 1e-12);
+        assert!((convert(0.25, "turn", "grad").unwrap() - 100.0).abs() < 1e-12);
+        assert!(is_angle("deg") && is_angle("rad/turn"));
+        assert!(!is_angle("m") && !is_angle("parsec"));
+    }
+
+    #[test]
+    fn test_all_and_base_units() {
+        assert!(all().iter().all(|unit| lookup(unit.symbol).is_some()));
+        assert_eq!(base_units(&lookup("kN").unwrap().dimension).as_deref(), Some("m*kg/s^2"));
+        assert_eq!(base_units(&lookup("Hz").unwrap().dimension).as_deref(), Some("1/s"));
+        assert_eq!(base_units(&DIMENSIONLESS), None);
+    }
+
+    #[test]
+    fn test_custom_units() {
+        let furlong = CustomUnit::new("furlong", 201.168, "m").unwrap();
+        assert!(CustomUnit::new("fortnight", 14.0, "sennight").is_err());
+        with_units(&[furlong], || {
+            assert!(is_unit("furlong"));
+            assert!((convert(8.0, "furlong", "mi").unwrap() - 1.0).abs() < 1e-12);
+            assert_eq!(multiply(Some("furlong"), Some("furlong")).unwrap(), (1.0, Some("furlong^2".to_string())));
+        });
+        assert!(!is_unit("furlong"));
+        assert!(CustomUnit::new("km", 1.0, "m").is_err());
+        assert!(CustomUnit::new("x1", 1.0, "m").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Worksheets: the lines of a session, evaluated again as they are edited.
+//!
+//! Each line is an assignment, an expression or blank, and may end with a `#` comment, as
+//! in a script without blocks. After an edit, [`Worksheet::evaluate`] recomputes only the
+//! lines whose text changed or that mention a variable whose value changed; the other
+//! lines keep their results.
+
+use crate::ast::Statement;
+use crate::calculator::{Calculator, Outcome};
+use crate::evaluator::ValueWithUnit;
+use crate::{parser, script};
+use std::collections::HashMap;
+
+/// Lines with their results, and the calculator that evaluates them.
+#[derive(Debug)]
+pub struct Worksheet {
+    calculator: Calculator,
+    /// The variables bound before the first line, such as those of a prelude.
+    base: Vec<(String, ValueWithUnit)>,
+    cells: Vec<Cell>,
+    /// The last version given to the value of a variable.
+    version: u64,
+}
+
+/// A line and what its last evaluation gave.
+#[derive(Debug)]
+struct Cell {
+    text: String,
+    /// The parsed line, or the parse error; `None` for a blank or comment line.
+    statement: Option<Result<Statement, String>>,
+    /// The names the line mentions, which include the variables it reads.
+    names: Vec<String>,
+    /// The versions of those variables when the line was last evaluated, `None` for an
+    /// unbound name; `None` altogether if the line has not been evaluated since it changed.
+    inputs: Option<Vec<Option<u64>>>,
+    result: Option<Result<Outcome, String>>,
+    warnings: Vec<String>,
+    /// The variable the line assigned, with the value and its version.
+    assigned: Option<(String, ValueWithUnit, u64)>,
+}
+
+impl Cell {
+    /// Parses a line, to be evaluated on the next pass.
+    fn new(text: &str, calculator: &Calculator) -> Self {
+        let code = script::strip_comment(text).trim();
+        let statement = (!code.is_empty()).then(|| {
+            parser::parse_statement(code, calculator.settings().number_format)
+                .map_err(|e| format!("Error parsing expression '{}': {}", code, e))
+        });
+        let mut names: Vec<String> = code
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            // The `x` of `3x` is a name too.
+            .map(|word| word.trim_start_matches(|c: char| c.is_ascii_digit()))
+            .filter(|word| !word.is_empty())
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.dedup();
+        Self {
+            text: text.to_string(),
+            statement,
+            names,
+            inputs: None,
+            result: None,
+            warnings: Vec::new(),
+            assigned: None,
+        }
+    }
+
+    /// Evaluates the line, binding the variable it assigns. The variable keeps its version
+    /// if its value did not change, so that the lines reading it are not recomputed.
+    fn evaluate(&mut self, calculator: &mut Calculator, version: &mut u64) {
+        let previous = self.assigned.take();
+        self.result = self.statement.as_ref().map(|statement| {
+            let statement = statement.clone()?;
+            let name = match &statement {
+                Statement::Assignment { name, .. } => Some(name.clone()),
+                Statement::Expression(_) => None,
+            };
+            let outcome = calculator.execute_statement(script::strip_comment(&self.text).trim(), statement);
+            if let (Some(name), Ok(Outcome::Value(value))) = (name, &outcome) {
+                let version = match previous {
+                    Some((old_name, old_value, old_version)) if old_name == name && same(&old_value, value) => old_version,
+                    _ => {
+                        *version += 1;
+                        *version
+                    }
+                };
+                self.assigned = Some((name, value.clone(), version));
+            }
+            outcome
+        });
+        self.warnings = calculator.take_warnings();
+    }
+}
+
+/// Checks whether two values are the same, unit and uncertainty included.
+fn same(a: &ValueWithUnit, b: &ValueWithUnit) -> bool {
+    a.value.to_bits() == b.value.to_bits() && a.unit == b.unit && a.kind == b.kind && a.uncertainty == b.uncertainty
+}
+
+impl Worksheet {
+    /// Creates a worksheet and evaluates it.
+    ///
+    /// # Arguments
+    ///
+    /// * `calculator` - The calculator to evaluate the lines with. The variables it has
+    ///   already bound are there for every line.
+    /// * `source` - The initial text, one line per line; an empty text gives one empty line.
+    pub fn new(calculator: Calculator, source: &str) -> Self {
+        let base = calculator.variables().into_iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+        let mut cells: Vec<Cell> = source.lines().map(|line| Cell::new(line, &calculator)).collect();
+        if cells.is_empty() {
+            cells.push(Cell::new("", &calculator));
+        }
+        let mut worksheet = Self { calculator, base, cells, version: 0 };
+        worksheet.evaluate();
+        worksheet
+    }
+
+    /// Returns the number of lines, at least one.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Checks whether the worksheet has only an empty line.
+    pub fn is_empty(&self) -> bool {
+        self.cells.len() == 1 && self.cells[0].text.is_empty()
+    }
+
+    /// Returns the text of a line.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The line, starting at 0; it must exist.
+    pub fn line(&self, index: usize) -> &str {
+        &self.cells[index].text
+    }
+
+    /// Returns the result of a line as of the last [`Worksheet::evaluate`], or `None` for a
+    /// blank or comment line.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The line, starting at 0; it must exist.
+    pub fn result(&self, index: usize) -> Option<&Result<Outcome, String>> {
+        self.cells[index].result.as_ref()
+    }
+
+    /// Returns the warnings of a line's last evaluation, such as an integral that did not converge.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The line, starting at 0; it must exist.
+    pub fn warnings(&self, index: usize) -> &[String] {
+        &self.cells[index].warnings
+    }
+
+    /// Returns the variables bound after the last line, sorted by name.
+    pub fn variables(&self) -> Vec<(&str, &ValueWithUnit)> {
+        self.calculator.variables()
+    }
+
+    /// Returns the calculator, e.g., for its settings.
+    pub fn calculator(&self) -> &Calculator {
+        &self.calculator
+    }
+
+    /// Returns the text of the worksheet, each line ending in a newline.
+    pub fn source(&self) -> String {
+        self.cells.iter().map(|cell| format!("{}\n", cell.text)).collect()
+    }
+
+    /// Replaces the text of a line; the line is recomputed on the next evaluation.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The line, starting at 0; it must exist.
+    /// * `text` - The new text.
+    pub fn set_line(&mut self, index: usize, text: &str) {
+        if self.cells[index].text != text {
+            let previous = std::mem::replace(&mut self.cells[index], Cell::new(text, &self.calculator));
+            // Kept to tell whether the value the line assigns has changed.
+            self.cells[index].assigned = previous.assigned;
+        }
+    }
+
+    /// Inserts a line before another, or at the end.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Where the new line goes, from 0 to [`Worksheet::len`].
+    /// * `text` - The text of the new line.
+    pub fn insert_line(&mut self, index: usize, text: &str) {
+        self.cells.insert(index, Cell::new(text, &self.calculator));
+    }
+
+    /// Removes a line, keeping at least one, and returns its text.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The line, starting at 0; it must exist.
+    pub fn remove_line(&mut self, index: usize) -> String {
+        let cell = self.cells.remove(index);
+        if self.cells.is_empty() {
+            self.cells.push(Cell::new("", &self.calculator));
+        }
+        cell.text
+    }
+
+    /// Evaluates the lines that changed since the last evaluation, and those that mention
+    /// a variable whose value changed since.
+    ///
+    /// # Returns
+    ///
+    /// The number of lines recomputed.
+    pub fn evaluate(&mut self) -> usize {
+        let bound: Vec<String> = self.calculator.variables().iter().map(|(name, _)| name.to_string()).collect();
+        for name in bound {
+            self.calculator.remove_variable(&name);
+        }
+        let mut versions: HashMap<String, u64> = HashMap::new();
+        for (name, value) in &self.base {
+            self.calculator.set_variable(name, value.clone());
+            versions.insert(name.clone(), 0);
+        }
+
+        let mut recomputed = 0;
+        for cell in &mut self.cells {
+            let inputs: Vec<Option<u64>> = cell.names.iter().map(|name| versions.get(name).copied()).collect();
+            if cell.inputs.as_ref() != Some(&inputs) {
+                cell.evaluate(&mut self.calculator, &mut self.version);
+                cell.inputs = Some(inputs);
+                recomputed += 1;
+            } else if let Some((name, value, _)) = &cell.assigned {
+                self.calculator.set_variable(name, value.clone());
+            }
+            if let Some((name, _, version)) = &cell.assigned {
+                versions.insert(name.clone(), *version);
+            }
+        }
+        recomputed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(worksheet: &Worksheet) -> Vec<String> {
+        (0..worksheet.len())
+            .map(|index| match worksheet.result(index) {
+                Some(Ok(outcome)) => outcome.to_string(),
+                Some(Err(_)) => "error".to_string(),
+                None => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let worksheet = Worksheet::new(Calculator::new(), "d = 42 km  # one way\nt = 2 h\n\nd / t to km/h\n1 m + 1 s\n");
+        assert_eq!(results(&worksheet), ["42 km", "2 h", "", "21 km/h", "error"]);
+        assert_eq!(worksheet.variables().len(), 2);
+        assert_eq!(worksheet.source().lines().next(), Some("d = 42 km  # one way"));
+    }
+
+    #[test]
+    fn test_recomputes_only_dependents() {
+        let mut worksheet = Worksheet::new(Calculator::new(), "a = 2\nb = 3\na * 10\nb * 10\n2 b\n");
+        assert_eq!(worksheet.evaluate(), 0);
+        worksheet.set_line(1, "b = 4");
+        assert_eq!(worksheet.evaluate(), 3);
+        assert_eq!(results(&worksheet), ["2", "4", "20", "40", "8"]);
+        // The same value leaves the lines that read it alone.
+        worksheet.set_line(0, "a = 4 / 2");
+        assert_eq!(worksheet.evaluate(), 1);
+    }
+
+    #[test]
+    fn test_insert_and_remove_lines() {
+        let mut worksheet = Worksheet::new(Calculator::new(), "x = 1\ny = x + 1\n");
+        worksheet.insert_line(1, "x = 10");
+        assert_eq!(worksheet.evaluate(), 2);
+        assert_eq!(results(&worksheet), ["1", "10", "11"]);
+        // `x = 10` no longer follows another `x`, but its value is the same.
+        assert_eq!(worksheet.remove_line(0), "x = 1");
+        assert_eq!(worksheet.evaluate(), 1);
+        worksheet.remove_line(0);
+        assert_eq!(worksheet.evaluate(), 1);
+        assert_eq!(results(&worksheet), ["error"]);
+        worksheet.remove_line(0);
+        assert!(worksheet.is_empty() && worksheet.variables().is_empty());
+    }
+
+    #[test]
+    fn test_base_variables() {
+        let mut calculator = Calculator::new();
+        calculator.set_variable("rate", ValueWithUnit::new(0.05, None));
+        let mut worksheet = Worksheet::new(calculator, "rate * 100\nrate = 0.1\n");
+        assert_eq!(results(&worksheet), ["5", "0.1"]);
+        worksheet.set_line(1, "");
+        worksheet.evaluate();
+        let variables = worksheet.variables();
+        assert_eq!((variables.len(), variables[0].0, variables[0].1.value), (1, "rate", 0.05));
+    }
+}
@@ -0,0 +1,65 @@
+/* Exercises the C API through include/textcalc.h; run by tests/ffi_test.rs. */
+
+#include <math.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "textcalc.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                   \
+    do {                                                                   \
+        if (!(condition)) {                                                \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,         \
+                    __LINE__, #condition);                                 \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+int main(void) {
+    TextCalc *calc = textcalc_new();
+    CHECK(calc != NULL);
+
+    TextCalcResult result = textcalc_eval(calc, "1.5 km * 2 to m");
+    CHECK(result.kind == TEXT_CALC_KIND_NUMBER);
+    CHECK(result.value == 3000.0);
+    CHECK(result.unit != NULL && strcmp(result.unit, "m") == 0);
+    CHECK(result.text != NULL && strcmp(result.text, "3000 m") == 0);
+    CHECK(result.error == NULL);
+    textcalc_result_free(&result);
+    CHECK(result.unit == NULL && result.text == NULL);
+
+    result = textcalc_eval(calc, "2 > 1");
+    CHECK(result.kind == TEXT_CALC_KIND_BOOLEAN);
+    CHECK(result.value == 1.0);
+    textcalc_result_free(&result);
+
+    result = textcalc_eval(calc, "d = 5 km");
+    CHECK(result.kind == TEXT_CALC_KIND_NUMBER);
+    CHECK(result.text != NULL && strcmp(result.text, "5 km") == 0);
+    textcalc_result_free(&result);
+
+    result = textcalc_eval(calc, "d * 2 to m");
+    CHECK(result.kind == TEXT_CALC_KIND_NUMBER);
+    CHECK(result.value == 10000.0);
+    CHECK(result.unit != NULL && strcmp(result.unit, "m") == 0);
+    textcalc_result_free(&result);
+
+    result = textcalc_eval(calc, "sqrt(");
+    CHECK(result.kind == TEXT_CALC_KIND_ERROR);
+    CHECK(isnan(result.value));
+    CHECK(result.error != NULL && strstr(result.error, "Parsing error") != NULL);
+    textcalc_result_free(&result);
+
+    result = textcalc_eval(NULL, "1");
+    CHECK(result.kind == TEXT_CALC_KIND_ERROR);
+    textcalc_result_free(&result);
+
+    textcalc_free(calc);
+
+    if (failures == 0) {
+        printf("ok\n");
+    }
+    return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,42 @@
+//! Compiles tests/c/ffi_test.c against include/textcalc.h, links it with the cdylib and
+//! runs it.
+
+#![cfg(target_os = "linux")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns the directory holding the cdylib built alongside this test binary.
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_c_api() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = library_dir();
+    assert!(
+        lib_dir.join("libtextcalculator.so").exists(),
+        "libtextcalculator.so not found in {}",
+        lib_dir.display()
+    );
+    let binary = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(root.join("tests/c/ffi_test.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .args(["-ltextcalculator", "-lm", "-Wall", "-Werror", "-o"])
+        .arg(&binary)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "compiling tests/c/ffi_test.c failed");
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -1,54 +1,338 @@
-use calculator::{parser, evaluator};
-use calculator::evaluator::ValueWithUnit;
+use textcalculator::{batch, evaluator, parser, plot, spec, symbolic};
+use textcalculator::script::{Output, Script};
+use textcalculator::ast::Expr;
+use textcalculator::calculator::{Calculator, NativeFunction, Outcome, Settings, Signature};
+use textcalculator::compile::Program;
+use textcalculator::config::Config;
+use textcalculator::currency::Rates;
+use textcalculator::export::{Document, Format};
+use textcalculator::uncertainty::Propagation;
+use textcalculator::worksheet::Worksheet;
 
 #[test]
-fn test_simple_expression() {
-    let expr = parser::parse_expression("3 + 4 * 2 / (1 - 5) ^ 2 ^ 3").unwrap();
-    let result = evaluator::evaluate(&expr).unwrap();
-    assert!((result.value - 3.0001220703125).abs() < 0.0001);
+fn test_calc_fixtures() {
+    let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut files: Vec<_> = std::fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+    files.retain(|path| path.extension().is_some_and(|extension| extension == "calc"));
+    assert!(!files.is_empty());
+    for path in files {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let report = spec::check(&source, &mut Calculator::new(), spec::DEFAULT_TOLERANCE).unwrap();
+        let failures: Vec<_> = report.failures().collect();
+        assert!(failures.is_empty(), "{}: {:?}", path.display(), failures);
+        assert!(report.passed() > 0, "{} has no expectations", path.display());
+    }
 }
 
 #[test]
-fn test_unit_conversion() {
-    let expr = parser::parse_expression("5 kg to lb").unwrap();
-    let result = evaluator::evaluate(&expr).unwrap();
-    assert_eq!(result.unit.unwrap(), "lb");
-    assert!((result.value - 11.0231).abs() < 0.0001);
+fn test_symbolic_differentiation() {
+    let expr = parser::parse_expression("diff(x^3 * sin(x), x)").unwrap();
+    let derivative = symbolic::expand_derivatives(&expr).unwrap();
+    assert_eq!(derivative.to_string(), "3 * x^2 * sin(x) + x^3 * cos(x)");
 }
 
 #[test]
-fn test_temperature_conversion() {
-    let expr = parser::parse_expression("100 C to F").unwrap();
+fn test_solve_equation() {
+    let expr = parser::parse_expression("solve(1000 * (1 + r)^5 = 1500, r)").unwrap();
     let result = evaluator::evaluate(&expr).unwrap();
-    assert_eq!(result.unit.unwrap(), "F");
-    assert_eq!(result.value, 212.0);
+    assert!((result.value - 0.0844717712).abs() < 1e-9);
+
+    let expr = parser::parse_expression("solve(x^2 + 3x = 10, x)").unwrap();
+    if let Expr::Function { args, .. } = expr {
+        let roots = textcalculator::solver::solve_call(&args, &evaluator::Context::new()).unwrap();
+        let values: Vec<f64> = roots.iter().map(|root| root.value).collect();
+        assert_eq!(values, vec![-5.0, 2.0]);
+    } else {
+        panic!("Expected Expr::Function");
+    }
 }
 
 #[test]
-fn test_function_evaluation() {
-    let expr = parser::parse_expression("sin(pi / 2)").unwrap();
+fn test_calculus_functions() {
+    let expr = parser::parse_expression("integrate(sin(x), x, 0, pi)").unwrap();
+    let result = evaluator::evaluate(&expr).unwrap();
+    assert!((result.value - 2.0).abs() < 1e-10);
+
+    let expr = parser::parse_expression("integrate(3 N, x, 0 m, 2 m)").unwrap();
     let result = evaluator::evaluate(&expr).unwrap();
-    assert!((result.value - 1.0).abs() < 0.0001);
+    assert!((result.value - 6.0).abs() < 1e-10);
+    assert_eq!(result.unit, Some("J".to_string()));
+
+    let expr = parser::parse_expression("sum(k^2, k, 1, 100)").unwrap();
+    assert_eq!(evaluator::evaluate(&expr).unwrap().value, 338350.0);
 }
 
 #[test]
-fn test_error_handling() {
-    let expr = parser::parse_expression("5 kg + 2 lb");
-    assert!(expr.is_ok());
-    let eval_result = evaluator::evaluate(&expr.unwrap());
-    assert!(eval_result.is_err());
+fn test_plot() {
+    let series = plot::sample("sin(x) / x", "x", (-10.0, 10.0), 41, &Calculator::new()).unwrap();
+    assert_eq!(series.points.len(), 41);
+    let chart = plot::render_braille(std::slice::from_ref(&series), 40, 10, false).unwrap();
+    assert_eq!(chart.lines().count(), 13);
+    assert!(plot::render_svg(&[series]).unwrap().contains("sin(x) / x"));
 }
 
 #[test]
-fn test_bracket_usage() {
-    let expr = parser::parse_expression("[2 * {3 + (4 - 1)}] / 5").unwrap();
-    let result = evaluator::evaluate(&expr).unwrap();
-    assert!((result.value - 2.4).abs() < 0.0001);
+fn test_table_and_map() {
+    let ranges = vec![batch::parse_range("x=1..3", &Calculator::new()).unwrap()];
+    let (table, errors) = batch::table("x^2", &ranges, &Calculator::new()).unwrap();
+    assert!(errors.is_empty());
+    assert_eq!(table.to_csv().unwrap(), "x,x^2\n1,1\n2,4\n3,9\n");
+
+    let data = "item,weight[kg]\nflour,2\n";
+    let (table, _) = batch::map(data.as_bytes(), "weight * 2", "double", &Calculator::new()).unwrap();
+    assert_eq!(table.to_csv().unwrap(), "item,weight[kg],double[kg]\nflour,2,4\n");
 }
 
 #[test]
-fn test_unary_minus() {
-    let expr = parser::parse_expression("-5 + 3 * -2").unwrap();
+fn test_comparisons_and_conditionals() {
+    let expr = parser::parse_expression("1 mi > 1500 m and not 2 kg == 2000 g").unwrap();
     let result = evaluator::evaluate(&expr).unwrap();
-    assert_eq!(result.value, -11.0);
+    assert!(result.is_boolean());
+    assert_eq!(result.to_string(), "false");
+
+    let expr = parser::parse_expression("60000 > 50000 ? 50000 * 0.2 + (60000 - 50000) * 0.3 : 60000 * 0.2").unwrap();
+    assert_eq!(evaluator::evaluate(&expr).unwrap().value, 13000.0);
+}
+
+#[test]
+fn test_implicit_multiplication_and_integer_operators() {
+    let evaluate = |input: &str| evaluator::evaluate(&parser::parse_expression(input).unwrap()).unwrap().value;
+    assert_eq!(evaluate("2 sqrt(4) + 3(4 + 5)"), 31.0);
+    assert_eq!(evaluate("5! + 17 mod 5 + 17 // 5"), 125.0);
+    assert!((evaluate("2pi") - 2.0 * std::f64::consts::PI).abs() < 1e-12);
+}
+
+#[test]
+fn test_number_literals_and_formats() {
+    let evaluate = |input: &str| evaluator::evaluate(&parser::parse_expression(input).unwrap()).unwrap().value;
+    assert_eq!(evaluate("-2^2 + 2^-2"), -3.75);
+    assert_eq!(evaluate("1_000 * 4.7k"), 4.7e6);
+    assert_eq!(evaluate("2.5e3 + 5E-1"), 2500.5);
+    assert_eq!(evaluate(".5 + 1"), 1.5);
+
+    let expr = parser::parse_expression_with("1.234,5 * 2", parser::NumberFormat::DecimalComma).unwrap();
+    assert_eq!(evaluator::evaluate(&expr).unwrap().value, 2469.0);
+    let expr = parser::parse_expression_with("if(2,000 > 1,500, 1,000, 0)", parser::NumberFormat::Grouped).unwrap();
+    assert_eq!(evaluator::evaluate(&expr).unwrap().value, 1000.0);
+}
+
+#[test]
+fn test_constants() {
+    let evaluate = |input: &str| evaluator::evaluate(&parser::parse_expression(input).unwrap()).unwrap();
+    let result = evaluate("G_newton * 5.972e24 kg / (6371 km)^2 to m/s^2");
+    assert!((result.value - 9.82).abs() < 0.01);
+    assert_eq!(result.unit.as_deref(), Some("m/s^2"));
+    assert!((evaluate("R * 273.15 K * 1 mol / atm to L").value - 22.414).abs() < 0.001);
+    assert!(symbolic::free_symbols(&parser::parse_expression("k_B * T").unwrap()).contains("T"));
+}
+
+#[test]
+fn test_embedded_calculator() {
+    struct Area;
+
+    impl NativeFunction for Area {
+        fn signature(&self) -> Signature {
+            Signature::new(&[Some("m"), Some("m")], Some("m^2"))
+        }
+
+        fn call(&self, args: &[f64]) -> Result<f64, String> {
+            Ok(args[0] * args[1])
+        }
+    }
+
+    let mut calculator = Calculator::new();
+    calculator.register_function("area", Area).unwrap();
+    calculator.define_unit("acre", 4046.8564224, "m^2").unwrap();
+    calculator.set_variable("side", evaluator::ValueWithUnit::new(1.0, Some("km".to_string())));
+    match calculator.eval("area(side, 500 m) to acre").unwrap() {
+        Outcome::Value(value) => assert!((value.value - 123.553).abs() < 0.001),
+        other => panic!("Expected a value, got {}", other),
+    }
+    assert!(calculator.eval("area(1 m, 2 s)").is_err());
+}
+
+#[test]
+fn test_uncertainty() {
+    let mut calculator = Calculator::new();
+    calculator.execute("g = 9.81 ± 0.02 m/s^2").unwrap();
+    calculator.execute("t = 1.50 ± 0.01 s").unwrap();
+    assert_eq!(calculator.eval("g * t^2 / 2 to m").unwrap().to_string(), "11.04 ± 0.15 m");
+    assert!(calculator.eval("sqrt(0 ± 1)").is_err());
+
+    let settings = Settings { propagation: Propagation::MonteCarlo { samples: 10_000 }, ..Settings::default() };
+    let mut calculator = Calculator::with_settings(settings);
+    match calculator.execute("sqrt((0 ± 1)^2)").unwrap() {
+        Outcome::Value(value) => assert!((value.value - 0.8).abs() < 0.05, "{}", value),
+        other => panic!("Expected a value, got {}", other),
+    }
+}
+
+#[test]
+fn test_explain() {
+    let trace = Calculator::new().explain("1 mi > 1500 m and 1 h // 25 min == 2").unwrap();
+    assert_eq!(trace.result.as_ref().unwrap().to_string(), "true");
+    let text = trace.render();
+    assert!(text.contains("  1 mi > 1500 m = true\n    conversion: 1 m = 0.0006213711922373 mi\n"), "{}", text);
+    assert!(text.contains("    1 h // (25 min) = 2\n      conversion: 1 h/min = 60\n"), "{}", text);
+    assert_eq!(trace.to_json()["children"][0]["notes"][0], "1 m = 0.0006213711922373 mi");
+}
+
+#[test]
+fn test_script() {
+    let source = "\
+fn compound(principal, rate, years) {
+    return principal * (1 + rate)^years
+}
+balance = 1000
+for year in 1..3 {
+    balance = compound(balance, 0.05, 1)
+    if balance > 1100 {
+        print \"Year {year}: {balance:.1}\"
+    }
+}
+balance
+while balance > 1 {
+    balance = balance +
+}";
+    let script = Script::parse(source, parser::NumberFormat::Standard).unwrap();
+    let mut calculator = Calculator::new();
+    let mut printed = Vec::new();
+    let error = script
+        .run(&mut calculator, &mut |output| {
+            if let Output::Print(text) = output {
+                printed.push(text);
+            }
+        })
+        .unwrap_err();
+    assert_eq!(printed, ["Year 2: 1102.5", "Year 3: 1157.6"]);
+    assert_eq!(calculator.eval("balance").unwrap().to_string(), "1157.625");
+    assert_eq!((error.line, error.column), (13, 24));
+}
+
+#[test]
+fn test_currencies() {
+    let rates = Rates::parse_csv("currency,rate,date\nEUR,1,2024-06-28\nUSD,1.0705,2024-06-28\nJPY,172.1,2024-06-27\n").unwrap();
+    let mut calculator = Calculator::new();
+    calculator.set_rates(rates).unwrap();
+    calculator.execute("price = 49.99 EUR").unwrap();
+    match calculator.eval("price * 3 to JPY").unwrap() {
+        Outcome::Value(value) => assert!((value.value - 25809.837).abs() < 1e-6, "{}", value),
+        other => panic!("Expected a value, got {}", other),
+    }
+    assert_eq!(
+        calculator.take_warnings(),
+        ["Converted EUR to JPY at the exchange rates of 2024-06-27 (JPY) and 2024-06-28 (EUR)"]
+    );
+    assert_eq!(calculator.eval("100 EUR > 100 USD").unwrap().to_string(), "true");
+    assert!(calculator.eval("price - 10 USD").is_err());
+}
+
+#[test]
+fn test_finance() {
+    let rates = Rates::parse_json(r#"{ "base": "EUR", "date": "2024-06-28", "rates": { "USD": 1.0705 } }"#).unwrap();
+    let mut calculator = Calculator::new();
+    calculator.set_rates(rates).unwrap();
+    calculator.execute("loan = 400000 USD").unwrap();
+    assert_eq!(calculator.eval("round(pmt(5%/12, 360, loan))").unwrap().to_string(), "-2147 USD");
+    assert_eq!(calculator.eval("round(fv(0, 12, -100 EUR, loan))").unwrap().to_string(), "-372457 EUR");
+    assert_eq!(calculator.take_warnings(), ["Converted USD to EUR at the exchange rates of 2024-06-28"]);
+    assert_eq!(calculator.eval("round(compound(1000, 4.5%, 10 years, monthly))").unwrap().to_string(), "1567");
+    assert_eq!(calculator.eval("round(irr([-1000, 300, 400, 500]) * 1000)").unwrap().to_string(), "89");
+}
+
+#[test]
+fn test_seeded_random_script() {
+    let source = "\
+rolls = 0
+for i in 1..10 {
+    rolls += 2d6
+    print \"{rand():.6}\"
+}
+rolls";
+    let run = |seed: u64| {
+        let script = Script::parse(source, parser::NumberFormat::Standard).unwrap();
+        let mut calculator = Calculator::with_settings(Settings { seed: Some(seed), ..Settings::default() });
+        let mut printed = Vec::new();
+        script
+            .run(&mut calculator, &mut |output| {
+                if let Output::Print(text) = output {
+                    printed.push(text);
+                }
+            })
+            .unwrap();
+        (calculator.variable("rolls").unwrap().value, printed)
+    };
+    let (rolls, printed) = run(7);
+    assert!((20.0..=120.0).contains(&rolls));
+    assert_eq!(printed.len(), 10);
+    assert_eq!(run(7), (rolls, printed.clone()));
+    assert_ne!(run(8).1, printed);
+}
+
+#[test]
+fn test_configuration() {
+    let directory = std::env::temp_dir().join(format!("textcalc-config-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("units.txt"), "furlong = 201.168 m\nfortnight = 14 day\n").unwrap();
+    std::fs::write(directory.join("prelude.calc"), "fn speed(d, t) {\n    return d / t to m/s\n}\nlap = 1.5 furlong\n").unwrap();
+    let text = "precision = 3\nnotation = \"scientific\"\nunits = [\"units.txt\"]\nprelude = \"prelude.calc\"\n";
+    std::fs::write(directory.join("config.toml"), text).unwrap();
+
+    let flags = Config { precision: Some(2), ..Config::default() };
+    let config = flags.or(Config::load(&directory.join("config.toml")).unwrap());
+    let mut calculator = Calculator::with_settings(config.settings());
+    assert!(config.load_definitions(&mut calculator).is_empty());
+    let outcome = calculator.eval("speed(1 furlong, 1 fortnight)").unwrap();
+    assert_eq!(calculator.format(&outcome), "1.7e-4 m/s");
+    assert_eq!(calculator.variable("lap").unwrap().to_string(), "1.5 furlong");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_worksheet() {
+    let mut calculator = Calculator::new();
+    let Outcome::Value(fuel) = calculator.eval("6.5 L").unwrap() else { panic!("expected a value") };
+    calculator.set_variable("fuel", fuel);
+    let mut worksheet = Worksheet::new(calculator, "distance = 120 km\nfuel / distance to L/km\ndistance to mi\n");
+    let result = |worksheet: &Worksheet, index| worksheet.result(index).unwrap().as_ref().unwrap().to_string();
+    assert!(result(&worksheet, 1).starts_with("0.054"));
+    worksheet.set_line(0, "distance = 130 km  # longer route");
+    assert_eq!(worksheet.evaluate(), 3);
+    assert_eq!(result(&worksheet, 1), "0.05 L/km");
+    worksheet.set_line(2, "fuel");
+    assert_eq!(worksheet.evaluate(), 1);
+    assert_eq!(result(&worksheet, 2), "6.5 L");
+}
+
+#[test]
+fn test_export() {
+    let source = "# Free fall\ng = 9.81 m/s^2\nh = g * (3 s)^2 / 2\nh + 1 kg\n";
+    let mut calculator = Calculator::new();
+    let document = Document::from_script(source, &mut calculator).unwrap();
+    let html = document.render(Format::Html, calculator.settings());
+    assert!(html.contains("<p>Free fall</p>"));
+    assert!(html.contains("<td class=\"result\">44.145 m</td>"));
+    assert!(html.contains("<tr class=\"error\">"));
+    let latex = document.render(Format::Latex, calculator.settings());
+    assert!(latex.contains("&\\Rightarrow \\SI{9.81}{m.s^{-2}}"));
+    assert!(latex.contains("\\textcolor{red}"));
+}
+
+#[test]
+fn test_compiled_program() {
+    let expr = parser::parse_expression("0.5 * 9.81 m/s^2 * t^2 to m").unwrap();
+    let mut program = Program::compile(&expr, &[("t", Some("s"))], &evaluator::Context::new()).unwrap();
+    assert_eq!(program.unit(), Some("m"));
+    let mut context = evaluator::Context::new();
+    for t in [0.0, 1.5, 3.0] {
+        context.set_variable("t", evaluator::ValueWithUnit::new(t, Some("s".to_string())));
+        let expected = evaluator::evaluate_with(&expr, &context).unwrap();
+        assert_eq!(program.evaluate(&[t]).unwrap().to_bits(), expected.value.to_bits());
+    }
+
+    // Rows that do not fit the compiled units, such as a missing cell, are evaluated as usual.
+    let data = "d[km],t[h]\n42,2\n,1\n";
+    let (table, errors) = batch::map(data.as_bytes(), "d / t to km/h", "speed", &Calculator::new()).unwrap();
+    assert_eq!(table.rows[0], ["42", "2", "21"]);
+    assert_eq!(errors, ["Row 2: Unknown variable: d"]);
 }
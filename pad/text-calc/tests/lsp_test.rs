@@ -0,0 +1,71 @@
+//! Runs the language server over an in-memory connection and talks to it as an editor would.
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use serde_json::{json, Value};
+use std::thread;
+use textcalculator::calculator::Settings;
+use textcalculator::lsp;
+
+/// Sends a request and returns the result of its response.
+fn request(client: &Connection, id: i32, method: &str, params: Value) -> Value {
+    let request = Request::new(RequestId::from(id), method.to_string(), params);
+    client.sender.send(Message::Request(request)).unwrap();
+    match client.receiver.recv().unwrap() {
+        Message::Response(response) => {
+            assert_eq!(response.id, RequestId::from(id));
+            response.result.unwrap_or(Value::Null)
+        }
+        other => panic!("Expected a response, got {:?}", other),
+    }
+}
+
+/// Sends a notification.
+fn notify(client: &Connection, method: &str, params: Value) {
+    client.sender.send(Message::Notification(Notification::new(method.to_string(), params))).unwrap();
+}
+
+/// Waits for the next diagnostics the server publishes.
+fn diagnostics(client: &Connection) -> Value {
+    match client.receiver.recv().unwrap() {
+        Message::Notification(notification) if notification.method == "textDocument/publishDiagnostics" => {
+            notification.params["diagnostics"].clone()
+        }
+        other => panic!("Expected diagnostics, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_language_server_session() {
+    let (server, client) = Connection::memory();
+    let server_thread = thread::spawn(move || lsp::serve(&server, Settings::default()));
+
+    let result = request(&client, 1, "initialize", json!({ "capabilities": {} }));
+    assert_eq!(result["capabilities"]["hoverProvider"], json!(true));
+    notify(&client, "initialized", json!({}));
+
+    let uri = "file:///tmp/trip.calc";
+    let text = "d = 42 km\nd / 2 h to km/h\n1 kg + 1 m\n";
+    let document = json!({ "uri": uri, "languageId": "calc", "version": 1, "text": text });
+    notify(&client, "textDocument/didOpen", json!({ "textDocument": document }));
+    let published = diagnostics(&client);
+    assert_eq!(published.as_array().unwrap().len(), 1);
+    assert_eq!(published[0]["range"]["start"], json!({ "line": 2, "character": 0 }));
+
+    let position = json!({ "textDocument": { "uri": uri }, "position": { "line": 1, "character": 0 } });
+    let hover = request(&client, 2, "textDocument/hover", position.clone());
+    assert_eq!(hover["contents"]["value"], json!("`d` = `42 km`"));
+    let definition = request(&client, 3, "textDocument/definition", position);
+    assert_eq!(definition["range"]["start"], json!({ "line": 0, "character": 0 }));
+
+    let range = json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 2, "character": 0 } });
+    let hints = request(&client, 4, "textDocument/inlayHint", json!({ "textDocument": { "uri": uri }, "range": range }));
+    assert_eq!(hints[1]["label"], json!("= 21 km/h"));
+
+    let change = json!({ "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": "d = 42 km\n" }] });
+    notify(&client, "textDocument/didChange", change);
+    assert_eq!(diagnostics(&client), json!([]));
+
+    request(&client, 5, "shutdown", Value::Null);
+    notify(&client, "exit", Value::Null);
+    server_thread.join().unwrap().unwrap();
+}
@@ -0,0 +1,66 @@
+//! Starts the HTTP server on a free localhost port and talks to it over TCP.
+
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use textcalculator::server::{Server, ServerOptions};
+
+/// Starts a server in a background thread and returns its port.
+fn start_server() -> u16 {
+    let (sender, receiver) = mpsc::channel();
+    // The server is not `Send`, so it is created on the thread that runs it.
+    thread::spawn(move || {
+        let server = Server::bind("127.0.0.1:0", ServerOptions::default()).unwrap();
+        sender.send(server.port().unwrap()).unwrap();
+        server.run();
+    });
+    receiver.recv().unwrap()
+}
+
+/// Sends one HTTP request and returns the status code and the JSON body of the response.
+fn request(port: u16, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_server_end_to_end() {
+    let port = start_server();
+
+    let (status, body) = request(port, "POST", "/eval", r#"{"expression": "5 kg to lb"}"#);
+    assert_eq!(status, 200);
+    assert_eq!(body["results"][0]["unit"], json!("lb"));
+    assert!((body["results"][0]["value"].as_f64().unwrap() - 11.0231).abs() < 0.0001);
+
+    let first = json!({ "expressions": ["d = 42 km", "t = 2 h"], "session": "trip" });
+    assert_eq!(request(port, "POST", "/eval", &first.to_string()).0, 200);
+    let second = json!({ "expression": "d / t to km/h", "session": "trip" });
+    let (_, body) = request(port, "POST", "/eval", &second.to_string());
+    assert_eq!(body["results"][0]["result"], json!("21 km/h"));
+
+    let (status, body) = request(port, "GET", "/units", "");
+    assert_eq!(status, 200);
+    assert!(body["units"].as_array().unwrap().iter().any(|unit| unit["symbol"] == "mi"));
+    let (_, body) = request(port, "GET", "/functions", "");
+    assert!(body["functions"].as_array().unwrap().iter().any(|function| function["name"] == "integrate"));
+
+    let (status, body) = request(port, "POST", "/eval", "{");
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().starts_with("Invalid request"));
+    assert_eq!(request(port, "GET", "/missing", "").0, 404);
+}